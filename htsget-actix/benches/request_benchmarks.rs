@@ -230,6 +230,8 @@ fn criterion_benchmark(c: &mut Criterion) {
     tags: None,
     notags: None,
     regions: None,
+    version: None,
+    index_version: None,
   };
   bench_pair(
     &mut group,
@@ -250,6 +252,8 @@ fn criterion_benchmark(c: &mut Criterion) {
       start: None,
       end: None,
     }]),
+    version: None,
+    index_version: None,
   };
   bench_pair(
     &mut group,
@@ -277,6 +281,8 @@ fn criterion_benchmark(c: &mut Criterion) {
         end: Some(5008321),
       },
     ]),
+    version: None,
+    index_version: None,
   };
   bench_pair(
     &mut group,
@@ -297,6 +303,8 @@ fn criterion_benchmark(c: &mut Criterion) {
       start: Some(1),
       end: Some(153),
     }]),
+    version: None,
+    index_version: None,
   };
   bench_pair(
     &mut group,
@@ -317,6 +325,8 @@ fn criterion_benchmark(c: &mut Criterion) {
       start: None,
       end: None,
     }]),
+    version: None,
+    index_version: None,
   };
   bench_pair(
     &mut group,
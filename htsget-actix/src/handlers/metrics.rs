@@ -0,0 +1,13 @@
+use actix_web::HttpResponse;
+
+use htsget_http::metrics::prometheus_handle;
+
+/// The content type of the Prometheus text format.
+pub const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Renders the recorded metrics in the Prometheus text format.
+pub async fn metrics() -> HttpResponse {
+  HttpResponse::Ok()
+    .content_type(PROMETHEUS_CONTENT_TYPE)
+    .body(prometheus_handle().render())
+}
@@ -1,5 +1,6 @@
 use std::collections::HashMap;
 
+use actix_web::http::header::RETRY_AFTER;
 use actix_web::web::{Path, Query};
 use actix_web::{http::StatusCode, Either, HttpRequest, Responder};
 use http::{HeaderMap as HttpHeaderMap, HeaderName, Method};
@@ -13,6 +14,7 @@ pub use crate::handlers::service_info::{
 };
 
 pub mod get;
+pub mod metrics;
 pub mod post;
 pub mod service_info;
 
@@ -37,11 +39,15 @@ fn handle_response(response: Result<JsonResponse>) -> Either<impl Responder, imp
   match response {
     Err(error) => {
       let (json, status_code) = error.to_json_representation();
-      Either::Left(
-        PrettyJson(json)
-          .customize()
-          .with_status(HttpVersionCompat::status_code_1_to_0_2(status_code)),
-      )
+
+      let mut response = PrettyJson(json)
+        .customize()
+        .with_status(HttpVersionCompat::status_code_1_to_0_2(status_code));
+      if let Some(retry_after) = error.retry_after() {
+        response = response.insert_header((RETRY_AFTER, retry_after.to_string()));
+      }
+
+      Either::Left(response)
     }
     Ok(json) => Either::Right(PrettyJson(json).customize().with_status(StatusCode::OK)),
   }
@@ -9,8 +9,10 @@ use htsget_config::config::advanced::cors::CorsConfig;
 use htsget_config::config::service_info::ServiceInfo;
 use htsget_config::config::ticket_server::TicketServerConfig;
 pub use htsget_config::config::{Config, USAGE};
+use htsget_http::metrics::prometheus_handle;
 use htsget_search::HtsGet;
 
+use crate::handlers::metrics::metrics;
 use crate::handlers::{get, post, reads_service_info, variants_service_info, HttpVersionCompat};
 
 pub mod handlers;
@@ -48,6 +50,13 @@ pub fn configure_server<H: HtsGet + Clone + Send + Sync + 'static>(
     );
 }
 
+/// Configure the `/metrics` endpoint, installing the metrics recorder.
+pub fn configure_metrics(service_config: &mut web::ServiceConfig) {
+  prometheus_handle();
+
+  service_config.route("/metrics", web::get().to(metrics));
+}
+
 /// Configure cors, settings allowed methods, max age, allowed origins, and if credentials
 /// are supported.
 pub fn configure_cors(cors: CorsConfig) -> Cors {
@@ -120,6 +129,10 @@ pub fn run_server<H: HtsGet + Clone + Send + Sync + 'static>(
     App::new()
       .configure(|service_config: &mut web::ServiceConfig| {
         configure_server(service_config, htsget.clone(), service_info.clone());
+
+        if config_copy.metrics() {
+          configure_metrics(service_config);
+        }
       })
       .wrap(configure_cors(config_copy.cors().clone()))
       .wrap(TracingLogger::default())
@@ -320,6 +333,40 @@ mod tests {
       .await;
   }
 
+  #[actix_web::test]
+  async fn metrics_endpoint() {
+    let config = default_test_config();
+    let app = test::init_service(App::new().configure(
+      |service_config: &mut web::ServiceConfig| {
+        configure_server(
+          service_config,
+          config.clone().into_locations(),
+          config.service_info().clone(),
+        );
+        configure_metrics(service_config);
+      },
+    ))
+    .await;
+
+    let response = test::TestRequest::get()
+      .uri("/reads/data/bam/htsnexus_test_NA12878?format=VCF")
+      .send_request(&app)
+      .await;
+    assert_eq!(response.status(), 400);
+
+    let response = test::TestRequest::get()
+      .uri("/metrics")
+      .send_request(&app)
+      .await;
+    assert_eq!(response.status(), 200);
+
+    let body = String::from_utf8(test::read_body(response).await.to_vec()).unwrap();
+    assert!(body.contains(
+      r#"htsget_requests_total{endpoint="reads",format="none",class="none",status="400"}"#
+    ));
+    assert!(body.contains(r#"htsget_errors_total{kind="UnsupportedFormat"}"#));
+  }
+
   #[actix_web::test]
   async fn service_info() {
     server::test_service_info(&ActixTestServer::default()).await;
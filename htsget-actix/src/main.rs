@@ -8,6 +8,7 @@ use htsget_actix::Config;
 use htsget_axum::server::data;
 use htsget_config::config::data_server::DataServerEnabled;
 use htsget_config::{command, package_info};
+use htsget_http::audit::setup_audit;
 
 #[actix_web::main]
 async fn main() -> io::Result<()> {
@@ -18,34 +19,44 @@ async fn main() -> io::Result<()> {
   if let Some(path) = Config::parse_args_with_command(command!())? {
     let mut config = Config::from_path(&path)?;
 
-    config.setup_tracing()?;
+    let tracing = config.setup_tracing_with_guard()?;
+    let _audit = setup_audit(config.audit())?;
 
     let service_info = config.service_info_mut();
     service_info.set_from_package_info(package_info!())?;
 
     debug!(config = ?config, "config parsed");
 
-    if let DataServerEnabled::Some(data_server) = config.data_server() {
-      let local_server = data::join_handle(data_server.clone()).await?;
-
-      let ticket_server_config = config.ticket_server().clone();
-      let service_info = config.service_info().clone();
-
-      select! {
-        local_server = local_server => Ok(local_server??),
-        actix_server = run_server(
-          config.into_locations(),
-          ticket_server_config,
-          service_info
-        )? => actix_server
-      }
-    } else {
-      let ticket_server_config = config.ticket_server().clone();
-      let service_info = config.service_info().clone();
-
-      run_server(config.into_locations(), ticket_server_config, service_info)?.await
-    }
+    // The actix server stops on SIGINT or SIGTERM, after which spans are flushed.
+    let result = run(config).await;
+
+    tracing.shutdown().await;
+    result
   } else {
     Ok(())
   }
 }
+
+/// Run the servers until either of them exits.
+async fn run(config: Config) -> io::Result<()> {
+  if let DataServerEnabled::Some(data_server) = config.data_server() {
+    let local_server = data::join_handle(data_server.clone()).await?;
+
+    let ticket_server_config = config.ticket_server().clone();
+    let service_info = config.service_info().clone();
+
+    select! {
+      local_server = local_server => Ok(local_server??),
+      actix_server = run_server(
+        config.into_locations(),
+        ticket_server_config,
+        service_info
+      )? => actix_server
+    }
+  } else {
+    let ticket_server_config = config.ticket_server().clone();
+    let service_info = config.service_info().clone();
+
+    run_server(config.into_locations(), ticket_server_config, service_info)?.await
+  }
+}
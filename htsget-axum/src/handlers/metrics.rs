@@ -0,0 +1,44 @@
+use std::time::Instant;
+
+use axum::extract::Request;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::Router;
+use http::header::CONTENT_TYPE;
+
+use htsget_http::metrics::{prometheus_handle, record_request};
+
+/// The content type of the Prometheus text format.
+pub const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Renders the recorded metrics in the Prometheus text format.
+pub async fn metrics() -> impl IntoResponse {
+  (
+    [(CONTENT_TYPE, PROMETHEUS_CONTENT_TYPE)],
+    prometheus_handle().render(),
+  )
+}
+
+/// Create a router with the `/metrics` endpoint, installing the metrics recorder.
+pub fn metrics_router() -> Router {
+  prometheus_handle();
+
+  Router::new().route("/metrics", get(metrics))
+}
+
+/// Middleware which records requests to the data server.
+pub async fn record_data_request(request: Request, next: Next) -> Response {
+  let start = Instant::now();
+  let response = next.run(request).await;
+
+  record_request(
+    "data",
+    None,
+    None,
+    response.status().as_u16(),
+    start.elapsed(),
+  );
+
+  response
+}
@@ -3,7 +3,8 @@ use std::collections::HashMap;
 use axum::extract::{Path, Query};
 use axum::response::IntoResponse;
 use axum_extra::response::ErasedJson;
-use http::{HeaderMap, StatusCode};
+use http::header::RETRY_AFTER;
+use http::{HeaderMap, HeaderValue, StatusCode};
 
 use htsget_config::types::{JsonResponse, Request};
 
@@ -12,17 +13,26 @@ pub use crate::handlers::service_info::{
 };
 
 pub mod get;
+pub mod metrics;
 pub mod post;
 pub mod service_info;
 
 /// Handles a response, converting errors to json and using the proper HTTP status code
-fn handle_response(response: htsget_http::Result<JsonResponse>) -> (StatusCode, impl IntoResponse) {
+fn handle_response(
+  response: htsget_http::Result<JsonResponse>,
+) -> (StatusCode, HeaderMap, impl IntoResponse) {
   match response {
     Err(error) => {
       let (json, status_code) = error.to_json_representation();
-      (status_code, ErasedJson::pretty(json))
+
+      let mut headers = HeaderMap::new();
+      if let Some(retry_after) = error.retry_after() {
+        headers.insert(RETRY_AFTER, HeaderValue::from(retry_after));
+      }
+
+      (status_code, headers, ErasedJson::pretty(json))
     }
-    Ok(json) => (StatusCode::OK, ErasedJson::pretty(json)),
+    Ok(json) => (StatusCode::OK, HeaderMap::new(), ErasedJson::pretty(json)),
   }
 }
 
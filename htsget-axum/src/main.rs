@@ -1,12 +1,16 @@
 use rustls::crypto::aws_lc_rs;
 use std::io;
 use tokio::select;
-use tracing::debug;
+use tokio::signal::ctrl_c;
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::{debug, info};
 
 use htsget_axum::server::{data, ticket};
 use htsget_config::config::data_server::DataServerEnabled;
 use htsget_config::config::Config;
 use htsget_config::{command, package_info};
+use htsget_http::audit::setup_audit;
 
 #[tokio::main]
 async fn main() -> io::Result<()> {
@@ -19,25 +23,57 @@ async fn main() -> io::Result<()> {
   {
     let mut config = Config::from_path(&path)?;
 
-    config.setup_tracing()?;
+    let tracing = config.setup_tracing_with_guard()?;
+    let _audit = setup_audit(config.audit())?;
 
     let service_info = config.service_info_mut();
     service_info.set_from_package_info(package_info!())?;
 
     debug!(config = ?config, "config parsed");
 
-    if let DataServerEnabled::Some(data_server) = config.data_server() {
-      let local_server = data::join_handle(data_server.clone()).await?;
-      let ticket_server = ticket::join_handle(config).await?;
+    let result = run(config).await;
 
-      select! {
-        local_server = local_server => Ok(local_server??),
-        axum_server = ticket_server => Ok(axum_server??)
-      }
-    } else {
-      Ok(ticket::join_handle(config).await?.await??)
-    }
+    tracing.shutdown().await;
+    result
   } else {
     Ok(())
   }
 }
+
+/// Run the servers until either of them exits, or a signal is received.
+async fn run(config: Config) -> io::Result<()> {
+  if let DataServerEnabled::Some(data_server) = config.data_server() {
+    let local_server = data::join_handle(data_server.clone()).await?;
+    let ticket_server = ticket::join_handle(config).await?;
+
+    select! {
+      local_server = local_server => Ok(local_server??),
+      axum_server = ticket_server => Ok(axum_server??),
+      signal = shutdown_signal() => signal
+    }
+  } else {
+    let ticket_server = ticket::join_handle(config).await?;
+
+    select! {
+      axum_server = ticket_server => Ok(axum_server??),
+      signal = shutdown_signal() => signal
+    }
+  }
+}
+
+/// Wait for a signal to shut down the server.
+async fn shutdown_signal() -> io::Result<()> {
+  #[cfg(unix)]
+  {
+    let mut terminate = signal(SignalKind::terminate())?;
+    select! {
+      result = ctrl_c() => result?,
+      _ = terminate.recv() => {}
+    }
+  }
+  #[cfg(not(unix))]
+  ctrl_c().await?;
+
+  info!("shutting down");
+  Ok(())
+}
@@ -1,16 +1,48 @@
 //! The axum data server.
 //!
 
+#[cfg(feature = "experimental")]
+use crate::error::Error::ServerError;
 use crate::error::Result;
-use crate::server::{configure_cors, BindServer, Server};
+use crate::handlers::metrics::{metrics_router, record_data_request};
+use crate::server::{configure_cors, trace_layer, BindServer, Server};
+#[cfg(feature = "experimental")]
+use axum::body::Body;
+#[cfg(feature = "experimental")]
+use axum::extract::{Path as UrlPath, Query, State};
+use axum::middleware::from_fn;
+#[cfg(feature = "experimental")]
+use axum::response::{IntoResponse, Response};
+#[cfg(feature = "experimental")]
+use axum::routing::get;
 use axum::Router;
+#[cfg(feature = "experimental")]
+use axum_extra::response::ErasedJson;
 use htsget_config::config::advanced::cors::CorsConfig;
 use htsget_config::config::data_server::DataServerConfig;
+#[cfg(feature = "experimental")]
+use htsget_config::storage::c4gh::{C4GHKeys, C4GHMode};
+#[cfg(feature = "experimental")]
+use htsget_config::storage::file::File;
+#[cfg(feature = "experimental")]
+use htsget_search::{
+  verify_decrypt_ticket, BytesPosition, C4GHStorage, FileStorage, GetOptions, HtsGetError,
+  DECRYPT_EXPIRES_PARAM, DECRYPT_KEYS_PARAM, DECRYPT_PATH, DECRYPT_SIGNATURE_PARAM,
+};
+#[cfg(feature = "experimental")]
+use http::header::{CONTENT_RANGE, RANGE};
+#[cfg(feature = "experimental")]
+use http::{HeaderMap, HeaderValue, StatusCode};
+#[cfg(feature = "experimental")]
+use std::cmp::min;
+#[cfg(feature = "experimental")]
+use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::path::Path;
 use tokio::task::JoinHandle;
+#[cfg(feature = "experimental")]
+use tokio_util::io::ReaderStream;
 use tower_http::services::ServeDir;
-use tower_http::trace::TraceLayer;
 use tracing::info;
 
 /// An data block server.
@@ -18,17 +50,65 @@ use tracing::info;
 pub struct DataServer {
   server: Server,
   cors: CorsConfig,
+  metrics: bool,
+  #[cfg(feature = "experimental")]
+  decrypt_keys: Vec<C4GHKeys>,
 }
 
 impl DataServer {
   /// Create a new data server.
   pub fn new(server: Server, cors: CorsConfig) -> Self {
-    Self { server, cors }
+    Self {
+      server,
+      cors,
+      metrics: false,
+      #[cfg(feature = "experimental")]
+      decrypt_keys: vec![],
+    }
+  }
+
+  /// Set whether the `/metrics` endpoint is enabled.
+  pub fn with_metrics(mut self, metrics: bool) -> Self {
+    self.metrics = metrics;
+    self
+  }
+
+  #[cfg(feature = "experimental")]
+  /// Set the C4GH keys of the locations in the decrypt mode, which the decrypt endpoint serves.
+  pub fn with_decrypt_keys(mut self, decrypt_keys: Vec<C4GHKeys>) -> Self {
+    self.decrypt_keys = decrypt_keys;
+    self
   }
 
   /// Run the data server, using the provided path, key and certificate.
   pub async fn serve<P: AsRef<Path>>(self, path: P) -> Result<()> {
-    self.server.serve(Self::router(self.cors, path)).await
+    #[cfg(feature = "experimental")]
+    if !self.decrypt_keys.is_empty() {
+      let router = Self::router_with_decrypt(self.cors, path, self.decrypt_keys)?;
+      return self
+        .server
+        .serve(Self::with_metrics_router(router, self.metrics))
+        .await;
+    }
+
+    self
+      .server
+      .serve(Self::with_metrics_router(
+        Self::router(self.cors, path),
+        self.metrics,
+      ))
+      .await
+  }
+
+  /// Record requests and add the `/metrics` endpoint to the router if metrics are enabled.
+  fn with_metrics_router(router: Router, metrics: bool) -> Router {
+    if metrics {
+      router
+        .layer(from_fn(record_data_request))
+        .merge(metrics_router())
+    } else {
+      router
+    }
   }
 
   /// Create the router for the data server.
@@ -36,7 +116,31 @@ impl DataServer {
     Router::new()
       .nest_service("/", ServeDir::new(path))
       .layer(configure_cors(cors))
-      .layer(TraceLayer::new_for_http())
+      .layer(trace_layer())
+  }
+
+  #[cfg(feature = "experimental")]
+  /// Create the router for the data server, with an endpoint that decrypts C4GH objects under the
+  /// path. Requests must have a decrypt ticket signed for one of the location keys.
+  pub fn router_with_decrypt<P: AsRef<Path>>(
+    cors: CorsConfig,
+    path: P,
+    decrypt_keys: Vec<C4GHKeys>,
+  ) -> Result<Router> {
+    let state = DecryptState {
+      storage: FileStorage::new(path.as_ref(), File::default())
+        .map_err(|err| ServerError(err.to_string()))?,
+      decrypt_keys,
+    };
+
+    Ok(
+      Router::new()
+        .route(&format!("/{}/*key", DECRYPT_PATH), get(decrypt))
+        .with_state(state)
+        .fallback_service(ServeDir::new(path))
+        .layer(configure_cors(cors))
+        .layer(trace_layer()),
+    )
   }
 
   /// Get the local address the server has bound to.
@@ -45,6 +149,184 @@ impl DataServer {
   }
 }
 
+#[cfg(feature = "experimental")]
+/// The state of the decrypt endpoint.
+#[derive(Debug, Clone)]
+struct DecryptState {
+  storage: FileStorage<File>,
+  decrypt_keys: Vec<C4GHKeys>,
+}
+
+#[cfg(feature = "experimental")]
+/// Convert an error into an htsget error response.
+fn error_response(err: htsget_http::HtsGetError) -> Response {
+  let (json, status_code) = err.to_json_representation();
+  (status_code, ErasedJson::pretty(json)).into_response()
+}
+
+#[cfg(feature = "experimental")]
+/// A range requested from the decrypt endpoint.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum RequestedRange {
+  /// A byte position with an exclusive end.
+  Position(BytesPosition),
+  /// The last bytes of the object, with the number of bytes.
+  Suffix(u64),
+}
+
+#[cfg(feature = "experimental")]
+impl RequestedRange {
+  /// Resolve the range to a byte position using the size of the object.
+  fn resolve(&self, size: u64) -> BytesPosition {
+    match self {
+      RequestedRange::Position(position) => position.clone(),
+      RequestedRange::Suffix(length) => {
+        BytesPosition::new(Some(size.saturating_sub(*length)), None, None)
+      }
+    }
+  }
+}
+
+#[cfg(feature = "experimental")]
+/// Parse a single `bytes=start-end`, `bytes=start-` or `bytes=-length` range header.
+fn parse_range(value: &HeaderValue) -> Option<RequestedRange> {
+  let (start, end) = value
+    .to_str()
+    .ok()?
+    .trim()
+    .strip_prefix("bytes=")?
+    .split_once('-')?;
+
+  if start.trim().is_empty() {
+    let length = end.trim().parse::<u64>().ok()?;
+    return (length > 0).then_some(RequestedRange::Suffix(length));
+  }
+
+  let start = start.trim().parse::<u64>().ok()?;
+  let end = match end.trim() {
+    "" => None,
+    end => {
+      let end = end.parse::<u64>().ok()?;
+      if end < start {
+        return None;
+      }
+
+      Some(end.checked_add(1)?)
+    }
+  };
+
+  Some(RequestedRange::Position(BytesPosition::new(
+    Some(start),
+    end,
+    None,
+  )))
+}
+
+#[cfg(feature = "experimental")]
+/// Decrypt the requested byte range of a C4GH object, streaming the plaintext. The request must
+/// have a ticket signed for the key and range, which selects the location keys to decrypt with.
+async fn decrypt(
+  State(state): State<DecryptState>,
+  UrlPath(key): UrlPath<String>,
+  Query(params): Query<HashMap<String, String>>,
+  headers: HeaderMap,
+) -> Response {
+  let range_header = headers
+    .get(RANGE)
+    .map(|value| value.to_str().unwrap_or_default())
+    .unwrap_or_default();
+
+  let invalid = || {
+    error_response(htsget_http::HtsGetError::PermissionDenied(
+      "invalid, expired or missing decrypt ticket".to_string(),
+    ))
+  };
+
+  let id = params
+    .get(DECRYPT_KEYS_PARAM)
+    .and_then(|id| id.parse::<u64>().ok());
+  let expires = params
+    .get(DECRYPT_EXPIRES_PARAM)
+    .and_then(|expires| expires.parse::<u64>().ok());
+  let (Some(id), Some(expires), Some(signature)) =
+    (id, expires, params.get(DECRYPT_SIGNATURE_PARAM))
+  else {
+    return invalid();
+  };
+
+  let Some((keys, ticket)) = state
+    .decrypt_keys
+    .iter()
+    .filter(|keys| keys.id() == id && keys.mode() == C4GHMode::Decrypt)
+    .find_map(|keys| Some((keys, keys.decrypt_ticket()?)))
+  else {
+    return invalid();
+  };
+
+  let secret = match ticket.read_secret().await {
+    Ok(secret) => secret,
+    Err(err) => return error_response(htsget_http::HtsGetError::InternalError(err.to_string())),
+  };
+  if !verify_decrypt_ticket(
+    secret.as_bytes(),
+    id,
+    &key,
+    range_header,
+    expires,
+    signature,
+  ) {
+    return invalid();
+  }
+
+  let range = match headers.get(RANGE) {
+    Some(value) => match parse_range(value) {
+      Some(range) => Some(range),
+      None => return StatusCode::RANGE_NOT_SATISFIABLE.into_response(),
+    },
+    None => None,
+  };
+
+  let decryption_keys = match keys.clone().keys().await {
+    Ok(keys) => keys,
+    Err(err) => return error_response(htsget_http::HtsGetError::InternalError(err.to_string())),
+  };
+  let storage = C4GHStorage::new(decryption_keys, state.storage.clone());
+
+  let resolve = |size| {
+    range
+      .as_ref()
+      .map(|range| range.resolve(size))
+      .unwrap_or_default()
+  };
+  let options = GetOptions::new(Default::default(), &headers);
+  let (data, size) = match storage.decrypt_range_with(&key, options, resolve).await {
+    Ok(decrypted) => decrypted,
+    Err(err) => return error_response(HtsGetError::from(err).into()),
+  };
+  let body = Body::from_stream(ReaderStream::new(data));
+
+  let Some(range) = range.map(|range| range.resolve(size)) else {
+    return (StatusCode::OK, body).into_response();
+  };
+
+  let start = range.get_start().unwrap_or_default();
+  if start >= size {
+    return (
+      StatusCode::RANGE_NOT_SATISFIABLE,
+      [(CONTENT_RANGE, format!("bytes */{}", size))],
+    )
+      .into_response();
+  }
+
+  let end = min(range.get_end().unwrap_or(size), size) - 1;
+  (
+    StatusCode::PARTIAL_CONTENT,
+    [(CONTENT_RANGE, format!("bytes {}-{}/{}", start, end, size))],
+    body,
+  )
+    .into_response()
+}
+
 impl From<DataServerConfig> for BindServer {
   /// Returns a data server with TLS enabled if the tls config is not None or without TLS enabled
   /// if it is None.
@@ -62,7 +344,12 @@ impl From<DataServerConfig> for BindServer {
 /// Spawn a task to run the data server.
 pub async fn join_handle(config: DataServerConfig) -> Result<JoinHandle<Result<()>>> {
   let local_path = config.local_path().to_path_buf();
-  let data_server = BindServer::from(config.clone()).bind_data_server().await?;
+  let data_server = BindServer::from(config.clone())
+    .bind_data_server()
+    .await?
+    .with_metrics(config.metrics());
+  #[cfg(feature = "experimental")]
+  let data_server = data_server.with_decrypt_keys(config.decrypt_keys().to_vec());
 
   info!(address = ?data_server.local_addr()?, "data server address bound to");
 
@@ -256,6 +543,179 @@ mod tests {
     .await;
   }
 
+  #[cfg(feature = "experimental")]
+  #[test]
+  fn parse_range_header() {
+    let parse = |value: &'static str| parse_range(&http::HeaderValue::from_static(value));
+
+    assert_eq!(
+      parse("bytes=1-3"),
+      Some(RequestedRange::Position(BytesPosition::new(
+        Some(1),
+        Some(4),
+        None
+      )))
+    );
+    assert_eq!(
+      parse("bytes=1-"),
+      Some(RequestedRange::Position(BytesPosition::new(
+        Some(1),
+        None,
+        None
+      )))
+    );
+    assert_eq!(parse("bytes=-2"), Some(RequestedRange::Suffix(2)));
+    assert_eq!(
+      RequestedRange::Suffix(2).resolve(6),
+      BytesPosition::new(Some(4), None, None)
+    );
+    assert_eq!(
+      RequestedRange::Suffix(10).resolve(6),
+      BytesPosition::new(Some(0), None, None)
+    );
+
+    assert_eq!(parse("bytes=-0"), None);
+    assert_eq!(parse("bytes=-"), None);
+    assert_eq!(parse("bytes=3-1"), None);
+    assert_eq!(parse("items=1-3"), None);
+  }
+
+  #[cfg(feature = "experimental")]
+  #[tokio::test]
+  async fn decrypt_endpoint() {
+    use htsget_config::storage::c4gh::local::C4GHLocal;
+    use htsget_config::storage::c4gh::passphrase::C4GHPassphrase;
+    use htsget_config::storage::c4gh::C4GHDecryptTicket;
+    use htsget_search::sign_decrypt_ticket;
+    use htsget_test::c4gh::encrypt_data;
+    use htsget_test::util::default_dir;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    let (_, base_path) = create_local_test_files().await;
+    File::create(base_path.path().join("folder/key3.c4gh"))
+      .await
+      .unwrap()
+      .write_all(&encrypt_data(b"value3"))
+      .await
+      .unwrap();
+
+    let secret = base_path.path().join("ticket_secret");
+    std::fs::write(&secret, "secret").unwrap();
+
+    let keys = C4GHKeys::try_from(C4GHLocal::new(
+      default_dir().join("data/c4gh/keys/bob.sec"),
+      default_dir().join("data/c4gh/keys/alice.pub"),
+    ))
+    .unwrap()
+    .with_mode(C4GHMode::Decrypt)
+    .with_decrypt_ticket(Some(C4GHDecryptTicket::new(C4GHPassphrase::File(secret))));
+    let id = keys.id();
+    let expires = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .unwrap()
+      .as_secs()
+      + 60;
+
+    let addr = SocketAddr::from_str("127.0.0.1:0").unwrap();
+    let server = Server::bind_addr(addr, None).await.unwrap();
+    let port = server.local_addr().unwrap().port();
+    let data_server = DataServer::new(server, default_cors_config()).with_decrypt_keys(vec![keys]);
+    let path = base_path.path().to_path_buf();
+    tokio::spawn(async move { data_server.serve(path).await.unwrap() });
+
+    let test_server = DataTestServer::default();
+    let signed_request = |range: Option<&'static str>, signed: &str, expires: u64| {
+      let request = test_server.request().method(Method::GET).uri(format!(
+        "http://localhost:{port}/decrypt/folder/key3?keys={id}&expires={expires}&signature={}",
+        sign_decrypt_ticket(b"secret", id, "folder/key3", signed, expires)
+      ));
+      match range {
+        Some(range) => request.insert_header(Header {
+          name: RANGE,
+          value: http::HeaderValue::from_static(range),
+        }),
+        None => request,
+      }
+    };
+    let request =
+      |range: Option<&'static str>, signed: &str| signed_request(range, signed, expires);
+
+    let response = test_server
+      .test_server(request(None, ""), "".to_string())
+      .await;
+    assert!(response.is_success());
+    assert_eq!(response.body, b"value3");
+
+    let response = test_server
+      .test_server(request(Some("bytes=1-3"), "bytes=1-3"), "".to_string())
+      .await;
+    assert_eq!(response.status, 206);
+    assert_eq!(response.headers.get(CONTENT_RANGE).unwrap(), "bytes 1-3/6");
+    assert_eq!(response.body, b"alu");
+
+    let response = test_server
+      .test_server(request(Some("bytes=1-"), "bytes=1-"), "".to_string())
+      .await;
+    assert_eq!(response.status, 206);
+    assert_eq!(response.headers.get(CONTENT_RANGE).unwrap(), "bytes 1-5/6");
+    assert_eq!(response.body, b"alue3");
+
+    let response = test_server
+      .test_server(request(Some("bytes=-2"), "bytes=-2"), "".to_string())
+      .await;
+    assert_eq!(response.status, 206);
+    assert_eq!(response.headers.get(CONTENT_RANGE).unwrap(), "bytes 4-5/6");
+    assert_eq!(response.body, b"e3");
+
+    let response = test_server
+      .test_server(request(Some("bytes=-10"), "bytes=-10"), "".to_string())
+      .await;
+    assert_eq!(response.status, 206);
+    assert_eq!(response.headers.get(CONTENT_RANGE).unwrap(), "bytes 0-5/6");
+    assert_eq!(response.body, b"value3");
+
+    let response = test_server
+      .test_server(request(Some("bytes=10-"), "bytes=10-"), "".to_string())
+      .await;
+    assert_eq!(response.status, 416);
+
+    // The signature must match the requested range.
+    let response = test_server
+      .test_server(request(Some("bytes=0-5"), "bytes=1-3"), "".to_string())
+      .await;
+    assert_eq!(response.status, 403);
+
+    // Expired tickets are rejected.
+    let response = test_server
+      .test_server(signed_request(None, "", expires - 120), "".to_string())
+      .await;
+    assert_eq!(response.status, 403);
+
+    // Requests without a ticket are rejected.
+    let response = test_server
+      .test_server(
+        test_server
+          .request()
+          .method(Method::GET)
+          .uri(format!("http://localhost:{port}/decrypt/folder/key3")),
+        "".to_string(),
+      )
+      .await;
+    assert_eq!(response.status, 403);
+
+    // Plaintext files are still served by the data server.
+    let response = test_server
+      .test_server(
+        test_server
+          .request()
+          .method(Method::GET)
+          .uri(format!("http://localhost:{port}/key1")),
+        "".to_string(),
+      )
+      .await;
+    assert_eq!(response.body, b"value1");
+  }
+
   fn tls_formatter() -> BindServer {
     let _ = aws_lc_rs::default_provider().install_default();
 
@@ -12,6 +12,8 @@ use axum::extract::Request;
 use axum::Router;
 use htsget_config::config::advanced::cors::CorsConfig;
 use htsget_config::config::service_info::ServiceInfo;
+#[cfg(feature = "otel")]
+use htsget_config::config::telemetry::set_parent_from_headers;
 use htsget_config::tls::TlsServerConfig;
 use htsget_config::types::Scheme;
 use htsget_search::HtsGet;
@@ -23,9 +25,11 @@ use hyper_util::server::conn::auto::Builder;
 use tokio::net::TcpListener;
 use tokio_rustls::TlsAcceptor;
 use tower::Service;
+use tower_http::classify::{ServerErrorsAsFailures, SharedClassifier};
 use tower_http::cors::{AllowHeaders, AllowMethods, AllowOrigin, CorsLayer, ExposeHeaders};
+use tower_http::trace::{DefaultMakeSpan, MakeSpan, TraceLayer};
 use tracing::trace;
-use tracing::{error, warn};
+use tracing::{error, warn, Level, Span};
 
 use crate::error::Error::ServerError;
 use crate::error::Result;
@@ -49,6 +53,33 @@ impl<H: HtsGet> AppState<H> {
   }
 }
 
+/// Creates the span of a request at the info level. With the `otel` feature, the span continues
+/// the trace from the W3C trace context headers of the request.
+#[derive(Debug, Clone)]
+pub struct RequestSpan(DefaultMakeSpan);
+
+impl Default for RequestSpan {
+  fn default() -> Self {
+    Self(DefaultMakeSpan::new().level(Level::INFO))
+  }
+}
+
+impl<B> MakeSpan<B> for RequestSpan {
+  fn make_span(&mut self, request: &http::Request<B>) -> Span {
+    let span = self.0.make_span(request);
+
+    #[cfg(feature = "otel")]
+    set_parent_from_headers(&span, request.headers());
+
+    span
+  }
+}
+
+/// Create the trace layer used by the ticket and data servers.
+pub fn trace_layer() -> TraceLayer<SharedClassifier<ServerErrorsAsFailures>, RequestSpan> {
+  TraceLayer::new_for_http().make_span_with(RequestSpan::default())
+}
+
 /// Configure cors, settings allowed methods, max age, allowed origins, and if credentials
 /// are supported.
 pub fn configure_cors(cors: CorsConfig) -> CorsLayer {
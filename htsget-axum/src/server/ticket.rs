@@ -2,8 +2,9 @@
 //!
 
 use crate::error::Result;
+use crate::handlers::metrics::metrics_router;
 use crate::handlers::{get, post, reads_service_info, variants_service_info};
-use crate::server::{configure_cors, AppState, BindServer, Server};
+use crate::server::{configure_cors, trace_layer, AppState, BindServer, Server};
 use axum::routing::get;
 use axum::Router;
 use htsget_config::config::advanced::cors::CorsConfig;
@@ -14,7 +15,6 @@ use htsget_search::HtsGet;
 use std::net::SocketAddr;
 use tokio::task::JoinHandle;
 use tower::ServiceBuilder;
-use tower_http::trace::TraceLayer;
 use tracing::info;
 
 impl From<TicketServerConfig> for BindServer {
@@ -38,6 +38,7 @@ pub struct TicketServer<H> {
   htsget: H,
   service_info: ServiceInfo,
   cors: CorsConfig,
+  metrics: bool,
 }
 
 impl<H> TicketServer<H>
@@ -51,15 +52,24 @@ where
       htsget,
       service_info,
       cors,
+      metrics: false,
     }
   }
 
+  /// Set whether the `/metrics` endpoint is enabled.
+  pub fn with_metrics(mut self, metrics: bool) -> Self {
+    self.metrics = metrics;
+    self
+  }
+
   /// Run the data server, using the key and certificate.
   pub async fn serve(self) -> Result<()> {
-    self
-      .server
-      .serve(Self::router(self.htsget, self.service_info, self.cors))
-      .await
+    let mut router = Self::router(self.htsget, self.service_info, self.cors);
+    if self.metrics {
+      router = router.merge(metrics_router());
+    }
+
+    self.server.serve(router).await
   }
 
   /// Create the router for the ticket server.
@@ -77,7 +87,7 @@ where
       .route("/variants/*id", get(get::variants).post(post::variants))
       .layer(
         ServiceBuilder::new()
-          .layer(TraceLayer::new_for_http())
+          .layer(trace_layer())
           .layer(configure_cors(cors)),
       )
       .with_state(AppState::new(htsget, service_info))
@@ -92,9 +102,11 @@ where
 /// Spawn a task to run the ticket server.
 pub async fn join_handle(config: Config) -> Result<JoinHandle<Result<()>>> {
   let service_info = config.service_info().clone();
+  let metrics = config.ticket_server().metrics();
   let ticket_server = BindServer::from(config.ticket_server().clone())
     .bind_ticket_server(config.into_locations(), service_info)
-    .await?;
+    .await?
+    .with_metrics(metrics);
 
   info!(address = ?ticket_server.local_addr()?, "ticket server address bound to");
 
@@ -302,6 +314,50 @@ mod tests {
     .await;
   }
 
+  #[tokio::test]
+  async fn metrics_endpoint() {
+    let config = default_test_config();
+    let app = TicketServer::router(
+      config.clone().into_locations(),
+      config.service_info().clone(),
+      config.ticket_server().cors().clone(),
+    )
+    .merge(metrics_router());
+
+    let response = app
+      .clone()
+      .oneshot(
+        Request::get("/variants/1-vcf/sample1-bcbio-cancer?class=header")
+          .body(Body::empty())
+          .unwrap(),
+      )
+      .await
+      .unwrap();
+    assert_eq!(response.status(), 200);
+
+    let response = app
+      .oneshot(Request::get("/metrics").body(Body::empty()).unwrap())
+      .await
+      .unwrap();
+    assert_eq!(response.status(), 200);
+
+    let body = String::from_utf8(
+      to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap()
+        .to_vec(),
+    )
+    .unwrap();
+    assert!(body.contains(
+      r#"htsget_requests_total{endpoint="variants",format="VCF",class="header",status="200"}"#
+    ));
+    assert!(body
+      .contains(r#"htsget_storage_operations_total{backend="file",operation="get",status="ok"}"#));
+    assert!(
+      body.contains(r#"htsget_search_duration_seconds_bucket{stage="read_index",format="VCF""#)
+    );
+  }
+
   #[tokio::test]
   async fn cors_simple_request() {
     cors::test_cors_simple_request(&AxumTestServer::default()).await;
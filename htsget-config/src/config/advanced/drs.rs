@@ -0,0 +1,188 @@
+//! The config for GA4GH Data Repository Service (DRS) locations.
+//!
+
+use crate::error::Error;
+use crate::error::Error::ParseError;
+use crate::error::Result;
+use crate::storage;
+#[cfg(feature = "experimental")]
+use crate::storage::c4gh::C4GHKeys;
+use crate::storage::drs::{default_cache_ttl, default_header_allowlist};
+use crate::tls::client::TlsClientConfig;
+use cfg_if::cfg_if;
+use http::Uri;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+
+/// Options for the DRS server config.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Drs {
+  #[serde(with = "http_serde::uri")]
+  url: Uri,
+  #[serde(default)]
+  index_suffix: Option<String>,
+  #[serde(default = "default_forward_headers")]
+  forward_headers: bool,
+  #[serde(default = "default_header_allowlist")]
+  header_allowlist: Vec<String>,
+  #[serde(default)]
+  header_blacklist: Vec<String>,
+  #[serde(default = "default_cache_ttl")]
+  cache_ttl: u64,
+  #[serde(skip_serializing, default)]
+  tls: TlsClientConfig,
+  #[cfg(feature = "experimental")]
+  #[serde(skip_serializing, default)]
+  keys: Option<C4GHKeys>,
+}
+
+impl Drs {
+  /// Create a new DRS storage.
+  pub fn new(
+    url: Uri,
+    index_suffix: Option<String>,
+    forward_headers: bool,
+    header_blacklist: Vec<String>,
+    tls: TlsClientConfig,
+  ) -> Self {
+    Self {
+      url,
+      index_suffix,
+      forward_headers,
+      header_allowlist: default_header_allowlist(),
+      header_blacklist,
+      cache_ttl: default_cache_ttl(),
+      tls,
+      #[cfg(feature = "experimental")]
+      keys: None,
+    }
+  }
+
+  /// Get the url of the DRS server.
+  pub fn url(&self) -> &Uri {
+    &self.url
+  }
+
+  /// Get the suffix used to derive the DRS id of index objects.
+  pub fn index_suffix(&self) -> Option<&str> {
+    self.index_suffix.as_deref()
+  }
+
+  /// Whether headers received in a query request should be forwarded to the DRS server.
+  pub fn forward_headers(&self) -> bool {
+    self.forward_headers
+  }
+
+  /// Get the headers that are forwarded to the DRS server.
+  pub fn header_allowlist(&self) -> &[String] {
+    &self.header_allowlist
+  }
+
+  /// Set the headers that are forwarded to the DRS server.
+  pub fn with_header_allowlist(mut self, header_allowlist: Vec<String>) -> Self {
+    self.header_allowlist = header_allowlist;
+    self
+  }
+
+  /// Get the tls client config.
+  pub fn tls(&self) -> &TlsClientConfig {
+    &self.tls
+  }
+
+  #[cfg(feature = "experimental")]
+  /// Set the C4GH keys.
+  pub fn set_keys(mut self, keys: Option<C4GHKeys>) -> Self {
+    self.keys = keys;
+    self
+  }
+
+  #[cfg(feature = "experimental")]
+  /// Get the C4GH keys.
+  pub fn keys(&self) -> Option<&C4GHKeys> {
+    self.keys.as_ref()
+  }
+}
+
+impl TryFrom<Drs> for storage::drs::Drs {
+  type Error = Error;
+
+  fn try_from(storage: Drs) -> Result<Self> {
+    let mut builder = Client::builder();
+
+    let (certs, identity) = storage.tls.into_inner();
+
+    if let Some(certs) = certs {
+      for cert in certs {
+        builder = builder.add_root_certificate(cert);
+      }
+    }
+    if let Some(identity) = identity {
+      builder = builder.identity(identity);
+    }
+
+    let client = builder
+      .build()
+      .map_err(|err| ParseError(format!("building drs storage client: {}", err)))?;
+
+    let drs_storage = Self::new(
+      storage.url,
+      storage.index_suffix,
+      storage.forward_headers,
+      storage.header_blacklist,
+      client,
+    )
+    .with_header_allowlist(storage.header_allowlist)
+    .with_cache_ttl(storage.cache_ttl);
+
+    cfg_if! {
+      if #[cfg(feature = "experimental")] {
+        Ok(drs_storage.set_keys(storage.keys))
+      } else {
+        Ok(drs_storage)
+      }
+    }
+  }
+}
+
+fn default_forward_headers() -> bool {
+  true
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::config::tests::test_serialize_and_deserialize;
+
+  #[test]
+  fn drs_backend() {
+    test_serialize_and_deserialize(
+      r#"
+      url = "https://drs.example.com"
+      index_suffix = ".bai"
+      forward_headers = false
+      header_allowlist = ["Authorization", "X-Api-Key"]
+      header_blacklist = ["Host"]
+      cache_ttl = 10
+      "#,
+      (
+        "https://drs.example.com/".to_string(),
+        Some(".bai".to_string()),
+        false,
+        vec!["Authorization".to_string(), "X-Api-Key".to_string()],
+        vec!["Host".to_string()],
+        10,
+      ),
+      |result: Drs| {
+        (
+          result.url().to_string(),
+          result.index_suffix().map(str::to_string),
+          result.forward_headers(),
+          result.header_allowlist,
+          result.header_blacklist,
+          result.cache_ttl,
+        )
+      },
+    );
+  }
+}
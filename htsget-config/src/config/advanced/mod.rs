@@ -5,6 +5,8 @@ use serde::{Deserialize, Serialize};
 
 pub mod allow_guard;
 pub mod cors;
+#[cfg(feature = "url")]
+pub mod drs;
 pub mod regex_location;
 #[cfg(feature = "url")]
 pub mod url;
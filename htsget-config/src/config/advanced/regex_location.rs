@@ -2,7 +2,7 @@
 //!
 
 use crate::config::advanced::allow_guard::AllowGuard;
-use crate::config::location::LocationEither;
+use crate::config::location::{LocationEither, ResolutionPolicy};
 use crate::storage::Backend;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
@@ -16,6 +16,8 @@ pub struct RegexLocation {
   substitution_string: String,
   backend: Backend,
   guard: Option<AllowGuard>,
+  resolution_policy: ResolutionPolicy,
+  verify_etags: bool,
 }
 
 impl RegexLocation {
@@ -31,9 +33,17 @@ impl RegexLocation {
       substitution_string,
       backend,
       guard,
+      resolution_policy: Default::default(),
+      verify_etags: false,
     }
   }
 
+  /// Set the resolution policy.
+  pub fn with_resolution_policy(mut self, resolution_policy: ResolutionPolicy) -> Self {
+    self.resolution_policy = resolution_policy;
+    self
+  }
+
   /// Get the regex.
   pub fn regex(&self) -> &Regex {
     &self.regex
@@ -53,6 +63,22 @@ impl RegexLocation {
   pub fn guard(&self) -> Option<&AllowGuard> {
     self.guard.as_ref()
   }
+
+  /// Get the resolution policy.
+  pub fn resolution_policy(&self) -> ResolutionPolicy {
+    self.resolution_policy
+  }
+
+  /// Set whether object etags are captured and checked during searches.
+  pub fn with_verify_etags(mut self, verify_etags: bool) -> Self {
+    self.verify_etags = verify_etags;
+    self
+  }
+
+  /// Whether object etags are captured and checked during searches.
+  pub fn verify_etags(&self) -> bool {
+    self.verify_etags
+  }
 }
 
 impl Default for RegexLocation {
@@ -74,9 +100,42 @@ impl From<RegexLocation> for LocationEither {
 
 #[cfg(test)]
 mod tests {
+  use crate::config::location::ResolutionPolicy;
   use crate::config::tests::test_serialize_and_deserialize;
   use crate::config::Config;
 
+  #[test]
+  fn regex_location_resolution_policy() {
+    test_serialize_and_deserialize(
+      r#"
+      [[locations]]
+      regex = "123-.*"
+      resolution_policy = "FirstExisting"
+      "#,
+      ResolutionPolicy::FirstExisting,
+      |result: Config| {
+        let location = result.locations.into_inner();
+        location[0].as_regex().unwrap().resolution_policy()
+      },
+    );
+  }
+
+  #[test]
+  fn regex_location_verify_etags() {
+    test_serialize_and_deserialize(
+      r#"
+      [[locations]]
+      regex = "123-.*"
+      verify_etags = true
+      "#,
+      true,
+      |result: Config| {
+        let location = result.locations.into_inner();
+        location[0].as_regex().unwrap().verify_etags()
+      },
+    );
+  }
+
   #[test]
   fn regex_location_file() {
     test_serialize_and_deserialize(
@@ -7,11 +7,18 @@ use crate::error::Result;
 use crate::storage;
 #[cfg(feature = "experimental")]
 use crate::storage::c4gh::C4GHKeys;
+use crate::storage::url::{
+  default_connect_timeout, default_cooldown, default_failure_threshold, default_read_timeout,
+  default_retries, default_retry_backoff, SizeDiscovery, UrlAuth,
+};
 use crate::tls::client::TlsClientConfig;
 use cfg_if::cfg_if;
 use http::Uri;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
 
 /// Options for the remote URL server config.
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -25,6 +32,30 @@ pub struct Url {
   forward_headers: bool,
   #[serde(default)]
   header_blacklist: Vec<String>,
+  #[serde(default)]
+  header_allowlist: Option<Vec<String>>,
+  #[serde(default)]
+  headers: HashMap<String, String>,
+  #[serde(default)]
+  auth: Option<UrlAuth>,
+  #[serde(default = "default_connect_timeout")]
+  connect_timeout: u64,
+  #[serde(default = "default_read_timeout")]
+  read_timeout: u64,
+  #[serde(default = "default_retries")]
+  retries: u32,
+  #[serde(default = "default_retry_backoff")]
+  retry_backoff: u64,
+  #[serde(default = "default_failure_threshold")]
+  failure_threshold: u64,
+  #[serde(default = "default_cooldown")]
+  cooldown: u64,
+  #[serde(default)]
+  size_discovery: SizeDiscovery,
+  #[serde(default)]
+  sizes: HashMap<String, u64>,
+  #[serde(default)]
+  size_manifest: Option<PathBuf>,
   #[serde(skip_serializing, default)]
   tls: TlsClientConfig,
   #[cfg(feature = "experimental")]
@@ -46,6 +77,18 @@ impl Url {
       response_url,
       forward_headers,
       header_blacklist,
+      header_allowlist: None,
+      headers: Default::default(),
+      auth: None,
+      connect_timeout: default_connect_timeout(),
+      read_timeout: default_read_timeout(),
+      retries: default_retries(),
+      retry_backoff: default_retry_backoff(),
+      failure_threshold: default_failure_threshold(),
+      cooldown: default_cooldown(),
+      size_discovery: Default::default(),
+      sizes: Default::default(),
+      size_manifest: None,
       tls,
       #[cfg(feature = "experimental")]
       keys: None,
@@ -91,7 +134,9 @@ impl TryFrom<Url> for storage::url::Url {
   type Error = Error;
 
   fn try_from(storage: Url) -> Result<Self> {
-    let mut builder = Client::builder();
+    let mut builder = Client::builder()
+      .connect_timeout(Duration::from_secs(storage.connect_timeout))
+      .read_timeout(Duration::from_secs(storage.read_timeout));
 
     let (certs, identity) = storage.tls.into_inner();
 
@@ -114,7 +159,25 @@ impl TryFrom<Url> for storage::url::Url {
       storage.forward_headers,
       storage.header_blacklist,
       client,
-    );
+    )
+    .with_timeouts(storage.connect_timeout, storage.read_timeout)
+    .with_retries(storage.retries, storage.retry_backoff)
+    .with_circuit_breaker(storage.failure_threshold, storage.cooldown)
+    .with_size_discovery(storage.size_discovery)
+    .with_header_allowlist(storage.header_allowlist)
+    .with_headers(storage.headers)
+    .with_auth(storage.auth);
+
+    let url_storage = storage
+      .sizes
+      .into_iter()
+      .fold(url_storage, |url_storage, (key, size)| {
+        url_storage.with_size(key, size)
+      });
+    let url_storage = match storage.size_manifest {
+      Some(size_manifest) => url_storage.with_size_manifest(size_manifest),
+      None => url_storage,
+    };
 
     cfg_if! {
       if #[cfg(feature = "experimental")] {
@@ -134,6 +197,7 @@ fn default_forward_headers() -> bool {
 mod tests {
   use super::*;
   use crate::config::tests::test_serialize_and_deserialize;
+  use crate::storage::url::Secret;
   #[test]
   fn url_backend() {
     test_serialize_and_deserialize(
@@ -159,4 +223,121 @@ mod tests {
       },
     );
   }
+
+  #[test]
+  fn url_backend_client_options() {
+    test_serialize_and_deserialize(
+      r#"
+      url = "https://example.com"
+      connect_timeout = 5
+      read_timeout = 60
+      retries = 1
+      retry_backoff = 200
+      failure_threshold = 10
+      cooldown = 60
+      "#,
+      (5, 60, 1, 200, 10, 60),
+      |result: Url| {
+        (
+          result.connect_timeout,
+          result.read_timeout,
+          result.retries,
+          result.retry_backoff,
+          result.failure_threshold,
+          result.cooldown,
+        )
+      },
+    );
+  }
+
+  #[test]
+  fn url_backend_client_options_default() {
+    test_serialize_and_deserialize(
+      r#"
+      url = "https://example.com"
+      "#,
+      (10, 30, 3, 100, 5, 30),
+      |result: Url| {
+        let result = storage::url::Url::try_from(result).unwrap();
+        (
+          result.connect_timeout().as_secs(),
+          result.read_timeout().as_secs(),
+          result.retries(),
+          result.retry_backoff().as_millis(),
+          result.failure_threshold(),
+          result.cooldown().as_secs(),
+        )
+      },
+    );
+  }
+
+  #[test]
+  fn url_backend_size_discovery() {
+    test_serialize_and_deserialize(
+      r#"
+      url = "https://example.com"
+      size_discovery = "Get"
+      size_manifest = "manifest.json"
+      sizes = { "id.bam" = 1024 }
+      "#,
+      (
+        SizeDiscovery::Get,
+        Some(PathBuf::from("manifest.json")),
+        HashMap::from([("id.bam".to_string(), 1024)]),
+      ),
+      |result: Url| {
+        let result = storage::url::Url::try_from(result).unwrap();
+        (
+          result.size_discovery(),
+          result.size_manifest().map(PathBuf::from),
+          result.sizes().clone(),
+        )
+      },
+    );
+  }
+
+  #[test]
+  fn url_backend_auth() {
+    test_serialize_and_deserialize(
+      r#"
+      url = "https://example.com"
+      header_allowlist = ["Range"]
+      headers = { "X-User" = "${header.x-user}" }
+      auth = { kind = "OAuth2", token_url = "https://example.com/token", client_id = "id", client_secret = { env = "CLIENT_SECRET" } }
+      "#,
+      (
+        Some(vec!["Range".to_string()]),
+        HashMap::from([("X-User".to_string(), "${header.x-user}".to_string())]),
+        Some(UrlAuth::OAuth2 {
+          token_url: "https://example.com/token".to_string(),
+          client_id: "id".to_string(),
+          client_secret: Secret::Env("CLIENT_SECRET".to_string()),
+          scope: None,
+        }),
+      ),
+      |result: Url| {
+        let result = storage::url::Url::try_from(result).unwrap();
+        (
+          result.header_allowlist().map(<[String]>::to_vec),
+          result.headers().clone(),
+          result.auth().cloned(),
+        )
+      },
+    );
+  }
+
+  #[test]
+  fn url_backend_basic_auth() {
+    test_serialize_and_deserialize(
+      r#"
+      url = "https://example.com"
+      auth = { kind = "Basic", username = "user", password = { file = "password" } }
+      "#,
+      Some(UrlAuth::Basic {
+        username: "user".to_string(),
+        password: Secret::File(PathBuf::from("password")),
+      }),
+      |result: Url| result.auth,
+    );
+  }
 }
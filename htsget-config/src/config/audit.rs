@@ -0,0 +1,191 @@
+//! Audit log configuration.
+//!
+
+use serde::{Deserialize, Serialize};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+
+/// Where audit records are written.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub enum AuditOutput {
+  /// Audit records are not written.
+  #[default]
+  None,
+  /// Write audit records to stdout.
+  Stdout,
+  /// Write audit records to a rotated file.
+  File,
+}
+
+/// How often the audit log file is rotated.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub enum AuditRotation {
+  Minutely,
+  Hourly,
+  #[default]
+  Daily,
+  Never,
+}
+
+/// Configuration for the audit log, which records data access as JSON Lines.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuditConfig {
+  output: AuditOutput,
+  directory: PathBuf,
+  prefix: String,
+  rotation: AuditRotation,
+  max_files: Option<NonZeroUsize>,
+  principal_header: Option<String>,
+}
+
+impl AuditConfig {
+  /// Create the audit config.
+  pub fn new(output: AuditOutput) -> Self {
+    Self {
+      output,
+      ..Default::default()
+    }
+  }
+
+  /// Get the output of audit records.
+  pub fn output(&self) -> AuditOutput {
+    self.output
+  }
+
+  /// Get the directory that audit log files are written to.
+  pub fn directory(&self) -> &Path {
+    &self.directory
+  }
+
+  /// Get the prefix of audit log file names.
+  pub fn prefix(&self) -> &str {
+    &self.prefix
+  }
+
+  /// Get the rotation of audit log files.
+  pub fn rotation(&self) -> AuditRotation {
+    self.rotation
+  }
+
+  /// Get the maximum number of audit log files that are kept.
+  pub fn max_files(&self) -> Option<NonZeroUsize> {
+    self.max_files
+  }
+
+  /// Get the request header which contains the authenticated principal.
+  pub fn principal_header(&self) -> Option<&str> {
+    self.principal_header.as_deref()
+  }
+
+  /// Set the directory that audit log files are written to.
+  pub fn with_directory(mut self, directory: impl Into<PathBuf>) -> Self {
+    self.directory = directory.into();
+    self
+  }
+
+  /// Set the prefix of audit log file names.
+  pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+    self.prefix = prefix.into();
+    self
+  }
+
+  /// Set the rotation of audit log files.
+  pub fn with_rotation(mut self, rotation: AuditRotation) -> Self {
+    self.rotation = rotation;
+    self
+  }
+
+  /// Set the maximum number of audit log files that are kept.
+  pub fn with_max_files(mut self, max_files: Option<NonZeroUsize>) -> Self {
+    self.max_files = max_files;
+    self
+  }
+
+  /// Set the request header which contains the authenticated principal.
+  pub fn with_principal_header(mut self, principal_header: Option<String>) -> Self {
+    self.principal_header = principal_header;
+    self
+  }
+}
+
+impl Default for AuditConfig {
+  fn default() -> Self {
+    Self {
+      output: Default::default(),
+      directory: default_directory().into(),
+      prefix: default_prefix().to_string(),
+      rotation: Default::default(),
+      max_files: None,
+      principal_header: None,
+    }
+  }
+}
+
+fn default_directory() -> &'static str {
+  "audit"
+}
+
+fn default_prefix() -> &'static str {
+  "htsget-audit"
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::config::tests::{test_config_from_env, test_config_from_file};
+
+  #[test]
+  fn audit_config_default() {
+    test_config_from_file("", |config| {
+      assert_eq!(config.audit(), &AuditConfig::default());
+      assert_eq!(config.audit().output(), AuditOutput::None);
+    });
+  }
+
+  #[test]
+  fn audit_config() {
+    test_config_from_file(
+      r#"
+      audit.output = "File"
+      audit.directory = "logs"
+      audit.prefix = "access"
+      audit.rotation = "Hourly"
+      audit.max_files = 24
+      audit.principal_header = "X-Principal"
+      "#,
+      |config| {
+        assert_eq!(
+          config.audit(),
+          &AuditConfig::new(AuditOutput::File)
+            .with_directory("logs")
+            .with_prefix("access")
+            .with_rotation(AuditRotation::Hourly)
+            .with_max_files(NonZeroUsize::new(24))
+            .with_principal_header(Some("X-Principal".to_string()))
+        );
+      },
+    );
+  }
+
+  #[test]
+  fn audit_config_zero_max_files() {
+    assert!(toml::from_str::<AuditConfig>("max_files = 0").is_err());
+  }
+
+  #[test]
+  fn audit_config_env() {
+    test_config_from_env(
+      vec![
+        ("HTSGET_AUDIT_OUTPUT", "Stdout"),
+        ("HTSGET_AUDIT_PRINCIPAL_HEADER", "X-Principal"),
+      ],
+      |config| {
+        assert_eq!(config.audit().output(), AuditOutput::Stdout);
+        assert_eq!(config.audit().principal_header(), Some("X-Principal"));
+      },
+    );
+  }
+}
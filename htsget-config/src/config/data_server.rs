@@ -3,6 +3,8 @@
 
 use crate::config::advanced::cors::CorsConfig;
 use crate::error::{Error::ParseError, Result};
+#[cfg(feature = "experimental")]
+use crate::storage::c4gh::C4GHKeys;
 use crate::storage::file::{default_localstorage_addr, default_path};
 use crate::tls::TlsServerConfig;
 use serde::{Deserialize, Serialize};
@@ -46,6 +48,10 @@ pub struct DataServerConfig {
   #[serde(skip_serializing)]
   tls: Option<TlsServerConfig>,
   cors: CorsConfig,
+  metrics: bool,
+  #[cfg(feature = "experimental")]
+  #[serde(skip)]
+  decrypt_keys: Vec<C4GHKeys>,
 }
 
 impl DataServerConfig {
@@ -61,6 +67,9 @@ impl DataServerConfig {
       local_path,
       tls,
       cors,
+      metrics: false,
+      #[cfg(feature = "experimental")]
+      decrypt_keys: vec![],
     }
   }
 
@@ -84,6 +93,30 @@ impl DataServerConfig {
     &self.cors
   }
 
+  /// Whether the `/metrics` endpoint is enabled.
+  pub fn metrics(&self) -> bool {
+    self.metrics
+  }
+
+  /// Set whether the `/metrics` endpoint is enabled.
+  pub fn with_metrics(mut self, metrics: bool) -> Self {
+    self.metrics = metrics;
+    self
+  }
+
+  #[cfg(feature = "experimental")]
+  /// Set the C4GH keys of the locations in the decrypt mode, which the decrypt endpoint serves.
+  pub fn set_decrypt_keys(mut self, decrypt_keys: Vec<C4GHKeys>) -> Self {
+    self.decrypt_keys = decrypt_keys;
+    self
+  }
+
+  #[cfg(feature = "experimental")]
+  /// Get the C4GH keys of the locations in the decrypt mode.
+  pub fn decrypt_keys(&self) -> &[C4GHKeys] {
+    &self.decrypt_keys
+  }
+
   /// Get the owned TLS config.
   pub fn into_tls(self) -> Option<TlsServerConfig> {
     self.tls
@@ -99,6 +132,9 @@ impl Default for DataServerConfig {
       local_path: default_path().into(),
       tls: Default::default(),
       cors: Default::default(),
+      metrics: false,
+      #[cfg(feature = "experimental")]
+      decrypt_keys: vec![],
     }
   }
 }
@@ -115,13 +151,15 @@ mod tests {
       addr = "127.0.0.1:8083"
       local_path = "path"
       cors.max_age = 1
+      metrics = true
       "#,
-      ("127.0.0.1:8083".to_string(), "path".to_string(), 1),
+      ("127.0.0.1:8083".to_string(), "path".to_string(), 1, true),
       |result: DataServerConfig| {
         (
           result.addr().to_string(),
           result.local_path().to_string_lossy().to_string(),
           result.cors.max_age(),
+          result.metrics(),
         )
       },
     );
@@ -11,7 +11,9 @@ use serde::de::Error;
 use serde::{Deserialize, Deserializer, Serialize};
 use std::result;
 #[cfg(feature = "url")]
-use {crate::config::advanced::url::Url, crate::error, http::Uri};
+use {
+  crate::config::advanced::drs::Drs, crate::config::advanced::url::Url, crate::error, http::Uri,
+};
 
 /// The locations of data.
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -63,6 +65,31 @@ impl LocationEither {
     }
   }
 
+  /// Get the pattern which identifies the location, which is the prefix of a simple location or
+  /// the regex of a regex location.
+  pub fn pattern(&self) -> &str {
+    match self {
+      LocationEither::Simple(location) => location.prefix(),
+      LocationEither::Regex(regex_location) => regex_location.regex().as_str(),
+    }
+  }
+
+  /// Get the resolution policy.
+  pub fn resolution_policy(&self) -> ResolutionPolicy {
+    match self {
+      LocationEither::Simple(location) => location.resolution_policy(),
+      LocationEither::Regex(regex_location) => regex_location.resolution_policy(),
+    }
+  }
+
+  /// Whether object etags are captured and checked when searching this location.
+  pub fn verify_etags(&self) -> bool {
+    match self {
+      LocationEither::Simple(location) => location.verify_etags(),
+      LocationEither::Regex(regex_location) => regex_location.verify_etags(),
+    }
+  }
+
   /// Get the simple location variant, returning an error otherwise.
   pub fn as_simple(&self) -> Result<&Location> {
     if let LocationEither::Simple(simple) = self {
@@ -88,18 +115,59 @@ impl Default for LocationEither {
   }
 }
 
+/// Determines whether a location is used when it matches a query.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub enum ResolutionPolicy {
+  /// Use the location if it matches the query.
+  #[default]
+  FirstMatch,
+  /// Use the location if it matches the query and the data file and its index exist in the
+  /// backend. Otherwise, the next matching location is tried.
+  FirstExisting,
+}
+
 /// Location config.
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 #[serde(default, from = "LocationWrapper", deny_unknown_fields)]
 pub struct Location {
   backend: Backend,
   prefix: String,
+  resolution_policy: ResolutionPolicy,
+  verify_etags: bool,
 }
 
 impl Location {
   /// Create a new location.
   pub fn new(backend: Backend, prefix: String) -> Self {
-    Self { backend, prefix }
+    Self {
+      backend,
+      prefix,
+      resolution_policy: Default::default(),
+      verify_etags: false,
+    }
+  }
+
+  /// Set the resolution policy.
+  pub fn with_resolution_policy(mut self, resolution_policy: ResolutionPolicy) -> Self {
+    self.resolution_policy = resolution_policy;
+    self
+  }
+
+  /// Get the resolution policy.
+  pub fn resolution_policy(&self) -> ResolutionPolicy {
+    self.resolution_policy
+  }
+
+  /// Set whether object etags are captured and checked during searches.
+  pub fn with_verify_etags(mut self, verify_etags: bool) -> Self {
+    self.verify_etags = verify_etags;
+    self
+  }
+
+  /// Whether object etags are captured and checked during searches.
+  pub fn verify_etags(&self) -> bool {
+    self.verify_etags
   }
 
   /// Get the storage backend.
@@ -144,6 +212,8 @@ struct StringLocation {
 struct MapLocation {
   backend: Backend,
   prefix: String,
+  resolution_policy: ResolutionPolicy,
+  verify_etags: bool,
 }
 
 /// A wrapper around location deserialization that can deserialize either a string
@@ -165,7 +235,9 @@ impl From<LocationWrapper> for Location {
   fn from(location: LocationWrapper) -> Self {
     match location {
       LocationWrapper::String(location) => Location::new(location.backend, location.prefix),
-      LocationWrapper::Map(location) => Location::new(location.backend, location.prefix),
+      LocationWrapper::Map(location) => Location::new(location.backend, location.prefix)
+        .with_resolution_policy(location.resolution_policy)
+        .with_verify_etags(location.verify_etags),
     }
   }
 }
@@ -223,6 +295,15 @@ impl<'de> Deserialize<'de> for StringLocation {
       });
     }
 
+    #[cfg(feature = "gcp")]
+    if let Some(s) = s.strip_prefix("gs://") {
+      let (bucket, prefix) = split(s)?;
+      return Ok(StringLocation {
+        backend: Backend::Gcs(storage::gcs::Gcs::new(bucket.to_string(), None, None)),
+        prefix,
+      });
+    }
+
     #[cfg(feature = "url")]
     if let Some(s_stripped) = s
       .strip_prefix("http://")
@@ -248,8 +329,23 @@ impl<'de> Deserialize<'de> for StringLocation {
       });
     }
 
+    #[cfg(feature = "url")]
+    if let Some(s) = s.strip_prefix("drs://") {
+      let (host, prefix) = split(s)?;
+
+      let uri: Uri = format!("https://{}", host).parse().map_err(Error::custom)?;
+      let drs = Drs::new(uri, None, true, vec![], Default::default())
+        .try_into()
+        .map_err(|err: error::Error| Error::custom(err.to_string()))?;
+
+      return Ok(StringLocation {
+        backend: Backend::Drs(drs),
+        prefix,
+      });
+    }
+
     Err(Error::custom(
-      "expected file://, s3://, http:// or https:// scheme",
+      "expected file://, s3://, gs://, http://, https:// or drs:// scheme",
     ))
   }
 }
@@ -364,6 +460,28 @@ mod tests {
     );
   }
 
+  #[cfg(feature = "gcp")]
+  #[test]
+  fn location_gcs() {
+    test_serialize_and_deserialize(
+      r#"
+      locations = "gs://bucket/prefix1"
+      "#,
+      ("bucket".to_string(), "prefix1".to_string()),
+      |result: Config| {
+        let result = result.locations.0;
+        assert_eq!(result.len(), 1);
+        if let LocationEither::Simple(location) = result.first().unwrap() {
+          if let Backend::Gcs(gcs) = location.backend() {
+            return (gcs.bucket().to_string(), location.prefix().to_string());
+          }
+        }
+
+        panic!();
+      },
+    );
+  }
+
   #[cfg(feature = "url")]
   #[test]
   fn location_url() {
@@ -400,6 +518,28 @@ mod tests {
     );
   }
 
+  #[cfg(feature = "url")]
+  #[test]
+  fn location_drs() {
+    test_serialize_and_deserialize(
+      r#"
+      locations = "drs://drs.example.com/prefix"
+      "#,
+      ("https://drs.example.com/".to_string(), "prefix".to_string()),
+      |result: Config| {
+        let result = result.locations.0;
+        assert_eq!(result.len(), 1);
+        if let LocationEither::Simple(location) = result.first().unwrap() {
+          if let Backend::Drs(drs) = location.backend() {
+            return (drs.url().to_string(), location.prefix().to_string());
+          }
+        }
+
+        panic!();
+      },
+    );
+  }
+
   fn assert_file_location(result: Config) -> (String, String) {
     let result = result.locations.0;
     assert_eq!(result.len(), 1);
@@ -6,27 +6,39 @@ use std::io;
 use std::path::{Path, PathBuf};
 
 use crate::config::advanced::FormattingStyle;
+use crate::config::audit::AuditConfig;
 use crate::config::data_server::DataServerEnabled;
 use crate::config::location::{Location, LocationEither, Locations};
 use crate::config::parser::from_path;
 use crate::config::service_info::ServiceInfo;
+#[cfg(feature = "otel")]
+use crate::config::telemetry::TelemetryConfig;
 use crate::config::ticket_server::TicketServerConfig;
 use crate::error::Error::{ArgParseError, ParseError, TracingError};
 use crate::error::Result;
+#[cfg(feature = "experimental")]
+use crate::storage::c4gh::C4GHMode;
 use crate::storage::file::File;
 use crate::storage::Backend;
 use clap::{Args as ClapArgs, Command, FromArgMatches, Parser};
+#[cfg(feature = "otel")]
+use opentelemetry_sdk::trace::TracerProvider;
 use serde::{Deserialize, Serialize};
 use tracing::subscriber::set_global_default;
+#[cfg(feature = "otel")]
+use tracing::warn;
 use tracing_subscriber::fmt::{format, layer};
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::{EnvFilter, Registry};
 
 pub mod advanced;
+pub mod audit;
 pub mod data_server;
 pub mod location;
 pub mod parser;
 pub mod service_info;
+#[cfg(feature = "otel")]
+pub mod telemetry;
 pub mod ticket_server;
 
 /// The usage string for htsget-rs.
@@ -57,6 +69,9 @@ pub struct Config {
   service_info: ServiceInfo,
   locations: Locations,
   formatting_style: FormattingStyle,
+  #[cfg(feature = "otel")]
+  telemetry: Option<TelemetryConfig>,
+  audit: AuditConfig,
 }
 
 impl Config {
@@ -74,14 +89,41 @@ impl Config {
       data_server,
       service_info,
       locations,
+      #[cfg(feature = "otel")]
+      telemetry: None,
+      audit: Default::default(),
     }
   }
 
+  /// Set the audit log config.
+  pub fn with_audit(mut self, audit: AuditConfig) -> Self {
+    self.audit = audit;
+    self
+  }
+
+  /// Set the OpenTelemetry config.
+  #[cfg(feature = "otel")]
+  pub fn with_telemetry(mut self, telemetry: Option<TelemetryConfig>) -> Self {
+    self.telemetry = telemetry;
+    self
+  }
+
   /// Get the ticket server config.
   pub fn formatting_style(&self) -> FormattingStyle {
     self.formatting_style
   }
 
+  /// Get the OpenTelemetry config.
+  #[cfg(feature = "otel")]
+  pub fn telemetry(&self) -> Option<&TelemetryConfig> {
+    self.telemetry.as_ref()
+  }
+
+  /// Get the audit log config.
+  pub fn audit(&self) -> &AuditConfig {
+    &self.audit
+  }
+
   /// Get the ticket server config.
   pub fn ticket_server(&self) -> &TicketServerConfig {
     &self.ticket_server
@@ -147,11 +189,27 @@ impl Config {
     Ok(config.resolvers_from_data_server_config()?)
   }
 
-  /// Setup tracing, using a global subscriber.
+  /// Setup tracing, using a global subscriber. If telemetry is configured, spans are also
+  /// exported using OTLP.
   pub fn setup_tracing(&self) -> Result<()> {
+    self.setup_tracing_with_guard().map(|_| ())
+  }
+
+  /// Setup tracing in the same way as `setup_tracing`, returning a guard which flushes exported
+  /// spans. The guard should be shut down before the process exits so that no spans are lost.
+  pub fn setup_tracing_with_guard(&self) -> Result<TracingGuard> {
     let env_filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
 
     let subscriber = Registry::default().with(env_filter);
+    #[cfg(feature = "otel")]
+    let (telemetry_layer, provider) = self
+      .telemetry
+      .as_ref()
+      .map(|telemetry| telemetry.layer())
+      .transpose()?
+      .unzip();
+    #[cfg(feature = "otel")]
+    let subscriber = subscriber.with(telemetry_layer);
 
     match self.formatting_style() {
       FormattingStyle::Full => set_global_default(subscriber.with(layer())),
@@ -167,7 +225,10 @@ impl Config {
     }
     .map_err(|err| TracingError(err.to_string()))?;
 
-    Ok(())
+    Ok(TracingGuard {
+      #[cfg(feature = "otel")]
+      provider,
+    })
   }
 
   /// Set the local resolvers from the data server config.
@@ -200,10 +261,107 @@ impl Config {
       })
       .collect::<Result<Vec<()>>>()?;
 
+    #[cfg(feature = "experimental")]
+    {
+      self = self.decrypt_keys_from_locations()?;
+    }
+
+    Ok(self)
+  }
+
+  #[cfg(feature = "experimental")]
+  /// Check that keys in the decrypt mode belong to a `File` backend, which the data server can
+  /// serve, and have a secret to sign tickets with. Replicas are checked recursively.
+  fn validate_decrypt_keys(backend: &Backend) -> Result<()> {
+    if let Some(keys) = backend
+      .keys()
+      .filter(|keys| keys.mode() == C4GHMode::Decrypt)
+    {
+      if backend.as_file().is_err() {
+        return Err(ParseError(format!(
+          "the Crypt4GH decrypt mode requires a `File` backend, found `{}`",
+          backend.kind()
+        )));
+      }
+      if keys.decrypt_ticket().is_none() {
+        return Err(ParseError(
+          "the Crypt4GH decrypt mode requires a `decrypt_ticket` secret".to_string(),
+        ));
+      }
+    }
+
+    #[cfg(feature = "replicas")]
+    if let Backend::Replicas(replicas) = backend {
+      for replica in replicas.replicas() {
+        Self::validate_decrypt_keys(replica.backend())?;
+      }
+    }
+
+    Ok(())
+  }
+
+  #[cfg(feature = "experimental")]
+  /// Set the keys of the `File` locations in the decrypt mode on the data server, so that it can
+  /// serve their decrypt endpoint.
+  fn decrypt_keys_from_locations(mut self) -> Result<Self> {
+    for location in self.locations.as_slice() {
+      Self::validate_decrypt_keys(location.backend())?;
+    }
+
+    let decrypt_keys = self
+      .locations
+      .as_slice()
+      .iter()
+      .filter_map(|location| location.backend().as_file().ok()?.keys())
+      .filter(|keys| keys.mode() == C4GHMode::Decrypt)
+      .cloned()
+      .collect();
+
+    if let DataServerEnabled::Some(data_server) = self.data_server {
+      self.data_server = DataServerEnabled::Some(data_server.set_decrypt_keys(decrypt_keys));
+    }
+
     Ok(self)
   }
 }
 
+/// A guard which flushes and shuts down exporting spans.
+#[derive(Debug, Clone, Default)]
+#[must_use]
+pub struct TracingGuard {
+  #[cfg(feature = "otel")]
+  provider: Option<TracerProvider>,
+}
+
+impl TracingGuard {
+  /// Export all spans which have ended. This should be called at the end of invocations of
+  /// short-lived environments like Lambda, which may be frozen before spans are exported.
+  pub async fn force_flush(&self) {
+    #[cfg(feature = "otel")]
+    if let Some(provider) = self.provider.clone() {
+      // Flushing blocks until the batch exporter task has exported the spans.
+      let results = tokio::task::spawn_blocking(move || provider.force_flush()).await;
+      for result in results.into_iter().flatten() {
+        if let Err(err) = result {
+          warn!(err = err.to_string(), "failed to flush spans");
+        }
+      }
+    }
+  }
+
+  /// Export all remaining spans and shut down the tracer provider.
+  pub async fn shutdown(self) {
+    #[cfg(feature = "otel")]
+    if let Some(provider) = self.provider {
+      match tokio::task::spawn_blocking(move || provider.shutdown()).await {
+        Ok(Err(err)) => warn!(err = err.to_string(), "failed to shut down tracer provider"),
+        Err(err) => warn!(err = err.to_string(), "failed to shut down tracer provider"),
+        _ => {}
+      }
+    }
+  }
+}
+
 impl Default for Config {
   fn default() -> Self {
     Self {
@@ -212,6 +370,9 @@ impl Default for Config {
       data_server: DataServerEnabled::Some(Default::default()),
       service_info: Default::default(),
       locations: Default::default(),
+      #[cfg(feature = "otel")]
+      telemetry: None,
+      audit: Default::default(),
     }
   }
 }
@@ -667,6 +828,109 @@ pub(crate) mod tests {
     );
   }
 
+  #[cfg(feature = "experimental")]
+  #[tokio::test]
+  async fn data_server_decrypt_keys() {
+    let keys = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+      .parent()
+      .unwrap()
+      .join("data/c4gh/keys");
+
+    test_config_from_file(
+      &format!(
+        r#"
+        [[locations]]
+        regex = "^trusted/(?P<key>.*)$"
+        substitution_string = "$key"
+        backend.kind = "File"
+        backend.keys.kind = "File"
+        backend.keys.private = "{private}"
+        backend.keys.public = "{public}"
+        backend.keys.mode = "Decrypt"
+        backend.keys.decrypt_ticket = {{ secret = {{ env = "HTSGET_DECRYPT_TICKET_SECRET" }} }}
+
+        [[locations]]
+        regex = ".*"
+        substitution_string = "$0"
+        backend.kind = "File"
+        backend.keys.kind = "File"
+        backend.keys.private = "{private}"
+        backend.keys.public = "{public}"
+        "#,
+        private = keys.join("bob.sec").to_string_lossy(),
+        public = keys.join("alice.pub").to_string_lossy()
+      ),
+      |config| {
+        assert_eq!(
+          config
+            .data_server()
+            .as_data_server_config()
+            .unwrap()
+            .decrypt_keys()
+            .len(),
+          1
+        );
+      },
+    );
+  }
+
+  #[cfg(feature = "experimental")]
+  fn decrypt_config(backend: &str, decrypt_ticket: &str) -> Config {
+    let keys = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+      .parent()
+      .unwrap()
+      .join("data/c4gh/keys");
+
+    toml::from_str(&format!(
+      r#"
+      [[locations]]
+      regex = ".*"
+      substitution_string = "$0"
+      {}
+      backend.keys.kind = "File"
+      backend.keys.private = "{}"
+      backend.keys.public = "{}"
+      backend.keys.mode = "Decrypt"
+      {}
+      "#,
+      backend,
+      keys.join("bob.sec").to_string_lossy(),
+      keys.join("alice.pub").to_string_lossy(),
+      decrypt_ticket
+    ))
+    .unwrap()
+  }
+
+  #[cfg(feature = "experimental")]
+  #[tokio::test]
+  async fn decrypt_mode_requires_ticket_secret() {
+    let decrypt_ticket =
+      r#"backend.keys.decrypt_ticket = { secret = { env = "HTSGET_DECRYPT_TICKET_SECRET" } }"#;
+
+    assert!(decrypt_config(r#"backend.kind = "File""#, decrypt_ticket)
+      .resolvers_from_data_server_config()
+      .is_ok());
+    assert!(decrypt_config(r#"backend.kind = "File""#, "")
+      .resolvers_from_data_server_config()
+      .is_err());
+  }
+
+  #[cfg(all(feature = "experimental", feature = "aws"))]
+  #[tokio::test]
+  async fn decrypt_mode_requires_file_backend() {
+    let err = decrypt_config(
+      r#"
+      backend.kind = "S3"
+      backend.bucket = "bucket"
+      "#,
+      r#"backend.keys.decrypt_ticket = { secret = { env = "HTSGET_DECRYPT_TICKET_SECRET" } }"#,
+    )
+    .resolvers_from_data_server_config()
+    .unwrap_err();
+
+    assert!(err.to_string().contains("requires a `File` backend"));
+  }
+
   fn assert_multiple(config: Config) {
     assert_eq!(config.locations().len(), 2);
     let config = config.locations.into_inner();
@@ -40,6 +40,8 @@ impl Parser<'_> {
           .replace("data_server_", "data_server.")
           .replace("cors_", "cors.")
           .replace("tls_", "tls.")
+          .replace("telemetry_", "telemetry.")
+          .replace("audit_", "audit.")
           .into()
       }))
       .extract()
@@ -0,0 +1,274 @@
+//! OpenTelemetry configuration, used to export spans using OTLP and propagate W3C trace context.
+//!
+
+use std::collections::HashMap;
+
+use http::HeaderMap;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::{global, KeyValue};
+use opentelemetry_http::{HeaderExtractor, HeaderInjector};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{Tracer, TracerProvider};
+use opentelemetry_sdk::{runtime, Resource};
+use serde::{Deserialize, Serialize};
+use tracing::{Span, Subscriber};
+use tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt};
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::error::Error::TracingError;
+use crate::error::Result;
+
+/// The default service name of exported spans.
+pub const DEFAULT_SERVICE_NAME: &str = "htsget-rs";
+
+/// The protocol used to export spans.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub enum OtlpProtocol {
+  #[default]
+  Grpc,
+  Http,
+}
+
+/// Configuration for exporting spans using OTLP.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct TelemetryConfig {
+  protocol: OtlpProtocol,
+  endpoint: Option<String>,
+  service_name: String,
+  resource_attributes: HashMap<String, String>,
+}
+
+impl TelemetryConfig {
+  /// Create a new telemetry config.
+  pub fn new(
+    protocol: OtlpProtocol,
+    endpoint: Option<String>,
+    service_name: String,
+    resource_attributes: HashMap<String, String>,
+  ) -> Self {
+    Self {
+      protocol,
+      endpoint,
+      service_name,
+      resource_attributes,
+    }
+  }
+
+  /// Get the export protocol.
+  pub fn protocol(&self) -> OtlpProtocol {
+    self.protocol
+  }
+
+  /// Get the collector endpoint. If this is not set, the `OTEL_EXPORTER_OTLP_ENDPOINT`
+  /// environment variable or the default endpoint of the protocol is used.
+  pub fn endpoint(&self) -> Option<&str> {
+    self.endpoint.as_deref()
+  }
+
+  /// Get the service name.
+  pub fn service_name(&self) -> &str {
+    &self.service_name
+  }
+
+  /// Get the resource attributes.
+  pub fn resource_attributes(&self) -> &HashMap<String, String> {
+    &self.resource_attributes
+  }
+
+  /// Get the resource which describes this service.
+  pub fn resource(&self) -> Resource {
+    let attributes = self
+      .resource_attributes
+      .iter()
+      .map(|(key, value)| KeyValue::new(key.to_string(), value.to_string()))
+      .chain([KeyValue::new("service.name", self.service_name.to_string())]);
+
+    Resource::default().merge(&Resource::new(attributes))
+  }
+
+  /// Create the tracing layer which exports spans, and the tracer provider which is used to flush
+  /// and shut down exporting. This installs the global tracer provider and the W3C trace context
+  /// propagator, and must be called from within a Tokio runtime.
+  pub fn layer<S>(&self) -> Result<(OpenTelemetryLayer<S, Tracer>, TracerProvider)>
+  where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+  {
+    let exporter = match self.protocol {
+      OtlpProtocol::Grpc => {
+        let mut builder = SpanExporter::builder().with_tonic();
+        if let Some(endpoint) = &self.endpoint {
+          builder = builder.with_endpoint(endpoint);
+        }
+        builder.build()
+      }
+      OtlpProtocol::Http => {
+        let mut builder = SpanExporter::builder().with_http();
+        if let Some(endpoint) = &self.endpoint {
+          builder = builder.with_endpoint(endpoint);
+        }
+        builder.build()
+      }
+    }
+    .map_err(|err| TracingError(err.to_string()))?;
+
+    let provider = TracerProvider::builder()
+      .with_batch_exporter(exporter, runtime::Tokio)
+      .with_resource(self.resource())
+      .build();
+    let tracer = provider.tracer(DEFAULT_SERVICE_NAME);
+
+    global::set_tracer_provider(provider.clone());
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    Ok((tracing_opentelemetry::layer().with_tracer(tracer), provider))
+  }
+}
+
+impl Default for TelemetryConfig {
+  fn default() -> Self {
+    Self {
+      protocol: Default::default(),
+      endpoint: None,
+      service_name: DEFAULT_SERVICE_NAME.to_string(),
+      resource_attributes: Default::default(),
+    }
+  }
+}
+
+/// Continue the trace of an incoming request by setting the parent of the span from the
+/// `traceparent` and `tracestate` headers.
+pub fn set_parent_from_headers(span: &Span, headers: &HeaderMap) {
+  let context =
+    global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(headers)));
+
+  span.set_parent(context);
+}
+
+/// Add the trace context of the current span to the headers of an outgoing request.
+pub fn inject_trace_context(headers: &mut HeaderMap) {
+  let context = Span::current().context();
+
+  global::get_text_map_propagator(|propagator| {
+    propagator.inject_context(&context, &mut HeaderInjector(headers))
+  });
+}
+
+/// Get the trace context of the current span as header names and values.
+pub fn trace_context() -> HashMap<String, String> {
+  let context = Span::current().context();
+  let mut headers = HashMap::new();
+
+  global::get_text_map_propagator(|propagator| propagator.inject_context(&context, &mut headers));
+
+  headers
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::config::tests::{test_config_from_env, test_config_from_file};
+  use opentelemetry::trace::TraceContextExt;
+  use tracing_subscriber::layer::SubscriberExt;
+  use tracing_subscriber::Registry;
+
+  #[test]
+  fn telemetry_config() {
+    test_config_from_file(
+      r#"
+      telemetry.protocol = "Http"
+      telemetry.endpoint = "http://localhost:4318"
+      telemetry.service_name = "htsget"
+      telemetry.resource_attributes = { "deployment.environment" = "dev" }
+      "#,
+      |config| {
+        let telemetry = config.telemetry().unwrap();
+        assert_eq!(telemetry.protocol(), OtlpProtocol::Http);
+        assert_eq!(telemetry.endpoint(), Some("http://localhost:4318"));
+        assert_eq!(telemetry.service_name(), "htsget");
+        assert_eq!(
+          telemetry.resource_attributes(),
+          &HashMap::from_iter([("deployment.environment".to_string(), "dev".to_string())])
+        );
+      },
+    );
+  }
+
+  #[test]
+  fn telemetry_config_env() {
+    test_config_from_env(
+      vec![("HTSGET_TELEMETRY_ENDPOINT", "http://localhost:4317")],
+      |config| {
+        let telemetry = config.telemetry().unwrap();
+        assert_eq!(telemetry.protocol(), OtlpProtocol::Grpc);
+        assert_eq!(telemetry.endpoint(), Some("http://localhost:4317"));
+        assert_eq!(telemetry.service_name(), DEFAULT_SERVICE_NAME);
+      },
+    );
+  }
+
+  #[test]
+  fn telemetry_resource() {
+    let resource = TelemetryConfig::new(
+      OtlpProtocol::Grpc,
+      None,
+      "htsget".to_string(),
+      HashMap::from_iter([("deployment.environment".to_string(), "dev".to_string())]),
+    )
+    .resource();
+
+    assert_eq!(
+      resource.get("service.name".into()),
+      Some("htsget".to_string().into())
+    );
+    assert_eq!(
+      resource.get("deployment.environment".into()),
+      Some("dev".to_string().into())
+    );
+  }
+
+  #[test]
+  fn propagate_trace_context() {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    let provider = TracerProvider::builder().build();
+    let subscriber = Registry::default()
+      .with(tracing_opentelemetry::layer().with_tracer(provider.tracer(DEFAULT_SERVICE_NAME)));
+
+    tracing::subscriber::with_default(subscriber, || {
+      let mut incoming = HeaderMap::new();
+      incoming.insert(
+        "traceparent",
+        "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+          .parse()
+          .unwrap(),
+      );
+
+      let span = tracing::info_span!("request");
+      set_parent_from_headers(&span, &incoming);
+      let _guard = span.enter();
+
+      let mut outgoing = HeaderMap::new();
+      inject_trace_context(&mut outgoing);
+
+      let traceparent = outgoing.get("traceparent").unwrap().to_str().unwrap();
+      assert!(traceparent.starts_with("00-4bf92f3577b34da6a3ce929d0e0e4736-"));
+      assert!(!traceparent.contains("00f067aa0ba902b7"));
+      assert_eq!(
+        trace_context().get("traceparent").map(String::as_str),
+        Some(traceparent)
+      );
+      assert_eq!(
+        Span::current()
+          .context()
+          .span()
+          .span_context()
+          .trace_id()
+          .to_string(),
+        "4bf92f3577b34da6a3ce929d0e0e4736"
+      );
+    });
+  }
+}
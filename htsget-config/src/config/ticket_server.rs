@@ -14,12 +14,18 @@ pub struct TicketServerConfig {
   #[serde(skip_serializing)]
   tls: Option<TlsServerConfig>,
   cors: CorsConfig,
+  metrics: bool,
 }
 
 impl TicketServerConfig {
   /// Create the ticket server config.
   pub fn new(addr: SocketAddr, tls: Option<TlsServerConfig>, cors: CorsConfig) -> Self {
-    Self { addr, tls, cors }
+    Self {
+      addr,
+      tls,
+      cors,
+      metrics: false,
+    }
   }
 
   /// Get the socket address.
@@ -37,6 +43,17 @@ impl TicketServerConfig {
     &self.cors
   }
 
+  /// Whether the `/metrics` endpoint is enabled.
+  pub fn metrics(&self) -> bool {
+    self.metrics
+  }
+
+  /// Set whether the `/metrics` endpoint is enabled.
+  pub fn with_metrics(mut self, metrics: bool) -> Self {
+    self.metrics = metrics;
+    self
+  }
+
   /// Get the owned TLS config.
   pub fn into_tls(self) -> Option<TlsServerConfig> {
     self.tls
@@ -49,6 +66,7 @@ impl Default for TicketServerConfig {
       addr: default_addr().parse().expect("expected valid address"),
       tls: Default::default(),
       cors: Default::default(),
+      metrics: false,
     }
   }
 }
@@ -73,4 +91,15 @@ mod tests {
       |result: TicketServerConfig| (result.addr().to_string(), result.cors.max_age()),
     );
   }
+
+  #[test]
+  fn ticket_server_metrics() {
+    test_serialize_and_deserialize(
+      r#"
+      metrics = true
+      "#,
+      true,
+      |result: TicketServerConfig| result.metrics(),
+    );
+  }
 }
@@ -2,11 +2,13 @@
 
 use crate::config::advanced::allow_guard::QueryAllowed;
 use crate::config::advanced::regex_location::RegexLocation;
-use crate::config::location::{LocationEither, Locations};
+use crate::config::location::{LocationEither, Locations, ResolutionPolicy};
 use crate::storage;
 use crate::storage::{Backend, ResolvedId};
-use crate::types::{Query, Response, Result};
+use crate::types::{HtsGetError, Query, Response, Result, ServedBy};
 use async_trait::async_trait;
+use regex::Captures;
+use std::borrow::Cow;
 use tracing::instrument;
 
 /// A trait which matches the query id, replacing the match in the substitution text.
@@ -15,6 +17,45 @@ pub trait IdResolver {
   fn resolve_id(&self, query: &Query) -> Option<ResolvedId>;
 }
 
+/// A trait which sets storage backend fields from regex capture groups. A named capture group
+/// which has the same name as a backend field, such as `(?P<bucket>...)` for the S3 bucket,
+/// replaces the value of that field with the captured text.
+pub trait ResolveCaptures: Sized {
+  /// Resolve the capture groups, returning the updated backend. This returns `None` if the
+  /// backend cannot be derived from the match, in which case the location does not resolve.
+  fn resolve_captures(&self, captures: &Captures) -> Result<Option<Self>>;
+
+  /// Get the text of a named capture group if it participated in the match.
+  fn captured<'a>(captures: &'a Captures, name: &str) -> Option<&'a str> {
+    captures.name(name).map(|capture| capture.as_str())
+  }
+}
+
+impl ResolveCaptures for Backend {
+  fn resolve_captures(&self, captures: &Captures) -> Result<Option<Self>> {
+    Ok(match self {
+      Backend::File(file) => file.resolve_captures(captures)?.map(Backend::File),
+      #[cfg(feature = "aws")]
+      Backend::S3(s3) => s3.resolve_captures(captures)?.map(Backend::S3),
+      #[cfg(feature = "gcp")]
+      Backend::Gcs(gcs) => gcs.resolve_captures(captures)?.map(Backend::Gcs),
+      #[cfg(feature = "azure")]
+      Backend::Azure(azure) => azure.resolve_captures(captures)?.map(Backend::Azure),
+      #[cfg(feature = "url")]
+      Backend::Url(url) => url.resolve_captures(captures)?.map(Backend::Url),
+      #[cfg(feature = "url")]
+      Backend::Drs(drs) => drs.resolve_captures(captures)?.map(Backend::Drs),
+      #[cfg(feature = "replicas")]
+      Backend::Replicas(replicas) => replicas.resolve_captures(captures)?.map(Backend::Replicas),
+    })
+  }
+}
+
+/// The error returned by `ResolveResponse` methods that an implementation does not support.
+fn unsupported_backend(kind: &str) -> HtsGetError {
+  HtsGetError::internal_error(format!("the `{kind}` backend is not supported"))
+}
+
 /// A trait for determining the response from `Storage`.
 #[async_trait]
 pub trait ResolveResponse {
@@ -25,9 +66,43 @@ pub trait ResolveResponse {
   #[cfg(feature = "aws")]
   async fn from_s3(s3_storage: &storage::s3::S3, query: &Query) -> Result<Response>;
 
+  /// Convert from `Gcs`.
+  #[cfg(feature = "gcp")]
+  async fn from_gcs(_gcs_storage: &storage::gcs::Gcs, _query: &Query) -> Result<Response> {
+    Err(unsupported_backend("Gcs"))
+  }
+
+  /// Convert from `Azure`.
+  #[cfg(feature = "azure")]
+  async fn from_azure(_azure_storage: &storage::azure::Azure, _query: &Query) -> Result<Response> {
+    Err(unsupported_backend("Azure"))
+  }
+
   /// Convert from `Url`.
   #[cfg(feature = "url")]
   async fn from_url(url_storage: &storage::url::Url, query: &Query) -> Result<Response>;
+
+  /// Convert from `Drs`.
+  #[cfg(feature = "url")]
+  async fn from_drs(_drs_storage: &storage::drs::Drs, _query: &Query) -> Result<Response> {
+    Err(unsupported_backend("Drs"))
+  }
+
+  /// Convert from `Replicas`.
+  #[cfg(feature = "replicas")]
+  async fn from_replicas(
+    _replicas_storage: &storage::replicas::Replicas,
+    _query: &Query,
+  ) -> Result<Response> {
+    Err(unsupported_backend("Replicas"))
+  }
+
+  /// Convert from any backend, but only if the data file and its index exist in it. Returns
+  /// `None` if they do not exist. This is used by locations with a `FirstExisting` resolution
+  /// policy.
+  async fn from_existing(backend: &Backend, _query: &Query) -> Result<Option<Response>> {
+    Err(unsupported_backend(backend.kind()))
+  }
 }
 
 /// A trait which uses storage to resolve requests into responses.
@@ -67,18 +142,23 @@ impl<T> ResolvedStorage<T> {
   }
 }
 
+/// Replace the match of the regex location in the id with its substitution string, using
+/// captures that have already been evaluated.
+fn substitute(regex_location: &RegexLocation, id: &str, captures: &Captures) -> ResolvedId {
+  let matched = captures
+    .get(0)
+    .expect("capture group 0 always participates in a match");
+
+  let mut resolved = id[..matched.start()].to_string();
+  captures.expand(regex_location.substitution_string(), &mut resolved);
+  resolved.push_str(&id[matched.end()..]);
+
+  ResolvedId::new(resolved)
+}
+
 impl IdResolver for LocationEither {
   #[instrument(level = "trace", skip(self), ret)]
   fn resolve_id(&self, query: &Query) -> Option<ResolvedId> {
-    let replace = |regex_location: &RegexLocation| {
-      Some(ResolvedId::new(
-        regex_location
-          .regex()
-          .replace(query.id(), regex_location.substitution_string())
-          .to_string(),
-      ))
-    };
-
     match self {
       LocationEither::Simple(location) => {
         if query.id().starts_with(location.prefix()) {
@@ -86,14 +166,14 @@ impl IdResolver for LocationEither {
         }
       }
       LocationEither::Regex(regex_location) => {
-        if regex_location.regex().is_match(query.id()) {
+        if let Some(captures) = regex_location.regex().captures(query.id()) {
           if let Some(guard) = regex_location.guard() {
             if guard.query_allowed(query) {
-              return replace(regex_location);
+              return Some(substitute(regex_location, query.id(), &captures));
             }
           }
 
-          return replace(regex_location);
+          return Some(substitute(regex_location, query.id(), &captures));
         }
       }
     }
@@ -109,36 +189,47 @@ impl StorageResolver for LocationEither {
     &self,
     query: &mut Query,
   ) -> Option<Result<Response>> {
-    let resolved_id = self.resolve_id(query)?;
-    let _matched_id = query.id().to_string();
+    // The regex is evaluated once, and its captures are used for both the id and the backend.
+    let (resolved_id, backend) = match self {
+      Self::Regex(regex_location) => {
+        let captures = regex_location.regex().captures(query.id())?;
+        let backend = match self.backend().resolve_captures(&captures) {
+          Ok(backend) => Cow::Owned(backend?),
+          Err(err) => return Some(Err(err)),
+        };
 
-    query.set_id(resolved_id.into_inner());
+        (substitute(regex_location, query.id(), &captures), backend)
+      }
+      Self::Simple(_) => (self.resolve_id(query)?, Cow::Borrowed(self.backend())),
+    };
 
-    match self.backend() {
-      Backend::File(file) => Some(T::from_file(file, query).await),
-      #[cfg(feature = "aws")]
-      Backend::S3(s3) => {
-        let s3 = if let Self::Regex(regex_location) = self {
-          if s3.bucket().is_empty() {
-            let first_match = regex_location
-              .regex()
-              .captures(&_matched_id)?
-              .get(1)?
-              .as_str()
-              .to_string();
-            &s3.clone().with_bucket(first_match)
-          } else {
-            s3
-          }
-        } else {
-          s3
-        };
+    query.set_id(resolved_id.into_inner());
+    query.set_verify_etags(self.verify_etags());
 
-        Some(T::from_s3(s3, query).await)
+    let response = match backend.as_ref() {
+      // The storage which checks for existence is also used for the response.
+      backend if self.resolution_policy() == ResolutionPolicy::FirstExisting => {
+        T::from_existing(backend, query).await.transpose()?
       }
+      Backend::File(file) => T::from_file(file, query).await,
+      #[cfg(feature = "aws")]
+      Backend::S3(s3) => T::from_s3(s3, query).await,
+      #[cfg(feature = "gcp")]
+      Backend::Gcs(gcs) => T::from_gcs(gcs, query).await,
+      #[cfg(feature = "azure")]
+      Backend::Azure(azure) => T::from_azure(azure, query).await,
       #[cfg(feature = "url")]
-      Backend::Url(url_storage) => Some(T::from_url(url_storage, query).await),
-    }
+      Backend::Url(url_storage) => T::from_url(url_storage, query).await,
+      #[cfg(feature = "url")]
+      Backend::Drs(drs_storage) => T::from_drs(drs_storage, query).await,
+      #[cfg(feature = "replicas")]
+      Backend::Replicas(replicas_storage) => T::from_replicas(replicas_storage, query).await,
+    };
+
+    Some(
+      response
+        .map(|response| response.with_served_by(ServedBy::new(self.pattern(), backend.kind()))),
+    )
   }
 }
 
@@ -157,8 +248,11 @@ impl StorageResolver for &[LocationEither] {
     query: &mut Query,
   ) -> Option<Result<Response>> {
     for location in self.iter() {
-      if let Some(location) = location.resolve_request::<T>(query).await {
-        return Some(location);
+      // Locations which do not resolve should not modify the query.
+      let mut location_query = query.clone();
+      if let Some(response) = location.resolve_request::<T>(&mut location_query).await {
+        *query = location_query;
+        return Some(response);
       }
     }
 
@@ -190,6 +284,8 @@ mod tests {
   use crate::config::location::Location;
   use crate::config::tests::{test_config_from_env, test_config_from_file};
   use crate::storage;
+  #[cfg(feature = "replicas")]
+  use crate::storage::replicas::{Replica, ReplicaPolicy, Replicas};
   use crate::types::Format::Bam;
   use crate::types::Scheme::Http;
   use crate::types::Url;
@@ -222,6 +318,22 @@ mod tests {
       ))
     }
 
+    #[cfg(feature = "gcp")]
+    async fn from_gcs(gcs_storage: &storage::gcs::Gcs, query: &Query) -> Result<Response> {
+      Ok(Response::new(
+        Bam,
+        Self::format_url(gcs_storage.bucket(), query.id()),
+      ))
+    }
+
+    #[cfg(feature = "azure")]
+    async fn from_azure(azure_storage: &storage::azure::Azure, query: &Query) -> Result<Response> {
+      Ok(Response::new(
+        Bam,
+        Self::format_url(azure_storage.container(), query.id()),
+      ))
+    }
+
     #[cfg(feature = "url")]
     async fn from_url(url: &storage::url::Url, query: &Query) -> Result<Response> {
       Ok(Response::new(
@@ -229,6 +341,36 @@ mod tests {
         Self::format_url(url.url().to_string().strip_suffix('/').unwrap(), query.id()),
       ))
     }
+
+    #[cfg(feature = "url")]
+    async fn from_drs(drs: &storage::drs::Drs, query: &Query) -> Result<Response> {
+      Ok(Response::new(
+        Bam,
+        Self::format_url(drs.url().to_string().strip_suffix('/').unwrap(), query.id()),
+      ))
+    }
+
+    #[cfg(feature = "replicas")]
+    async fn from_replicas(
+      replicas: &storage::replicas::Replicas,
+      query: &Query,
+    ) -> Result<Response> {
+      let file = replicas
+        .replicas()
+        .first()
+        .and_then(|replica| replica.backend().as_file().ok())
+        .ok_or_else(|| HtsGetError::not_found("no file replica"))?;
+
+      Self::from_file(file, query).await
+    }
+
+    async fn from_existing(backend: &Backend, query: &Query) -> Result<Option<Response>> {
+      // Only objects in the `exists` directory exist.
+      match backend.as_file() {
+        Ok(file) if file.local_path() == "exists" => Self::from_file(file, query).await.map(Some),
+        _ => Ok(None),
+      }
+    }
   }
 
   impl TestResolveResponse {
@@ -257,6 +399,33 @@ mod tests {
     expected_resolved_request(vec![location.into()], "127.0.0.1:8080/id-1").await;
   }
 
+  #[tokio::test]
+  async fn resolver_resolve_served_by() {
+    let file = storage::file::File::new(
+      Http,
+      Authority::from_static("127.0.0.1:8080"),
+      "data".to_string(),
+    );
+
+    let locations = Locations::new(vec![
+      Location::new(Backend::File(file.clone()), "other".to_string()).into(),
+      RegexLocation::new(
+        "id".parse().unwrap(),
+        "$0-test".to_string(),
+        Backend::File(file),
+        Default::default(),
+      )
+      .into(),
+    ]);
+
+    let response = locations
+      .resolve_request::<TestResolveResponse>(&mut Query::new_with_default_request("id-1", Bam))
+      .await
+      .unwrap()
+      .unwrap();
+    assert_eq!(response.served_by(), &[ServedBy::new("id", "File")]);
+  }
+
   #[cfg(feature = "aws")]
   #[tokio::test]
   async fn resolver_resolve_s3_request_tagged() {
@@ -273,6 +442,38 @@ mod tests {
     expected_resolved_request(vec![location.into()], "id2/id-1").await;
   }
 
+  #[cfg(feature = "gcp")]
+  #[tokio::test]
+  async fn resolver_resolve_gcs_request() {
+    let regex_location = RegexLocation::new(
+      "(id)-1".parse().unwrap(),
+      "$1-test".to_string(),
+      Backend::Gcs(storage::gcs::Gcs::default()),
+      Default::default(),
+    );
+    expected_resolved_request(vec![regex_location.into()], "id/id-test").await;
+
+    let gcs_storage = storage::gcs::Gcs::new("id2".to_string(), None, None);
+    let location = Location::new(Backend::Gcs(gcs_storage), "".to_string());
+    expected_resolved_request(vec![location.into()], "id2/id-1").await;
+  }
+
+  #[cfg(feature = "azure")]
+  #[tokio::test]
+  async fn resolver_resolve_azure_request() {
+    let regex_location = RegexLocation::new(
+      "(id)-1".parse().unwrap(),
+      "$1-test".to_string(),
+      Backend::Azure(storage::azure::Azure::default()),
+      Default::default(),
+    );
+    expected_resolved_request(vec![regex_location.into()], "id/id-test").await;
+
+    let azure_storage = storage::azure::Azure::new("account".to_string(), "id2".to_string(), None);
+    let location = Location::new(Backend::Azure(azure_storage), "".to_string());
+    expected_resolved_request(vec![location.into()], "id2/id-1").await;
+  }
+
   #[cfg(feature = "aws")]
   #[tokio::test]
   async fn resolver_resolve_s3_request() {
@@ -297,6 +498,23 @@ mod tests {
       "".to_string(),
     );
     expected_resolved_request(vec![location.into()], "bucket/id-1").await;
+
+    // A location which cannot derive the bucket falls through to the next location.
+    let no_bucket_location = RegexLocation::new(
+      "^id-1$".parse().unwrap(),
+      "$0".to_string(),
+      Backend::S3(storage::s3::S3::default()),
+      Default::default(),
+    );
+    let location = Location::new(
+      Backend::S3(storage::s3::S3::new("bucket".to_string(), None, false)),
+      "".to_string(),
+    );
+    expected_resolved_request(
+      vec![no_bucket_location.into(), location.into()],
+      "bucket/id-1",
+    )
+    .await;
   }
 
   #[cfg(feature = "url")]
@@ -327,6 +545,183 @@ mod tests {
     expected_resolved_request(vec![location.into()], "https://example.com/id-1").await;
   }
 
+  #[cfg(feature = "url")]
+  #[tokio::test]
+  async fn resolver_resolve_drs_request() {
+    let client = ClientBuilder::new().build().unwrap();
+    let drs_storage = storage::drs::Drs::new(
+      "https://drs.example.com/".parse().unwrap(),
+      None,
+      true,
+      vec![],
+      client,
+    );
+
+    let regex_location = RegexLocation::new(
+      "(id)-1".parse().unwrap(),
+      "$1-test".to_string(),
+      Backend::Drs(drs_storage.clone()),
+      Default::default(),
+    );
+    expected_resolved_request(
+      vec![regex_location.clone().into()],
+      "https://drs.example.com/id-test",
+    )
+    .await;
+
+    let location = Location::new(Backend::Drs(drs_storage), "".to_string());
+    expected_resolved_request(vec![location.into()], "https://drs.example.com/id-1").await;
+  }
+
+  #[cfg(feature = "replicas")]
+  #[tokio::test]
+  async fn resolver_resolve_replicas_request() {
+    let replica = |authority| {
+      Replica::new(
+        Backend::File(storage::file::File::new(
+          Http,
+          Authority::from_static(authority),
+          "data".to_string(),
+        )),
+        None,
+      )
+    };
+    let replicas = Replicas::new(
+      vec![replica("127.0.0.1:8080"), replica("127.0.0.1:8081")],
+      ReplicaPolicy::PrimaryFirst,
+    );
+
+    let regex_location = RegexLocation::new(
+      "^(?P<authority>.*?)-(?P<key>.*)$".parse().unwrap(),
+      "$key".to_string(),
+      Backend::Replicas(replicas.clone()),
+      Default::default(),
+    );
+    expected_resolved_request(vec![regex_location.into()], "id/1").await;
+
+    let location = Location::new(Backend::Replicas(replicas), "".to_string());
+    expected_resolved_request(vec![location.into()], "127.0.0.1:8080/id-1").await;
+  }
+
+  #[cfg(feature = "aws")]
+  #[tokio::test]
+  async fn resolver_resolve_named_captures_request() {
+    let regex_location = RegexLocation::new(
+      "^(?P<bucket>.*?)/(?P<key>.*)$".parse().unwrap(),
+      "$key".to_string(),
+      Backend::S3(storage::s3::S3::new("default".to_string(), None, false)),
+      Default::default(),
+    );
+
+    assert_eq!(
+      Locations::new(vec![regex_location.into()])
+        .resolve_request::<TestResolveResponse>(&mut Query::new_with_default_request(
+          "bucket/id-1",
+          Bam
+        ))
+        .await
+        .unwrap()
+        .unwrap(),
+      Response::new(Bam, vec![Url::new("bucket/id-1")])
+    );
+  }
+
+  #[cfg(feature = "url")]
+  #[tokio::test]
+  async fn resolver_resolve_named_captures_url_request() {
+    let client = ClientBuilder::new().build().unwrap();
+    let url_storage = storage::url::Url::new(
+      "https://example.com/".parse().unwrap(),
+      "https://example.com/".parse().unwrap(),
+      true,
+      vec![],
+      client,
+    );
+
+    let regex_location = RegexLocation::new(
+      "^(?P<host>.*?)/(?P<key>.*)$".parse().unwrap(),
+      "$key".to_string(),
+      Backend::Url(url_storage),
+      Default::default(),
+    );
+    let regex_location_with_url = RegexLocation::new(
+      "^(?P<url>https://.*?)/(?P<key>.*)$".parse().unwrap(),
+      "$key".to_string(),
+      regex_location.backend().clone(),
+      Default::default(),
+    );
+
+    assert_eq!(
+      Locations::new(vec![regex_location_with_url.into()])
+        .resolve_request::<TestResolveResponse>(&mut Query::new_with_default_request(
+          "https://localhost:8080/id-1",
+          Bam
+        ))
+        .await
+        .unwrap()
+        .unwrap(),
+      Response::new(Bam, vec![Url::new("https://localhost:8080/id-1")])
+    );
+    assert_eq!(
+      Locations::new(vec![regex_location.into()])
+        .resolve_request::<TestResolveResponse>(&mut Query::new_with_default_request(
+          "host/id-1",
+          Bam
+        ))
+        .await
+        .unwrap()
+        .unwrap(),
+      Response::new(Bam, vec![Url::new("https://example.com/id-1")])
+    );
+  }
+
+  #[tokio::test]
+  async fn resolver_resolve_first_existing_request() {
+    let missing = storage::file::File::new(
+      Http,
+      Authority::from_static("127.0.0.1:8080"),
+      "missing".to_string(),
+    );
+    let exists = storage::file::File::new(
+      Http,
+      Authority::from_static("127.0.0.1:8081"),
+      "exists".to_string(),
+    );
+
+    let first_existing = RegexLocation::new(
+      "id".parse().unwrap(),
+      "$0-missing".to_string(),
+      Backend::File(missing.clone()),
+      Default::default(),
+    )
+    .with_resolution_policy(ResolutionPolicy::FirstExisting);
+    let second_existing = RegexLocation::new(
+      "id".parse().unwrap(),
+      "$0-exists".to_string(),
+      Backend::File(exists),
+      Default::default(),
+    )
+    .with_resolution_policy(ResolutionPolicy::FirstExisting);
+
+    expected_resolved_request(
+      vec![first_existing.clone().into(), second_existing.into()],
+      "127.0.0.1:8081/id-exists-1",
+    )
+    .await;
+
+    let first_match = Location::new(Backend::File(missing), "".to_string());
+    expected_resolved_request(
+      vec![first_existing.clone().into(), first_match.into()],
+      "127.0.0.1:8080/id-1",
+    )
+    .await;
+
+    assert!(Locations::new(vec![first_existing.into()])
+      .resolve_request::<TestResolveResponse>(&mut Query::new_with_default_request("id-1", Bam))
+      .await
+      .is_none());
+  }
+
   #[test]
   fn resolver_array_resolve_id() {
     let resolver = Locations::new(vec![
@@ -0,0 +1,229 @@
+//! Configuration for storage on Azure Blob Storage.
+//!
+
+use crate::resolver::ResolveCaptures;
+#[cfg(feature = "experimental")]
+use crate::storage::c4gh::C4GHKeys;
+use crate::types::Result;
+use regex::Captures;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fmt::{Debug, Formatter};
+
+/// Configuration struct for Azure Blob Storage.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Azure {
+  account: String,
+  container: String,
+  endpoint: Option<String>,
+  #[serde(skip_serializing)]
+  account_key: Option<String>,
+  identity_endpoint: Option<String>,
+  expires_in: u64,
+  #[cfg(feature = "experimental")]
+  #[serde(skip_serializing)]
+  keys: Option<C4GHKeys>,
+}
+
+impl Azure {
+  /// Create a new Azure storage.
+  pub fn new(account: String, container: String, endpoint: Option<String>) -> Self {
+    Self {
+      account,
+      container,
+      endpoint,
+      ..Default::default()
+    }
+  }
+
+  /// Get the storage account name.
+  pub fn account(&self) -> &str {
+    &self.account
+  }
+
+  /// Get the container.
+  pub fn container(&self) -> &str {
+    &self.container
+  }
+
+  /// Set the container.
+  pub fn with_container(mut self, container: String) -> Self {
+    self.container = container;
+    self
+  }
+
+  /// Get the endpoint.
+  pub fn endpoint(&self) -> Option<&str> {
+    self.endpoint.as_deref()
+  }
+
+  /// Set the endpoint.
+  pub fn with_endpoint(mut self, endpoint: String) -> Self {
+    self.endpoint = Some(endpoint);
+    self
+  }
+
+  /// Get the base64 encoded account key.
+  pub fn account_key(&self) -> Option<&str> {
+    self.account_key.as_deref()
+  }
+
+  /// Set the base64 encoded account key.
+  pub fn with_account_key(mut self, account_key: String) -> Self {
+    self.account_key = Some(account_key);
+    self
+  }
+
+  /// Get the endpoint used to fetch managed identity tokens.
+  pub fn identity_endpoint(&self) -> Option<&str> {
+    self.identity_endpoint.as_deref()
+  }
+
+  /// Set the endpoint used to fetch managed identity tokens.
+  pub fn with_identity_endpoint(mut self, identity_endpoint: String) -> Self {
+    self.identity_endpoint = Some(identity_endpoint);
+    self
+  }
+
+  /// Get the number of seconds that SAS tickets are valid for.
+  pub fn expires_in(&self) -> u64 {
+    self.expires_in
+  }
+
+  /// Set the number of seconds that SAS tickets are valid for.
+  pub fn with_expires_in(mut self, expires_in: u64) -> Self {
+    self.expires_in = expires_in;
+    self
+  }
+
+  #[cfg(feature = "experimental")]
+  /// Set the C4GH keys.
+  pub fn with_keys(mut self, keys: Option<C4GHKeys>) -> Self {
+    self.keys = keys;
+    self
+  }
+
+  #[cfg(feature = "experimental")]
+  /// Get the C4GH keys.
+  pub fn keys(&self) -> Option<&C4GHKeys> {
+    self.keys.as_ref()
+  }
+}
+
+impl Default for Azure {
+  fn default() -> Self {
+    Self {
+      account: Default::default(),
+      container: Default::default(),
+      endpoint: None,
+      account_key: None,
+      identity_endpoint: None,
+      expires_in: 3600,
+      #[cfg(feature = "experimental")]
+      keys: None,
+    }
+  }
+}
+
+impl Debug for Azure {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    f.debug_struct("Azure")
+      .field("account", &self.account)
+      .field("container", &self.container)
+      .field("endpoint", &self.endpoint)
+      .field("identity_endpoint", &self.identity_endpoint)
+      .field("expires_in", &self.expires_in)
+      .finish()
+  }
+}
+
+impl ResolveCaptures for Azure {
+  fn resolve_captures(&self, captures: &Captures) -> Result<Option<Self>> {
+    let mut azure = self.clone();
+
+    if let Some(container) = Self::captured(captures, "container") {
+      azure.container = container.to_string();
+    } else if azure.container.is_empty() {
+      // An empty container defaults to the first capture group.
+      let Some(container) = captures.get(1) else {
+        return Ok(None);
+      };
+      azure.container = container.as_str().to_string();
+    }
+
+    if let Some(account) = Self::captured(captures, "account") {
+      azure.account = account.to_string();
+    }
+    if let Some(endpoint) = Self::captured(captures, "endpoint") {
+      azure.endpoint = Some(endpoint.to_string());
+    }
+
+    Ok(Some(azure))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::config::tests::test_serialize_and_deserialize;
+  use regex::Regex;
+
+  #[test]
+  fn azure_backend() {
+    test_serialize_and_deserialize(
+      r#"
+      account = "devstoreaccount1"
+      container = "container"
+      endpoint = "http://127.0.0.1:10000/devstoreaccount1"
+      expires_in = 60
+      "#,
+      (
+        "devstoreaccount1".to_string(),
+        "container".to_string(),
+        "http://127.0.0.1:10000/devstoreaccount1".to_string(),
+        60,
+      ),
+      |result: Azure| {
+        (
+          result.account().to_string(),
+          result.container().to_string(),
+          result.endpoint().unwrap().to_string(),
+          result.expires_in(),
+        )
+      },
+    );
+  }
+
+  #[test]
+  fn azure_account_key_not_serialized() {
+    let azure = Azure::new("account".to_string(), "container".to_string(), None)
+      .with_account_key("key".to_string());
+
+    let serialized = toml::to_string(&azure).unwrap();
+    assert!(!serialized.contains("account_key"));
+    assert!(!format!("{:?}", azure).contains("account_key"));
+  }
+
+  #[test]
+  fn azure_resolve_captures() {
+    let regex = Regex::new("^(?P<account>.*?)/(?P<container>.*?)/(?P<key>.*)$").unwrap();
+    let captures = regex.captures("account/container/key").unwrap();
+
+    let result = Azure::default()
+      .resolve_captures(&captures)
+      .unwrap()
+      .unwrap();
+    assert_eq!(result.account(), "account");
+    assert_eq!(result.container(), "container");
+
+    let regex = Regex::new("^(container)/(?P<key>.*)$").unwrap();
+    let captures = regex.captures("container/key").unwrap();
+
+    let result = Azure::default()
+      .resolve_captures(&captures)
+      .unwrap()
+      .unwrap();
+    assert_eq!(result.container(), "container");
+  }
+}
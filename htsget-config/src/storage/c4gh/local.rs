@@ -2,23 +2,59 @@
 //!
 
 use crate::error::{Error, Result};
-use crate::storage::c4gh::C4GHKeys;
-use crypt4gh::keys::{get_private_key, get_public_key};
+use crate::storage::c4gh::passphrase::{passphrases, read_private_key};
+use crate::storage::c4gh::{C4GHKeyFields, C4GHKeySet, C4GHKeys};
+use crypt4gh::keys::get_public_key;
 use serde::Deserialize;
 use std::path::PathBuf;
 
-/// Local C4GH key storage.
+/// Local C4GH key storage. The private and public keys can be a single path or a list of paths.
 #[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
 #[serde(deny_unknown_fields)]
 pub struct C4GHLocal {
-  private: PathBuf,
-  public: PathBuf,
+  #[serde(flatten)]
+  keys: C4GHKeyFields<PathBuf>,
 }
 
 impl C4GHLocal {
   /// Create a new local C4GH key storage.
   pub fn new(private: PathBuf, public: PathBuf) -> Self {
-    Self { private, public }
+    C4GHKeyFields::new(private, public).into()
+  }
+
+  /// Get the key fields.
+  pub fn keys(&self) -> &C4GHKeyFields<PathBuf> {
+    &self.keys
+  }
+
+  /// Read the C4GH keys, decrypting the private keys using the passphrase if they are encrypted.
+  pub async fn get_keys(self) -> Result<C4GHKeySet> {
+    let (private, public, passphrase) = self.keys.into_inner();
+    let private = private.into_vec();
+    let passphrases = passphrases(passphrase, private.len())?;
+
+    let mut private_keys = vec![];
+    for (private, passphrase) in private.into_iter().zip(passphrases) {
+      let passphrase = match passphrase {
+        Some(passphrase) => Some(passphrase.read().await?),
+        None => None,
+      };
+      private_keys.push(read_private_key(private, passphrase)?);
+    }
+
+    let recipient_public_keys = public
+      .into_vec()
+      .into_iter()
+      .map(get_public_key)
+      .collect::<std::result::Result<Vec<_>, _>>()?;
+
+    C4GHKeySet::new(private_keys, recipient_public_keys)
+  }
+}
+
+impl From<C4GHKeyFields<PathBuf>> for C4GHLocal {
+  fn from(keys: C4GHKeyFields<PathBuf>) -> Self {
+    Self { keys }
   }
 }
 
@@ -26,21 +62,20 @@ impl TryFrom<C4GHLocal> for C4GHKeys {
   type Error = Error;
 
   fn try_from(local: C4GHLocal) -> Result<Self> {
-    let private_key = get_private_key(local.private, Ok("".to_string()))?;
-    let recipient_public_key = get_public_key(local.public)?;
-
-    let handle =
-      tokio::spawn(async move { Ok(C4GHKeys::from_key_pair(private_key, recipient_public_key)) });
-
-    Ok(C4GHKeys::from_join_handle(handle))
+    let keys = local.keys.clone();
+    Ok(keys.configure(C4GHKeys::from_join_handle(tokio::spawn(local.get_keys()))))
   }
 }
 
 #[cfg(test)]
 mod tests {
+  use super::*;
   use crate::config::tests::test_config_from_file;
   use crate::config::Config;
+  use crate::storage::c4gh::passphrase::C4GHPassphrase;
+  use crate::storage::c4gh::{C4GHMode, OneOrMany};
   use crate::storage::Backend;
+  use crypt4gh::keys::generate_keys;
   use std::fs::copy;
   use std::path::PathBuf;
   use tempfile::TempDir;
@@ -88,6 +123,149 @@ mod tests {
       },
     );
   }
+  #[test]
+  fn config_local_multiple_keys() {
+    let local: C4GHLocal = toml::from_str(
+      r#"
+      private = ["new.sec", "old.sec"]
+      public = "recipient.pub"
+      "#,
+    )
+    .unwrap();
+
+    assert_eq!(
+      local,
+      C4GHLocal::from(C4GHKeyFields::new_with_keys(
+        vec![PathBuf::from("new.sec"), PathBuf::from("old.sec")],
+        vec![PathBuf::from("recipient.pub")],
+      ))
+    );
+  }
+
+  #[test]
+  fn config_local_encrypted_indexes() {
+    let local: C4GHLocal = toml::from_str(
+      r#"
+      private = "private.sec"
+      public = "recipient.pub"
+      encrypted_indexes = true
+      "#,
+    )
+    .unwrap();
+
+    assert!(local.keys().encrypted_indexes());
+  }
+
+  #[test]
+  fn config_local_unknown_field() {
+    assert!(toml::from_str::<C4GHLocal>(
+      r#"
+      private = "private.sec"
+      public = "recipient.pub"
+      unknown = "unknown"
+      "#,
+    )
+    .is_err());
+  }
+
+  #[test]
+  fn config_local_passphrase() {
+    let local: C4GHLocal = toml::from_str(
+      r#"
+      private = "private.sec"
+      public = "recipient.pub"
+      passphrase = { env = "C4GH_PASSPHRASE" }
+      "#,
+    )
+    .unwrap();
+
+    assert_eq!(
+      local.keys().passphrase(),
+      Some(&OneOrMany::One(C4GHPassphrase::Env(
+        "C4GH_PASSPHRASE".to_string()
+      )))
+    );
+  }
+
+  #[tokio::test]
+  async fn config_local_decrypt_mode() {
+    let parent = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+      .parent()
+      .unwrap()
+      .join("data/c4gh/keys");
+
+    let local: C4GHLocal = toml::from_str(&format!(
+      r#"
+      private = "{}"
+      public = "{}"
+      mode = "Decrypt"
+      "#,
+      parent.join("bob.sec").to_string_lossy(),
+      parent.join("alice.pub").to_string_lossy()
+    ))
+    .unwrap();
+    assert_eq!(local.keys().mode(), C4GHMode::Decrypt);
+
+    let keys = C4GHKeys::try_from(local).unwrap();
+    assert_eq!(keys.mode(), C4GHMode::Decrypt);
+  }
+
+  #[tokio::test]
+  async fn config_local_encrypted_key() {
+    let tmp = TempDir::new().unwrap();
+    let private_key = tmp.path().join("encrypted.sec");
+    let public_key = tmp.path().join("encrypted.pub");
+    generate_keys(
+      private_key.clone(),
+      public_key.clone(),
+      || Ok("passphrase".to_string()),
+      None,
+    )
+    .unwrap();
+
+    let passphrase = tmp.path().join("passphrase");
+    std::fs::write(&passphrase, "passphrase").unwrap();
+
+    let fields = C4GHKeyFields::new(private_key, public_key);
+    let keys = C4GHKeys::try_from(C4GHLocal::from(
+      fields
+        .clone()
+        .with_passphrase(C4GHPassphrase::File(passphrase).into()),
+    ))
+    .unwrap();
+    assert_eq!(keys.keys().await.unwrap().len(), 1);
+
+    let err = C4GHKeys::try_from(C4GHLocal::from(fields))
+      .unwrap()
+      .keys()
+      .await
+      .unwrap_err()
+      .to_string();
+    assert!(err.contains("no passphrase was set"));
+  }
+
+  #[tokio::test]
+  async fn config_local_key_set() {
+    let parent = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+      .parent()
+      .unwrap()
+      .join("data/c4gh/keys");
+
+    let keys = C4GHKeys::try_from(C4GHLocal::from(C4GHKeyFields::new_with_keys(
+      vec![parent.join("bob.sec"), parent.join("alice.sec")],
+      vec![parent.join("alice.pub"), parent.join("bob.pub")],
+    )))
+    .unwrap();
+    let key_set = keys.key_set().await.unwrap();
+
+    assert_eq!(key_set.decryption_keys().len(), 2);
+    assert_eq!(key_set.recipient_keys().len(), 2);
+    assert!(key_set
+      .recipient_keys()
+      .iter()
+      .all(|key| key.privkey == key_set.decryption_keys()[0].privkey));
+  }
+
   #[tokio::test]
   async fn config_local_storage_c4gh() {
     test_c4gh_storage_config(r#"kind = "File""#, |config| {
@@ -4,46 +4,525 @@
 use crate::error::Error::{IoError, ParseError};
 use crate::error::{Error, Result};
 use crate::storage::c4gh::local::C4GHLocal;
+use crate::storage::c4gh::passphrase::C4GHPassphrase;
 #[cfg(feature = "aws")]
 use crate::storage::c4gh::secrets_manager::C4GHSecretsManager;
+#[cfg(feature = "url")]
+use crate::storage::c4gh::vault::C4GHVault;
 use crypt4gh::error::Crypt4GHError;
-use futures_util::future::{BoxFuture, Shared};
+use crypt4gh::Keys;
+use futures_util::future::{self, BoxFuture, Shared};
 use futures_util::FutureExt;
 use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::any::Any;
+use std::fmt;
+use std::fmt::{Debug, Formatter};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
 use tokio::task::{JoinError, JoinHandle};
+use tracing::warn;
 
 pub mod local;
+pub mod passphrase;
 
 #[cfg(feature = "aws")]
 pub mod secrets_manager;
+#[cfg(feature = "url")]
+pub mod vault;
+
+/// A single value or a list of values.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged, deny_unknown_fields)]
+pub enum OneOrMany<T> {
+  One(T),
+  Many(Vec<T>),
+}
+
+impl<T> OneOrMany<T> {
+  /// Get the values as a vec.
+  pub fn into_vec(self) -> Vec<T> {
+    match self {
+      OneOrMany::One(value) => vec![value],
+      OneOrMany::Many(values) => values,
+    }
+  }
+}
+
+impl<T> From<T> for OneOrMany<T> {
+  fn from(value: T) -> Self {
+    Self::One(value)
+  }
+}
+
+/// The resolved C4GH keys. The decryption keys are tried in order when reading a header, and the
+/// recipient keys are used to re-encrypt headers for clients.
+#[derive(Clone)]
+pub struct C4GHKeySet {
+  decryption_keys: Vec<Keys>,
+  recipient_keys: Vec<Keys>,
+}
+
+impl Debug for C4GHKeySet {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    f.debug_struct("C4GHKeySet")
+      .field("decryption_keys", &self.decryption_keys.len())
+      .field("recipient_keys", &self.recipient_keys.len())
+      .finish()
+  }
+}
+
+impl C4GHKeySet {
+  /// Create the key set from private keys, in the order they are tried, and recipient public
+  /// keys. Headers are re-encrypted for the recipients using the first private key.
+  pub fn new(private_keys: Vec<Vec<u8>>, recipient_public_keys: Vec<Vec<u8>>) -> Result<Self> {
+    let (Some(sender), Some(recipient)) = (private_keys.first(), recipient_public_keys.first())
+    else {
+      return Err(ParseError(
+        "at least one C4GH private and public key must be set".to_string(),
+      ));
+    };
+
+    let decryption_keys = private_keys
+      .iter()
+      .map(|private_key| Keys {
+        method: 0,
+        privkey: private_key.clone(),
+        recipient_pubkey: recipient.clone(),
+      })
+      .collect();
+    let recipient_keys = recipient_public_keys
+      .into_iter()
+      .map(|public_key| Keys {
+        method: 0,
+        privkey: sender.clone(),
+        recipient_pubkey: public_key,
+      })
+      .collect();
+
+    Ok(Self {
+      decryption_keys,
+      recipient_keys,
+    })
+  }
+
+  /// Get the decryption keys.
+  pub fn decryption_keys(&self) -> &[Keys] {
+    &self.decryption_keys
+  }
+
+  /// Get the recipient keys.
+  pub fn recipient_keys(&self) -> &[Keys] {
+    &self.recipient_keys
+  }
+
+  /// Get the inner values.
+  pub fn into_inner(self) -> (Vec<Keys>, Vec<Keys>) {
+    (self.decryption_keys, self.recipient_keys)
+  }
+}
+
+/// The default size of the C4GH state cache in bytes.
+pub const DEFAULT_STATE_CACHE_SIZE: usize = 64 * 1024 * 1024;
+
+/// The default state cache size.
+fn default_state_cache_size() -> usize {
+  DEFAULT_STATE_CACHE_SIZE
+}
+
+/// How C4GH objects are returned to clients.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub enum C4GHMode {
+  /// Return Crypt4GH streams, with the header re-encrypted for the recipients.
+  #[default]
+  #[serde(alias = "reencrypt", alias = "REENCRYPT")]
+  Reencrypt,
+  /// Return plaintext byte ranges, which are decrypted on the fly by the data server.
+  #[serde(alias = "decrypt", alias = "DECRYPT")]
+  Decrypt,
+}
+
+/// The default number of seconds that decrypt tickets are valid for.
+pub const DEFAULT_DECRYPT_TICKET_EXPIRES_IN: u64 = 3600;
+
+fn default_decrypt_ticket_expires_in() -> u64 {
+  DEFAULT_DECRYPT_TICKET_EXPIRES_IN
+}
+
+/// Signing options for the tickets of the decrypt mode. Tickets are signed by the ticket server
+/// and verified by the data server, so both must read the same secret.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct C4GHDecryptTicket {
+  secret: C4GHPassphrase,
+  #[serde(default = "default_decrypt_ticket_expires_in")]
+  expires_in: u64,
+}
+
+impl C4GHDecryptTicket {
+  /// Create the ticket options from the secret used to sign tickets.
+  pub fn new(secret: C4GHPassphrase) -> Self {
+    Self {
+      secret,
+      expires_in: default_decrypt_ticket_expires_in(),
+    }
+  }
+
+  /// Set the number of seconds that tickets are valid for.
+  pub fn with_expires_in(mut self, expires_in: u64) -> Self {
+    self.expires_in = expires_in;
+    self
+  }
+
+  /// Get the number of seconds that tickets are valid for.
+  pub fn expires_in(&self) -> u64 {
+    self.expires_in
+  }
+
+  /// Read the secret used to sign tickets. This is read in the same way as a passphrase.
+  pub async fn read_secret(&self) -> Result<String> {
+    self.secret.read().await
+  }
+}
+
+/// The key fields shared by all C4GH key locations, where `T` identifies a key in the location.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct C4GHKeyFields<T> {
+  private: OneOrMany<T>,
+  public: OneOrMany<T>,
+  #[serde(default)]
+  passphrase: Option<OneOrMany<C4GHPassphrase>>,
+  #[serde(default = "default_state_cache_size")]
+  state_cache_size: usize,
+  #[serde(default)]
+  mode: C4GHMode,
+  #[serde(default)]
+  encrypted_indexes: bool,
+  #[serde(default)]
+  decrypt_ticket: Option<C4GHDecryptTicket>,
+}
+
+impl<T> C4GHKeyFields<T> {
+  /// Create the key fields from a private and public key.
+  pub fn new(private: T, public: T) -> Self {
+    Self::from_keys(private.into(), public.into())
+  }
+
+  /// Create the key fields with multiple private keys, which are tried in order, and multiple
+  /// recipient public keys.
+  pub fn new_with_keys(private: Vec<T>, public: Vec<T>) -> Self {
+    Self::from_keys(OneOrMany::Many(private), OneOrMany::Many(public))
+  }
+
+  fn from_keys(private: OneOrMany<T>, public: OneOrMany<T>) -> Self {
+    Self {
+      private,
+      public,
+      passphrase: None,
+      state_cache_size: default_state_cache_size(),
+      mode: Default::default(),
+      encrypted_indexes: false,
+      decrypt_ticket: None,
+    }
+  }
+
+  /// Set the passphrase used to decrypt the private keys.
+  pub fn with_passphrase(mut self, passphrase: OneOrMany<C4GHPassphrase>) -> Self {
+    self.passphrase = Some(passphrase);
+    self
+  }
+
+  /// Set the size of the state cache in bytes.
+  pub fn with_state_cache_size(mut self, state_cache_size: usize) -> Self {
+    self.state_cache_size = state_cache_size;
+    self
+  }
+
+  /// Set the mode used to return objects.
+  pub fn with_mode(mut self, mode: C4GHMode) -> Self {
+    self.mode = mode;
+    self
+  }
+
+  /// Set whether to look for encrypted indexes before plaintext indexes.
+  pub fn with_encrypted_indexes(mut self, encrypted_indexes: bool) -> Self {
+    self.encrypted_indexes = encrypted_indexes;
+    self
+  }
+
+  /// Set the options used to sign tickets in the decrypt mode.
+  pub fn with_decrypt_ticket(mut self, decrypt_ticket: C4GHDecryptTicket) -> Self {
+    self.decrypt_ticket = Some(decrypt_ticket);
+    self
+  }
+
+  /// Get the private keys.
+  pub fn private(&self) -> &OneOrMany<T> {
+    &self.private
+  }
+
+  /// Get the recipient public keys.
+  pub fn public(&self) -> &OneOrMany<T> {
+    &self.public
+  }
+
+  /// Get the passphrase.
+  pub fn passphrase(&self) -> Option<&OneOrMany<C4GHPassphrase>> {
+    self.passphrase.as_ref()
+  }
+
+  /// Get the state cache size.
+  pub fn state_cache_size(&self) -> usize {
+    self.state_cache_size
+  }
+
+  /// Get the mode.
+  pub fn mode(&self) -> C4GHMode {
+    self.mode
+  }
+
+  /// Get whether to look for encrypted indexes.
+  pub fn encrypted_indexes(&self) -> bool {
+    self.encrypted_indexes
+  }
+
+  /// Get the options used to sign tickets in the decrypt mode.
+  pub fn decrypt_ticket(&self) -> Option<&C4GHDecryptTicket> {
+    self.decrypt_ticket.as_ref()
+  }
+
+  /// Get the inner values.
+  pub fn into_inner(
+    self,
+  ) -> (
+    OneOrMany<T>,
+    OneOrMany<T>,
+    Option<OneOrMany<C4GHPassphrase>>,
+  ) {
+    (self.private, self.public, self.passphrase)
+  }
+
+  /// Apply the state cache size, mode, index and ticket options to the keys.
+  pub(crate) fn configure(&self, keys: C4GHKeys) -> C4GHKeys {
+    keys
+      .with_state_cache_size(self.state_cache_size)
+      .with_mode(self.mode)
+      .with_encrypted_indexes(self.encrypted_indexes)
+      .with_decrypt_ticket(self.decrypt_ticket.clone())
+  }
+}
+
+type SharedKeys = Shared<BoxFuture<'static, Result<C4GHKeySet>>>;
+type FetchKeys = Arc<dyn Fn() -> JoinHandle<Result<C4GHKeySet>> + Send + Sync>;
+
+/// The keys which are served to requests, and when they were last refreshed.
+struct RefreshState {
+  refreshed_at: Instant,
+  current: SharedKeys,
+  refreshing: bool,
+}
+
+/// Refreshes C4GH keys after a time to live, so that rotated keys are used without a restart.
+/// The keys are fetched again by a single background task, and requests continue to be served
+/// the current keys until the task completes. The current keys are kept if a refresh fails.
+#[derive(Clone)]
+pub struct C4GHKeysRefresh {
+  ttl: Duration,
+  fetch: FetchKeys,
+  state: Arc<Mutex<Option<RefreshState>>>,
+}
+
+impl Debug for C4GHKeysRefresh {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    f.debug_struct("C4GHKeysRefresh")
+      .field("ttl", &self.ttl)
+      .finish_non_exhaustive()
+  }
+}
+
+impl C4GHKeysRefresh {
+  /// Get the current keys, starting a refresh in the background if the time to live has elapsed.
+  async fn key_set(&self, initial: SharedKeys) -> Result<C4GHKeySet> {
+    let current = {
+      let mut state = self.state.lock().unwrap_or_else(|err| err.into_inner());
+      let state = state.get_or_insert_with(|| RefreshState {
+        refreshed_at: Instant::now(),
+        current: initial,
+        refreshing: false,
+      });
+
+      if !state.refreshing && state.refreshed_at.elapsed() >= self.ttl {
+        state.refreshing = true;
+        self.spawn_refresh();
+      }
+
+      state.current.clone()
+    };
+
+    current.await
+  }
+
+  /// Fetch the keys in a background task, replacing the current keys if the fetch succeeds.
+  fn spawn_refresh(&self) {
+    let fetch = (self.fetch)();
+    let state = self.state.clone();
+
+    tokio::spawn(async move {
+      let result = fetch.await.map_err(Error::from).and_then(|keys| keys);
+
+      let mut state = state.lock().unwrap_or_else(|err| err.into_inner());
+      if let Some(state) = state.as_mut() {
+        match result {
+          Ok(keys) => state.current = future::ready(Ok(keys)).boxed().shared(),
+          Err(err) => warn!(error = %err, "failed to refresh C4GH keys, using the previous keys"),
+        }
+
+        state.refreshed_at = Instant::now();
+        state.refreshing = false;
+      }
+    });
+  }
+}
 
 /// Config for Crypt4GH keys.
 #[derive(Deserialize, Debug, Clone)]
 #[serde(try_from = "C4GHKeyLocation", deny_unknown_fields)]
 pub struct C4GHKeys {
   // Store a cloneable future so that it can be resolved outside serde.
-  keys: Shared<BoxFuture<'static, Result<Vec<crypt4gh::Keys>>>>,
+  keys: SharedKeys,
+  state_cache_size: usize,
+  mode: C4GHMode,
+  encrypted_indexes: bool,
+  decrypt_ticket: Option<C4GHDecryptTicket>,
+  refresh: Option<C4GHKeysRefresh>,
+  id: u64,
+  // State created by users of the keys, such as caches, which is shared by clones of the keys.
+  extension: Arc<OnceLock<Arc<dyn Any + Send + Sync>>>,
 }
 
 impl C4GHKeys {
-  /// Get the inner value.
-  pub async fn keys(self) -> Result<Vec<crypt4gh::Keys>> {
-    self.keys.await
+  /// Get the decryption keys.
+  pub async fn keys(self) -> Result<Vec<Keys>> {
+    Ok(self.key_set().await?.decryption_keys)
+  }
+
+  /// Get the decryption and recipient keys.
+  pub async fn key_set(self) -> Result<C4GHKeySet> {
+    match self.refresh {
+      Some(refresh) => refresh.key_set(self.keys).await,
+      None => self.keys.await,
+    }
+  }
+
+  /// Refresh the keys using `fetch` when they are older than the time to live.
+  pub fn with_refresh<F>(mut self, ttl: Duration, fetch: F) -> Self
+  where
+    F: Fn() -> JoinHandle<Result<C4GHKeySet>> + Send + Sync + 'static,
+  {
+    self.refresh = Some(C4GHKeysRefresh {
+      ttl,
+      fetch: Arc::new(fetch),
+      state: Default::default(),
+    });
+    self
   }
 
   /// Construct the C4GH keys from a key pair.
-  pub fn from_key_pair(private_key: Vec<u8>, recipient_public_key: Vec<u8>) -> Vec<crypt4gh::Keys> {
-    vec![crypt4gh::Keys {
+  pub fn from_key_pair(private_key: Vec<u8>, recipient_public_key: Vec<u8>) -> Vec<Keys> {
+    vec![Keys {
       method: 0,
       privkey: private_key,
       recipient_pubkey: recipient_public_key,
     }]
   }
 
+  /// Get the size of the state cache in bytes.
+  pub fn state_cache_size(&self) -> usize {
+    self.state_cache_size
+  }
+
+  /// Set the size of the state cache in bytes.
+  pub fn with_state_cache_size(mut self, state_cache_size: usize) -> Self {
+    self.state_cache_size = state_cache_size;
+    self
+  }
+
+  /// Get the identifier of the keys, which is shared by its clones. Keys loaded from config are
+  /// identified by their key location, so the id is the same in each process which loads the
+  /// same config. This is used to select the keys of a decrypt ticket.
+  pub fn id(&self) -> u64 {
+    self.id
+  }
+
+  /// Set the identifier of the keys.
+  pub fn with_id(mut self, id: u64) -> Self {
+    self.id = id;
+    self
+  }
+
+  /// Get the mode used to return objects.
+  pub fn mode(&self) -> C4GHMode {
+    self.mode
+  }
+
+  /// Set the mode used to return objects.
+  pub fn with_mode(mut self, mode: C4GHMode) -> Self {
+    self.mode = mode;
+    self
+  }
+
+  /// Get whether encrypted indexes are looked for before plaintext indexes.
+  pub fn encrypted_indexes(&self) -> bool {
+    self.encrypted_indexes
+  }
+
+  /// Set whether encrypted indexes are looked for before plaintext indexes.
+  pub fn with_encrypted_indexes(mut self, encrypted_indexes: bool) -> Self {
+    self.encrypted_indexes = encrypted_indexes;
+    self
+  }
+
+  /// Get the options used to sign tickets in the decrypt mode.
+  pub fn decrypt_ticket(&self) -> Option<&C4GHDecryptTicket> {
+    self.decrypt_ticket.as_ref()
+  }
+
+  /// Set the options used to sign tickets in the decrypt mode.
+  pub fn with_decrypt_ticket(mut self, decrypt_ticket: Option<C4GHDecryptTicket>) -> Self {
+    self.decrypt_ticket = decrypt_ticket;
+    self
+  }
+
+  /// Get the state which is shared by all clones of the keys, initializing it on first use.
+  /// Returns `None` if the state was already initialized with a different type.
+  pub fn extension<T, F>(&self, init: F) -> Option<Arc<T>>
+  where
+    T: Send + Sync + 'static,
+    F: FnOnce() -> T,
+  {
+    self
+      .extension
+      .get_or_init(|| Arc::new(init()))
+      .clone()
+      .downcast()
+      .ok()
+  }
+
   /// Construct from an existing join handle.
-  pub fn from_join_handle(handle: JoinHandle<Result<Vec<crypt4gh::Keys>>>) -> Self {
+  pub fn from_join_handle(handle: JoinHandle<Result<C4GHKeySet>>) -> Self {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
     Self {
       keys: handle.map(|value| value?).boxed().shared(),
+      state_cache_size: default_state_cache_size(),
+      mode: Default::default(),
+      encrypted_indexes: false,
+      decrypt_ticket: None,
+      refresh: None,
+      id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+      extension: Default::default(),
     }
   }
 }
@@ -64,11 +543,16 @@ impl TryFrom<C4GHKeyLocation> for C4GHKeys {
   type Error = Error;
 
   fn try_from(location: C4GHKeyLocation) -> Result<Self> {
-    match location {
+    let id = location.id();
+    let keys: C4GHKeys = match location {
       C4GHKeyLocation::File(file) => file.try_into(),
       #[cfg(feature = "aws")]
       C4GHKeyLocation::SecretsManager(secrets_manager) => secrets_manager.try_into(),
-    }
+      #[cfg(feature = "url")]
+      C4GHKeyLocation::Vault(vault) => vault.try_into(),
+    }?;
+
+    Ok(keys.with_id(id))
   }
 }
 
@@ -82,4 +566,134 @@ pub enum C4GHKeyLocation {
   #[cfg(feature = "aws")]
   #[serde(alias = "secretsmanager", alias = "SECRETSMANAGER")]
   SecretsManager(C4GHSecretsManager),
+  #[cfg(feature = "url")]
+  #[serde(alias = "vault", alias = "VAULT")]
+  Vault(C4GHVault),
+}
+
+impl C4GHKeyLocation {
+  /// Get an identifier which is derived from the configured location, so that it is stable
+  /// between processes and restarts.
+  pub fn id(&self) -> u64 {
+    let digest = Sha256::digest(format!("{:?}", self));
+    u64::from_be_bytes(digest[..8].try_into().expect("digest is at least 8 bytes"))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[tokio::test]
+  async fn key_location_id() {
+    let location = |private: &str| {
+      toml::from_str::<C4GHKeyLocation>(&format!(
+        r#"
+        kind = "File"
+        private = "{}"
+        public = "alice.pub"
+        decrypt_ticket = {{ secret = {{ env = "TICKET_SECRET" }}, expires_in = 60 }}
+        "#,
+        private
+      ))
+      .unwrap()
+    };
+
+    assert_eq!(location("bob.sec").id(), location("bob.sec").id());
+    assert_ne!(location("bob.sec").id(), location("alice.sec").id());
+
+    let keys = C4GHKeys::try_from(location("bob.sec")).unwrap();
+    assert_eq!(keys.id(), location("bob.sec").id());
+    assert_eq!(
+      keys.decrypt_ticket(),
+      Some(
+        &C4GHDecryptTicket::new(C4GHPassphrase::Env("TICKET_SECRET".to_string()))
+          .with_expires_in(60)
+      )
+    );
+  }
+
+  #[tokio::test]
+  async fn keys_refresh() {
+    let keys_dir = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+      .parent()
+      .unwrap()
+      .join("data/c4gh/keys");
+    let fetch = move |private: &str| {
+      let private_key = crypt4gh::keys::get_private_key(keys_dir.join(private), Ok("".to_string()));
+      let public_key = crypt4gh::keys::get_public_key(keys_dir.join("alice.pub"));
+      tokio::spawn(async move { C4GHKeySet::new(vec![private_key?], vec![public_key?]) })
+    };
+
+    let initial = fetch("bob.sec");
+    let keys =
+      C4GHKeys::from_join_handle(initial).with_refresh(Duration::ZERO, move || fetch("alice.sec"));
+    let bob = keys.clone().key_set().await.unwrap();
+
+    let mut alice = keys.clone().key_set().await.unwrap();
+    for _ in 0..100 {
+      if alice.decryption_keys()[0].privkey != bob.decryption_keys()[0].privkey {
+        break;
+      }
+
+      tokio::time::sleep(Duration::from_millis(10)).await;
+      alice = keys.clone().key_set().await.unwrap();
+    }
+
+    assert_ne!(
+      bob.decryption_keys()[0].privkey,
+      alice.decryption_keys()[0].privkey
+    );
+  }
+
+  #[tokio::test]
+  async fn keys_refresh_serves_current_keys() {
+    let keys_dir = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+      .parent()
+      .unwrap()
+      .join("data/c4gh/keys");
+    let private_key =
+      crypt4gh::keys::get_private_key(keys_dir.join("bob.sec"), Ok("".to_string())).unwrap();
+    let public_key = crypt4gh::keys::get_public_key(keys_dir.join("alice.pub")).unwrap();
+
+    let fetches = Arc::new(AtomicU64::new(0));
+    let counter = fetches.clone();
+    let keys = C4GHKeys::from_join_handle(tokio::spawn(async move {
+      C4GHKeySet::new(vec![private_key], vec![public_key])
+    }))
+    .with_refresh(Duration::ZERO, move || {
+      counter.fetch_add(1, Ordering::Relaxed);
+      tokio::spawn(future::pending())
+    });
+
+    for _ in 0..3 {
+      let key_set = tokio::time::timeout(Duration::from_secs(1), keys.clone().key_set())
+        .await
+        .unwrap();
+      assert_eq!(key_set.unwrap().decryption_keys().len(), 1);
+    }
+
+    assert_eq!(fetches.load(Ordering::Relaxed), 1);
+  }
+
+  #[tokio::test]
+  async fn keys_refresh_failure_uses_previous_keys() {
+    let keys_dir = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+      .parent()
+      .unwrap()
+      .join("data/c4gh/keys");
+    let private_key =
+      crypt4gh::keys::get_private_key(keys_dir.join("bob.sec"), Ok("".to_string())).unwrap();
+    let public_key = crypt4gh::keys::get_public_key(keys_dir.join("alice.pub")).unwrap();
+
+    let keys = C4GHKeys::from_join_handle(tokio::spawn(async move {
+      C4GHKeySet::new(vec![private_key], vec![public_key])
+    }))
+    .with_refresh(Duration::ZERO, || {
+      tokio::spawn(async { Err(IoError("unavailable".to_string())) })
+    });
+
+    assert_eq!(keys.clone().keys().await.unwrap().len(), 1);
+    assert_eq!(keys.clone().keys().await.unwrap().len(), 1);
+  }
 }
@@ -0,0 +1,266 @@
+//! Passphrases for encrypted C4GH private keys.
+//!
+
+use crate::error::Error::{IoError, ParseError};
+use crate::error::Result;
+#[cfg(feature = "aws")]
+use crate::storage::c4gh::secrets_manager::C4GHSecretsManager;
+use crate::storage::c4gh::OneOrMany;
+#[cfg(feature = "aws")]
+use aws_config::{load_defaults, BehaviorVersion};
+#[cfg(feature = "aws")]
+use aws_sdk_secretsmanager::Client;
+use base64::engine::general_purpose;
+use base64::Engine;
+use crypt4gh::keys::get_private_key;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+
+const PRIVATE_KEY_BEGIN: &str = "-----BEGIN CRYPT4GH PRIVATE KEY-----";
+const PRIVATE_KEY_END: &str = "-----END CRYPT4GH PRIVATE KEY-----";
+const MAGIC_WORD: &[u8] = b"c4gh-v1";
+const NO_KDF: &str = "none";
+
+/// The passphrase of an encrypted C4GH private key.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase", deny_unknown_fields)]
+pub enum C4GHPassphrase {
+  /// Read the passphrase from an environment variable.
+  Env(String),
+  /// Read the passphrase from a file, ignoring any trailing newline.
+  File(PathBuf),
+  /// Read the passphrase from a secret in AWS Secrets Manager.
+  #[cfg(feature = "aws")]
+  SecretsManager(String),
+}
+
+impl C4GHPassphrase {
+  /// Read the passphrase.
+  pub async fn read(&self) -> Result<String> {
+    #[cfg(feature = "aws")]
+    if let C4GHPassphrase::SecretsManager(_) = self {
+      return self
+        .read_with_client(&Client::new(
+          &load_defaults(BehaviorVersion::latest()).await,
+        ))
+        .await;
+    }
+
+    self.read_local()
+  }
+
+  /// Read a passphrase which is stored in an environment variable or a file.
+  fn read_local(&self) -> Result<String> {
+    let passphrase = match self {
+      C4GHPassphrase::Env(name) => env::var(name).map_err(|err| {
+        ParseError(format!(
+          "failed to read C4GH passphrase from `{}`: {}",
+          name, err
+        ))
+      })?,
+      C4GHPassphrase::File(path) => fs::read_to_string(path).map_err(|err| {
+        IoError(format!(
+          "failed to read C4GH passphrase from `{}`: {}",
+          path.display(),
+          err
+        ))
+      })?,
+      #[cfg(feature = "aws")]
+      C4GHPassphrase::SecretsManager(id) => {
+        return Err(ParseError(format!(
+          "C4GH passphrase `{}` is stored in Secrets Manager",
+          id
+        )))
+      }
+    };
+
+    Ok(passphrase.trim_end_matches(['\r', '\n']).to_string())
+  }
+
+  /// Read the passphrase, using the client if it is stored in Secrets Manager.
+  #[cfg(feature = "aws")]
+  pub async fn read_with_client(&self, client: &Client) -> Result<String> {
+    match self {
+      C4GHPassphrase::SecretsManager(id) => {
+        let passphrase = String::from_utf8(C4GHSecretsManager::get_secret(client, id).await?)
+          .map_err(|_| ParseError(format!("C4GH passphrase `{}` is not valid UTF-8", id)))?;
+
+        Ok(passphrase.trim_end_matches(['\r', '\n']).to_string())
+      }
+      _ => self.read_local(),
+    }
+  }
+}
+
+/// Get the passphrase for each of the private keys. A single passphrase applies to all keys,
+/// otherwise there must be one passphrase per key.
+pub fn passphrases(
+  passphrase: Option<OneOrMany<C4GHPassphrase>>,
+  n_keys: usize,
+) -> Result<Vec<Option<C4GHPassphrase>>> {
+  match passphrase {
+    None => Ok(vec![None; n_keys]),
+    Some(OneOrMany::One(passphrase)) => Ok(vec![Some(passphrase); n_keys]),
+    Some(OneOrMany::Many(passphrases)) if passphrases.len() == n_keys => {
+      Ok(passphrases.into_iter().map(Some).collect())
+    }
+    Some(OneOrMany::Many(_)) => Err(ParseError(
+      "the number of C4GH passphrases must match the number of private keys".to_string(),
+    )),
+  }
+}
+
+/// Check whether the private key is encrypted. Returns `None` if the key is not in the C4GH
+/// format, such as an OpenSSH key.
+fn is_encrypted(path: &Path) -> Result<Option<bool>> {
+  let key = fs::read_to_string(path).map_err(|err| {
+    IoError(format!(
+      "failed to read C4GH private key `{}`: {}",
+      path.display(),
+      err
+    ))
+  })?;
+
+  let Some(key) = key
+    .trim()
+    .strip_prefix(PRIVATE_KEY_BEGIN)
+    .and_then(|key| key.strip_suffix(PRIVATE_KEY_END))
+  else {
+    return Ok(None);
+  };
+  let Ok(key) = general_purpose::STANDARD.decode(key.split_whitespace().collect::<String>()) else {
+    return Ok(None);
+  };
+
+  // The magic word is followed by the length prefixed name of the key derivation function.
+  let Some(kdf) = key.strip_prefix(MAGIC_WORD).and_then(|key| {
+    let (length, kdf) = key.split_at_checked(2)?;
+    kdf.get(..u16::from_be_bytes([length[0], length[1]]) as usize)
+  }) else {
+    return Ok(None);
+  };
+
+  Ok(Some(kdf != NO_KDF.as_bytes()))
+}
+
+/// Read a C4GH private key, decrypting it using the passphrase if it is encrypted.
+pub fn read_private_key(path: PathBuf, passphrase: Option<String>) -> Result<Vec<u8>> {
+  let encrypted = is_encrypted(&path)?;
+  if encrypted == Some(true) && passphrase.is_none() {
+    return Err(ParseError(format!(
+      "C4GH private key `{}` is encrypted, but no passphrase was set",
+      path.display()
+    )));
+  }
+
+  let display = path.display().to_string();
+  get_private_key(path, Ok(passphrase.unwrap_or_default())).map_err(|err| {
+    if encrypted == Some(true) {
+      ParseError(format!(
+        "failed to decrypt C4GH private key `{}`, the passphrase is wrong: {}",
+        display, err
+      ))
+    } else {
+      ParseError(format!("invalid C4GH private key `{}`: {}", display, err))
+    }
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crypt4gh::keys::generate_keys;
+  use tempfile::TempDir;
+
+  fn test_keys_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+      .parent()
+      .unwrap()
+      .join("data/c4gh/keys")
+  }
+
+  fn generate_encrypted_key(tmp: &TempDir) -> PathBuf {
+    let private_key = tmp.path().join("encrypted.sec");
+    generate_keys(
+      private_key.clone(),
+      tmp.path().join("encrypted.pub"),
+      || Ok("passphrase".to_string()),
+      None,
+    )
+    .unwrap();
+
+    private_key
+  }
+
+  #[test]
+  fn read_unencrypted_private_key() {
+    let path = test_keys_dir().join("bob.sec");
+
+    assert_eq!(is_encrypted(&path).unwrap(), Some(false));
+    assert!(read_private_key(path, None).is_ok());
+  }
+
+  #[test]
+  fn read_encrypted_private_key() {
+    let tmp = TempDir::new().unwrap();
+    let path = generate_encrypted_key(&tmp);
+
+    assert_eq!(is_encrypted(&path).unwrap(), Some(true));
+    assert!(read_private_key(path, Some("passphrase".to_string())).is_ok());
+  }
+
+  #[test]
+  fn read_encrypted_private_key_missing_passphrase() {
+    let tmp = TempDir::new().unwrap();
+    let path = generate_encrypted_key(&tmp);
+
+    let err = read_private_key(path, None).unwrap_err().to_string();
+    assert!(err.contains("no passphrase was set"));
+  }
+
+  #[test]
+  fn read_encrypted_private_key_wrong_passphrase() {
+    let tmp = TempDir::new().unwrap();
+    let path = generate_encrypted_key(&tmp);
+
+    let err = read_private_key(path, Some("wrong".to_string()))
+      .unwrap_err()
+      .to_string();
+    assert!(err.contains("the passphrase is wrong"));
+  }
+
+  #[test]
+  fn read_invalid_private_key() {
+    let tmp = TempDir::new().unwrap();
+    let path = tmp.path().join("invalid.sec");
+    fs::write(&path, "invalid").unwrap();
+
+    let err = read_private_key(path, None).unwrap_err().to_string();
+    assert!(err.contains("invalid C4GH private key"));
+  }
+
+  #[tokio::test]
+  async fn read_passphrase_from_file() {
+    let tmp = TempDir::new().unwrap();
+    let path = tmp.path().join("passphrase");
+    fs::write(&path, "passphrase\n").unwrap();
+
+    assert_eq!(
+      C4GHPassphrase::File(path).read().await.unwrap(),
+      "passphrase"
+    );
+  }
+
+  #[test]
+  fn passphrases_for_keys() {
+    let passphrase = C4GHPassphrase::Env("PASSPHRASE".to_string());
+
+    assert_eq!(passphrases(None, 2).unwrap(), vec![None, None]);
+    assert_eq!(
+      passphrases(Some(passphrase.clone().into()), 2).unwrap(),
+      vec![Some(passphrase.clone()), Some(passphrase.clone())]
+    );
+    assert!(passphrases(Some(OneOrMany::Many(vec![passphrase])), 2).is_err());
+  }
+}
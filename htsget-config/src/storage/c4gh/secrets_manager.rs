@@ -3,23 +3,24 @@
 
 use crate::error::Error::ParseError;
 use crate::error::{Error, Result};
-use crate::storage::c4gh::C4GHKeys;
+use crate::storage::c4gh::passphrase::{passphrases, read_private_key};
+use crate::storage::c4gh::{C4GHKeyFields, C4GHKeySet, C4GHKeys};
 use aws_config::{load_defaults, BehaviorVersion};
 use aws_sdk_secretsmanager::error::SdkError;
 use aws_sdk_secretsmanager::Client;
-use crypt4gh::keys::{get_private_key, get_public_key};
-use crypt4gh::Keys;
+use crypt4gh::keys::get_public_key;
 use serde::Deserialize;
 use std::fs;
 use std::path::Path;
 use tempfile::TempDir;
 
-/// C4GH secrets manager key storage.
+/// C4GH secrets manager key storage. The private and public keys can be a single secret or a list
+/// of secrets.
 #[derive(Deserialize, Debug, Clone)]
 #[serde(deny_unknown_fields)]
 pub struct C4GHSecretsManager {
-  private: String,
-  public: String,
+  #[serde(flatten)]
+  keys: C4GHKeyFields<String>,
   #[serde(skip)]
   client: Option<Client>,
 }
@@ -27,11 +28,12 @@ pub struct C4GHSecretsManager {
 impl C4GHSecretsManager {
   /// Create a new C4GH secrets manager key storage.
   pub fn new(private: String, public: String) -> Self {
-    Self {
-      private,
-      public,
-      client: None,
-    }
+    C4GHKeyFields::new(private, public).into()
+  }
+
+  /// Get the key fields.
+  pub fn keys(&self) -> &C4GHKeyFields<String> {
+    &self.keys
   }
 
   /// Set the client.
@@ -59,7 +61,7 @@ impl C4GHSecretsManager {
   }
 
   /// Retrieve the C4GH keys from secrets manager.
-  pub async fn get_keys(self) -> Result<Vec<Keys>> {
+  pub async fn get_keys(self) -> Result<C4GHKeySet> {
     let client = if let Some(client) = self.client {
       client
     } else {
@@ -68,16 +70,37 @@ impl C4GHSecretsManager {
 
     // Should not have to do this, but the Crypt4GH library expects a path.
     let tmp = TempDir::new()?;
-    let private_key = tmp.path().join("private_key");
-    Self::write_to_file(&private_key, self.private, &client).await?;
 
-    let recipient_public_key = tmp.path().join("public_key");
-    Self::write_to_file(&recipient_public_key, self.public, &client).await?;
+    let (private, public, passphrase) = self.keys.into_inner();
+    let private = private.into_vec();
+    let passphrases = passphrases(passphrase, private.len())?;
+
+    let mut private_keys = vec![];
+    for (i, (private, passphrase)) in private.into_iter().zip(passphrases).enumerate() {
+      let private_key = tmp.path().join(format!("private_key_{}", i));
+      Self::write_to_file(&private_key, private, &client).await?;
+
+      let passphrase = match passphrase {
+        Some(passphrase) => Some(passphrase.read_with_client(&client).await?),
+        None => None,
+      };
+      private_keys.push(read_private_key(private_key, passphrase)?);
+    }
+
+    let mut recipient_public_keys = vec![];
+    for (i, public) in public.into_vec().into_iter().enumerate() {
+      let recipient_public_key = tmp.path().join(format!("public_key_{}", i));
+      Self::write_to_file(&recipient_public_key, public, &client).await?;
+      recipient_public_keys.push(get_public_key(recipient_public_key)?);
+    }
 
-    let private_key = get_private_key(private_key, Ok("".to_string()))?;
-    let recipient_public_key = get_public_key(recipient_public_key)?;
+    C4GHKeySet::new(private_keys, recipient_public_keys)
+  }
+}
 
-    Ok(C4GHKeys::from_key_pair(private_key, recipient_public_key))
+impl From<C4GHKeyFields<String>> for C4GHSecretsManager {
+  fn from(keys: C4GHKeyFields<String>) -> Self {
+    Self { keys, client: None }
   }
 }
 
@@ -91,9 +114,10 @@ impl TryFrom<C4GHSecretsManager> for C4GHKeys {
   type Error = Error;
 
   fn try_from(secrets_manager: C4GHSecretsManager) -> Result<Self> {
-    Ok(C4GHKeys::from_join_handle(tokio::spawn(
+    let keys = secrets_manager.keys.clone();
+    Ok(keys.configure(C4GHKeys::from_join_handle(tokio::spawn(
       secrets_manager.get_keys(),
-    )))
+    ))))
   }
 }
 
@@ -106,6 +130,7 @@ mod tests {
   use std::path::PathBuf;
 
   use super::*;
+  use crate::storage::c4gh::passphrase::C4GHPassphrase;
 
   async fn test_get_keys(rules: &[&Rule]) {
     let client = mock_client!(aws_sdk_secretsmanager, RuleMode::Sequential, rules);
@@ -176,4 +201,62 @@ mod tests {
 
     test_get_keys(&[&get_private_key, &get_recipient_public_key]).await;
   }
+
+  #[tokio::test]
+  async fn config_test_get_keys_with_passphrase() {
+    let tmp = TempDir::new().unwrap();
+    let private_key_path = tmp.path().join("encrypted.sec");
+    let public_key_path = tmp.path().join("encrypted.pub");
+    crypt4gh::keys::generate_keys(
+      private_key_path.clone(),
+      public_key_path.clone(),
+      || Ok("passphrase".to_string()),
+      None,
+    )
+    .unwrap();
+
+    let private_key = read(private_key_path).unwrap();
+    let recipient_public_key = read(public_key_path).unwrap();
+
+    let get_private_key = mock!(Client::get_secret_value)
+      .match_requests(|req| req.secret_id() == Some("private_key"))
+      .then_output(move || {
+        GetSecretValueOutput::builder()
+          .secret_binary(Blob::new(private_key.clone()))
+          .build()
+      });
+    let get_passphrase = mock!(Client::get_secret_value)
+      .match_requests(|req| req.secret_id() == Some("passphrase"))
+      .then_output(|| {
+        GetSecretValueOutput::builder()
+          .secret_string("passphrase")
+          .build()
+      });
+    let get_recipient_public_key = mock!(Client::get_secret_value)
+      .match_requests(|req| req.secret_id() == Some("recipient_public_key"))
+      .then_output(move || {
+        GetSecretValueOutput::builder()
+          .secret_binary(Blob::new(recipient_public_key.clone()))
+          .build()
+      });
+
+    let client = mock_client!(
+      aws_sdk_secretsmanager,
+      RuleMode::Sequential,
+      &[&get_private_key, &get_passphrase, &get_recipient_public_key]
+    );
+
+    let keys: C4GHKeys = C4GHSecretsManager::from(
+      C4GHKeyFields::new(
+        "private_key".to_string(),
+        "recipient_public_key".to_string(),
+      )
+      .with_passphrase(C4GHPassphrase::SecretsManager("passphrase".to_string()).into()),
+    )
+    .with_client(client)
+    .try_into()
+    .unwrap();
+
+    assert_eq!(keys.keys().await.unwrap().len(), 1);
+  }
 }
@@ -0,0 +1,599 @@
+//! Obtain C4GH keys from HashiCorp Vault.
+//!
+
+use crate::error::Error::{IoError, ParseError};
+use crate::error::{Error, Result};
+use crate::storage::c4gh::passphrase::{passphrases, read_private_key};
+use crate::storage::c4gh::{C4GHKeyFields, C4GHKeySet, C4GHKeys};
+use crate::storage::url::Secret;
+use crate::tls::client::TlsClientConfig;
+use base64::engine::general_purpose;
+use base64::Engine;
+use crypt4gh::keys::get_public_key;
+use reqwest::{Client, RequestBuilder};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::fs;
+use std::time::Duration;
+use tempfile::TempDir;
+
+/// The header containing the Vault token.
+pub const VAULT_TOKEN_HEADER: &str = "X-Vault-Token";
+/// The header containing the Vault namespace.
+pub const VAULT_NAMESPACE_HEADER: &str = "X-Vault-Namespace";
+
+fn default_kv_mount() -> String {
+  "secret".to_string()
+}
+
+fn default_transit_mount() -> String {
+  "transit".to_string()
+}
+
+fn default_approle_mount() -> String {
+  "approle".to_string()
+}
+
+fn default_auth() -> VaultAuth {
+  VaultAuth::Token {
+    token: Secret::Env("VAULT_TOKEN".to_string()),
+  }
+}
+
+/// How htsget-rs authenticates with Vault.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "kind", deny_unknown_fields)]
+pub enum VaultAuth {
+  /// A Vault token.
+  #[serde(alias = "token", alias = "TOKEN")]
+  Token { token: Secret },
+  /// Log in using an AppRole role id and secret id.
+  #[serde(alias = "approle", alias = "APPROLE")]
+  AppRole {
+    #[serde(default = "default_approle_mount")]
+    mount: String,
+    role_id: String,
+    secret_id: Secret,
+  },
+}
+
+/// A PEM formatted key stored in Vault.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "kind", deny_unknown_fields)]
+pub enum VaultSecret {
+  /// A field of a secret in the KV version 2 secrets engine.
+  #[serde(alias = "kv", alias = "KV")]
+  Kv {
+    #[serde(default = "default_kv_mount")]
+    mount: String,
+    path: String,
+    field: String,
+  },
+  /// A key encrypted by the transit secrets engine, which Vault decrypts using the named key.
+  #[serde(alias = "transit", alias = "TRANSIT")]
+  Transit {
+    #[serde(default = "default_transit_mount")]
+    mount: String,
+    key: String,
+    ciphertext: String,
+  },
+}
+
+/// C4GH Vault key storage. The private and public keys can be a single secret or a list of
+/// secrets.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct C4GHVault {
+  address: String,
+  #[serde(default)]
+  namespace: Option<String>,
+  #[serde(default = "default_auth")]
+  auth: VaultAuth,
+  #[serde(flatten)]
+  keys: C4GHKeyFields<VaultSecret>,
+  #[serde(default)]
+  ttl: Option<u64>,
+  #[serde(default)]
+  tls: TlsClientConfig,
+  #[serde(skip)]
+  client: Option<Client>,
+}
+
+impl C4GHVault {
+  /// Create a new C4GH Vault key storage, which authenticates using the `VAULT_TOKEN`
+  /// environment variable.
+  pub fn new(address: String, keys: C4GHKeyFields<VaultSecret>) -> Self {
+    Self {
+      address,
+      namespace: None,
+      auth: default_auth(),
+      keys,
+      ttl: None,
+      tls: Default::default(),
+      client: None,
+    }
+  }
+
+  /// Set the Vault namespace.
+  pub fn with_namespace(mut self, namespace: String) -> Self {
+    self.namespace = Some(namespace);
+    self
+  }
+
+  /// Set how htsget-rs authenticates with Vault.
+  pub fn with_auth(mut self, auth: VaultAuth) -> Self {
+    self.auth = auth;
+    self
+  }
+
+  /// Set the time to live of the keys in seconds, after which they are fetched again.
+  pub fn with_ttl(mut self, ttl: u64) -> Self {
+    self.ttl = Some(ttl);
+    self
+  }
+
+  /// Get the key fields.
+  pub fn keys(&self) -> &C4GHKeyFields<VaultSecret> {
+    &self.keys
+  }
+
+  /// Set the client.
+  pub fn with_client(mut self, client: Client) -> Self {
+    self.client = Some(client);
+    self
+  }
+
+  fn client(&self) -> Result<Client> {
+    if let Some(client) = &self.client {
+      return Ok(client.clone());
+    }
+
+    let mut builder = Client::builder();
+    let (certs, identity) = self.tls.clone().into_inner();
+    for cert in certs.into_iter().flatten() {
+      builder = builder.add_root_certificate(cert);
+    }
+    if let Some(identity) = identity {
+      builder = builder.identity(identity);
+    }
+
+    builder
+      .build()
+      .map_err(|err| ParseError(format!("building Vault client: {}", err)))
+  }
+
+  fn url(&self, path: &str) -> String {
+    format!("{}/v1/{}", self.address.trim_end_matches('/'), path)
+  }
+
+  /// Send a request to Vault, returning the JSON response.
+  async fn send(&self, request: RequestBuilder) -> Result<Value> {
+    let request = match &self.namespace {
+      Some(namespace) => request.header(VAULT_NAMESPACE_HEADER, namespace),
+      None => request,
+    };
+
+    let response = request
+      .send()
+      .await
+      .map_err(|err| IoError(format!("requesting Vault: {}", err)))?;
+    let status = response.status();
+    let body = response
+      .bytes()
+      .await
+      .map_err(|err| IoError(format!("reading Vault response: {}", err)))?;
+
+    if !status.is_success() {
+      return Err(IoError(format!(
+        "Vault returned {}: {}",
+        status,
+        String::from_utf8_lossy(&body)
+      )));
+    }
+
+    serde_json::from_slice(&body)
+      .map_err(|err| ParseError(format!("parsing Vault response: {}", err)))
+  }
+
+  /// Get a Vault token, logging in if using AppRole.
+  async fn token(&self, client: &Client) -> Result<String> {
+    let read = |secret: &Secret| secret.read().map_err(|err| ParseError(err.to_string()));
+
+    match &self.auth {
+      VaultAuth::Token { token } => read(token),
+      VaultAuth::AppRole {
+        mount,
+        role_id,
+        secret_id,
+      } => {
+        let body = json!({ "role_id": role_id, "secret_id": read(secret_id)? });
+        let response = self
+          .send(
+            client
+              .post(self.url(&format!("auth/{}/login", mount)))
+              .body(body.to_string()),
+          )
+          .await?;
+
+        response
+          .pointer("/auth/client_token")
+          .and_then(Value::as_str)
+          .map(str::to_string)
+          .ok_or_else(|| ParseError("missing client token in Vault login response".to_string()))
+      }
+    }
+  }
+
+  /// Read a key from Vault.
+  pub async fn get_secret(
+    &self,
+    client: &Client,
+    token: &str,
+    secret: &VaultSecret,
+  ) -> Result<Vec<u8>> {
+    match secret {
+      VaultSecret::Kv { mount, path, field } => {
+        let response = self
+          .send(
+            client
+              .get(self.url(&format!("{}/data/{}", mount, path)))
+              .header(VAULT_TOKEN_HEADER, token),
+          )
+          .await?;
+
+        response
+          .pointer("/data/data")
+          .and_then(|data| data.get(field))
+          .and_then(Value::as_str)
+          .map(|value| value.as_bytes().to_vec())
+          .ok_or_else(|| {
+            ParseError(format!(
+              "missing field `{}` in Vault secret `{}/{}`",
+              field, mount, path
+            ))
+          })
+      }
+      VaultSecret::Transit {
+        mount,
+        key,
+        ciphertext,
+      } => {
+        let response = self
+          .send(
+            client
+              .post(self.url(&format!("{}/decrypt/{}", mount, key)))
+              .header(VAULT_TOKEN_HEADER, token)
+              .body(json!({ "ciphertext": ciphertext }).to_string()),
+          )
+          .await?;
+
+        let plaintext = response
+          .pointer("/data/plaintext")
+          .and_then(Value::as_str)
+          .ok_or_else(|| {
+            ParseError(format!(
+              "missing plaintext in Vault transit response for `{}/{}`",
+              mount, key
+            ))
+          })?;
+
+        general_purpose::STANDARD.decode(plaintext).map_err(|err| {
+          ParseError(format!(
+            "invalid plaintext in Vault transit response for `{}/{}`: {}",
+            mount, key, err
+          ))
+        })
+      }
+    }
+  }
+
+  /// Retrieve the C4GH keys from Vault.
+  pub async fn get_keys(self) -> Result<C4GHKeySet> {
+    let client = self.client()?;
+    let token = self.token(&client).await?;
+
+    // The Crypt4GH library expects a path.
+    let tmp = TempDir::new()?;
+
+    let (private, public, passphrase) = self.keys.clone().into_inner();
+    let private = private.into_vec();
+    let passphrases = passphrases(passphrase, private.len())?;
+
+    let mut private_keys = vec![];
+    for (i, (private, passphrase)) in private.iter().zip(passphrases).enumerate() {
+      let private_key = tmp.path().join(format!("private_key_{}", i));
+      fs::write(
+        &private_key,
+        self.get_secret(&client, &token, private).await?,
+      )?;
+
+      let passphrase = match passphrase {
+        Some(passphrase) => Some(passphrase.read().await?),
+        None => None,
+      };
+      private_keys.push(read_private_key(private_key, passphrase)?);
+    }
+
+    let mut recipient_public_keys = vec![];
+    for (i, public) in public.into_vec().iter().enumerate() {
+      let recipient_public_key = tmp.path().join(format!("public_key_{}", i));
+      fs::write(
+        &recipient_public_key,
+        self.get_secret(&client, &token, public).await?,
+      )?;
+      recipient_public_keys.push(get_public_key(recipient_public_key)?);
+    }
+
+    C4GHKeySet::new(private_keys, recipient_public_keys)
+  }
+}
+
+impl TryFrom<C4GHVault> for C4GHKeys {
+  type Error = Error;
+
+  fn try_from(vault: C4GHVault) -> Result<Self> {
+    let ttl = vault.ttl;
+    let keys = vault
+      .keys
+      .configure(C4GHKeys::from_join_handle(tokio::spawn(
+        vault.clone().get_keys(),
+      )));
+
+    Ok(match ttl {
+      Some(ttl) => keys.with_refresh(Duration::from_secs(ttl), move || {
+        tokio::spawn(vault.clone().get_keys())
+      }),
+      None => keys,
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::storage::c4gh::OneOrMany;
+  use axum::extract::{Path, State};
+  use axum::http::{HeaderMap, StatusCode};
+  use axum::routing::{get, post};
+  use axum::{Json, Router};
+  use std::path::PathBuf;
+  use std::sync::atomic::{AtomicUsize, Ordering};
+  use std::sync::Arc;
+  use tokio::net::TcpListener;
+
+  const TOKEN: &str = "token"; // pragma: allowlist secret
+  const APPROLE_TOKEN: &str = "approle-token"; // pragma: allowlist secret
+
+  #[derive(Clone)]
+  struct MockVault {
+    private_key: String,
+    public_key: String,
+    reads: Arc<AtomicUsize>,
+  }
+
+  fn authorized(headers: &HeaderMap) -> bool {
+    headers
+      .get(VAULT_TOKEN_HEADER)
+      .is_some_and(|token| token == TOKEN || token == APPROLE_TOKEN)
+  }
+
+  async fn kv(
+    State(vault): State<MockVault>,
+    Path(path): Path<String>,
+    headers: HeaderMap,
+  ) -> std::result::Result<Json<Value>, StatusCode> {
+    if !authorized(&headers) {
+      return Err(StatusCode::FORBIDDEN);
+    }
+    if path != "htsget/c4gh" {
+      return Err(StatusCode::NOT_FOUND);
+    }
+    vault.reads.fetch_add(1, Ordering::SeqCst);
+
+    Ok(Json(json!({
+      "data": {
+        "data": { "private": vault.private_key, "public": vault.public_key },
+        "metadata": { "version": 1 }
+      }
+    })))
+  }
+
+  async fn transit(
+    State(vault): State<MockVault>,
+    headers: HeaderMap,
+    body: String,
+  ) -> std::result::Result<Json<Value>, StatusCode> {
+    if !authorized(&headers) {
+      return Err(StatusCode::FORBIDDEN);
+    }
+
+    // The mock "encrypts" by prefixing the base64 encoded plaintext.
+    let body: Value = serde_json::from_str(&body).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let plaintext = body["ciphertext"]
+      .as_str()
+      .and_then(|ciphertext| ciphertext.strip_prefix("vault:v1:"))
+      .ok_or(StatusCode::BAD_REQUEST)?;
+    vault.reads.fetch_add(1, Ordering::SeqCst);
+
+    Ok(Json(json!({ "data": { "plaintext": plaintext } })))
+  }
+
+  async fn login(body: String) -> std::result::Result<Json<Value>, StatusCode> {
+    let body: Value = serde_json::from_str(&body).map_err(|_| StatusCode::BAD_REQUEST)?;
+    if body["role_id"] != "role" || body["secret_id"] != "secret" {
+      return Err(StatusCode::BAD_REQUEST);
+    }
+
+    Ok(Json(
+      json!({ "auth": { "client_token": APPROLE_TOKEN, "lease_duration": 3600 } }),
+    ))
+  }
+
+  async fn with_mock_vault<F, Fut>(test: F)
+  where
+    F: FnOnce(String, MockVault) -> Fut,
+    Fut: std::future::Future<Output = ()>,
+  {
+    let keys = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+      .parent()
+      .unwrap()
+      .join("data/c4gh/keys");
+    let vault = MockVault {
+      private_key: fs::read_to_string(keys.join("bob.sec")).unwrap(),
+      public_key: fs::read_to_string(keys.join("alice.pub")).unwrap(),
+      reads: Default::default(),
+    };
+
+    let router = Router::new()
+      .route("/v1/secret/data/*path", get(kv))
+      .route("/v1/transit/decrypt/htsget", post(transit))
+      .route("/v1/auth/approle/login", post(login))
+      .with_state(vault.clone());
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+
+    test(address, vault).await;
+  }
+
+  fn kv_secret(field: &str) -> VaultSecret {
+    VaultSecret::Kv {
+      mount: default_kv_mount(),
+      path: "htsget/c4gh".to_string(),
+      field: field.to_string(),
+    }
+  }
+
+  fn token_file(tmp: &TempDir, name: &str, value: &str) -> Secret {
+    let path = tmp.path().join(name);
+    fs::write(&path, value).unwrap();
+    Secret::File(path)
+  }
+
+  #[test]
+  fn config_vault() {
+    let vault: C4GHVault = toml::from_str(
+      r#"
+      address = "https://vault.example.com"
+      auth = { kind = "AppRole", role_id = "role", secret_id = { env = "VAULT_SECRET_ID" } }
+      private = { kind = "Transit", key = "htsget", ciphertext = "vault:v1:abc" }
+      public = { kind = "Kv", path = "htsget/c4gh", field = "public" }
+      ttl = 300
+      "#,
+    )
+    .unwrap();
+
+    assert_eq!(
+      vault.auth,
+      VaultAuth::AppRole {
+        mount: default_approle_mount(),
+        role_id: "role".to_string(),
+        secret_id: Secret::Env("VAULT_SECRET_ID".to_string()),
+      }
+    );
+    assert_eq!(
+      *vault.keys.private(),
+      OneOrMany::One(VaultSecret::Transit {
+        mount: default_transit_mount(),
+        key: "htsget".to_string(),
+        ciphertext: "vault:v1:abc".to_string(),
+      })
+    );
+    assert_eq!(*vault.keys.public(), OneOrMany::One(kv_secret("public")));
+    assert_eq!(vault.ttl, Some(300));
+  }
+
+  #[tokio::test]
+  async fn vault_kv_token() {
+    with_mock_vault(|address, _| async move {
+      let tmp = TempDir::new().unwrap();
+      let keys: C4GHKeys = C4GHVault::new(
+        address,
+        C4GHKeyFields::new(kv_secret("private"), kv_secret("public")),
+      )
+      .with_auth(VaultAuth::Token {
+        token: token_file(&tmp, "token", TOKEN),
+      })
+      .try_into()
+      .unwrap();
+
+      assert_eq!(keys.keys().await.unwrap().len(), 1);
+    })
+    .await;
+  }
+
+  #[tokio::test]
+  async fn vault_transit_approle() {
+    with_mock_vault(|address, vault| async move {
+      let tmp = TempDir::new().unwrap();
+      let ciphertext = format!(
+        "vault:v1:{}",
+        general_purpose::STANDARD.encode(&vault.private_key)
+      );
+      let keys: C4GHKeys = C4GHVault::new(
+        address,
+        C4GHKeyFields::new(
+          VaultSecret::Transit {
+            mount: default_transit_mount(),
+            key: "htsget".to_string(),
+            ciphertext,
+          },
+          kv_secret("public"),
+        ),
+      )
+      .with_auth(VaultAuth::AppRole {
+        mount: default_approle_mount(),
+        role_id: "role".to_string(),
+        secret_id: token_file(&tmp, "secret_id", "secret"),
+      })
+      .try_into()
+      .unwrap();
+
+      assert_eq!(keys.keys().await.unwrap().len(), 1);
+    })
+    .await;
+  }
+
+  #[tokio::test]
+  async fn vault_unauthorized() {
+    with_mock_vault(|address, _| async move {
+      let tmp = TempDir::new().unwrap();
+      let keys: C4GHKeys = C4GHVault::new(
+        address,
+        C4GHKeyFields::new(kv_secret("private"), kv_secret("public")),
+      )
+      .with_auth(VaultAuth::Token {
+        token: token_file(&tmp, "token", "wrong"),
+      })
+      .try_into()
+      .unwrap();
+
+      assert!(keys.keys().await.unwrap_err().to_string().contains("403"));
+    })
+    .await;
+  }
+
+  #[tokio::test]
+  async fn vault_ttl_refresh() {
+    with_mock_vault(|address, vault| async move {
+      let tmp = TempDir::new().unwrap();
+      let keys: C4GHKeys = C4GHVault::new(
+        address,
+        C4GHKeyFields::new(kv_secret("private"), kv_secret("public")),
+      )
+      .with_auth(VaultAuth::Token {
+        token: token_file(&tmp, "token", TOKEN),
+      })
+      .with_ttl(0)
+      .try_into()
+      .unwrap();
+
+      keys.clone().keys().await.unwrap();
+      assert_eq!(vault.reads.load(Ordering::SeqCst), 2);
+
+      keys.clone().keys().await.unwrap();
+      assert_eq!(vault.reads.load(Ordering::SeqCst), 4);
+    })
+    .await;
+  }
+}
@@ -0,0 +1,217 @@
+//! Configuration for GA4GH Data Repository Service (DRS) storage.
+//!
+
+use crate::config::advanced;
+use crate::resolver::ResolveCaptures;
+#[cfg(feature = "experimental")]
+use crate::storage::c4gh::C4GHKeys;
+use crate::types::{HtsGetError, Result};
+use http::{HeaderMap, HeaderValue, Uri};
+use regex::Captures;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// The resolved access information for a DRS object.
+#[derive(Debug, Clone)]
+pub struct DrsAccess {
+  url: Uri,
+  headers: HeaderMap,
+  size: u64,
+}
+
+impl DrsAccess {
+  /// Create a new DRS access.
+  pub fn new(url: Uri, headers: HeaderMap, size: u64) -> Self {
+    Self { url, headers, size }
+  }
+
+  /// Get the access url.
+  pub fn url(&self) -> &Uri {
+    &self.url
+  }
+
+  /// Get the headers required to fetch the access url.
+  pub fn headers(&self) -> &HeaderMap {
+    &self.headers
+  }
+
+  /// Get the size of the object.
+  pub fn size(&self) -> u64 {
+    self.size
+  }
+}
+
+/// The key of a cached DRS access, which is the DRS object url and the forwarded headers, because
+/// access urls can depend on the caller's authorization.
+pub type DrsAccessKey = (String, Vec<(String, HeaderValue)>);
+
+/// A cache of resolved DRS objects, which is shared between requests. Entries expire after the
+/// ttl, so that access urls which are signed by the DRS server are refreshed.
+#[derive(Debug, Clone, Default)]
+pub struct DrsObjectCache {
+  objects: Arc<Mutex<HashMap<DrsAccessKey, (DrsAccess, Instant)>>>,
+}
+
+impl DrsObjectCache {
+  /// Get the cached access if it has not expired.
+  pub fn get(&self, key: &DrsAccessKey) -> Option<DrsAccess> {
+    self
+      .objects
+      .lock()
+      .ok()?
+      .get(key)
+      .filter(|(_, expires_at)| Instant::now() < *expires_at)
+      .map(|(access, _)| access.clone())
+  }
+
+  /// Cache the access for the ttl, removing any expired entries.
+  pub fn insert(&self, key: DrsAccessKey, access: DrsAccess, ttl: Duration) {
+    if ttl.is_zero() {
+      return;
+    }
+
+    if let Ok(mut objects) = self.objects.lock() {
+      let now = Instant::now();
+      objects.retain(|_, (_, expires_at)| now < *expires_at);
+      objects.insert(key, (access, now + ttl));
+    }
+  }
+}
+
+/// DRS server storage struct. Ids are resolved into access URLs by calling the DRS server,
+/// which are then used to fetch data.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(try_from = "advanced::drs::Drs", deny_unknown_fields)]
+pub struct Drs {
+  #[serde(with = "http_serde::uri")]
+  url: Uri,
+  index_suffix: Option<String>,
+  forward_headers: bool,
+  header_allowlist: Vec<String>,
+  header_blacklist: Vec<String>,
+  cache_ttl: u64,
+  #[serde(skip_serializing)]
+  client: Client,
+  #[serde(skip)]
+  objects: DrsObjectCache,
+  #[cfg(feature = "experimental")]
+  #[serde(skip_serializing)]
+  keys: Option<C4GHKeys>,
+}
+
+impl Drs {
+  /// Create a new DRS storage client.
+  pub fn new(
+    url: Uri,
+    index_suffix: Option<String>,
+    forward_headers: bool,
+    header_blacklist: Vec<String>,
+    client: Client,
+  ) -> Self {
+    Self {
+      url,
+      index_suffix,
+      forward_headers,
+      header_allowlist: default_header_allowlist(),
+      header_blacklist,
+      cache_ttl: default_cache_ttl(),
+      client,
+      objects: Default::default(),
+      #[cfg(feature = "experimental")]
+      keys: None,
+    }
+  }
+
+  /// Get the url of the DRS server. This is used for ids which are not `drs://` URIs.
+  pub fn url(&self) -> &Uri {
+    &self.url
+  }
+
+  /// Get the suffix appended to the DRS id of a data object to find its index. If this is not
+  /// set, the DRS id of the index is the htsget index file name, e.g. `<id>.bam.bai`.
+  pub fn index_suffix(&self) -> Option<&str> {
+    self.index_suffix.as_deref()
+  }
+
+  /// Whether to forward query headers to the DRS server.
+  pub fn forward_headers(&self) -> bool {
+    self.forward_headers
+  }
+
+  /// Get the headers that are forwarded to the DRS server. No other headers are forwarded.
+  pub fn header_allowlist(&self) -> &[String] {
+    &self.header_allowlist
+  }
+
+  /// Set the headers that are forwarded to the DRS server.
+  pub fn with_header_allowlist(mut self, header_allowlist: Vec<String>) -> Self {
+    self.header_allowlist = header_allowlist;
+    self
+  }
+
+  /// Get the headers that should not be forwarded.
+  pub fn header_blacklist(&self) -> &[String] {
+    &self.header_blacklist
+  }
+
+  /// Get how long resolved DRS objects are cached for. A ttl of zero disables the cache.
+  pub fn cache_ttl(&self) -> Duration {
+    Duration::from_secs(self.cache_ttl)
+  }
+
+  /// Set how long resolved DRS objects are cached for, in seconds.
+  pub fn with_cache_ttl(mut self, cache_ttl: u64) -> Self {
+    self.cache_ttl = cache_ttl;
+    self
+  }
+
+  /// Get the shared cache of resolved DRS objects.
+  pub fn objects(&self) -> &DrsObjectCache {
+    &self.objects
+  }
+
+  /// Get an owned client by cloning.
+  pub fn client_cloned(&self) -> Client {
+    self.client.clone()
+  }
+
+  #[cfg(feature = "experimental")]
+  /// Set the C4GH keys.
+  pub fn set_keys(mut self, keys: Option<C4GHKeys>) -> Self {
+    self.keys = keys;
+    self
+  }
+
+  #[cfg(feature = "experimental")]
+  /// Get the C4GH keys.
+  pub fn keys(&self) -> Option<&C4GHKeys> {
+    self.keys.as_ref()
+  }
+}
+
+impl ResolveCaptures for Drs {
+  fn resolve_captures(&self, captures: &Captures) -> Result<Option<Self>> {
+    let mut drs = self.clone();
+
+    if let Some(url) = Self::captured(captures, "url") {
+      drs.url = url
+        .parse()
+        .map_err(|err| HtsGetError::parse_error(format!("invalid captured url: {}", err)))?;
+    }
+
+    Ok(Some(drs))
+  }
+}
+
+/// The number of seconds that resolved DRS objects are cached for by default.
+pub(crate) fn default_cache_ttl() -> u64 {
+  60
+}
+
+/// The headers forwarded to the DRS server by default, which carry the caller's authorization.
+pub(crate) fn default_header_allowlist() -> Vec<String> {
+  vec!["Authorization".to_string()]
+}
@@ -5,11 +5,13 @@ use crate::config::data_server::DataServerConfig;
 use crate::error::Error;
 use crate::error::Error::ParseError;
 use crate::error::Result;
+use crate::resolver::ResolveCaptures;
 #[cfg(feature = "experimental")]
 use crate::storage::c4gh::C4GHKeys;
 use crate::tls::KeyPairScheme;
-use crate::types::Scheme;
+use crate::types::{HtsGetError, Scheme};
 use http::uri::Authority;
+use regex::Captures;
 use serde::{Deserialize, Serialize};
 use std::str::FromStr;
 
@@ -73,6 +75,22 @@ impl File {
   }
 }
 
+impl ResolveCaptures for File {
+  fn resolve_captures(&self, captures: &Captures) -> crate::types::Result<Option<Self>> {
+    let mut file = self.clone();
+
+    if let Some(authority) = Self::captured(captures, "authority") {
+      file.authority = Authority::from_str(authority)
+        .map_err(|err| HtsGetError::parse_error(format!("invalid captured authority: {}", err)))?;
+    }
+    if let Some(local_path) = Self::captured(captures, "local_path") {
+      file.local_path = local_path.to_string();
+    }
+
+    Ok(Some(file))
+  }
+}
+
 impl Default for File {
   fn default() -> Self {
     Self::new(Scheme::Http, default_authority(), default_path().into())
@@ -130,4 +148,17 @@ mod tests {
       },
     );
   }
+
+  #[test]
+  fn file_resolve_captures() {
+    let regex = regex::Regex::new("^(?P<local_path>.*?)/(?P<key>.*)$").unwrap();
+    let captures = regex.captures("data/key").unwrap();
+
+    let result = File::default()
+      .resolve_captures(&captures)
+      .unwrap()
+      .unwrap();
+    assert_eq!(result.local_path(), "data");
+    assert_eq!(result.authority(), &default_authority());
+  }
 }
@@ -0,0 +1,212 @@
+//! Configuration for storage on Google Cloud Storage.
+//!
+
+use crate::resolver::ResolveCaptures;
+#[cfg(feature = "experimental")]
+use crate::storage::c4gh::C4GHKeys;
+use crate::types::Result;
+use regex::Captures;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Configuration struct for Google Cloud Storage.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Gcs {
+  bucket: String,
+  endpoint: Option<String>,
+  credentials: Option<PathBuf>,
+  #[serde(deserialize_with = "deserialize_expires_in")]
+  expires_in: u64,
+  #[cfg(feature = "experimental")]
+  #[serde(skip_serializing)]
+  keys: Option<C4GHKeys>,
+}
+
+impl Gcs {
+  /// Create a new GCS storage.
+  pub fn new(bucket: String, endpoint: Option<String>, credentials: Option<PathBuf>) -> Self {
+    Self {
+      bucket,
+      endpoint,
+      credentials,
+      ..Default::default()
+    }
+  }
+
+  /// Get the bucket.
+  pub fn bucket(&self) -> &str {
+    &self.bucket
+  }
+
+  /// Set the bucket.
+  pub fn with_bucket(mut self, bucket: String) -> Self {
+    self.bucket = bucket;
+    self
+  }
+
+  /// Get the endpoint.
+  pub fn endpoint(&self) -> Option<&str> {
+    self.endpoint.as_deref()
+  }
+
+  /// Set the endpoint.
+  pub fn with_endpoint(mut self, endpoint: String) -> Self {
+    self.endpoint = Some(endpoint);
+    self
+  }
+
+  /// Get the path to the service account credentials.
+  pub fn credentials(&self) -> Option<&Path> {
+    self.credentials.as_deref()
+  }
+
+  /// Set the path to the service account credentials.
+  pub fn with_credentials(mut self, credentials: PathBuf) -> Self {
+    self.credentials = Some(credentials);
+    self
+  }
+
+  /// Get the number of seconds that signed URLs are valid for.
+  pub fn expires_in(&self) -> u64 {
+    self.expires_in
+  }
+
+  /// Set the number of seconds that signed URLs are valid for.
+  pub fn with_expires_in(mut self, expires_in: u64) -> Self {
+    self.expires_in = expires_in;
+    self
+  }
+
+  #[cfg(feature = "experimental")]
+  /// Set the C4GH keys.
+  pub fn with_keys(mut self, keys: Option<C4GHKeys>) -> Self {
+    self.keys = keys;
+    self
+  }
+
+  #[cfg(feature = "experimental")]
+  /// Get the C4GH keys.
+  pub fn keys(&self) -> Option<&C4GHKeys> {
+    self.keys.as_ref()
+  }
+}
+
+impl Default for Gcs {
+  fn default() -> Self {
+    Self {
+      bucket: Default::default(),
+      endpoint: None,
+      credentials: None,
+      expires_in: 3600,
+      #[cfg(feature = "experimental")]
+      keys: None,
+    }
+  }
+}
+
+/// The maximum number of seconds that a V4 signed URL can be valid for.
+pub const MAX_EXPIRES_IN: u64 = 604800;
+
+fn deserialize_expires_in<'de, D>(deserializer: D) -> std::result::Result<u64, D::Error>
+where
+  D: Deserializer<'de>,
+{
+  let expires_in = u64::deserialize(deserializer)?;
+  if expires_in > MAX_EXPIRES_IN {
+    return Err(Error::custom(format!(
+      "`expires_in` must be at most {MAX_EXPIRES_IN} seconds"
+    )));
+  }
+
+  Ok(expires_in)
+}
+
+impl ResolveCaptures for Gcs {
+  fn resolve_captures(&self, captures: &Captures) -> Result<Option<Self>> {
+    let mut gcs = self.clone();
+
+    if let Some(bucket) = Self::captured(captures, "bucket") {
+      gcs.bucket = bucket.to_string();
+    } else if gcs.bucket.is_empty() {
+      // An empty bucket defaults to the first capture group.
+      let Some(bucket) = captures.get(1) else {
+        return Ok(None);
+      };
+      gcs.bucket = bucket.as_str().to_string();
+    }
+
+    if let Some(endpoint) = Self::captured(captures, "endpoint") {
+      gcs.endpoint = Some(endpoint.to_string());
+    }
+
+    Ok(Some(gcs))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::config::tests::test_serialize_and_deserialize;
+  use regex::Regex;
+
+  #[test]
+  fn gcs_backend() {
+    test_serialize_and_deserialize(
+      r#"
+      bucket = "bucket"
+      endpoint = "http://127.0.0.1:4443"
+      credentials = "service-account.json"
+      expires_in = 60
+      "#,
+      (
+        "bucket".to_string(),
+        "http://127.0.0.1:4443".to_string(),
+        PathBuf::from("service-account.json"),
+        60,
+      ),
+      |result: Gcs| {
+        (
+          result.bucket().to_string(),
+          result.endpoint().unwrap().to_string(),
+          result.credentials().unwrap().to_path_buf(),
+          result.expires_in(),
+        )
+      },
+    );
+  }
+
+  #[test]
+  fn gcs_expires_in_too_long() {
+    assert!(toml::from_str::<Gcs>("expires_in = 604800").is_ok());
+    assert!(toml::from_str::<Gcs>("expires_in = 604801").is_err());
+  }
+
+  #[test]
+  fn gcs_resolve_captures() {
+    let regex = Regex::new("^(bucket)/(?P<key>.*)$").unwrap();
+    let captures = regex.captures("bucket/key").unwrap();
+
+    let result = Gcs::default().resolve_captures(&captures).unwrap().unwrap();
+    assert_eq!(result.bucket(), "bucket");
+
+    let result = Gcs::new("configured".to_string(), None, None)
+      .resolve_captures(&captures)
+      .unwrap()
+      .unwrap();
+    assert_eq!(result.bucket(), "configured");
+
+    let regex = Regex::new("^(?P<key>.*)$").unwrap();
+    let captures = regex.captures("key").unwrap();
+    let result = Gcs::default().resolve_captures(&captures).unwrap().unwrap();
+    assert_eq!(result.bucket(), "key");
+
+    let regex = Regex::new("^key$").unwrap();
+    let captures = regex.captures("key").unwrap();
+    assert!(Gcs::default()
+      .resolve_captures(&captures)
+      .unwrap()
+      .is_none());
+  }
+}
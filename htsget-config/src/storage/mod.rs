@@ -1,19 +1,43 @@
 //! Storage backends.
 //!
 
-#[cfg(any(feature = "url", feature = "aws"))]
+#[cfg(any(
+  feature = "url",
+  feature = "aws",
+  feature = "gcp",
+  feature = "azure",
+  feature = "replicas"
+))]
 use crate::error::Error;
 use crate::error::Result;
+#[cfg(feature = "azure")]
+use crate::storage::azure::Azure;
+#[cfg(feature = "experimental")]
+use crate::storage::c4gh::C4GHKeys;
+#[cfg(feature = "url")]
+use crate::storage::drs::Drs;
 use crate::storage::file::File;
+#[cfg(feature = "gcp")]
+use crate::storage::gcs::Gcs;
+#[cfg(feature = "replicas")]
+use crate::storage::replicas::Replicas;
 #[cfg(feature = "aws")]
 use crate::storage::s3::S3;
 #[cfg(feature = "url")]
 use crate::storage::url::Url;
 use serde::{Deserialize, Serialize};
 
+#[cfg(feature = "azure")]
+pub mod azure;
 #[cfg(feature = "experimental")]
 pub mod c4gh;
+#[cfg(feature = "url")]
+pub mod drs;
 pub mod file;
+#[cfg(feature = "gcp")]
+pub mod gcs;
+#[cfg(feature = "replicas")]
+pub mod replicas;
 #[cfg(feature = "aws")]
 pub mod s3;
 #[cfg(feature = "url")]
@@ -45,20 +69,79 @@ pub enum Backend {
   #[cfg(feature = "aws")]
   #[serde(alias = "s3")]
   S3(S3),
+  #[cfg(feature = "gcp")]
+  #[serde(alias = "gcs", alias = "GCS")]
+  Gcs(Gcs),
+  #[cfg(feature = "azure")]
+  #[serde(alias = "azure", alias = "AZURE")]
+  Azure(Azure),
   #[cfg(feature = "url")]
   #[serde(alias = "url", alias = "URL")]
   Url(Url),
+  #[cfg(feature = "url")]
+  #[serde(alias = "drs", alias = "DRS")]
+  Drs(Drs),
+  #[cfg(feature = "replicas")]
+  #[serde(alias = "replicas", alias = "REPLICAS")]
+  Replicas(Replicas),
 }
 
 impl Backend {
+  /// Get the C4GH keys of the backend.
+  #[cfg(feature = "experimental")]
+  pub fn keys(&self) -> Option<&C4GHKeys> {
+    match self {
+      Backend::File(file) => file.keys(),
+      #[cfg(feature = "aws")]
+      Backend::S3(s3) => s3.keys(),
+      #[cfg(feature = "gcp")]
+      Backend::Gcs(gcs) => gcs.keys(),
+      #[cfg(feature = "azure")]
+      Backend::Azure(azure) => azure.keys(),
+      #[cfg(feature = "url")]
+      Backend::Url(url) => url.keys(),
+      #[cfg(feature = "url")]
+      Backend::Drs(drs) => drs.keys(),
+      #[cfg(feature = "replicas")]
+      Backend::Replicas(replicas) => replicas.keys(),
+    }
+  }
+
+  /// Get the kind of backend, as it is named in the config.
+  pub fn kind(&self) -> &'static str {
+    match self {
+      Backend::File(_) => "File",
+      #[cfg(feature = "aws")]
+      Backend::S3(_) => "S3",
+      #[cfg(feature = "gcp")]
+      Backend::Gcs(_) => "Gcs",
+      #[cfg(feature = "azure")]
+      Backend::Azure(_) => "Azure",
+      #[cfg(feature = "url")]
+      Backend::Url(_) => "Url",
+      #[cfg(feature = "url")]
+      Backend::Drs(_) => "Drs",
+      #[cfg(feature = "replicas")]
+      Backend::Replicas(_) => "Replicas",
+    }
+  }
+
   /// Get the file variant and error if it is not `File`.
   pub fn as_file(&self) -> Result<&File> {
     match self {
       Backend::File(file) => Ok(file),
       #[cfg(feature = "aws")]
       Backend::S3(_) => Err(Error::ParseError("not a `File` variant".to_string())),
+      #[cfg(feature = "gcp")]
+      Backend::Gcs(_) => Err(Error::ParseError("not a `File` variant".to_string())),
+      #[cfg(feature = "azure")]
+      Backend::Azure(_) => Err(Error::ParseError("not a `File` variant".to_string())),
       #[cfg(feature = "url")]
       Backend::Url(_) => Err(Error::ParseError("not a `File` variant".to_string())),
+      #[cfg(feature = "url")]
+      Backend::Drs(_) => Err(Error::ParseError("not a `File` variant".to_string())),
+      #[cfg(feature = "replicas")]
+      Backend::Replicas(_) => Err(Error::ParseError("not a `File` variant".to_string())),
     }
   }
 
@@ -72,6 +155,26 @@ impl Backend {
     }
   }
 
+  /// Get the gcs variant and error if it is not `Gcs`.
+  #[cfg(feature = "gcp")]
+  pub fn as_gcs(&self) -> Result<&Gcs> {
+    if let Backend::Gcs(gcs) = self {
+      Ok(gcs)
+    } else {
+      Err(Error::ParseError("not a `Gcs` variant".to_string()))
+    }
+  }
+
+  /// Get the azure variant and error if it is not `Azure`.
+  #[cfg(feature = "azure")]
+  pub fn as_azure(&self) -> Result<&Azure> {
+    if let Backend::Azure(azure) = self {
+      Ok(azure)
+    } else {
+      Err(Error::ParseError("not a `Azure` variant".to_string()))
+    }
+  }
+
   /// Get the url variant and error if it is not `Url`.
   #[cfg(feature = "url")]
   pub fn as_url(&self) -> Result<&Url> {
@@ -81,6 +184,26 @@ impl Backend {
       Err(Error::ParseError("not a `File` variant".to_string()))
     }
   }
+
+  /// Get the drs variant and error if it is not `Drs`.
+  #[cfg(feature = "url")]
+  pub fn as_drs(&self) -> Result<&Drs> {
+    if let Backend::Drs(drs) = self {
+      Ok(drs)
+    } else {
+      Err(Error::ParseError("not a `Drs` variant".to_string()))
+    }
+  }
+
+  /// Get the replicas variant and error if it is not `Replicas`.
+  #[cfg(feature = "replicas")]
+  pub fn as_replicas(&self) -> Result<&Replicas> {
+    if let Backend::Replicas(replicas) = self {
+      Ok(replicas)
+    } else {
+      Err(Error::ParseError("not a `Replicas` variant".to_string()))
+    }
+  }
 }
 
 impl Default for Backend {
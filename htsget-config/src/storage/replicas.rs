@@ -0,0 +1,358 @@
+//! Configuration for replicated storage, which fails over between multiple backends.
+//!
+
+use crate::resolver::ResolveCaptures;
+#[cfg(feature = "experimental")]
+use crate::storage::c4gh::C4GHKeys;
+use crate::storage::Backend;
+use crate::types::Result;
+use regex::Captures;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::num::NonZeroU64;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Determines which replica is used for the tickets returned to the client.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub enum ReplicaPolicy {
+  /// Use the first healthy replica.
+  #[default]
+  PrimaryFirst,
+  /// Rotate between healthy replicas.
+  RoundRobin,
+  /// Use the healthy replica with a region matching the region header of the request, falling
+  /// back to the first healthy replica.
+  Region,
+}
+
+/// A single replica, which is a storage backend with an optional region.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Replica {
+  backend: Backend,
+  region: Option<String>,
+}
+
+impl Replica {
+  /// Create a new replica.
+  pub fn new(backend: Backend, region: Option<String>) -> Self {
+    Self { backend, region }
+  }
+
+  /// Get the storage backend.
+  pub fn backend(&self) -> &Backend {
+    &self.backend
+  }
+
+  /// Get the region.
+  pub fn region(&self) -> Option<&str> {
+    self.region.as_deref()
+  }
+}
+
+/// The health of a single replica.
+#[derive(Debug, Clone, Copy, Default)]
+struct HealthState {
+  consecutive_failures: u64,
+  unhealthy_until: Option<Instant>,
+}
+
+/// Health tracking for replicas, which is shared between requests.
+#[derive(Debug, Clone, Default)]
+pub struct ReplicaHealth {
+  states: Arc<Mutex<HashMap<usize, HealthState>>>,
+  next: Arc<AtomicUsize>,
+}
+
+impl ReplicaHealth {
+  /// Check whether the replica at the index is healthy.
+  pub fn is_healthy(&self, index: usize) -> bool {
+    self
+      .states
+      .lock()
+      .map(|states| {
+        states
+          .get(&index)
+          .and_then(|state| state.unhealthy_until)
+          .is_none_or(|until| Instant::now() >= until)
+      })
+      .unwrap_or(true)
+  }
+
+  /// Record a successful operation on the replica at the index.
+  pub fn record_success(&self, index: usize) {
+    if let Ok(mut states) = self.states.lock() {
+      states.insert(index, HealthState::default());
+    }
+  }
+
+  /// Record a failed operation on the replica at the index. The replica is marked unhealthy for
+  /// the cooldown once the number of consecutive failures reaches the threshold.
+  pub fn record_failure(&self, index: usize, failure_threshold: u64, cooldown: Duration) {
+    if let Ok(mut states) = self.states.lock() {
+      let state = states.entry(index).or_default();
+      state.consecutive_failures += 1;
+
+      if state.consecutive_failures >= failure_threshold {
+        state.unhealthy_until = Some(Instant::now() + cooldown);
+      }
+    }
+  }
+
+  /// Get the next index used for round-robin selection.
+  pub fn next_index(&self) -> usize {
+    self.next.fetch_add(1, Ordering::Relaxed)
+  }
+}
+
+/// Configuration for replicated storage.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Replicas {
+  replicas: Vec<Replica>,
+  policy: ReplicaPolicy,
+  region_header: String,
+  timeout: u64,
+  failure_threshold: NonZeroU64,
+  cooldown: u64,
+  #[serde(skip)]
+  health: ReplicaHealth,
+  #[cfg(feature = "experimental")]
+  #[serde(skip_serializing)]
+  keys: Option<C4GHKeys>,
+}
+
+impl Replicas {
+  /// Create a new replicated storage.
+  pub fn new(replicas: Vec<Replica>, policy: ReplicaPolicy) -> Self {
+    Self {
+      replicas,
+      policy,
+      ..Default::default()
+    }
+  }
+
+  /// Get the replicas.
+  pub fn replicas(&self) -> &[Replica] {
+    &self.replicas
+  }
+
+  /// Get the replica policy.
+  pub fn policy(&self) -> ReplicaPolicy {
+    self.policy
+  }
+
+  /// Get the request header used to find the region of the client.
+  pub fn region_header(&self) -> &str {
+    &self.region_header
+  }
+
+  /// Set the region header.
+  pub fn with_region_header(mut self, region_header: String) -> Self {
+    self.region_header = region_header;
+    self
+  }
+
+  /// Get the timeout of an operation on a single replica. For data requests this covers
+  /// obtaining the response stream, not reading the whole response.
+  pub fn timeout(&self) -> Duration {
+    Duration::from_secs(self.timeout)
+  }
+
+  /// Set the timeout in seconds.
+  pub fn with_timeout(mut self, timeout: u64) -> Self {
+    self.timeout = timeout;
+    self
+  }
+
+  /// Get the number of consecutive failures before a replica is marked unhealthy.
+  pub fn failure_threshold(&self) -> u64 {
+    self.failure_threshold.get()
+  }
+
+  /// Set the failure threshold.
+  pub fn with_failure_threshold(mut self, failure_threshold: NonZeroU64) -> Self {
+    self.failure_threshold = failure_threshold;
+    self
+  }
+
+  /// Get the time that an unhealthy replica is skipped for.
+  pub fn cooldown(&self) -> Duration {
+    Duration::from_secs(self.cooldown)
+  }
+
+  /// Set the cooldown in seconds.
+  pub fn with_cooldown(mut self, cooldown: u64) -> Self {
+    self.cooldown = cooldown;
+    self
+  }
+
+  /// Get the shared replica health.
+  pub fn health(&self) -> &ReplicaHealth {
+    &self.health
+  }
+
+  #[cfg(feature = "experimental")]
+  /// Set the C4GH keys.
+  pub fn set_keys(mut self, keys: Option<C4GHKeys>) -> Self {
+    self.keys = keys;
+    self
+  }
+
+  #[cfg(feature = "experimental")]
+  /// Get the C4GH keys.
+  pub fn keys(&self) -> Option<&C4GHKeys> {
+    self.keys.as_ref()
+  }
+}
+
+impl Default for Replicas {
+  fn default() -> Self {
+    Self {
+      replicas: vec![],
+      policy: Default::default(),
+      region_header: default_region_header().to_string(),
+      timeout: 10,
+      failure_threshold: NonZeroU64::new(3).expect("expected non-zero threshold"),
+      cooldown: 30,
+      health: Default::default(),
+      #[cfg(feature = "experimental")]
+      keys: None,
+    }
+  }
+}
+
+impl ResolveCaptures for Replicas {
+  fn resolve_captures(&self, captures: &Captures) -> Result<Option<Self>> {
+    let mut replicas = self.clone();
+
+    let resolved = self
+      .replicas
+      .iter()
+      .map(|replica| {
+        Ok(
+          replica
+            .backend
+            .resolve_captures(captures)?
+            .map(|backend| Replica::new(backend, replica.region.clone())),
+        )
+      })
+      .collect::<Result<Option<Vec<_>>>>()?;
+
+    Ok(resolved.map(|resolved| {
+      replicas.replicas = resolved;
+      replicas
+    }))
+  }
+}
+
+pub(crate) fn default_region_header() -> &'static str {
+  "x-htsget-region"
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::config::tests::test_serialize_and_deserialize;
+
+  #[test]
+  fn replicas_backend() {
+    test_serialize_and_deserialize(
+      r#"
+      policy = "Region"
+      region_header = "x-region"
+      timeout = 5
+
+      [[replicas]]
+      region = "primary"
+      backend.kind = "File"
+      backend.local_path = "primary"
+
+      [[replicas]]
+      region = "secondary"
+      backend.kind = "File"
+      backend.local_path = "secondary"
+      "#,
+      (
+        ReplicaPolicy::Region,
+        "x-region".to_string(),
+        5,
+        vec![
+          (Some("primary".to_string()), "primary".to_string()),
+          (Some("secondary".to_string()), "secondary".to_string()),
+        ],
+      ),
+      |result: Replicas| {
+        (
+          result.policy(),
+          result.region_header().to_string(),
+          result.timeout().as_secs(),
+          result
+            .replicas()
+            .iter()
+            .map(|replica| {
+              (
+                replica.region().map(str::to_string),
+                replica
+                  .backend()
+                  .as_file()
+                  .unwrap()
+                  .local_path()
+                  .to_string(),
+              )
+            })
+            .collect::<Vec<_>>(),
+        )
+      },
+    );
+  }
+
+  #[test]
+  fn replicas_zero_failure_threshold() {
+    let result = toml::from_str::<Replicas>(
+      r#"
+      failure_threshold = 0
+
+      [[replicas]]
+      backend.kind = "File"
+      "#,
+    );
+    assert!(result.is_err());
+  }
+
+  #[test]
+  fn replica_health() {
+    let health = ReplicaHealth::default();
+    assert!(health.is_healthy(0));
+
+    health.record_failure(0, 2, Duration::from_secs(30));
+    assert!(health.is_healthy(0));
+
+    health.record_failure(0, 2, Duration::from_secs(30));
+    assert!(!health.is_healthy(0));
+    assert!(health.clone().is_healthy(1));
+
+    health.record_success(0);
+    assert!(health.is_healthy(0));
+  }
+
+  #[test]
+  fn replica_health_cooldown() {
+    let health = ReplicaHealth::default();
+
+    health.record_failure(0, 1, Duration::ZERO);
+    assert!(health.is_healthy(0));
+  }
+
+  #[test]
+  fn replica_health_next_index() {
+    let health = ReplicaHealth::default();
+    let shared = health.clone();
+
+    assert_eq!(health.next_index(), 0);
+    assert_eq!(shared.next_index(), 1);
+  }
+}
@@ -1,17 +1,340 @@
 //! Configuration for storage on AWS S3.
 //!
 
+use crate::error::Error;
+use crate::resolver::ResolveCaptures;
 #[cfg(feature = "experimental")]
 use crate::storage::c4gh::C4GHKeys;
+use crate::types::{Query, Result, INDEX_VERSION_PARAM, VERSION_PARAM};
+use regex::Captures;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::{Debug, Formatter};
+use std::path::{Path, PathBuf};
+use std::result;
 
-/// Configuration struct for S3 storage.
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+/// The retrieval tier used when restoring archived objects.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub enum RestoreTier {
+  #[default]
+  Standard,
+  Bulk,
+  Expedited,
+}
+
+/// The restore config fields, which are validated when converting to a `Restore`.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+struct RestoreFields {
+  tier: RestoreTier,
+  days: i32,
+  retry_after: u64,
+}
+
+impl Default for RestoreFields {
+  fn default() -> Self {
+    let restore = Restore::default();
+    Self {
+      tier: restore.tier,
+      days: restore.days,
+      retry_after: restore.retry_after,
+    }
+  }
+}
+
+impl TryFrom<RestoreFields> for Restore {
+  type Error = Error;
+
+  fn try_from(fields: RestoreFields) -> result::Result<Self, Self::Error> {
+    if fields.days < 1 {
+      return Err(Error::ParseError(format!(
+        "restore days must be at least 1, got {}",
+        fields.days
+      )));
+    }
+
+    Ok(Self::new(fields.tier, fields.days, fields.retry_after))
+  }
+}
+
+/// Configuration for restoring objects in the Glacier or Deep Archive storage classes.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(try_from = "RestoreFields", deny_unknown_fields)]
+pub struct Restore {
+  tier: RestoreTier,
+  days: i32,
+  retry_after: u64,
+}
+
+impl Restore {
+  /// Create a new restore config.
+  pub fn new(tier: RestoreTier, days: i32, retry_after: u64) -> Self {
+    Self {
+      tier,
+      days,
+      retry_after,
+    }
+  }
+
+  /// Get the retrieval tier.
+  pub fn tier(&self) -> RestoreTier {
+    self.tier
+  }
+
+  /// Get the number of days that restored copies are kept for.
+  pub fn days(&self) -> i32 {
+    self.days
+  }
+
+  /// Get the number of seconds that clients are told to wait before retrying.
+  pub fn retry_after(&self) -> u64 {
+    self.retry_after
+  }
+}
+
+impl Default for Restore {
+  fn default() -> Self {
+    Self {
+      tier: Default::default(),
+      days: 1,
+      retry_after: 3600,
+    }
+  }
+}
+
+/// How the tickets for a CDN are signed.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[serde(deny_unknown_fields)]
+pub enum CdnSigning {
+  /// Add the signature to the query parameters of the ticket URL.
+  #[default]
+  Url,
+  /// Add the signature as signed cookies to the ticket headers.
+  Cookie,
+}
+
+/// Configuration for returning ticket URLs which point at a CDN, such as CloudFront, in front of
+/// the bucket. Indexes and headers are still read directly from the bucket.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(deny_unknown_fields)]
+pub struct Cdn {
+  domain: String,
+  #[serde(default)]
+  key_pair_id: Option<String>,
+  #[serde(default)]
+  private_key: Option<PathBuf>,
+  #[serde(default)]
+  signing: CdnSigning,
+}
+
+impl Cdn {
+  /// Create a new CDN config which returns unsigned URLs.
+  pub fn new(domain: String) -> Self {
+    Self {
+      domain,
+      key_pair_id: None,
+      private_key: None,
+      signing: Default::default(),
+    }
+  }
+
+  /// Sign URLs using the key pair id and the path to a PEM encoded RSA private key.
+  pub fn with_signing(
+    mut self,
+    key_pair_id: String,
+    private_key: PathBuf,
+    signing: CdnSigning,
+  ) -> Self {
+    self.key_pair_id = Some(key_pair_id);
+    self.private_key = Some(private_key);
+    self.signing = signing;
+    self
+  }
+
+  /// Get the domain of the CDN, e.g. `https://d111111abcdef8.cloudfront.net`.
+  pub fn domain(&self) -> &str {
+    &self.domain
+  }
+
+  /// Get the id of the public key used to verify signatures.
+  pub fn key_pair_id(&self) -> Option<&str> {
+    self.key_pair_id.as_deref()
+  }
+
+  /// Get the path to the private key used to sign URLs.
+  pub fn private_key(&self) -> Option<&Path> {
+    self.private_key.as_deref()
+  }
+
+  /// Get how tickets are signed.
+  pub fn signing(&self) -> CdnSigning {
+    self.signing
+  }
+}
+
+/// The S3 config fields, which are validated when converting to `S3`.
+#[derive(Deserialize)]
 #[serde(default, deny_unknown_fields)]
+struct S3Fields {
+  bucket: String,
+  endpoint: Option<String>,
+  path_style: bool,
+  restore: Option<Restore>,
+  profile: Option<String>,
+  region: Option<String>,
+  access_key_id: Option<String>,
+  secret_access_key: Option<String>,
+  session_token: Option<String>,
+  role_arn: Option<String>,
+  external_id: Option<String>,
+  expires_in: u64,
+  requester_pays: bool,
+  sse_customer_key: Option<String>,
+  versions: HashMap<String, String>,
+  version_manifest: Option<PathBuf>,
+  version_separator: Option<String>,
+  cdn: Option<Cdn>,
+  #[cfg(feature = "experimental")]
+  keys: Option<C4GHKeys>,
+}
+
+impl Default for S3Fields {
+  fn default() -> Self {
+    let S3 {
+      bucket,
+      endpoint,
+      path_style,
+      restore,
+      profile,
+      region,
+      access_key_id,
+      secret_access_key,
+      session_token,
+      role_arn,
+      external_id,
+      expires_in,
+      requester_pays,
+      sse_customer_key,
+      versions,
+      version_manifest,
+      version_separator,
+      cdn,
+      #[cfg(feature = "experimental")]
+      keys,
+    } = S3::default();
+
+    Self {
+      bucket,
+      endpoint,
+      path_style,
+      restore,
+      profile,
+      region,
+      access_key_id,
+      secret_access_key,
+      session_token,
+      role_arn,
+      external_id,
+      expires_in,
+      requester_pays,
+      sse_customer_key,
+      versions,
+      version_manifest,
+      version_separator,
+      cdn,
+      #[cfg(feature = "experimental")]
+      keys,
+    }
+  }
+}
+
+impl TryFrom<S3Fields> for S3 {
+  type Error = Error;
+
+  fn try_from(fields: S3Fields) -> result::Result<Self, Self::Error> {
+    let S3Fields {
+      bucket,
+      endpoint,
+      path_style,
+      restore,
+      profile,
+      region,
+      access_key_id,
+      secret_access_key,
+      session_token,
+      role_arn,
+      external_id,
+      expires_in,
+      requester_pays,
+      sse_customer_key,
+      versions,
+      version_manifest,
+      version_separator,
+      cdn,
+      #[cfg(feature = "experimental")]
+      keys,
+    } = fields;
+
+    // CloudFront cannot forward the SSE-C or requester pays headers to S3, so CDN tickets
+    // would fail for these objects.
+    if cdn.is_some() && (sse_customer_key.is_some() || requester_pays) {
+      return Err(Error::ParseError(
+        "a cdn cannot be used with `sse_customer_key` or `requester_pays`".to_string(),
+      ));
+    }
+
+    Ok(Self {
+      bucket,
+      endpoint,
+      path_style,
+      restore,
+      profile,
+      region,
+      access_key_id,
+      secret_access_key,
+      session_token,
+      role_arn,
+      external_id,
+      expires_in,
+      requester_pays,
+      sse_customer_key,
+      versions,
+      version_manifest,
+      version_separator,
+      cdn,
+      #[cfg(feature = "experimental")]
+      keys,
+    })
+  }
+}
+
+/// Configuration struct for S3 storage.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(try_from = "S3Fields", deny_unknown_fields)]
 pub struct S3 {
   bucket: String,
   endpoint: Option<String>,
   path_style: bool,
+  restore: Option<Restore>,
+  profile: Option<String>,
+  region: Option<String>,
+  access_key_id: Option<String>,
+  #[serde(skip_serializing)]
+  secret_access_key: Option<String>,
+  #[serde(skip_serializing)]
+  session_token: Option<String>,
+  role_arn: Option<String>,
+  external_id: Option<String>,
+  expires_in: u64,
+  requester_pays: bool,
+  #[serde(skip_serializing)]
+  sse_customer_key: Option<String>,
+  versions: HashMap<String, String>,
+  version_manifest: Option<PathBuf>,
+  version_separator: Option<String>,
+  cdn: Option<Cdn>,
   #[cfg(feature = "experimental")]
   #[serde(skip_serializing)]
   keys: Option<C4GHKeys>,
@@ -24,8 +347,7 @@ impl S3 {
       bucket,
       endpoint,
       path_style,
-      #[cfg(feature = "experimental")]
-      keys: None,
+      ..Default::default()
     }
   }
 
@@ -62,6 +384,190 @@ impl S3 {
     self
   }
 
+  /// Get the restore config for archived objects.
+  pub fn restore(&self) -> Option<&Restore> {
+    self.restore.as_ref()
+  }
+
+  /// Set the restore config.
+  pub fn with_restore(mut self, restore: Restore) -> Self {
+    self.restore = Some(restore);
+    self
+  }
+
+  /// Get the named profile used to load credentials.
+  pub fn profile(&self) -> Option<&str> {
+    self.profile.as_deref()
+  }
+
+  /// Set the named profile.
+  pub fn with_profile(mut self, profile: String) -> Self {
+    self.profile = Some(profile);
+    self
+  }
+
+  /// Get the region.
+  pub fn region(&self) -> Option<&str> {
+    self.region.as_deref()
+  }
+
+  /// Set the region.
+  pub fn with_region(mut self, region: String) -> Self {
+    self.region = Some(region);
+    self
+  }
+
+  /// Get the static access key id.
+  pub fn access_key_id(&self) -> Option<&str> {
+    self.access_key_id.as_deref()
+  }
+
+  /// Get the static secret access key.
+  pub fn secret_access_key(&self) -> Option<&str> {
+    self.secret_access_key.as_deref()
+  }
+
+  /// Get the static session token.
+  pub fn session_token(&self) -> Option<&str> {
+    self.session_token.as_deref()
+  }
+
+  /// Set static credentials, which take precedence over the profile and default credential chain.
+  pub fn with_static_credentials(
+    mut self,
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: Option<String>,
+  ) -> Self {
+    self.access_key_id = Some(access_key_id);
+    self.secret_access_key = Some(secret_access_key);
+    self.session_token = session_token;
+    self
+  }
+
+  /// Get the ARN of the role to assume.
+  pub fn role_arn(&self) -> Option<&str> {
+    self.role_arn.as_deref()
+  }
+
+  /// Get the external id used when assuming the role.
+  pub fn external_id(&self) -> Option<&str> {
+    self.external_id.as_deref()
+  }
+
+  /// Set the role to assume, with an optional external id.
+  pub fn with_role_arn(mut self, role_arn: String, external_id: Option<String>) -> Self {
+    self.role_arn = Some(role_arn);
+    self.external_id = external_id;
+    self
+  }
+
+  /// Get the number of seconds that presigned URLs are valid for.
+  pub fn expires_in(&self) -> u64 {
+    self.expires_in
+  }
+
+  /// Set the number of seconds that presigned URLs are valid for.
+  pub fn with_expires_in(mut self, expires_in: u64) -> Self {
+    self.expires_in = expires_in;
+    self
+  }
+
+  /// Whether requests are made to a requester pays bucket.
+  pub fn requester_pays(&self) -> bool {
+    self.requester_pays
+  }
+
+  /// Set whether requests are made to a requester pays bucket.
+  pub fn with_requester_pays(mut self, requester_pays: bool) -> Self {
+    self.requester_pays = requester_pays;
+    self
+  }
+
+  /// Get the base64 encoded SSE-C customer key.
+  pub fn sse_customer_key(&self) -> Option<&str> {
+    self.sse_customer_key.as_deref()
+  }
+
+  /// Set the base64 encoded SSE-C customer key.
+  pub fn with_sse_customer_key(mut self, sse_customer_key: String) -> Self {
+    self.sse_customer_key = Some(sse_customer_key);
+    self
+  }
+
+  /// Get the object versions that are pinned by key.
+  pub fn versions(&self) -> &HashMap<String, String> {
+    &self.versions
+  }
+
+  /// Pin the version of the object with the key.
+  pub fn with_version(mut self, key: String, version: String) -> Self {
+    self.versions.insert(key, version);
+    self
+  }
+
+  /// Get the path to a JSON manifest which maps keys to object versions.
+  pub fn version_manifest(&self) -> Option<&Path> {
+    self.version_manifest.as_deref()
+  }
+
+  /// Set the version manifest.
+  pub fn with_version_manifest(mut self, version_manifest: PathBuf) -> Self {
+    self.version_manifest = Some(version_manifest);
+    self
+  }
+
+  /// Get the separator between an id and a version suffix, e.g. `@` for `sample@<version>`.
+  pub fn version_separator(&self) -> Option<&str> {
+    self.version_separator.as_deref()
+  }
+
+  /// Set the version separator.
+  pub fn with_version_separator(mut self, version_separator: String) -> Self {
+    self.version_separator = Some(version_separator);
+    self
+  }
+
+  /// Get the CDN config used for tickets.
+  pub fn cdn(&self) -> Option<&Cdn> {
+    self.cdn.as_ref()
+  }
+
+  /// Set the CDN config.
+  pub fn with_cdn(mut self, cdn: Cdn) -> Self {
+    self.cdn = Some(cdn);
+    self
+  }
+
+  /// Pin the versions of the data and index objects of the query using the `version` and
+  /// `indexVersion` query parameters of the request, or a version suffix on the id if a version
+  /// separator is set. The suffix is removed from the id of the query. These versions take
+  /// precedence over other versions.
+  pub fn with_query_versions(mut self, query: &mut Query) -> Self {
+    if let Some((id, version)) = self
+      .version_separator
+      .as_deref()
+      .and_then(|separator| query.id().rsplit_once(separator))
+      .filter(|(id, version)| !id.is_empty() && !version.is_empty())
+      .map(|(id, version)| (id.to_string(), version.to_string()))
+    {
+      query.set_id(id);
+      self = self.with_version(query.format().fmt_file(query.id()), version);
+    }
+
+    let params = query.request().query();
+    let format = query.format();
+
+    if let Some(version) = params.get(VERSION_PARAM) {
+      self = self.with_version(format.fmt_file(query.id()), version.to_string());
+    }
+    if let Some(version) = params.get(INDEX_VERSION_PARAM) {
+      self = self.with_version(format.fmt_index(query.id()), version.to_string());
+    }
+
+    self
+  }
+
   #[cfg(feature = "experimental")]
   /// Set the C4GH keys.
   pub fn with_keys(mut self, keys: Option<C4GHKeys>) -> Self {
@@ -76,10 +582,83 @@ impl S3 {
   }
 }
 
+impl Default for S3 {
+  fn default() -> Self {
+    Self {
+      bucket: Default::default(),
+      endpoint: None,
+      path_style: false,
+      restore: None,
+      profile: None,
+      region: None,
+      access_key_id: None,
+      secret_access_key: None,
+      session_token: None,
+      role_arn: None,
+      external_id: None,
+      expires_in: 1000,
+      requester_pays: false,
+      sse_customer_key: None,
+      versions: Default::default(),
+      version_manifest: None,
+      version_separator: None,
+      cdn: None,
+      #[cfg(feature = "experimental")]
+      keys: None,
+    }
+  }
+}
+
+impl Debug for S3 {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    f.debug_struct("S3")
+      .field("bucket", &self.bucket)
+      .field("endpoint", &self.endpoint)
+      .field("path_style", &self.path_style)
+      .field("restore", &self.restore)
+      .field("profile", &self.profile)
+      .field("region", &self.region)
+      .field("access_key_id", &self.access_key_id)
+      .field("role_arn", &self.role_arn)
+      .field("external_id", &self.external_id)
+      .field("expires_in", &self.expires_in)
+      .field("requester_pays", &self.requester_pays)
+      .field("versions", &self.versions)
+      .field("version_manifest", &self.version_manifest)
+      .field("version_separator", &self.version_separator)
+      .field("cdn", &self.cdn)
+      .finish_non_exhaustive()
+  }
+}
+
+impl ResolveCaptures for S3 {
+  fn resolve_captures(&self, captures: &Captures) -> Result<Option<Self>> {
+    let mut s3 = self.clone();
+
+    if let Some(bucket) = Self::captured(captures, "bucket") {
+      s3.bucket = bucket.to_string();
+    } else if s3.bucket.is_empty() {
+      // An empty bucket defaults to the first capture group.
+      let Some(bucket) = captures.get(1) else {
+        return Ok(None);
+      };
+      s3.bucket = bucket.as_str().to_string();
+    }
+
+    if let Some(endpoint) = Self::captured(captures, "endpoint") {
+      s3.endpoint = Some(endpoint.to_string());
+    }
+
+    Ok(Some(s3))
+  }
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
   use crate::config::tests::test_serialize_and_deserialize;
+  use crate::types::{Format, Request};
+  use regex::Regex;
 
   #[test]
   fn s3_backend() {
@@ -99,4 +678,217 @@ mod tests {
       },
     );
   }
+
+  #[test]
+  fn s3_backend_restore() {
+    test_serialize_and_deserialize(
+      r#"
+      bucket = "bucket"
+      restore.tier = "Bulk"
+      restore.days = 7
+      "#,
+      Some(Restore::new(RestoreTier::Bulk, 7, 3600)),
+      |result: S3| result.restore,
+    );
+  }
+
+  #[test]
+  fn s3_backend_restore_invalid_days() {
+    let result = toml::from_str::<S3>(
+      r#"
+      bucket = "bucket"
+      restore.days = 0
+      "#,
+    );
+
+    assert!(result.is_err());
+  }
+
+  #[test]
+  fn s3_backend_cdn_with_sse_customer_key() {
+    let result = toml::from_str::<S3>(
+      r#"
+      bucket = "bucket"
+      sse_customer_key = "key"
+      cdn = { domain = "https://cdn.example.com" }
+      "#,
+    );
+    assert!(result.is_err());
+
+    let result = toml::from_str::<S3>(
+      r#"
+      bucket = "bucket"
+      requester_pays = true
+      cdn = { domain = "https://cdn.example.com" }
+      "#,
+    );
+    assert!(result.is_err());
+  }
+
+  #[test]
+  fn s3_backend_credentials() {
+    test_serialize_and_deserialize(
+      r#"
+      bucket = "bucket"
+      profile = "profile"
+      region = "ap-southeast-2"
+      role_arn = "arn:aws:iam::123456789012:role/htsget"
+      external_id = "external-id"
+      expires_in = 60
+      requester_pays = true
+      "#,
+      (
+        "profile".to_string(),
+        "ap-southeast-2".to_string(),
+        "arn:aws:iam::123456789012:role/htsget".to_string(),
+        "external-id".to_string(),
+        60,
+        true,
+      ),
+      |result: S3| {
+        (
+          result.profile().unwrap().to_string(),
+          result.region().unwrap().to_string(),
+          result.role_arn().unwrap().to_string(),
+          result.external_id().unwrap().to_string(),
+          result.expires_in(),
+          result.requester_pays(),
+        )
+      },
+    );
+  }
+
+  #[test]
+  fn s3_backend_versions() {
+    test_serialize_and_deserialize(
+      r#"
+      bucket = "bucket"
+      version_manifest = "manifest.json"
+      version_separator = "@"
+      versions = { "id.bam" = "version" }
+      "#,
+      (
+        Some(PathBuf::from("manifest.json")),
+        Some("@".to_string()),
+        HashMap::from([("id.bam".to_string(), "version".to_string())]),
+      ),
+      |result: S3| {
+        (
+          result.version_manifest().map(Path::to_path_buf),
+          result.version_separator().map(str::to_string),
+          result.versions().clone(),
+        )
+      },
+    );
+  }
+
+  #[test]
+  fn s3_query_versions() {
+    let request = Request::new(
+      "id".to_string(),
+      HashMap::from([
+        (VERSION_PARAM.to_string(), "data".to_string()),
+        (INDEX_VERSION_PARAM.to_string(), "index".to_string()),
+      ]),
+      Default::default(),
+    );
+    let mut query = Query::new("id", Format::Bam, request);
+
+    let result = S3::default()
+      .with_version("id.bam".to_string(), "manifest".to_string())
+      .with_query_versions(&mut query);
+    assert_eq!(
+      result.versions(),
+      &HashMap::from([
+        ("id.bam".to_string(), "data".to_string()),
+        ("id.bam.bai".to_string(), "index".to_string()),
+      ])
+    );
+  }
+
+  #[test]
+  fn s3_id_suffix_versions() {
+    let mut query = Query::new_with_default_request("id@data", Format::Bam);
+
+    let result = S3::default()
+      .with_version_separator("@".to_string())
+      .with_query_versions(&mut query);
+    assert_eq!(query.id(), "id");
+    assert_eq!(
+      result.versions(),
+      &HashMap::from([("id.bam".to_string(), "data".to_string())])
+    );
+
+    let mut query = Query::new_with_default_request("id@data", Format::Bam);
+    let result = S3::default().with_query_versions(&mut query);
+    assert_eq!(query.id(), "id@data");
+    assert!(result.versions().is_empty());
+  }
+
+  #[test]
+  fn s3_secrets_not_serialized() {
+    let s3 = S3::default()
+      .with_static_credentials(
+        "access_key_id".to_string(),
+        "secret_access_key".to_string(),
+        Some("session_token".to_string()),
+      )
+      .with_sse_customer_key("sse_customer_key".to_string());
+
+    let serialized = toml::to_string(&s3).unwrap();
+    let debug = format!("{:?}", s3);
+    for secret in ["secret_access_key", "session_token", "sse_customer_key"] {
+      assert!(!serialized.contains(secret));
+      assert!(!debug.contains(secret));
+    }
+    assert!(serialized.contains("access_key_id"));
+  }
+
+  #[test]
+  fn s3_resolve_captures() {
+    let regex = Regex::new("^(?P<bucket>.*?)/(?P<endpoint>.*?)/(?P<key>.*)$").unwrap();
+    let captures = regex.captures("bucket/127.0.0.1:8083/key").unwrap();
+
+    let result = S3::default().resolve_captures(&captures).unwrap().unwrap();
+    assert_eq!(result.bucket(), "bucket");
+    assert_eq!(result.endpoint(), Some("127.0.0.1:8083"));
+  }
+
+  #[test]
+  fn s3_resolve_captures_first_group() {
+    let regex = Regex::new("^(id)-(?P<key>.*)$").unwrap();
+    let captures = regex.captures("id-1").unwrap();
+
+    let result = S3::default().resolve_captures(&captures).unwrap().unwrap();
+    assert_eq!(result.bucket(), "id");
+    assert_eq!(result.endpoint(), None);
+
+    let result = S3::new("bucket".to_string(), None, false)
+      .resolve_captures(&captures)
+      .unwrap()
+      .unwrap();
+    assert_eq!(result.bucket(), "bucket");
+
+    let regex = Regex::new("^id-1$").unwrap();
+    let captures = regex.captures("id-1").unwrap();
+    assert!(S3::default().resolve_captures(&captures).unwrap().is_none());
+  }
+
+  #[test]
+  fn s3_backend_cdn() {
+    test_serialize_and_deserialize(
+      r#"
+      bucket = "bucket"
+      cdn = { domain = "https://cdn.example.com", key_pair_id = "K2JCJMDEHXQW5F", private_key = "private_key.pem", signing = "Cookie" }
+      "#,
+      Some(
+        Cdn::new("https://cdn.example.com".to_string()).with_signing(
+          "K2JCJMDEHXQW5F".to_string(),
+          PathBuf::from("private_key.pem"),
+          CdnSigning::Cookie,
+        ),
+      ),
+      |result: S3| result.cdn().cloned(),
+    );
+  }
 }
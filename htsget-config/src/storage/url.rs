@@ -2,11 +2,150 @@
 //!
 
 use crate::config::advanced;
+use crate::resolver::ResolveCaptures;
 #[cfg(feature = "experimental")]
 use crate::storage::c4gh::C4GHKeys;
+use crate::types::{HtsGetError, Result};
 use http::Uri;
+use regex::Captures;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::{env, fs};
+
+/// The state of the circuit breaker for a single host.
+#[derive(Debug, Clone, Copy, Default)]
+struct BreakerState {
+  consecutive_failures: u64,
+  open_until: Option<Instant>,
+}
+
+/// A per-host circuit breaker, which is shared between requests. Once a host fails too many
+/// times in a row, requests to it are rejected until the cooldown has passed.
+#[derive(Debug, Clone, Default)]
+pub struct CircuitBreaker {
+  states: Arc<Mutex<HashMap<String, BreakerState>>>,
+}
+
+impl CircuitBreaker {
+  /// Get the time remaining until the circuit for the host closes, if it is open.
+  pub fn open_for(&self, host: &str) -> Option<Duration> {
+    self
+      .states
+      .lock()
+      .ok()?
+      .get(host)
+      .and_then(|state| state.open_until)
+      .and_then(|until| until.checked_duration_since(Instant::now()))
+  }
+
+  /// Record a successful request to the host.
+  pub fn record_success(&self, host: &str) {
+    if let Ok(mut states) = self.states.lock() {
+      states.remove(host);
+    }
+  }
+
+  /// Record a failed request to the host. The circuit opens for the cooldown once the number of
+  /// consecutive failures reaches the threshold.
+  pub fn record_failure(&self, host: &str, failure_threshold: u64, cooldown: Duration) {
+    if let Ok(mut states) = self.states.lock() {
+      let state = states.entry(host.to_string()).or_default();
+      state.consecutive_failures += 1;
+
+      if state.consecutive_failures >= failure_threshold {
+        state.open_until = Some(Instant::now() + cooldown);
+      }
+    }
+  }
+}
+
+/// A secret which is read from an environment variable or a file when it is used.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase", deny_unknown_fields)]
+pub enum Secret {
+  /// Read the secret from an environment variable.
+  Env(String),
+  /// Read the secret from a file, ignoring any trailing newline.
+  File(PathBuf),
+}
+
+impl Secret {
+  /// Read the value of the secret.
+  pub fn read(&self) -> Result<String> {
+    match self {
+      Secret::Env(name) => env::var(name).map_err(|err| {
+        HtsGetError::internal_error(format!("reading secret from `{}`: {}", name, err))
+      }),
+      Secret::File(path) => fs::read_to_string(path)
+        .map(|secret| secret.trim_end_matches(['\r', '\n']).to_string())
+        .map_err(|err| {
+          HtsGetError::internal_error(format!("reading secret from `{}`: {}", path.display(), err))
+        }),
+    }
+  }
+}
+
+/// Credentials used by htsget-rs when making requests to the url server. These are never
+/// added to the tickets returned to the client.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "kind", deny_unknown_fields)]
+pub enum UrlAuth {
+  /// A static bearer token.
+  Bearer { token: Secret },
+  /// Basic authentication with a username and password.
+  Basic { username: String, password: Secret },
+  /// A bearer token fetched using the OAuth2 client credentials grant.
+  OAuth2 {
+    token_url: String,
+    client_id: String,
+    client_secret: Secret,
+    scope: Option<String>,
+  },
+}
+
+/// A cache for tokens fetched by the url storage, which is shared between requests.
+#[derive(Debug, Clone, Default)]
+pub struct TokenCache {
+  token: Arc<Mutex<Option<(String, Instant)>>>,
+}
+
+impl TokenCache {
+  /// Get the cached token if it has not expired.
+  pub fn get(&self) -> Option<String> {
+    self
+      .token
+      .lock()
+      .ok()?
+      .as_ref()
+      .filter(|(_, expires_at)| Instant::now() < *expires_at)
+      .map(|(token, _)| token.clone())
+  }
+
+  /// Cache the token until it expires.
+  pub fn set(&self, token: String, expires_at: Instant) {
+    if let Ok(mut cached) = self.token.lock() {
+      *cached = Some((token, expires_at));
+    }
+  }
+}
+
+/// Determines how the size of an object is discovered.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub enum SizeDiscovery {
+  /// Use a `HEAD` request, falling back to a ranged `GET` request if it fails or does not
+  /// return a `Content-Length`.
+  #[default]
+  HeadOrGet,
+  /// Only use a `HEAD` request.
+  Head,
+  /// Only use a `GET` request with `Range: bytes=0-0`, reading the size from `Content-Range`.
+  Get,
+}
 
 /// Remote URL server storage struct.
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -18,8 +157,24 @@ pub struct Url {
   response_url: Uri,
   forward_headers: bool,
   header_blacklist: Vec<String>,
+  header_allowlist: Option<Vec<String>>,
+  headers: HashMap<String, String>,
+  auth: Option<UrlAuth>,
+  connect_timeout: u64,
+  read_timeout: u64,
+  retries: u32,
+  retry_backoff: u64,
+  failure_threshold: u64,
+  cooldown: u64,
+  size_discovery: SizeDiscovery,
+  sizes: HashMap<String, u64>,
+  size_manifest: Option<PathBuf>,
   #[serde(skip_serializing)]
   client: Client,
+  #[serde(skip)]
+  breaker: CircuitBreaker,
+  #[serde(skip)]
+  token_cache: TokenCache,
   #[cfg(feature = "experimental")]
   #[serde(skip_serializing)]
   keys: Option<C4GHKeys>,
@@ -39,7 +194,21 @@ impl Url {
       response_url,
       forward_headers,
       header_blacklist,
+      header_allowlist: None,
+      headers: Default::default(),
+      auth: None,
+      connect_timeout: default_connect_timeout(),
+      read_timeout: default_read_timeout(),
+      retries: default_retries(),
+      retry_backoff: default_retry_backoff(),
+      failure_threshold: default_failure_threshold(),
+      cooldown: default_cooldown(),
+      size_discovery: Default::default(),
+      sizes: Default::default(),
+      size_manifest: None,
       client,
+      breaker: Default::default(),
+      token_cache: Default::default(),
       #[cfg(feature = "experimental")]
       keys: None,
     }
@@ -65,11 +234,141 @@ impl Url {
     &self.header_blacklist
   }
 
+  /// Get the headers that are allowed to be forwarded. If set, no other headers are forwarded.
+  pub fn header_allowlist(&self) -> Option<&[String]> {
+    self.header_allowlist.as_deref()
+  }
+
+  /// Set the header allowlist.
+  pub fn with_header_allowlist(mut self, header_allowlist: Option<Vec<String>>) -> Self {
+    self.header_allowlist = header_allowlist;
+    self
+  }
+
+  /// Get the templated headers which are added to requests to the url server. Values can
+  /// contain `${env.NAME}` and `${header.NAME}`, which are replaced with environment variables
+  /// and request headers.
+  pub fn headers(&self) -> &HashMap<String, String> {
+    &self.headers
+  }
+
+  /// Set the templated headers.
+  pub fn with_headers(mut self, headers: HashMap<String, String>) -> Self {
+    self.headers = headers;
+    self
+  }
+
+  /// Get the credentials used for requests to the url server.
+  pub fn auth(&self) -> Option<&UrlAuth> {
+    self.auth.as_ref()
+  }
+
+  /// Set the credentials.
+  pub fn with_auth(mut self, auth: Option<UrlAuth>) -> Self {
+    self.auth = auth;
+    self
+  }
+
+  /// Get the shared token cache.
+  pub fn token_cache(&self) -> &TokenCache {
+    &self.token_cache
+  }
+
   /// Get an owned client by cloning.
   pub fn client_cloned(&self) -> Client {
     self.client.clone()
   }
 
+  /// Get the timeout for connecting to the server.
+  pub fn connect_timeout(&self) -> Duration {
+    Duration::from_secs(self.connect_timeout)
+  }
+
+  /// Get the timeout for reading a response from the server.
+  pub fn read_timeout(&self) -> Duration {
+    Duration::from_secs(self.read_timeout)
+  }
+
+  /// Set the connect and read timeouts in seconds. These are recorded for serialization, and
+  /// should match the timeouts of the client.
+  pub fn with_timeouts(mut self, connect_timeout: u64, read_timeout: u64) -> Self {
+    self.connect_timeout = connect_timeout;
+    self.read_timeout = read_timeout;
+    self
+  }
+
+  /// Get the number of times a failed GET or HEAD request is retried.
+  pub fn retries(&self) -> u32 {
+    self.retries
+  }
+
+  /// Get the base delay between retries, which grows exponentially with added jitter.
+  pub fn retry_backoff(&self) -> Duration {
+    Duration::from_millis(self.retry_backoff)
+  }
+
+  /// Set the number of retries and the base backoff in milliseconds.
+  pub fn with_retries(mut self, retries: u32, retry_backoff: u64) -> Self {
+    self.retries = retries;
+    self.retry_backoff = retry_backoff;
+    self
+  }
+
+  /// Get the number of consecutive failures before the circuit for a host opens.
+  pub fn failure_threshold(&self) -> u64 {
+    self.failure_threshold
+  }
+
+  /// Get the time that the circuit for a host stays open.
+  pub fn cooldown(&self) -> Duration {
+    Duration::from_secs(self.cooldown)
+  }
+
+  /// Set the failure threshold and the cooldown in seconds.
+  pub fn with_circuit_breaker(mut self, failure_threshold: u64, cooldown: u64) -> Self {
+    self.failure_threshold = failure_threshold;
+    self.cooldown = cooldown;
+    self
+  }
+
+  /// Get the shared circuit breaker.
+  pub fn breaker(&self) -> &CircuitBreaker {
+    &self.breaker
+  }
+
+  /// Get the method used to discover the size of objects.
+  pub fn size_discovery(&self) -> SizeDiscovery {
+    self.size_discovery
+  }
+
+  /// Set the size discovery method.
+  pub fn with_size_discovery(mut self, size_discovery: SizeDiscovery) -> Self {
+    self.size_discovery = size_discovery;
+    self
+  }
+
+  /// Get the object sizes that are known ahead of time by key.
+  pub fn sizes(&self) -> &HashMap<String, u64> {
+    &self.sizes
+  }
+
+  /// Set the size of the object with the key, which is used instead of requesting it.
+  pub fn with_size(mut self, key: String, size: u64) -> Self {
+    self.sizes.insert(key, size);
+    self
+  }
+
+  /// Get the path to a JSON manifest which maps keys to object sizes.
+  pub fn size_manifest(&self) -> Option<&Path> {
+    self.size_manifest.as_deref()
+  }
+
+  /// Set the size manifest.
+  pub fn with_size_manifest(mut self, size_manifest: PathBuf) -> Self {
+    self.size_manifest = Some(size_manifest);
+    self
+  }
+
   #[cfg(feature = "experimental")]
   /// Set the C4GH keys.
   pub fn set_keys(mut self, keys: Option<C4GHKeys>) -> Self {
@@ -83,3 +382,140 @@ impl Url {
     self.keys.as_ref()
   }
 }
+
+impl ResolveCaptures for Url {
+  fn resolve_captures(&self, captures: &Captures) -> Result<Option<Self>> {
+    let mut url = self.clone();
+    let parse = |value: &str| {
+      value
+        .parse::<Uri>()
+        .map_err(|err| HtsGetError::parse_error(format!("invalid captured url: {}", err)))
+    };
+
+    if let Some(captured_url) = Self::captured(captures, "url") {
+      let captured_url = parse(captured_url)?;
+      // The response url defaults to the url, so it should follow a captured url.
+      if self.response_url == self.url {
+        url.response_url = captured_url.clone();
+      }
+      url.url = captured_url;
+    }
+    if let Some(response_url) = Self::captured(captures, "response_url") {
+      url.response_url = parse(response_url)?;
+    }
+
+    Ok(Some(url))
+  }
+}
+
+pub(crate) fn default_connect_timeout() -> u64 {
+  10
+}
+
+pub(crate) fn default_read_timeout() -> u64 {
+  30
+}
+
+pub(crate) fn default_retries() -> u32 {
+  3
+}
+
+pub(crate) fn default_retry_backoff() -> u64 {
+  100
+}
+
+pub(crate) fn default_failure_threshold() -> u64 {
+  5
+}
+
+pub(crate) fn default_cooldown() -> u64 {
+  30
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use regex::Regex;
+  use tempfile::TempDir;
+
+  #[test]
+  fn url_resolve_captures() {
+    let url = Url::new(
+      "https://example.com".parse().unwrap(),
+      "https://example.com".parse().unwrap(),
+      true,
+      vec![],
+      Client::new(),
+    );
+    let regex = Regex::new("^(?P<url>https://.*?)/(?P<key>.*)$").unwrap();
+    let captures = regex.captures("https://localhost:8080/key").unwrap();
+
+    let result = url.resolve_captures(&captures).unwrap().unwrap();
+    assert_eq!(result.url().to_string(), "https://localhost:8080/");
+    assert_eq!(result.response_url().to_string(), "https://localhost:8080/");
+
+    let regex =
+      Regex::new("^(?P<url>https://.*?)/(?P<response_url>https://.*?)/(?P<key>.*)$").unwrap();
+    let captures = regex
+      .captures("https://localhost:8080/https://localhost:8081/key")
+      .unwrap();
+
+    let result = url.resolve_captures(&captures).unwrap().unwrap();
+    assert_eq!(result.url().to_string(), "https://localhost:8080/");
+    assert_eq!(result.response_url().to_string(), "https://localhost:8081/");
+  }
+
+  #[test]
+  fn circuit_breaker_opens_after_threshold() {
+    let breaker = CircuitBreaker::default();
+
+    breaker.record_failure("example.com", 2, Duration::from_secs(30));
+    assert!(breaker.open_for("example.com").is_none());
+
+    breaker.record_failure("example.com", 2, Duration::from_secs(30));
+    assert!(breaker.open_for("example.com").is_some());
+    assert!(breaker.open_for("localhost").is_none());
+
+    breaker.record_success("example.com");
+    assert!(breaker.open_for("example.com").is_none());
+  }
+
+  #[test]
+  fn secret_from_env() {
+    env::set_var("HTSGET_TEST_URL_SECRET", "secret");
+
+    assert_eq!(
+      Secret::Env("HTSGET_TEST_URL_SECRET".to_string())
+        .read()
+        .unwrap(),
+      "secret"
+    );
+    assert!(Secret::Env("HTSGET_TEST_URL_SECRET_MISSING".to_string())
+      .read()
+      .is_err());
+  }
+
+  #[test]
+  fn secret_from_file() {
+    let tmp = TempDir::new().unwrap();
+    let path = tmp.path().join("secret");
+    fs::write(&path, "secret\n").unwrap();
+
+    assert_eq!(Secret::File(path).read().unwrap(), "secret");
+  }
+
+  #[test]
+  fn token_cache_expires() {
+    let cache = TokenCache::default();
+    assert_eq!(cache.get(), None);
+
+    cache.set(
+      "token".to_string(),
+      Instant::now() + Duration::from_secs(60),
+    );
+    assert_eq!(cache.get(), Some("token".to_string()));
+
+    cache.set("token".to_string(), Instant::now());
+    assert_eq!(cache.get(), None);
+  }
+}
@@ -19,6 +19,11 @@ use crate::error::Error::ParseError;
 /// The result type returning a `HtsGetError`.
 pub type Result<T> = result::Result<T, HtsGetError>;
 
+/// The query parameter used to pin the version of the data object.
+pub const VERSION_PARAM: &str = "version";
+/// The query parameter used to pin the version of the index object.
+pub const INDEX_VERSION_PARAM: &str = "indexVersion";
+
 /// An enumeration with all the possible formats.
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all(serialize = "UPPERCASE"), deny_unknown_fields)]
@@ -294,6 +299,14 @@ impl Request {
   pub fn headers(&self) -> &HeaderMap {
     &self.headers
   }
+
+  /// Set a query parameter if the value is present.
+  pub fn with_query_param(mut self, key: impl Into<String>, value: Option<String>) -> Self {
+    if let Some(value) = value {
+      self.query.insert(key.into(), value);
+    }
+    self
+  }
 }
 
 /// A query contains all the parameters that can be used when requesting
@@ -312,6 +325,8 @@ pub struct Query {
   no_tags: NoTags,
   /// The raw HTTP request information.
   request: Request,
+  /// Whether object etags are captured and checked during the search.
+  verify_etags: bool,
 }
 
 impl Query {
@@ -327,6 +342,7 @@ impl Query {
       tags: Tags::Tagged(TaggedTypeAll::All),
       no_tags: NoTags(None),
       request,
+      verify_etags: false,
     }
   }
 
@@ -341,6 +357,11 @@ impl Query {
     self.id = id.into();
   }
 
+  /// Set whether object etags are captured and checked during the search.
+  pub fn set_verify_etags(&mut self, verify_etags: bool) {
+    self.verify_etags = verify_etags;
+  }
+
   /// Set the is and return self.
   pub fn with_id(mut self, id: impl Into<String>) -> Self {
     self.set_id(id);
@@ -441,6 +462,11 @@ impl Query {
   pub fn request(&self) -> &Request {
     &self.request
   }
+
+  /// Whether object etags are captured and checked during the search.
+  pub fn verify_etags(&self) -> bool {
+    self.verify_etags
+  }
 }
 
 /// Htsget specific errors.
@@ -449,6 +475,9 @@ pub enum HtsGetError {
   #[error("not found: {0}")]
   NotFound(String),
 
+  #[error("permission denied: {0}")]
+  PermissionDenied(String),
+
   #[error("unsupported Format: {0}")]
   UnsupportedFormat(String),
 
@@ -466,6 +495,9 @@ pub enum HtsGetError {
 
   #[error("internal error: {0}")]
   InternalError(String),
+
+  #[error("unavailable: {0}")]
+  Unavailable(String, Option<u64>),
 }
 
 impl HtsGetError {
@@ -474,6 +506,11 @@ impl HtsGetError {
     Self::NotFound(message.into())
   }
 
+  /// Create a `PermissionDenied` error.
+  pub fn permission_denied<S: Into<String>>(message: S) -> Self {
+    Self::PermissionDenied(message.into())
+  }
+
   /// Create an `UnsupportedFormat` error.
   pub fn unsupported_format<S: Into<String>>(format: S) -> Self {
     Self::UnsupportedFormat(format.into())
@@ -503,6 +540,11 @@ impl HtsGetError {
   pub fn internal_error<S: Into<String>>(message: S) -> Self {
     Self::InternalError(message.into())
   }
+
+  /// Create an `Unavailable` error, with the number of seconds after which the request can be retried.
+  pub fn unavailable<S: Into<String>>(message: S, retry_after: Option<u64>) -> Self {
+    Self::Unavailable(message.into(), retry_after)
+  }
 }
 
 impl From<HtsGetError> for io::Error {
@@ -658,18 +700,78 @@ impl From<Response> for JsonResponse {
   }
 }
 
+/// The location and storage backend which served a query.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServedBy {
+  location: String,
+  backend: String,
+}
+
+impl ServedBy {
+  /// Create a new `ServedBy`.
+  pub fn new(location: impl Into<String>, backend: impl Into<String>) -> Self {
+    Self {
+      location: location.into(),
+      backend: backend.into(),
+    }
+  }
+
+  /// Get the location, which is the prefix or regex of the location config.
+  pub fn location(&self) -> &str {
+    &self.location
+  }
+
+  /// Get the kind of storage backend.
+  pub fn backend(&self) -> &str {
+    &self.backend
+  }
+}
+
 /// The response for a HtsGet query.
-#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Eq, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct Response {
   pub format: Format,
   pub urls: Vec<Url>,
+  /// The locations which served the response. This is not part of the htsget response.
+  #[serde(skip)]
+  served_by: Vec<ServedBy>,
 }
 
 impl Response {
   /// Create a new `Response`.
   pub fn new(format: Format, urls: Vec<Url>) -> Self {
-    Self { format, urls }
+    Self {
+      format,
+      urls,
+      served_by: vec![],
+    }
+  }
+
+  /// Add a location which served the response.
+  pub fn with_served_by(mut self, served_by: ServedBy) -> Self {
+    self.served_by.push(served_by);
+    self
+  }
+
+  /// Get the locations which served the response.
+  pub fn served_by(&self) -> &[ServedBy] {
+    &self.served_by
+  }
+
+  /// Merge another response into this one, appending its urls.
+  pub fn merge(&mut self, mut other: Response) {
+    self.urls.append(&mut other.urls);
+    self.served_by.append(&mut other.served_by);
+  }
+}
+
+/// Responses are equal if their htsget responses are equal, regardless of which locations
+/// served them.
+impl PartialEq for Response {
+  fn eq(&self, other: &Self) -> bool {
+    self.format == other.format && self.urls == other.urls
   }
 }
 
@@ -791,6 +893,18 @@ mod tests {
     assert!(matches!(result, HtsGetError::InternalError(message) if message == "error"));
   }
 
+  #[test]
+  fn htsget_error_permission_denied() {
+    let result = HtsGetError::permission_denied("error");
+    assert!(matches!(result, HtsGetError::PermissionDenied(message) if message == "error"));
+  }
+
+  #[test]
+  fn htsget_error_unavailable() {
+    let result = HtsGetError::unavailable("error", Some(60));
+    assert!(matches!(result, HtsGetError::Unavailable(message, Some(60)) if message == "error"));
+  }
+
   #[test]
   fn query_new() {
     let result = Query::new_with_default_request("NA12878", Format::Bam);
@@ -0,0 +1,495 @@
+//! A structured audit log of data access, written as JSON Lines.
+//!
+//! Audit records are separate from tracing, so they are not affected by the log level or
+//! formatting style. The log is installed once using [setup_audit], and requests are not audited
+//! until it is installed. Records are written on a background thread, so requests do not block
+//! on the output. In short-lived environments like Lambda, [setup_blocking_audit] writes records
+//! before the request returns instead.
+//!
+
+use std::io;
+use std::io::Write;
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+
+use chrono::{SecondsFormat, Utc};
+use http::Method;
+use serde::Serialize;
+use tracing::warn;
+use tracing_appender::non_blocking::{NonBlocking, NonBlockingBuilder, WorkerGuard};
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+
+use htsget_config::config::audit::{AuditConfig, AuditOutput, AuditRotation};
+use htsget_config::types::{Class, Fields, Format, JsonResponse, Query, Request, ServedBy, Url};
+
+use crate::{Endpoint, Result};
+
+static AUDIT_LOG: OnceLock<AuditLog> = OnceLock::new();
+
+/// Install the global audit log from the config. Nothing is installed if the output is `None`.
+/// The returned guard flushes the remaining records when it is dropped, so it should be kept
+/// until the process exits.
+pub fn setup_audit(config: &AuditConfig) -> io::Result<Option<WorkerGuard>> {
+  let Some((audit_log, guard)) = AuditLog::from_config(config)? else {
+    return Ok(None);
+  };
+  install_audit(audit_log);
+
+  Ok(Some(guard))
+}
+
+/// Install the global audit log from the config, writing and flushing each record before the
+/// request returns. This should be used in environments like Lambda, which may be frozen after
+/// an invocation before a background thread writes the records.
+pub fn setup_blocking_audit(config: &AuditConfig) -> io::Result<()> {
+  if let Some(audit_log) = AuditLog::from_config_blocking(config)? {
+    install_audit(audit_log);
+  }
+
+  Ok(())
+}
+
+fn install_audit(audit_log: AuditLog) {
+  if AUDIT_LOG.set(audit_log).is_err() {
+    warn!("the audit log is already installed");
+  }
+}
+
+/// The output of the audit log.
+enum AuditWriter {
+  NonBlocking(NonBlocking),
+  Blocking(Mutex<Box<dyn Write + Send>>),
+}
+
+/// Writes audit records as JSON Lines.
+pub struct AuditLog {
+  writer: AuditWriter,
+  principal_header: Option<String>,
+}
+
+impl AuditLog {
+  /// Create an audit log which writes to the writer on a background thread. The principal is
+  /// read from the request header if it is set. Records are never dropped, so writes wait if the
+  /// background thread falls behind. The returned guard flushes the remaining records when it is
+  /// dropped.
+  pub fn new(
+    writer: impl Write + Send + 'static,
+    principal_header: Option<String>,
+  ) -> (Self, WorkerGuard) {
+    let (writer, guard) = NonBlockingBuilder::default()
+      .lossy(false)
+      .thread_name("htsget-audit")
+      .finish(writer);
+
+    (
+      Self {
+        writer: AuditWriter::NonBlocking(writer),
+        principal_header,
+      },
+      guard,
+    )
+  }
+
+  /// Create an audit log which writes and flushes each record on the calling thread.
+  pub fn new_blocking(
+    writer: impl Write + Send + 'static,
+    principal_header: Option<String>,
+  ) -> Self {
+    Self {
+      writer: AuditWriter::Blocking(Mutex::new(Box::new(writer))),
+      principal_header,
+    }
+  }
+
+  /// Create an audit log from the config, returning `None` if the output is `None`.
+  pub fn from_config(config: &AuditConfig) -> io::Result<Option<(Self, WorkerGuard)>> {
+    let principal_header = config.principal_header().map(str::to_string);
+
+    Ok(Self::config_writer(config)?.map(|writer| Self::new(writer, principal_header)))
+  }
+
+  /// Create a blocking audit log from the config, returning `None` if the output is `None`.
+  pub fn from_config_blocking(config: &AuditConfig) -> io::Result<Option<Self>> {
+    let principal_header = config.principal_header().map(str::to_string);
+
+    Ok(Self::config_writer(config)?.map(|writer| Self::new_blocking(writer, principal_header)))
+  }
+
+  /// Create the writer of the configured output.
+  fn config_writer(config: &AuditConfig) -> io::Result<Option<Box<dyn Write + Send>>> {
+    match config.output() {
+      AuditOutput::None => Ok(None),
+      AuditOutput::Stdout => Ok(Some(Box::new(io::stdout()))),
+      AuditOutput::File => {
+        let rotation = match config.rotation() {
+          AuditRotation::Minutely => Rotation::MINUTELY,
+          AuditRotation::Hourly => Rotation::HOURLY,
+          AuditRotation::Daily => Rotation::DAILY,
+          AuditRotation::Never => Rotation::NEVER,
+        };
+
+        let mut builder = RollingFileAppender::builder()
+          .rotation(rotation)
+          .filename_prefix(config.prefix())
+          .filename_suffix("jsonl");
+        if let Some(max_files) = config.max_files() {
+          builder = builder.max_log_files(max_files.get());
+        }
+
+        let appender = builder
+          .build(config.directory())
+          .map_err(io::Error::other)?;
+
+        Ok(Some(Box::new(appender)))
+      }
+    }
+  }
+
+  /// Get the request header which contains the principal.
+  pub fn principal_header(&self) -> Option<&str> {
+    self.principal_header.as_deref()
+  }
+
+  /// Write a record as a single line. The line is written as one message, so records are not
+  /// interleaved.
+  pub fn write(&self, record: &AuditRecord) -> io::Result<()> {
+    let mut line = serde_json::to_vec(record)?;
+    line.push(b'\n');
+
+    match &self.writer {
+      AuditWriter::NonBlocking(writer) => writer.clone().write_all(&line),
+      AuditWriter::Blocking(writer) => {
+        let mut writer = writer.lock().unwrap_or_else(|err| err.into_inner());
+        writer.write_all(&line)?;
+        writer.flush()
+      }
+    }
+  }
+}
+
+/// The region and fields requested by a query.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AuditQuery {
+  format: Format,
+  class: Class,
+  reference_name: Option<String>,
+  start: Option<u32>,
+  end: Option<u32>,
+  fields: Fields,
+}
+
+impl From<&Query> for AuditQuery {
+  fn from(query: &Query) -> Self {
+    Self {
+      format: query.format(),
+      class: query.class(),
+      reference_name: query.reference_name().map(str::to_string),
+      start: query.interval().start(),
+      end: query.interval().end(),
+      fields: query.fields().clone(),
+    }
+  }
+}
+
+/// The outcome of a request.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AuditOutcome {
+  status: u16,
+  error: Option<String>,
+}
+
+/// A single audit record.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AuditRecord {
+  timestamp: String,
+  principal: Option<String>,
+  method: String,
+  endpoint: String,
+  id: String,
+  queries: Vec<AuditQuery>,
+  served_by: Vec<ServedBy>,
+  urls: usize,
+  bytes: Option<u64>,
+  outcome: AuditOutcome,
+  duration_ms: u128,
+}
+
+/// An audit record which is in progress while a request is handled.
+#[derive(Debug)]
+pub(crate) struct AuditEvent {
+  start: Instant,
+  timestamp: String,
+  principal: Option<String>,
+  method: Method,
+  endpoint: String,
+  id: String,
+  queries: Vec<AuditQuery>,
+}
+
+impl AuditEvent {
+  /// Start auditing a request, returning `None` if the audit log is not installed.
+  pub(crate) fn start(endpoint: &Endpoint, method: Method, request: &Request) -> Option<Self> {
+    AUDIT_LOG
+      .get()
+      .map(|audit_log| Self::new(endpoint, method, request, audit_log.principal_header()))
+  }
+
+  /// Create an audit event, reading the principal from the request header.
+  pub(crate) fn new(
+    endpoint: &Endpoint,
+    method: Method,
+    request: &Request,
+    principal_header: Option<&str>,
+  ) -> Self {
+    let principal = principal_header
+      .and_then(|header| request.headers().get(header))
+      .and_then(|value| value.to_str().ok())
+      .map(str::to_string);
+
+    Self {
+      start: Instant::now(),
+      timestamp: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+      principal,
+      method,
+      endpoint: endpoint.as_str().to_string(),
+      id: request.path().to_string(),
+      queries: vec![],
+    }
+  }
+
+  /// Add the queries of the request.
+  pub(crate) fn add_queries<'a>(&mut self, queries: impl IntoIterator<Item = &'a Query>) {
+    self
+      .queries
+      .extend(queries.into_iter().map(AuditQuery::from));
+  }
+
+  /// Create the audit record from the response.
+  pub(crate) fn into_record(self, response: &Result<JsonResponse>) -> AuditRecord {
+    let (served_by, urls, bytes, outcome) = match response {
+      Ok(response) => (
+        response.htsget.served_by().to_vec(),
+        response.htsget.urls.len(),
+        ticket_bytes(&response.htsget.urls),
+        AuditOutcome {
+          status: 200,
+          error: None,
+        },
+      ),
+      Err(err) => {
+        let (json, status) = err.to_json_representation();
+        (
+          vec![],
+          0,
+          None,
+          AuditOutcome {
+            status: status.as_u16(),
+            error: Some(format!("{}: {}", json.htsget.error, json.htsget.message)),
+          },
+        )
+      }
+    };
+
+    AuditRecord {
+      timestamp: self.timestamp,
+      principal: self.principal,
+      method: self.method.to_string(),
+      endpoint: self.endpoint,
+      id: self.id,
+      queries: self.queries,
+      served_by,
+      urls,
+      bytes,
+      outcome,
+      duration_ms: self.start.elapsed().as_millis(),
+    }
+  }
+
+  /// Write the audit record of the response to the global audit log.
+  pub(crate) fn finish(self, response: &Result<JsonResponse>) {
+    if let Some(audit_log) = AUDIT_LOG.get() {
+      if let Err(err) = audit_log.write(&self.into_record(response)) {
+        warn!(error = %err, "failed to write audit record");
+      }
+    }
+  }
+}
+
+/// Get the number of bytes covered by the tickets, or `None` if any ticket has an unknown size.
+fn ticket_bytes(urls: &[Url]) -> Option<u64> {
+  urls.iter().map(url_bytes).sum()
+}
+
+/// Get the number of bytes covered by a ticket, from its `Range` header or its data url.
+fn url_bytes(url: &Url) -> Option<u64> {
+  if let Some(data) = url.url.strip_prefix("data:") {
+    let (_, data) = data.split_once(";base64,")?;
+    let padding = data.bytes().rev().take_while(|byte| *byte == b'=').count();
+
+    return Some((data.len() / 4 * 3).checked_sub(padding)? as u64);
+  }
+
+  let (_, range) = url
+    .headers
+    .as_ref()?
+    .as_ref_inner()
+    .iter()
+    .find(|(name, _)| name.eq_ignore_ascii_case("range"))?;
+  let (start, end) = range.strip_prefix("bytes=")?.split_once('-')?;
+  let (start, end) = (start.parse::<u64>().ok()?, end.parse::<u64>().ok()?);
+
+  end.checked_sub(start).map(|length| length + 1)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+  use std::sync::{Arc, Mutex};
+
+  use http::{HeaderMap, HeaderValue};
+  use serde_json::{json, Value};
+
+  use htsget_config::types::{Headers, Response};
+
+  use crate::HtsGetError;
+
+  use super::*;
+
+  #[derive(Clone, Default)]
+  struct SharedWriter(Arc<Mutex<Vec<u8>>>);
+
+  impl Write for SharedWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+      self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+      Ok(())
+    }
+  }
+
+  #[test]
+  fn ticket_bytes_ranges() {
+    let urls = vec![
+      Url::new("data:;base64,QkFNAQ=="),
+      Url::new("http://localhost/data")
+        .with_headers(Headers::default().with_header("Range", "bytes=0-99")),
+      Url::new("http://localhost/data")
+        .with_headers(Headers::default().with_header("range", "bytes=100-149")),
+    ];
+
+    assert_eq!(ticket_bytes(&urls), Some(4 + 100 + 50));
+  }
+
+  #[test]
+  fn ticket_bytes_open_range() {
+    let urls = vec![
+      Url::new("http://localhost/data")
+        .with_headers(Headers::default().with_header("Range", "bytes=0-99")),
+      Url::new("http://localhost/data")
+        .with_headers(Headers::default().with_header("Range", "bytes=100-")),
+    ];
+
+    assert_eq!(ticket_bytes(&urls), None);
+  }
+
+  #[test]
+  fn write_audit_records() {
+    let writer = SharedWriter::default();
+    let (audit_log, guard) = AuditLog::new(writer.clone(), Some("X-Principal".to_string()));
+
+    let mut headers = HeaderMap::new();
+    headers.insert("X-Principal", HeaderValue::from_static("user@example.com"));
+    let request = Request::new("data/bam/id".to_string(), HashMap::new(), headers);
+
+    let mut event = AuditEvent::new(
+      &Endpoint::Reads,
+      Method::GET,
+      &request,
+      audit_log.principal_header(),
+    );
+    event.add_queries([&Query::new("data/bam/id", Format::Bam, request.clone())
+      .with_reference_name("chr1")
+      .with_start(10)
+      .with_end(20)]);
+
+    let response = Ok(JsonResponse::from(
+      Response::new(
+        Format::Bam,
+        vec![Url::new("http://localhost/data/bam/id.bam")
+          .with_headers(Headers::default().with_header("Range", "bytes=0-99"))],
+      )
+      .with_served_by(ServedBy::new("data", "File")),
+    ));
+    audit_log.write(&event.into_record(&response)).unwrap();
+
+    let event = AuditEvent::new(&Endpoint::Reads, Method::POST, &request, None);
+    audit_log
+      .write(&event.into_record(&Err(HtsGetError::NotFound("not found".to_string()))))
+      .unwrap();
+    drop(guard);
+
+    let output = String::from_utf8(writer.0.lock().unwrap().clone()).unwrap();
+    let records = output
+      .lines()
+      .map(|line| serde_json::from_str::<Value>(line).unwrap())
+      .collect::<Vec<_>>();
+    assert_eq!(records.len(), 2);
+
+    assert_eq!(records[0]["principal"], json!("user@example.com"));
+    assert_eq!(records[0]["method"], json!("GET"));
+    assert_eq!(records[0]["endpoint"], json!("reads"));
+    assert_eq!(records[0]["id"], json!("data/bam/id"));
+    assert_eq!(
+      records[0]["queries"],
+      json!([{
+        "format": "BAM",
+        "class": "body",
+        "reference_name": "chr1",
+        "start": 10,
+        "end": 20,
+        "fields": "All"
+      }])
+    );
+    assert_eq!(
+      records[0]["served_by"],
+      json!([{ "location": "data", "backend": "File" }])
+    );
+    assert_eq!(records[0]["urls"], json!(1));
+    assert_eq!(records[0]["bytes"], json!(100));
+    assert_eq!(
+      records[0]["outcome"],
+      json!({ "status": 200, "error": null })
+    );
+
+    assert_eq!(records[1]["principal"], Value::Null);
+    assert_eq!(records[1]["method"], json!("POST"));
+    assert_eq!(records[1]["bytes"], Value::Null);
+    assert_eq!(
+      records[1]["outcome"],
+      json!({ "status": 404, "error": "NotFound: not found" })
+    );
+  }
+
+  #[test]
+  fn write_blocking_audit_records() {
+    let writer = SharedWriter::default();
+    let audit_log = AuditLog::new_blocking(writer.clone(), None);
+
+    let request = Request::new("data/bam/id".to_string(), HashMap::new(), HeaderMap::new());
+    let event = AuditEvent::new(&Endpoint::Reads, Method::GET, &request, None);
+    audit_log
+      .write(&event.into_record(&Err(HtsGetError::NotFound("not found".to_string()))))
+      .unwrap();
+
+    // The record is written before the call returns, without dropping a guard.
+    let output = String::from_utf8(writer.0.lock().unwrap().clone()).unwrap();
+    let records = output
+      .lines()
+      .map(|line| serde_json::from_str::<Value>(line).unwrap())
+      .collect::<Vec<_>>();
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0]["id"], json!("data/bam/id"));
+    assert_eq!(records[0]["outcome"]["status"], json!(404));
+  }
+}
@@ -26,20 +26,22 @@ pub enum HtsGetError {
   InvalidRange(String),
   #[error("InternalError")]
   InternalError(String),
+  #[error("Unavailable")]
+  Unavailable(String, Option<u64>),
 }
 
 /// A helper struct implementing [serde's Serialize trait](Serialize) to allow
 /// easily converting HtsGetErrors to JSON
 #[derive(Serialize)]
 pub struct JsonHtsGetError {
-  error: String,
-  message: String,
+  pub(crate) error: String,
+  pub(crate) message: String,
 }
 
 /// The "htsget" container wrapping the actual error response above
 #[derive(Serialize)]
 pub struct WrappedHtsGetError {
-  htsget: JsonHtsGetError,
+  pub(crate) htsget: JsonHtsGetError,
 }
 
 impl HtsGetError {
@@ -55,6 +57,7 @@ impl HtsGetError {
       | HtsGetError::InvalidInput(err)
       | HtsGetError::InvalidRange(err) => (err, StatusCode::BAD_REQUEST),
       HtsGetError::InternalError(err) => (err, StatusCode::INTERNAL_SERVER_ERROR),
+      HtsGetError::Unavailable(err, _) => (err, StatusCode::SERVICE_UNAVAILABLE),
     };
 
     (
@@ -67,17 +70,27 @@ impl HtsGetError {
       status_code,
     )
   }
+
+  /// Get the value of the `Retry-After` header that should be returned with the error, if any.
+  pub fn retry_after(&self) -> Option<u64> {
+    match self {
+      HtsGetError::Unavailable(_, retry_after) => *retry_after,
+      _ => None,
+    }
+  }
 }
 
 impl From<HtsGetSearchError> for HtsGetError {
   fn from(error: HtsGetSearchError) -> Self {
     match error {
       HtsGetSearchError::NotFound(err) => Self::NotFound(err),
+      HtsGetSearchError::PermissionDenied(err) => Self::PermissionDenied(err),
       HtsGetSearchError::UnsupportedFormat(err) => Self::UnsupportedFormat(err),
       HtsGetSearchError::InvalidInput(err) => Self::InvalidInput(err),
       HtsGetSearchError::InvalidRange(err) => Self::InvalidRange(err),
       HtsGetSearchError::IoError(err) | HtsGetSearchError::ParseError(err) => Self::NotFound(err),
       HtsGetSearchError::InternalError(err) => Self::InternalError(err),
+      HtsGetSearchError::Unavailable(err, retry_after) => Self::Unavailable(err, retry_after),
     }
   }
 }
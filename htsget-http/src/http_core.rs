@@ -1,12 +1,17 @@
+use std::time::Instant;
+
 use futures::stream::FuturesOrdered;
 use futures::StreamExt;
+use http::Method;
 use tokio::select;
 use tracing::debug;
 use tracing::instrument;
 
-use htsget_config::types::{JsonResponse, Request, Response};
+use htsget_config::types::{JsonResponse, Query, Request, Response};
 use htsget_search::HtsGet;
 
+use crate::audit::AuditEvent;
+use crate::metrics::record_response;
 use crate::HtsGetError::InvalidInput;
 use crate::{
   convert_to_query, match_format, merge_responses, Endpoint, HtsGetError, PostRequest, Result,
@@ -21,16 +26,36 @@ pub async fn get(
   request: Request,
   endpoint: Endpoint,
 ) -> Result<JsonResponse> {
-  let format = match_format(&endpoint, request.query().get("format"))?;
-  let query = convert_to_query(request, format)?;
+  let start = Instant::now();
+  let mut audit = AuditEvent::start(&endpoint, Method::GET, &request);
+  let query = match_format(&endpoint, request.query().get("format"))
+    .and_then(|format| convert_to_query(request, format));
+  if let Some(audit) = audit.as_mut() {
+    audit.add_queries(query.iter());
+  }
+  let (format, class) = query
+    .as_ref()
+    .map(|query| (Some(query.format()), Some(query.class())))
+    .unwrap_or_default();
+
+  let response = match query {
+    Ok(query) => {
+      debug!(endpoint = ?endpoint, query = ?query, "getting GET response");
 
-  debug!(endpoint = ?endpoint, query = ?query, "getting GET response");
+      searcher
+        .search(query)
+        .await
+        .map_err(Into::into)
+        .map(JsonResponse::from)
+    }
+    Err(err) => Err(err),
+  };
 
-  searcher
-    .search(query)
-    .await
-    .map_err(Into::into)
-    .map(JsonResponse::from)
+  record_response(&endpoint, format, class, &response, start.elapsed());
+  if let Some(audit) = audit {
+    audit.finish(&response);
+  }
+  response
 }
 
 /// Gets a response in JSON for a POST request.
@@ -42,16 +67,46 @@ pub async fn post(
   request: Request,
   endpoint: Endpoint,
 ) -> Result<JsonResponse> {
-  if !request.query().is_empty() {
-    return Err(InvalidInput(
+  let start = Instant::now();
+  let mut audit = AuditEvent::start(&endpoint, Method::POST, &request);
+  let queries = if request.query().is_empty() {
+    body.get_queries(request, &endpoint)
+  } else {
+    Err(InvalidInput(
       "query parameters should be empty for a POST request".to_string(),
-    ));
+    ))
+  };
+  if let Some(audit) = audit.as_mut() {
+    audit.add_queries(queries.iter().flatten());
   }
+  let (format, class) = queries
+    .as_ref()
+    .ok()
+    .and_then(|queries| queries.first())
+    .map(|query| (Some(query.format()), Some(query.class())))
+    .unwrap_or_default();
 
-  let queries = body.get_queries(request, &endpoint)?;
+  let response = match queries {
+    Ok(queries) => {
+      debug!(endpoint = ?endpoint, queries = ?queries, "getting POST response");
 
-  debug!(endpoint = ?endpoint, queries = ?queries, "getting POST response");
+      search_all(searcher, queries).await
+    }
+    Err(err) => Err(err),
+  };
+
+  record_response(&endpoint, format, class, &response, start.elapsed());
+  if let Some(audit) = audit {
+    audit.finish(&response);
+  }
+  response
+}
 
+/// Search all the queries concurrently, merging the responses.
+async fn search_all(
+  searcher: impl HtsGet + Clone + Send + Sync + 'static,
+  queries: Vec<Query>,
+) -> Result<JsonResponse> {
   let mut futures = FuturesOrdered::new();
   for query in queries {
     let owned_searcher = searcher.clone();
@@ -11,8 +11,10 @@ use query_builder::QueryBuilder;
 pub use service_info::get_service_info_json;
 pub use service_info::{Htsget, ServiceInfo, Type};
 
+pub mod audit;
 mod error;
 mod http_core;
+pub mod metrics;
 mod post_request;
 mod query_builder;
 mod service_info;
@@ -25,6 +27,16 @@ pub enum Endpoint {
   Variants,
 }
 
+impl Endpoint {
+  /// Get the path segment of the endpoint.
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      Self::Reads => "reads",
+      Self::Variants => "variants",
+    }
+  }
+}
+
 impl FromStr for Endpoint {
   type Err = ();
 
@@ -69,8 +81,8 @@ fn convert_to_query(request: Request, format: Format) -> Result<Query> {
 }
 
 fn merge_responses(responses: Vec<Response>) -> Option<Response> {
-  responses.into_iter().reduce(|mut acc, mut response| {
-    acc.urls.append(&mut response.urls);
+  responses.into_iter().reduce(|mut acc, response| {
+    acc.merge(response);
     acc
   })
 }
@@ -182,6 +194,8 @@ mod tests {
       tags: None,
       notags: None,
       regions: None,
+      version: None,
+      index_version: None,
     };
 
     let mut expected_response_headers = Headers::default();
@@ -203,6 +217,8 @@ mod tests {
       tags: None,
       notags: None,
       regions: None,
+      version: None,
+      index_version: None,
     };
 
     assert!(matches!(
@@ -225,6 +241,8 @@ mod tests {
         start: Some(149),
         end: Some(200),
       }]),
+      version: None,
+      index_version: None,
     };
 
     let mut expected_response_headers = Headers::default();
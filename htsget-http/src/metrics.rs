@@ -0,0 +1,134 @@
+//! Prometheus metrics for htsget requests.
+//!
+//! Metrics are recorded using the [metrics] facade, which does nothing until a recorder is
+//! installed using [prometheus_handle]. Servers install the recorder when their `/metrics`
+//! endpoint is enabled.
+//!
+
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use metrics::{counter, histogram, Label};
+use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
+use tracing::warn;
+
+use htsget_config::types::{Class, Format, JsonResponse};
+
+use crate::{Endpoint, HtsGetError, Result};
+
+/// The total number of requests.
+pub const REQUESTS_TOTAL: &str = "htsget_requests_total";
+/// The duration of requests in seconds.
+pub const REQUEST_DURATION_SECONDS: &str = "htsget_request_duration_seconds";
+/// The total number of errors, by `HtsGetError` kind.
+pub const ERRORS_TOTAL: &str = "htsget_errors_total";
+
+/// Histogram buckets, in seconds, used for all durations.
+const DURATION_BUCKETS: &[f64] = &[
+  0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+static HANDLE: OnceLock<PrometheusHandle> = OnceLock::new();
+
+/// Install the global Prometheus recorder, returning a handle which renders the recorded
+/// metrics. The recorder is installed once, and later calls return the same handle.
+pub fn prometheus_handle() -> PrometheusHandle {
+  HANDLE
+    .get_or_init(|| {
+      let recorder = PrometheusBuilder::new()
+        .set_buckets(DURATION_BUCKETS)
+        .expect("expected valid buckets")
+        .build_recorder();
+      let handle = recorder.handle();
+
+      if let Err(err) = metrics::set_global_recorder(recorder) {
+        warn!(error = %err, "failed to install the metrics recorder");
+      }
+
+      handle
+    })
+    .clone()
+}
+
+/// Record a request to an endpoint. The format and class are `none` if they are not known.
+pub fn record_request(
+  endpoint: &str,
+  format: Option<Format>,
+  class: Option<Class>,
+  status: u16,
+  duration: Duration,
+) {
+  let labels = vec![
+    Label::new("endpoint", endpoint.to_string()),
+    Label::new(
+      "format",
+      format.map_or_else(|| "none".to_string(), |format| format.to_string()),
+    ),
+    Label::new(
+      "class",
+      class.map_or_else(
+        || "none".to_string(),
+        |class| format!("{:?}", class).to_lowercase(),
+      ),
+    ),
+    Label::new("status", status.to_string()),
+  ];
+
+  counter!(REQUESTS_TOTAL, labels.clone()).increment(1);
+  histogram!(REQUEST_DURATION_SECONDS, labels).record(duration.as_secs_f64());
+}
+
+/// Record an error by its kind.
+pub fn record_error(error: &HtsGetError) {
+  counter!(ERRORS_TOTAL, "kind" => error.to_string()).increment(1);
+}
+
+/// Record the response of a GET or POST request.
+pub(crate) fn record_response(
+  endpoint: &Endpoint,
+  format: Option<Format>,
+  class: Option<Class>,
+  response: &Result<JsonResponse>,
+  duration: Duration,
+) {
+  let status = match response {
+    Ok(_) => 200,
+    Err(err) => {
+      record_error(err);
+      err.to_json_representation().1.as_u16()
+    }
+  };
+
+  record_request(endpoint.as_str(), format, class, status, duration);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn render_metrics() {
+    let handle = prometheus_handle();
+
+    // Other tests may record requests concurrently, so use an endpoint that only this test uses.
+    record_request(
+      "test",
+      Some(Format::Bam),
+      Some(Class::Body),
+      200,
+      Duration::from_millis(5),
+    );
+    record_request("test", None, None, 404, Duration::from_millis(5));
+    record_error(&HtsGetError::NotFound("not found".to_string()));
+
+    let rendered = handle.render();
+    assert!(rendered.contains(
+      r#"htsget_requests_total{endpoint="test",format="BAM",class="body",status="200"} 1"#
+    ));
+    assert!(rendered.contains(
+      r#"htsget_requests_total{endpoint="test",format="none",class="none",status="404"} 1"#
+    ));
+    assert!(rendered.contains(r#"htsget_errors_total{kind="NotFound"}"#));
+    assert!(rendered.contains(r#"htsget_request_duration_seconds_bucket{endpoint="test""#));
+  }
+}
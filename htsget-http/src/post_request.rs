@@ -1,7 +1,7 @@
 use serde::{Deserialize, Serialize};
 use tracing::instrument;
 
-use htsget_config::types::{Format, Query, Request};
+use htsget_config::types::{Format, Query, Request, INDEX_VERSION_PARAM, VERSION_PARAM};
 
 use crate::{match_format, Endpoint, QueryBuilder, Result};
 
@@ -16,6 +16,9 @@ pub struct PostRequest {
   pub tags: Option<Vec<String>>,
   pub notags: Option<Vec<String>>,
   pub regions: Option<Vec<Region>>,
+  pub version: Option<String>,
+  #[serde(rename = "indexVersion")]
+  pub index_version: Option<String>,
 }
 
 /// A struct that contains the data to quest for a specific region. It is only meant to be use
@@ -33,6 +36,9 @@ impl PostRequest {
   #[instrument(level = "trace", skip_all, ret)]
   pub(crate) fn get_queries(self, request: Request, endpoint: &Endpoint) -> Result<Vec<Query>> {
     let format = match_format(endpoint, self.format.clone())?;
+    let request = request
+      .with_query_param(VERSION_PARAM, self.version.clone())
+      .with_query_param(INDEX_VERSION_PARAM, self.index_version.clone());
 
     if let Some(ref regions) = self.regions {
       regions
@@ -78,6 +84,8 @@ mod tests {
         tags: None,
         notags: None,
         regions: None,
+        version: None,
+        index_version: None,
       }
       .get_queries(request.clone(), &Endpoint::Variants)
       .unwrap(),
@@ -85,6 +93,28 @@ mod tests {
     );
   }
 
+  #[test]
+  fn post_request_with_versions() {
+    let request = Request::new_with_id("id".to_string());
+
+    let queries = PostRequest {
+      format: Some("BAM".to_string()),
+      class: None,
+      fields: None,
+      tags: None,
+      notags: None,
+      regions: None,
+      version: Some("data".to_string()),
+      index_version: Some("index".to_string()),
+    }
+    .get_queries(request, &Endpoint::Reads)
+    .unwrap();
+
+    let params = queries[0].request().query();
+    assert_eq!(params.get(VERSION_PARAM).unwrap(), "data");
+    assert_eq!(params.get(INDEX_VERSION_PARAM).unwrap(), "index");
+  }
+
   #[test]
   fn post_request_with_one_region() {
     let request = Request::new_with_id("id".to_string());
@@ -101,6 +131,8 @@ mod tests {
           start: Some(150),
           end: Some(153),
         }]),
+        version: None,
+        index_version: None,
       }
       .get_queries(request.clone(), &Endpoint::Variants)
       .unwrap(),
@@ -135,6 +167,8 @@ mod tests {
             end: Some(154),
           }
         ]),
+        version: None,
+        index_version: None,
       }
       .get_queries(request.clone(), &Endpoint::Variants)
       .unwrap(),
@@ -1,7 +1,10 @@
 use htsget_axum::server::ticket::TicketServer;
 use htsget_config::config::Config;
 use htsget_config::{command, package_info};
-use lambda_http::{run, Error};
+use htsget_http::audit::setup_blocking_audit;
+use http_body_util::{BodyExt, Full};
+use lambda_http::tower::ServiceExt;
+use lambda_http::{run, service_fn, Error, Request, Response};
 use rustls::crypto::aws_lc_rs;
 use std::env::set_var;
 use std::io;
@@ -20,7 +23,10 @@ async fn main() -> Result<(), Error> {
   if let Some(path) = Config::parse_args_with_command(command!())? {
     let mut config = Config::from_path(&path)?;
 
-    config.setup_tracing()?;
+    let tracing = config.setup_tracing_with_guard()?;
+    // Write audit records before each invocation returns, as the Lambda environment may be
+    // frozen before a background thread writes them.
+    setup_blocking_audit(config.audit())?;
 
     let service_info = config.service_info_mut();
     service_info.set_from_package_info(package_info!())?;
@@ -31,7 +37,26 @@ async fn main() -> Result<(), Error> {
     let cors = config.ticket_server().cors().clone();
     let router = TicketServer::router(config.into_locations(), service_info, cors);
 
-    run(router).await
+    let handler_tracing = tracing.clone();
+    let handler = service_fn(move |request: Request| {
+      let router = router.clone();
+      let tracing = handler_tracing.clone();
+
+      async move {
+        let (parts, body) = router.oneshot(request).await?.into_parts();
+        // Read the body so that the request span ends, and flush spans before the invocation
+        // returns, as the Lambda environment may be frozen until the next invocation.
+        let body = body.collect().await?.to_bytes();
+        tracing.force_flush().await;
+
+        Ok::<_, Error>(Response::from_parts(parts, Full::new(body)))
+      }
+    });
+
+    let result = run(handler).await;
+
+    tracing.shutdown().await;
+    result
   } else {
     Ok(())
   }
@@ -0,0 +1,113 @@
+use std::error::Error;
+use std::path::PathBuf;
+
+use clap::Parser;
+
+use htsget_config::config::Config;
+use htsget_search::prepare::Prepare;
+use htsget_search::Format;
+
+#[cfg(feature = "experimental")]
+use crypt4gh::keys::get_public_key;
+#[cfg(feature = "experimental")]
+use htsget_config::storage::c4gh::passphrase::read_private_key;
+#[cfg(feature = "experimental")]
+use htsget_search::prepare::Encryption;
+
+/// Compress, index and optionally encrypt a BAM, CRAM, VCF or BCF file so that it can be served
+/// by htsget-rs, and check that it can be searched.
+#[derive(Parser, Debug)]
+#[command(author, version, about)]
+struct Args {
+  #[arg(help = "The input BAM, CRAM, VCF or BCF file")]
+  input: PathBuf,
+  #[arg(
+    short,
+    long,
+    default_value = ".",
+    help = "The directory to write to, which should be the local path of a location"
+  )]
+  output: PathBuf,
+  #[arg(
+    long,
+    help = "The id of the output files, defaults to the input file name"
+  )]
+  id: Option<String>,
+  #[arg(
+    short,
+    long,
+    value_parser = parse_format,
+    help = "The format of the input, one of BAM, CRAM, VCF or BCF, defaults to the input extension"
+  )]
+  format: Option<Format>,
+  #[cfg(feature = "experimental")]
+  #[arg(
+    long,
+    help = "Encrypt the output using Crypt4GH with this private key, which htsget-rs should use"
+  )]
+  private_key: Option<PathBuf>,
+  #[cfg(feature = "experimental")]
+  #[arg(
+    long = "recipient",
+    requires = "private_key",
+    help = "An additional public key to encrypt the output for, can be repeated"
+  )]
+  recipients: Vec<PathBuf>,
+  #[cfg(feature = "experimental")]
+  #[arg(
+    long,
+    env = "C4GH_PASSPHRASE",
+    hide_env_values = true,
+    help = "The passphrase of the private key"
+  )]
+  passphrase: Option<String>,
+  #[cfg(feature = "experimental")]
+  #[arg(long, requires = "private_key", help = "Also encrypt the index files")]
+  encrypt_indexes: bool,
+}
+
+fn parse_format(format: &str) -> Result<Format, String> {
+  match format.to_lowercase().as_str() {
+    "bam" => Ok(Format::Bam),
+    "cram" => Ok(Format::Cram),
+    "vcf" => Ok(Format::Vcf),
+    "bcf" => Ok(Format::Bcf),
+    _ => Err(format!("unsupported format `{}`", format)),
+  }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+  let args = Args::parse();
+
+  let tracing = Config::default().setup_tracing_with_guard()?;
+
+  let mut prepare = Prepare::new(args.input, args.output)?;
+  if let Some(id) = args.id {
+    prepare = prepare.with_id(id);
+  }
+  if let Some(format) = args.format {
+    prepare = prepare.with_format(format);
+  }
+
+  #[cfg(feature = "experimental")]
+  if let Some(private_key) = args.private_key {
+    let recipients = args
+      .recipients
+      .into_iter()
+      .map(get_public_key)
+      .collect::<Result<Vec<_>, _>>()?;
+
+    prepare = prepare.with_encryption(Some(
+      Encryption::new(read_private_key(private_key, args.passphrase)?, recipients)?
+        .with_encrypt_indexes(args.encrypt_indexes),
+    ));
+  }
+
+  for file in prepare.run().await? {
+    println!("{}", file.display());
+  }
+
+  tracing.shutdown().await;
+  Ok(())
+}
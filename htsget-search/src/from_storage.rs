@@ -14,7 +14,10 @@ use async_trait::async_trait;
 use htsget_config::config::location::Locations;
 use htsget_config::resolver::{ResolveResponse, StorageResolver};
 use htsget_config::storage;
-use htsget_storage::Storage;
+use htsget_config::storage::Backend;
+use htsget_storage::error::StorageError;
+use htsget_storage::types::HeadOptions;
+use htsget_storage::{Storage, StorageTrait};
 use tracing::debug;
 use tracing::instrument;
 
@@ -58,7 +61,25 @@ impl ResolveResponse for HtsGetFromStorage {
 
   #[cfg(feature = "aws")]
   async fn from_s3(s3_storage: &storage::s3::S3, query: &Query) -> Result<Response> {
-    let storage = Storage::from_s3(s3_storage).await;
+    let mut query = query.clone();
+    let storage = Storage::from_s3(&s3_storage.clone().with_query_versions(&mut query)).await;
+    let searcher = HtsGetFromStorage::new(storage?);
+    searcher.search(query).await
+  }
+
+  #[cfg(feature = "gcp")]
+  async fn from_gcs(gcs_storage: &storage::gcs::Gcs, query: &Query) -> Result<Response> {
+    let storage = Storage::from_gcs(gcs_storage).await;
+    let searcher = HtsGetFromStorage::new(storage?);
+    searcher.search(query.clone()).await
+  }
+
+  #[cfg(feature = "azure")]
+  async fn from_azure(
+    azure_storage_config: &storage::azure::Azure,
+    query: &Query,
+  ) -> Result<Response> {
+    let storage = Storage::from_azure(azure_storage_config).await;
     let searcher = HtsGetFromStorage::new(storage?);
     searcher.search(query.clone()).await
   }
@@ -69,6 +90,52 @@ impl ResolveResponse for HtsGetFromStorage {
     let searcher = HtsGetFromStorage::new(storage?);
     searcher.search(query.clone()).await
   }
+
+  #[cfg(feature = "url")]
+  async fn from_drs(drs_storage_config: &storage::drs::Drs, query: &Query) -> Result<Response> {
+    let storage = Storage::from_drs(drs_storage_config).await;
+    let searcher = HtsGetFromStorage::new(storage?);
+    searcher.search(query.clone()).await
+  }
+
+  #[cfg(feature = "replicas")]
+  async fn from_replicas(
+    replicas_storage_config: &storage::replicas::Replicas,
+    query: &Query,
+  ) -> Result<Response> {
+    let storage = Storage::from_replicas(replicas_storage_config).await;
+    let searcher = HtsGetFromStorage::new(storage?);
+    searcher.search(query.clone()).await
+  }
+
+  async fn from_existing(backend: &Backend, query: &Query) -> Result<Option<Response>> {
+    let mut query = query.clone();
+    let storage = Self::storage_for(backend, &mut query).await?;
+    let format = query.format();
+
+    for key in [format.fmt_file(query.id()), format.fmt_index(query.id())] {
+      let options = HeadOptions::new(query.request().headers());
+      match storage.exists(&key, options).await {
+        Ok(true) => {}
+        Ok(false) => {
+          debug!(key, "key does not exist in storage backend");
+          return Ok(None);
+        }
+        // Some backends deny access rather than report a missing key, e.g. S3 without
+        // `s3:ListBucket` permissions.
+        Err(StorageError::PermissionDenied(err)) => {
+          debug!(key, %err, "access denied to key in storage backend");
+          return Ok(None);
+        }
+        Err(err) => return Err(err.into()),
+      }
+    }
+
+    HtsGetFromStorage::new(storage)
+      .search(query)
+      .await
+      .map(Some)
+  }
 }
 
 impl HtsGetFromStorage {
@@ -83,6 +150,16 @@ impl HtsGetFromStorage {
   pub fn into_inner(self) -> Storage {
     self.storage
   }
+
+  /// Create the storage for a backend, applying any object versions requested by the query.
+  #[cfg_attr(not(feature = "aws"), allow(unused_variables))]
+  async fn storage_for(backend: &Backend, query: &mut Query) -> Result<Storage> {
+    match backend {
+      #[cfg(feature = "aws")]
+      Backend::S3(s3) => Ok(Storage::from_s3(&s3.clone().with_query_versions(query)).await?),
+      backend => Ok(Storage::from_backend(backend).await?),
+    }
+  }
 }
 
 #[cfg(test)]
@@ -95,7 +172,7 @@ pub(crate) mod tests {
     htsget_storage::s3::S3Storage, htsget_test::aws_mocks::with_s3_test_server, std::fs::create_dir,
   };
 
-  use htsget_config::config::location::{Location, LocationEither};
+  use htsget_config::config::location::{Location, LocationEither, ResolutionPolicy};
   use htsget_config::storage;
   use htsget_config::storage::Backend;
   use htsget_config::types::Class::Body;
@@ -204,6 +281,42 @@ pub(crate) mod tests {
     .await;
   }
 
+  #[tokio::test]
+  async fn search_resolvers_first_existing() {
+    with_config_local_storage(
+      |_, local_storage| async {
+        let empty_dir = TempDir::new().unwrap();
+        let empty_storage = storage::file::File::new(
+          Http,
+          Authority::from_static("127.0.0.1:8081"),
+          empty_dir.path().to_str().unwrap().to_string(),
+        );
+
+        let locations = Locations::new(vec![
+          LocationEither::Simple(
+            Location::new(Backend::File(empty_storage), "".to_string())
+              .with_resolution_policy(ResolutionPolicy::FirstExisting),
+          ),
+          LocationEither::Simple(Location::new(Backend::File(local_storage), "".to_string())),
+        ]);
+
+        let filename = "spec-v4.3";
+        let query = Query::new_with_default_request(filename, Format::Vcf);
+        let response = locations.search(query).await;
+
+        assert_eq!(response, expected_vcf_response(filename));
+
+        Some((
+          VCF_FILE_NAME_SPEC.to_string(),
+          (response.unwrap(), Body).into(),
+        ))
+      },
+      "data/vcf",
+      &[],
+    )
+    .await;
+  }
+
   fn expected_vcf_response(filename: &str) -> Result<Response> {
     Ok(Response::new(
       Format::Vcf,
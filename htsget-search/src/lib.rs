@@ -10,7 +10,14 @@ pub use htsget_config::types::{
 };
 pub use htsget_storage::Storage;
 
+#[cfg(feature = "experimental")]
+pub use htsget_storage::c4gh::storage::{
+  sign_decrypt_ticket, verify_decrypt_ticket, C4GHStorage, DECRYPT_EXPIRES_PARAM,
+  DECRYPT_KEYS_PARAM, DECRYPT_PATH, DECRYPT_SIGNATURE_PARAM,
+};
 pub use htsget_storage::local::FileStorage;
+#[cfg(feature = "experimental")]
+pub use htsget_storage::types::{BytesPosition, GetOptions};
 
 use std::fmt::Display;
 use std::str::FromStr;
@@ -22,6 +29,8 @@ pub mod bam_search;
 pub mod bcf_search;
 pub mod cram_search;
 pub mod from_storage;
+#[cfg(feature = "prepare")]
+pub mod prepare;
 pub mod search;
 pub mod vcf_search;
 
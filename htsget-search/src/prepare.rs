@@ -0,0 +1,550 @@
+//! Prepare BAM, CRAM, VCF and BCF files so that they can be served by htsget-rs. This compresses
+//! and indexes files, optionally encrypts them using Crypt4GH, and then checks that they can be
+//! searched.
+//!
+
+use std::fs;
+use std::io;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use htsget_config::resolver::ResolveResponse;
+use htsget_config::storage::file::File;
+use htsget_config::types::Class;
+use noodles::bam::bai;
+use noodles::cram::crai;
+use noodles::csi::binning_index::index::reference_sequence::bin::Chunk;
+use noodles::csi::binning_index::Indexer;
+use noodles::sam::alignment::Record as _;
+use noodles::sam::header::record::value::map::header::{sort_order, tag};
+use noodles::vcf::variant::Record as _;
+use noodles::{bam, bcf, bgzf, cram, csi, tabix, vcf};
+use tokio::task::spawn_blocking;
+use tracing::info;
+
+#[cfg(feature = "experimental")]
+use crypt4gh::Keys;
+#[cfg(feature = "experimental")]
+use htsget_config::storage::c4gh::{C4GHKeySet, C4GHKeys};
+#[cfg(feature = "experimental")]
+use std::collections::HashSet;
+#[cfg(feature = "experimental")]
+use std::fmt;
+#[cfg(feature = "experimental")]
+use std::fmt::{Debug, Formatter};
+
+use crate::from_storage::HtsGetFromStorage;
+use crate::{ConcurrencyError, Format, HtsGetError, Query, Result};
+
+/// The size of the fixed BGZF block header, up to and including the block size field.
+const BGZF_HEADER_SIZE: usize = 18;
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// The keys used to encrypt prepared files.
+#[cfg(feature = "experimental")]
+#[derive(Clone)]
+pub struct Encryption {
+  private_key: Vec<u8>,
+  public_key: Vec<u8>,
+  recipient_public_keys: Vec<Vec<u8>>,
+  encrypt_indexes: bool,
+}
+
+#[cfg(feature = "experimental")]
+impl Debug for Encryption {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    f.debug_struct("Encryption")
+      .field("recipient_public_keys", &self.recipient_public_keys.len())
+      .field("encrypt_indexes", &self.encrypt_indexes)
+      .finish_non_exhaustive()
+  }
+}
+
+#[cfg(feature = "experimental")]
+impl Encryption {
+  /// Encrypt files using the private key, for the recipients. The files are also encrypted for
+  /// the public key of the private key, so that htsget-rs can decrypt them using the private key.
+  pub fn new(private_key: Vec<u8>, recipient_public_keys: Vec<Vec<u8>>) -> Result<Self> {
+    let public_key = crypt4gh::keys::get_public_key_from_private_key(&private_key)
+      .map_err(|err| HtsGetError::parse_error(format!("invalid C4GH private key: {}", err)))?;
+
+    Ok(Self {
+      private_key,
+      public_key,
+      recipient_public_keys,
+      encrypt_indexes: false,
+    })
+  }
+
+  /// Also encrypt the index files.
+  pub fn with_encrypt_indexes(mut self, encrypt_indexes: bool) -> Self {
+    self.encrypt_indexes = encrypt_indexes;
+    self
+  }
+
+  /// Get the keys that htsget-rs uses to read the encrypted files.
+  pub fn keys(&self) -> C4GHKeys {
+    let private_key = self.private_key.clone();
+    let public_key = self.public_key.clone();
+
+    C4GHKeys::from_join_handle(tokio::spawn(async move {
+      C4GHKeySet::new(vec![private_key], vec![public_key])
+    }))
+    .with_encrypted_indexes(self.encrypt_indexes)
+  }
+
+  /// Encrypt the file, writing it with a `.c4gh` suffix and removing the plaintext file.
+  pub fn encrypt_file(&self, path: &Path) -> Result<PathBuf> {
+    let mut encrypted = path.as_os_str().to_owned();
+    encrypted.push(".c4gh");
+    let encrypted = PathBuf::from(encrypted);
+
+    let keys = self
+      .recipient_public_keys
+      .iter()
+      .chain([&self.public_key])
+      .map(|public_key| Keys {
+        method: 0,
+        privkey: self.private_key.clone(),
+        recipient_pubkey: public_key.clone(),
+      })
+      .collect::<HashSet<_>>();
+
+    let mut writer = BufWriter::new(fs::File::create(&encrypted)?);
+    crypt4gh::encrypt(
+      &keys,
+      &mut BufReader::new(fs::File::open(path)?),
+      &mut writer,
+      0,
+      None,
+    )
+    .map_err(|err| {
+      HtsGetError::internal_error(format!("failed to encrypt `{}`: {}", path.display(), err))
+    })?;
+    writer.flush()?;
+
+    fs::remove_file(path)?;
+
+    Ok(encrypted)
+  }
+}
+
+/// Prepares a file so that it can be served by a location with the output directory as its
+/// local path.
+#[derive(Debug, Clone)]
+pub struct Prepare {
+  input: PathBuf,
+  output: PathBuf,
+  id: String,
+  format: Format,
+  #[cfg(feature = "experimental")]
+  encryption: Option<Encryption>,
+}
+
+impl Prepare {
+  /// Prepare the input file, writing to the output directory. The id and format are derived from
+  /// the input file name.
+  pub fn new(input: PathBuf, output: PathBuf) -> Result<Self> {
+    let (id, format) = input
+      .file_name()
+      .and_then(|name| Self::id_and_format(&name.to_string_lossy()))
+      .ok_or_else(|| {
+        HtsGetError::unsupported_format(format!(
+          "cannot determine the format of `{}`",
+          input.display()
+        ))
+      })?;
+
+    Ok(Self {
+      input,
+      output,
+      id,
+      format,
+      #[cfg(feature = "experimental")]
+      encryption: None,
+    })
+  }
+
+  /// Get the id and format from a file name.
+  pub fn id_and_format(name: &str) -> Option<(String, Format)> {
+    [
+      (".vcf.gz", Format::Vcf),
+      (".vcf", Format::Vcf),
+      (".bam", Format::Bam),
+      (".cram", Format::Cram),
+      (".bcf", Format::Bcf),
+    ]
+    .into_iter()
+    .find_map(|(ending, format)| {
+      name
+        .strip_suffix(ending)
+        .filter(|id| !id.is_empty())
+        .map(|id| (id.to_string(), format))
+    })
+  }
+
+  /// Set the id, which the file is written under in the output directory.
+  pub fn with_id(mut self, id: String) -> Self {
+    self.id = id;
+    self
+  }
+
+  /// Set the format of the input.
+  pub fn with_format(mut self, format: Format) -> Self {
+    self.format = format;
+    self
+  }
+
+  /// Encrypt the prepared files using Crypt4GH.
+  #[cfg(feature = "experimental")]
+  pub fn with_encryption(mut self, encryption: Option<Encryption>) -> Self {
+    self.encryption = encryption;
+    self
+  }
+
+  /// Write the prepared files and check that they can be searched, returning the written files.
+  pub async fn run(self) -> Result<Vec<PathBuf>> {
+    let prepare = self.clone();
+    let files = spawn_blocking(move || prepare.write_files())
+      .await
+      .map_err(ConcurrencyError::new)??;
+
+    self.self_test().await?;
+
+    Ok(files)
+  }
+
+  /// Write the data, index and GZI files.
+  pub fn write_files(&self) -> Result<Vec<PathBuf>> {
+    if self.format == Format::Bam {
+      Self::check_bam_sorted(&self.input)?;
+    }
+
+    fs::create_dir_all(&self.output)?;
+
+    let data = self.output.join(self.format.fmt_file(&self.id));
+    self.write_data(&data)?;
+    info!(data = %data.display(), "wrote data file");
+
+    let index = self.output.join(self.format.fmt_index(&self.id));
+    Self::write_index(self.format, &data, &index)?;
+    info!(index = %index.display(), "wrote index file");
+
+    let mut indexes = vec![index];
+    if let Ok(gzi) = self.format.fmt_gzi(&self.id) {
+      let gzi = self.output.join(gzi);
+      Self::write_gzi(&data, &gzi)?;
+      info!(gzi = %gzi.display(), "wrote GZI file");
+
+      indexes.push(gzi);
+    }
+
+    #[cfg(feature = "experimental")]
+    if let Some(encryption) = &self.encryption {
+      let data = encryption.encrypt_file(&data)?;
+      let indexes = if encryption.encrypt_indexes {
+        indexes
+          .iter()
+          .map(|index| encryption.encrypt_file(index))
+          .collect::<Result<Vec<_>>>()?
+      } else {
+        indexes
+      };
+      info!(data = %data.display(), "encrypted files");
+
+      return Ok([vec![data], indexes].concat());
+    }
+
+    Ok([vec![data], indexes].concat())
+  }
+
+  /// Check whether the file starts with the gzip magic bytes.
+  fn is_gzip(path: &Path) -> Result<bool> {
+    let mut magic = [0; 2];
+    match fs::File::open(path)?.read_exact(&mut magic) {
+      Ok(_) => Ok(magic == GZIP_MAGIC),
+      Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+      Err(err) => Err(err.into()),
+    }
+  }
+
+  /// Write the data file, compressing uncompressed VCF files using BGZF.
+  fn write_data(&self, data: &Path) -> Result<()> {
+    if self.format == Format::Vcf && !Self::is_gzip(&self.input)? {
+      let mut writer = bgzf::Writer::new(fs::File::create(data)?);
+      io::copy(
+        &mut BufReader::new(fs::File::open(&self.input)?),
+        &mut writer,
+      )?;
+      writer.finish()?;
+    } else if fs::canonicalize(&self.input)? != fs::canonicalize(data).unwrap_or_default() {
+      fs::copy(&self.input, data)?;
+    }
+
+    Ok(())
+  }
+
+  /// Index the data file, writing the index.
+  fn write_index(format: Format, data: &Path, index: &Path) -> Result<()> {
+    match format {
+      Format::Bam => bai::write(index, &Self::index_bam(data)?)?,
+      Format::Cram => crai::write(index, &cram::index(data)?)?,
+      Format::Vcf => tabix::write(index, &vcf::index(data)?)?,
+      Format::Bcf => csi::write(index, &Self::index_bcf(data)?)?,
+    }
+
+    Ok(())
+  }
+
+  /// Check that the BAM header declares `SO:coordinate`, which is required to build a BAI index.
+  fn check_bam_sorted(data: &Path) -> Result<()> {
+    let header = bam::io::Reader::new(fs::File::open(data)?).read_header()?;
+    let sort_order = header
+      .header()
+      .and_then(|header| header.other_fields().get(&tag::SORT_ORDER));
+
+    match sort_order {
+      Some(sort_order) if sort_order == sort_order::COORDINATE => Ok(()),
+      _ => Err(HtsGetError::invalid_input(format!(
+        "`{}` is not coordinate sorted, expected `SO:coordinate` in the header",
+        data.display()
+      ))),
+    }
+  }
+
+  /// Build a BAI index from a coordinate sorted BAM file.
+  fn index_bam(data: &Path) -> io::Result<bai::Index> {
+    let mut reader = bam::io::Reader::new(fs::File::open(data)?);
+    let header = reader.read_header()?;
+
+    let mut indexer = Indexer::default();
+    let mut record = bam::Record::default();
+    let mut start_position = reader.get_ref().virtual_position();
+
+    while reader.read_record(&mut record)? != 0 {
+      let end_position = reader.get_ref().virtual_position();
+
+      let context = match (
+        record.reference_sequence_id().transpose()?,
+        record.alignment_start().transpose()?,
+        record.alignment_end().transpose()?,
+      ) {
+        (Some(id), Some(start), Some(end)) => Some((id, start, end, !record.flags().is_unmapped())),
+        _ => None,
+      };
+
+      indexer.add_record(context, Chunk::new(start_position, end_position))?;
+      start_position = end_position;
+    }
+
+    Ok(indexer.build(header.reference_sequences().len()))
+  }
+
+  /// Build a CSI index from a BCF file.
+  fn index_bcf(data: &Path) -> io::Result<csi::Index> {
+    let mut reader = bcf::io::Reader::new(fs::File::open(data)?);
+    let header = reader.read_header()?;
+
+    let mut indexer = Indexer::default();
+    let mut record = bcf::Record::default();
+    let mut start_position = reader.get_ref().virtual_position();
+
+    while reader.read_record(&mut record)? != 0 {
+      let end_position = reader.get_ref().virtual_position();
+
+      let start = record
+        .variant_start()
+        .transpose()?
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing position"))?;
+      let end = record.variant_end(&header)?;
+      let context = Some((record.reference_sequence_id()?, start, end, true));
+
+      indexer.add_record(context, Chunk::new(start_position, end_position))?;
+      start_position = end_position;
+    }
+
+    Ok(indexer.build(header.contigs().len()))
+  }
+
+  /// Write a GZI index of the BGZF data file. This records the compressed and uncompressed
+  /// offsets at the start of each non-empty block, after the first, matching `bgzip --reindex`.
+  fn write_gzi(data: &Path, gzi: &Path) -> Result<()> {
+    let mut reader = BufReader::new(fs::File::open(data)?);
+    let size = reader.get_ref().metadata()?.len();
+
+    let mut entries = vec![];
+    let (mut compressed, mut uncompressed) = (0, 0);
+    while compressed < size {
+      let mut header = [0; BGZF_HEADER_SIZE];
+      reader.read_exact(&mut header)?;
+      if header[..4] != [GZIP_MAGIC[0], GZIP_MAGIC[1], 8, 4] || &header[12..14] != b"BC" {
+        return Err(HtsGetError::parse_error(format!(
+          "`{}` is not BGZF compressed",
+          data.display()
+        )));
+      }
+
+      let block_size = u64::from(u16::from_le_bytes([header[16], header[17]])) + 1;
+      reader.seek(SeekFrom::Start(compressed + block_size - 4))?;
+      let mut block_uncompressed = [0; 4];
+      reader.read_exact(&mut block_uncompressed)?;
+      let block_uncompressed = u64::from(u32::from_le_bytes(block_uncompressed));
+
+      if compressed != 0 && block_uncompressed != 0 {
+        entries.push((compressed, uncompressed));
+      }
+      compressed += block_size;
+      uncompressed += block_uncompressed;
+    }
+
+    let mut writer = BufWriter::new(fs::File::create(gzi)?);
+    writer.write_all(&(entries.len() as u64).to_le_bytes())?;
+    for (compressed, uncompressed) in entries {
+      writer.write_all(&compressed.to_le_bytes())?;
+      writer.write_all(&uncompressed.to_le_bytes())?;
+    }
+    writer.flush()?;
+
+    Ok(())
+  }
+
+  /// Check that the header and the whole file can be searched using the output directory.
+  pub async fn self_test(&self) -> Result<()> {
+    let file = File::default().set_local_path(self.output.to_string_lossy().to_string());
+    #[cfg(feature = "experimental")]
+    let file = file.set_keys(self.encryption.as_ref().map(Encryption::keys));
+
+    for class in [Class::Header, Class::Body] {
+      let query = Query::new_with_default_request(&self.id, self.format).with_class(class);
+      HtsGetFromStorage::from_file(&file, &query).await?;
+    }
+    info!(id = %self.id, format = %self.format, "self-test passed");
+
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use htsget_test::util::default_dir;
+  use tempfile::TempDir;
+
+  #[test]
+  fn id_and_format() {
+    assert_eq!(
+      Prepare::id_and_format("sample.vcf.gz"),
+      Some(("sample".to_string(), Format::Vcf))
+    );
+    assert_eq!(
+      Prepare::id_and_format("sample.vcf"),
+      Some(("sample".to_string(), Format::Vcf))
+    );
+    assert_eq!(
+      Prepare::id_and_format("sample.bam"),
+      Some(("sample".to_string(), Format::Bam))
+    );
+    assert_eq!(Prepare::id_and_format("sample.txt"), None);
+    assert_eq!(Prepare::id_and_format(".bam"), None);
+  }
+
+  #[tokio::test]
+  async fn prepare_bam() {
+    let tmp = TempDir::new().unwrap();
+    let files = Prepare::new(
+      default_dir().join("data/bam/htsnexus_test_NA12878.bam"),
+      tmp.path().to_path_buf(),
+    )
+    .unwrap()
+    .run()
+    .await
+    .unwrap();
+
+    assert_eq!(
+      files,
+      vec![
+        tmp.path().join("htsnexus_test_NA12878.bam"),
+        tmp.path().join("htsnexus_test_NA12878.bam.bai"),
+        tmp.path().join("htsnexus_test_NA12878.bam.gzi"),
+      ]
+    );
+    assert_eq!(
+      fs::read(tmp.path().join("htsnexus_test_NA12878.bam.gzi")).unwrap(),
+      fs::read(default_dir().join("data/bam/htsnexus_test_NA12878.bam.gzi")).unwrap()
+    );
+  }
+
+  #[tokio::test]
+  async fn prepare_unsorted_bam() {
+    let tmp = TempDir::new().unwrap();
+    let input = tmp.path().join("unsorted.bam");
+    let mut writer = bam::io::Writer::new(fs::File::create(&input).unwrap());
+    writer
+      .write_header(&noodles::sam::Header::default())
+      .unwrap();
+    writer.try_finish().unwrap();
+
+    let output = tmp.path().join("output");
+    let result = Prepare::new(input, output.clone()).unwrap().run().await;
+
+    assert!(matches!(result, Err(HtsGetError::InvalidInput(_))));
+    assert!(!output.exists());
+  }
+
+  #[tokio::test]
+  async fn prepare_uncompressed_vcf() {
+    let tmp = TempDir::new().unwrap();
+    let files = Prepare::new(
+      default_dir().join("data/vcf/spec-v4.3_uncompressed.vcf"),
+      tmp.path().to_path_buf(),
+    )
+    .unwrap()
+    .with_id("spec-v4.3".to_string())
+    .run()
+    .await
+    .unwrap();
+
+    assert_eq!(files[0], tmp.path().join("spec-v4.3.vcf.gz"));
+    assert!(Prepare::is_gzip(&files[0]).unwrap());
+    assert!(tmp.path().join("spec-v4.3.vcf.gz.tbi").exists());
+  }
+
+  #[cfg(feature = "experimental")]
+  #[tokio::test]
+  async fn prepare_encrypted_bam() {
+    use crypt4gh::keys::{generate_keys, get_private_key, get_public_key};
+
+    let tmp = TempDir::new().unwrap();
+    let keys = TempDir::new().unwrap();
+    let private_key = keys.path().join("prepare.sec");
+    let public_key = keys.path().join("prepare.pub");
+    generate_keys(private_key.clone(), public_key, || Ok("".to_string()), None).unwrap();
+
+    let encryption = Encryption::new(
+      get_private_key(private_key, Ok("".to_string())).unwrap(),
+      vec![get_public_key(default_dir().join("data/c4gh/keys/alice.pub")).unwrap()],
+    )
+    .unwrap()
+    .with_encrypt_indexes(true);
+
+    let files = Prepare::new(
+      default_dir().join("data/bam/htsnexus_test_NA12878.bam"),
+      tmp.path().to_path_buf(),
+    )
+    .unwrap()
+    .with_encryption(Some(encryption))
+    .run()
+    .await
+    .unwrap();
+
+    assert_eq!(
+      files,
+      vec![
+        tmp.path().join("htsnexus_test_NA12878.bam.c4gh"),
+        tmp.path().join("htsnexus_test_NA12878.bam.bai.c4gh"),
+        tmp.path().join("htsnexus_test_NA12878.bam.gzi.c4gh"),
+      ]
+    );
+    assert!(!tmp.path().join("htsnexus_test_NA12878.bam").exists());
+  }
+}
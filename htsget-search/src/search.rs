@@ -6,10 +6,13 @@
 //!
 
 use std::collections::BTreeSet;
+use std::future::Future;
+use std::time::Instant;
 
 use async_trait::async_trait;
 use futures::StreamExt;
 use futures_util::stream::FuturesOrdered;
+use metrics::histogram;
 use noodles::bgzf::{gzi, VirtualPosition};
 use noodles::csi::binning_index::index::reference_sequence::bin::Chunk;
 use noodles::csi::binning_index::index::Index;
@@ -39,6 +42,23 @@ pub(crate) static BGZF_EOF: &[u8] = &[
 
 pub(crate) const MAX_BGZF_ISIZE: u64 = 1 << 16;
 
+/// The duration of the stages of a search in seconds.
+pub const SEARCH_DURATION_SECONDS: &str = "htsget_search_duration_seconds";
+
+/// Record the duration of a stage of a search, such as reading the index.
+pub(crate) async fn timed<T>(
+  stage: &'static str,
+  format: Format,
+  future: impl Future<Output = T>,
+) -> T {
+  let start = Instant::now();
+  let output = future.await;
+  histogram!(SEARCH_DURATION_SECONDS, "stage" => stage, "format" => format.to_string())
+    .record(start.elapsed().as_secs_f64());
+
+  output
+}
+
 /// Helper function to find the first non-none value from a set of futures.
 pub(crate) async fn find_first<T>(
   msg: &str,
@@ -261,15 +281,21 @@ where
           )));
         }
 
-        let index = self.read_index(&query).await?;
+        let etag = self.etag(&query).await?;
+        let index = timed("read_index", format, self.read_index(&query)).await?;
         let header_end = self.get_header_end_offset(&index).await?;
 
-        self.preprocess(&query, header_end).await?;
+        self.preprocess(&query, header_end, etag.as_deref()).await?;
 
         let mut byte_ranges = match query.reference_name().as_ref() {
           None => self.get_byte_ranges_for_all(&query).await?,
           Some(reference_name) => {
-            let (header, mut reader) = self.get_header(&query, header_end).await?;
+            let (header, mut reader) = timed(
+              "get_header",
+              format,
+              self.get_header(&query, header_end, etag.as_deref()),
+            )
+            .await?;
 
             let mut byte_ranges = self
               .get_byte_ranges_for_reference_name(
@@ -303,15 +329,27 @@ where
           )
           .await?;
 
-        self.build_response(&query, blocks).await
+        timed(
+          "build_response",
+          format,
+          self.build_response(&query, blocks, etag),
+        )
+        .await
       }
       Class::Header => {
-        let index = self.read_index(&query).await?;
+        let format = self.get_format();
+        let etag = self.etag(&query).await?;
+        let index = timed("read_index", format, self.read_index(&query)).await?;
         let header_end = self.get_header_end_offset(&index).await?;
 
-        self.preprocess(&query, header_end).await?;
+        self.preprocess(&query, header_end, etag.as_deref()).await?;
 
-        let (_, mut reader) = self.get_header(&query, header_end).await?;
+        let (_, mut reader) = timed(
+          "get_header",
+          format,
+          self.get_header(&query, header_end, etag.as_deref()),
+        )
+        .await?;
 
         let header_byte_ranges = self
           .get_byte_ranges_for_header(&index, &mut reader, &query)
@@ -325,12 +363,17 @@ where
           )
           .await?;
 
-        self.build_response(&query, blocks).await
+        timed(
+          "build_response",
+          format,
+          self.build_response(&query, blocks, etag),
+        )
+        .await
       }
     }
   }
 
-  async fn preprocess(&mut self, query: &Query, header_end: u64) -> Result<()> {
+  async fn preprocess(&mut self, query: &Query, header_end: u64, etag: Option<&str>) -> Result<()> {
     Ok(
       self
         .mut_storage()
@@ -339,7 +382,8 @@ where
           GetOptions::new(
             BytesPosition::default().with_end(header_end),
             query.request().headers(),
-          ),
+          )
+          .with_etag(etag.map(ToString::to_string)),
         )
         .await?,
     )
@@ -357,9 +401,37 @@ where
     )
   }
 
-  /// Build the response from the query using urls.
+  /// Get the etag of the data object, which identifies its current version. Reads of the data
+  /// object and the returned tickets are pinned to this etag. The etag is only captured if the
+  /// location of the query verifies etags. The index is read in a single request, so it is not
+  /// pinned. Storage reuses the head request for the size of the data object, so this does not
+  /// add a request to the search.
+  #[instrument(level = "trace", skip(self))]
+  async fn etag(&self, query: &Query) -> Result<Option<String>> {
+    if !query.verify_etags() {
+      return Ok(None);
+    }
+
+    Ok(
+      self
+        .get_storage()
+        .etag(
+          &query.format().fmt_file(query.id()),
+          HeadOptions::new(query.request().headers()),
+        )
+        .await?,
+    )
+  }
+
+  /// Build the response from the query using urls. If an etag is present, the urls are pinned
+  /// to that version of the data object.
   #[instrument(level = "trace", skip(self, byte_ranges))]
-  async fn build_response(&self, query: &Query, byte_ranges: Vec<DataBlock>) -> Result<Response> {
+  async fn build_response(
+    &self,
+    query: &Query,
+    byte_ranges: Vec<DataBlock>,
+    etag: Option<String>,
+  ) -> Result<Response> {
     trace!("building response");
     let mut urls = vec![];
     let storage = self.get_storage();
@@ -374,7 +446,8 @@ where
             storage
               .range_url(
                 &query_owned.format().fmt_file(query_owned.id()),
-                RangeUrlOptions::new(range, query_owned.request().headers()),
+                RangeUrlOptions::new(range, query_owned.request().headers())
+                  .with_etag(etag.clone()),
               )
               .await?,
           );
@@ -389,14 +462,21 @@ where
     Ok(Response::new(query.format(), urls))
   }
 
-  /// Get the header from the file specified by the id and format.
+  /// Get the header from the file specified by the id and format. If an etag is present, the read
+  /// fails if the file no longer matches it.
   #[instrument(level = "trace", skip(self))]
-  async fn get_header(&self, query: &Query, offset: u64) -> Result<(Header, Reader)> {
+  async fn get_header(
+    &self,
+    query: &Query,
+    offset: u64,
+    etag: Option<&str>,
+  ) -> Result<(Header, Reader)> {
     trace!("getting header");
     let get_options = GetOptions::new(
       BytesPosition::default().with_end(offset),
       query.request().headers(),
-    );
+    )
+    .with_etag(etag.map(ToString::to_string));
 
     let reader_type = self
       .get_storage()
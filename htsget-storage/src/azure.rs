@@ -0,0 +1,978 @@
+//! Module providing an implementation for the [StorageTrait] trait using Azure Blob Storage.
+//!
+
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use base64::engine::general_purpose;
+use base64::Engine;
+use chrono::{DateTime, TimeDelta, Utc};
+use futures_util::TryStreamExt;
+use hmac::{Hmac, Mac};
+use http::header::{AUTHORIZATION, CONTENT_LENGTH, ETAG, IF_MATCH, RANGE};
+use http::{HeaderMap, Method, StatusCode};
+use reqwest::{Client, RequestBuilder};
+use serde::Deserialize;
+use sha2::Sha256;
+use tokio_util::io::StreamReader;
+use tracing::{debug, instrument};
+
+use crate::types::{BytesRange, HeadCache};
+use crate::StorageError::{
+  AzureError, InternalError, KeyNotFound, PermissionDenied, ResponseError, ServerError,
+};
+use crate::{
+  GetOptions, HeadOptions, RangeUrlOptions, Result, StorageError, StorageMiddleware, StorageTrait,
+  Streamable, Url,
+};
+
+/// The Blob service version used for requests and SAS tokens.
+pub const API_VERSION: &str = "2020-12-06";
+/// The resource that managed identity tokens are requested for.
+const STORAGE_RESOURCE: &str = "https://storage.azure.com/";
+/// The default Azure Instance Metadata Service token endpoint.
+const DEFAULT_IDENTITY_ENDPOINT: &str = "http://169.254.169.254/metadata/identity/oauth2/token";
+/// The environment variable used to find the account key.
+const ACCOUNT_KEY_ENV: &str = "AZURE_STORAGE_KEY";
+/// The environment variables set by App Service and Container Apps for managed identity.
+const IDENTITY_ENDPOINT_ENV: &str = "IDENTITY_ENDPOINT";
+const IDENTITY_HEADER_ENV: &str = "IDENTITY_HEADER";
+/// Tokens and keys are refreshed this many seconds before they expire.
+const REFRESH_BEFORE: i64 = 300;
+
+/// The account, endpoint, account key and identity endpoint that shared clients are keyed by.
+type SharedKey = (String, Option<String>, Option<String>, Option<String>);
+/// A client and credentials which are shared between requests.
+type Shared = (Client, AzureCredentials);
+
+/// A managed identity access token.
+#[derive(Clone)]
+struct AccessToken {
+  token: String,
+  expires_on: DateTime<Utc>,
+}
+
+/// The managed identity token response.
+#[derive(Deserialize)]
+struct TokenResponse {
+  access_token: String,
+  expires_on: String,
+}
+
+/// A user delegation key, used to sign SAS tokens when authenticating with a managed identity.
+#[derive(Clone)]
+struct UserDelegationKey {
+  signed_oid: String,
+  signed_tid: String,
+  signed_start: String,
+  signed_expiry: String,
+  signed_service: String,
+  signed_version: String,
+  value: Vec<u8>,
+  expires_on: DateTime<Utc>,
+}
+
+/// Fetches and caches managed identity tokens and user delegation keys.
+#[derive(Clone)]
+pub struct ManagedIdentity {
+  endpoint: String,
+  header: Option<String>,
+  token: Arc<Mutex<Option<AccessToken>>>,
+  delegation_key: Arc<Mutex<Option<UserDelegationKey>>>,
+}
+
+impl Debug for ManagedIdentity {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    f.debug_struct("ManagedIdentity")
+      .field("endpoint", &self.endpoint)
+      .finish()
+  }
+}
+
+impl ManagedIdentity {
+  /// Create a managed identity using the token endpoint, and an optional identity header which
+  /// is sent as `X-IDENTITY-HEADER`. Without an identity header, the endpoint is treated as the
+  /// Azure Instance Metadata Service.
+  pub fn new(endpoint: String, header: Option<String>) -> Self {
+    Self {
+      endpoint,
+      header,
+      token: Default::default(),
+      delegation_key: Default::default(),
+    }
+  }
+
+  /// Get a cached access token, or request a new one.
+  async fn token(&self, client: &Client) -> Result<String> {
+    if let Some(token) = self.token.lock().ok().and_then(|token| token.clone()) {
+      if token.expires_on - refresh_before() > Utc::now() {
+        return Ok(token.token);
+      }
+    }
+
+    let api_version = if self.header.is_some() {
+      "2019-08-01"
+    } else {
+      "2018-02-01"
+    };
+    let request = client.get(format!(
+      "{}?api-version={}&resource={}",
+      self.endpoint,
+      api_version,
+      encode(STORAGE_RESOURCE, true)
+    ));
+    let request = match &self.header {
+      Some(header) => request.header("X-IDENTITY-HEADER", header),
+      None => request.header("Metadata", "true"),
+    };
+
+    let response = request
+      .send()
+      .await
+      .map_err(|err| InternalError(format!("failed to request managed identity token: {}", err)))?;
+    if !response.status().is_success() {
+      return Err(InternalError(format!(
+        "managed identity endpoint returned {}",
+        response.status()
+      )));
+    }
+
+    let body = response
+      .bytes()
+      .await
+      .map_err(|err| InternalError(format!("failed to read managed identity token: {}", err)))?;
+    let response: TokenResponse = serde_json::from_slice(&body)
+      .map_err(|err| InternalError(format!("failed to parse managed identity token: {}", err)))?;
+    let expires_on = response
+      .expires_on
+      .parse::<i64>()
+      .ok()
+      .and_then(|expires_on| DateTime::from_timestamp(expires_on, 0))
+      .ok_or_else(|| InternalError("invalid managed identity token expiry".to_string()))?;
+
+    if let Ok(mut token) = self.token.lock() {
+      *token = Some(AccessToken {
+        token: response.access_token.clone(),
+        expires_on,
+      });
+    }
+
+    Ok(response.access_token)
+  }
+}
+
+/// The credentials used to access Azure Blob Storage.
+#[derive(Clone)]
+pub enum AzureCredentials {
+  /// A decoded shared account key.
+  SharedKey(Vec<u8>),
+  /// A managed identity.
+  ManagedIdentity(ManagedIdentity),
+}
+
+impl Debug for AzureCredentials {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::SharedKey(_) => write!(f, "SharedKey"),
+      Self::ManagedIdentity(identity) => identity.fmt(f),
+    }
+  }
+}
+
+impl AzureCredentials {
+  /// Create shared key credentials from a base64 encoded account key.
+  pub fn from_account_key(account_key: &str) -> Result<Self> {
+    general_purpose::STANDARD
+      .decode(account_key)
+      .map(Self::SharedKey)
+      .map_err(|err| InternalError(format!("invalid azure account key: {}", err)))
+  }
+}
+
+/// Implementation for the [StorageTrait] trait using Azure Blob Storage. Tickets contain service
+/// SAS URLs when using an account key, and user delegation SAS URLs when using a managed identity.
+#[derive(Debug, Clone)]
+pub struct AzureStorage {
+  client: Client,
+  account: String,
+  container: String,
+  endpoint: String,
+  credentials: AzureCredentials,
+  expires_in: Duration,
+  heads: HeadCache<HeaderMap>,
+}
+
+impl AzureStorage {
+  /// Create a new Azure storage.
+  pub fn new(
+    client: Client,
+    account: String,
+    container: String,
+    endpoint: Option<String>,
+    credentials: AzureCredentials,
+    expires_in: Duration,
+  ) -> Self {
+    let endpoint = endpoint
+      .unwrap_or_else(|| format!("https://{}.blob.core.windows.net", account))
+      .trim_end_matches('/')
+      .to_string();
+
+    Self {
+      client,
+      account,
+      container,
+      endpoint,
+      credentials,
+      expires_in,
+      heads: Default::default(),
+    }
+  }
+
+  /// Create a new Azure storage. The account key is read from the config, or the
+  /// `AZURE_STORAGE_KEY` environment variable. If neither is set, a managed identity is used.
+  pub fn new_with_default_config(
+    account: String,
+    container: String,
+    endpoint: Option<String>,
+    account_key: Option<&str>,
+    identity_endpoint: Option<&str>,
+    expires_in: Duration,
+  ) -> Result<Self> {
+    let account_key = account_key
+      .map(str::to_string)
+      .or_else(|| env::var(ACCOUNT_KEY_ENV).ok());
+
+    let key = (
+      account.clone(),
+      endpoint.clone(),
+      account_key,
+      identity_endpoint.map(str::to_string),
+    );
+    let (client, credentials) = Self::shared(key)?;
+
+    Ok(Self::new(
+      client,
+      account,
+      container,
+      endpoint,
+      credentials,
+      expires_in,
+    ))
+  }
+
+  /// Get the client and credentials for an account. These are only created once and then shared
+  /// between requests, so that managed identity tokens and user delegation keys are reused.
+  fn shared(key: SharedKey) -> Result<Shared> {
+    static SHARED: LazyLock<Mutex<HashMap<SharedKey, Shared>>> = LazyLock::new(Default::default);
+
+    if let Some(shared) = SHARED
+      .lock()
+      .ok()
+      .and_then(|shared| shared.get(&key).cloned())
+    {
+      return Ok(shared);
+    }
+
+    let credentials = match &key.2 {
+      Some(account_key) => AzureCredentials::from_account_key(account_key)?,
+      None => {
+        let header = env::var(IDENTITY_HEADER_ENV).ok();
+        let endpoint = key
+          .3
+          .clone()
+          .or_else(|| header.as_ref().and(env::var(IDENTITY_ENDPOINT_ENV).ok()))
+          .unwrap_or_else(|| DEFAULT_IDENTITY_ENDPOINT.to_string());
+
+        AzureCredentials::ManagedIdentity(ManagedIdentity::new(endpoint, header))
+      }
+    };
+
+    let shared = (Client::new(), credentials);
+    if let Ok(mut cache) = SHARED.lock() {
+      cache.insert(key, shared.clone());
+    }
+
+    Ok(shared)
+  }
+
+  /// Get the unsigned URL of the blob.
+  pub fn blob_url(&self, key: &str) -> String {
+    format!(
+      "{}/{}/{}",
+      self.endpoint,
+      encode(&self.container, true),
+      encode(key, false)
+    )
+  }
+
+  /// Get the canonicalized resource of the blob used for signing.
+  fn canonicalized_resource(&self, key: &str) -> String {
+    format!("/blob/{}/{}/{}", self.account, self.container, key)
+  }
+
+  /// Get the protocol that the SAS token is restricted to.
+  fn signed_protocol(&self) -> &str {
+    if self.endpoint.starts_with("https://") {
+      "https"
+    } else {
+      ""
+    }
+  }
+
+  /// Get the SAS query string for reading the blob.
+  pub async fn sas_query(&self, key: &str, now: DateTime<Utc>) -> Result<String> {
+    let expiry = format_time(now + self.expires_in);
+    let protocol = self.signed_protocol();
+    let resource = self.canonicalized_resource(key);
+
+    let mut query = vec![
+      ("sv", API_VERSION.to_string()),
+      ("se", expiry.clone()),
+      ("sr", "b".to_string()),
+      ("sp", "r".to_string()),
+    ];
+    if !protocol.is_empty() {
+      query.push(("spr", protocol.to_string()));
+    }
+
+    let signature = match &self.credentials {
+      AzureCredentials::SharedKey(account_key) => {
+        let string_to_sign = [
+          "r",
+          "",
+          &expiry,
+          &resource,
+          "",
+          "",
+          protocol,
+          API_VERSION,
+          "b",
+          "",
+          "",
+          "",
+          "",
+          "",
+          "",
+          "",
+        ]
+        .join("\n");
+
+        sign(account_key, &string_to_sign)?
+      }
+      AzureCredentials::ManagedIdentity(identity) => {
+        let key = self.user_delegation_key(identity, now).await?;
+        let string_to_sign = [
+          "r",
+          "",
+          &expiry,
+          &resource,
+          &key.signed_oid,
+          &key.signed_tid,
+          &key.signed_start,
+          &key.signed_expiry,
+          &key.signed_service,
+          &key.signed_version,
+          "",
+          "",
+          "",
+          "",
+          protocol,
+          API_VERSION,
+          "b",
+          "",
+          "",
+          "",
+          "",
+          "",
+          "",
+          "",
+        ]
+        .join("\n");
+
+        query.extend([
+          ("skoid", key.signed_oid.clone()),
+          ("sktid", key.signed_tid.clone()),
+          ("skt", key.signed_start.clone()),
+          ("ske", key.signed_expiry.clone()),
+          ("sks", key.signed_service.clone()),
+          ("skv", key.signed_version.clone()),
+        ]);
+
+        sign(&key.value, &string_to_sign)?
+      }
+    };
+    query.push(("sig", signature));
+
+    Ok(
+      query
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, encode(value, true)))
+        .collect::<Vec<_>>()
+        .join("&"),
+    )
+  }
+
+  /// Get a cached user delegation key, or request a new one using the managed identity.
+  async fn user_delegation_key(
+    &self,
+    identity: &ManagedIdentity,
+    now: DateTime<Utc>,
+  ) -> Result<UserDelegationKey> {
+    let expires_on = now + self.expires_in;
+    if let Some(key) = identity
+      .delegation_key
+      .lock()
+      .ok()
+      .and_then(|key| key.clone())
+    {
+      if key.expires_on >= expires_on + refresh_before() {
+        return Ok(key);
+      }
+    }
+
+    // Request a key which is valid for a while longer than the ticket, so that it can be reused.
+    let key_expiry = expires_on + self.expires_in;
+    let body = format!(
+      "<?xml version=\"1.0\" encoding=\"utf-8\"?>\
+      <KeyInfo><Start>{}</Start><Expiry>{}</Expiry></KeyInfo>",
+      format_time(now - refresh_before()),
+      format_time(key_expiry)
+    );
+
+    let token = identity.token(&self.client).await?;
+    let response = self
+      .client
+      .post(format!(
+        "{}/?restype=service&comp=userdelegationkey",
+        self.endpoint
+      ))
+      .header(AUTHORIZATION, format!("Bearer {}", token))
+      .header("x-ms-version", API_VERSION)
+      .body(body)
+      .send()
+      .await
+      .map_err(|err| InternalError(format!("failed to request user delegation key: {}", err)))?;
+    if !response.status().is_success() {
+      return Err(InternalError(format!(
+        "user delegation key request returned {}",
+        response.status()
+      )));
+    }
+
+    let xml = response
+      .text()
+      .await
+      .map_err(|err| InternalError(format!("failed to read user delegation key: {}", err)))?;
+    let element = |name: &str| {
+      xml_element(&xml, name)
+        .ok_or_else(|| InternalError(format!("missing {} in user delegation key", name)))
+    };
+
+    let key = UserDelegationKey {
+      signed_oid: element("SignedOid")?,
+      signed_tid: element("SignedTid")?,
+      signed_start: element("SignedStart")?,
+      signed_expiry: element("SignedExpiry")?,
+      signed_service: element("SignedService")?,
+      signed_version: element("SignedVersion")?,
+      value: general_purpose::STANDARD
+        .decode(element("Value")?)
+        .map_err(|err| InternalError(format!("invalid user delegation key: {}", err)))?,
+      expires_on: key_expiry,
+    };
+
+    if let Ok(mut delegation_key) = identity.delegation_key.lock() {
+      *delegation_key = Some(key.clone());
+    }
+
+    Ok(key)
+  }
+
+  /// Create an authorized request for the blob.
+  async fn request(&self, method: Method, key: &str) -> Result<RequestBuilder> {
+    match &self.credentials {
+      AzureCredentials::SharedKey(_) => {
+        let query = self.sas_query(key, Utc::now()).await?;
+        Ok(
+          self
+            .client
+            .request(method, format!("{}?{}", self.blob_url(key), query)),
+        )
+      }
+      AzureCredentials::ManagedIdentity(identity) => {
+        let token = identity.token(&self.client).await?;
+        Ok(
+          self
+            .client
+            .request(method, self.blob_url(key))
+            .header(AUTHORIZATION, format!("Bearer {}", token)),
+        )
+      }
+    }
+  }
+
+  /// Get the response headers of a head request for the key, reusing the headers of an earlier
+  /// head request for the key. This is used for the size and etag of objects, so that both come
+  /// from one request.
+  async fn head_headers(&self, key: &str) -> Result<HeaderMap> {
+    self
+      .heads
+      .get_or_try_insert(key, || async {
+        Ok(
+          self
+            .send_request(key, Method::HEAD, "", None)
+            .await?
+            .headers()
+            .clone(),
+        )
+      })
+      .await
+  }
+
+  /// Send a request to the Blob service, mapping error statuses.
+  async fn send_request(
+    &self,
+    key: &str,
+    method: Method,
+    range: &str,
+    etag: Option<&str>,
+  ) -> Result<reqwest::Response> {
+    let mut request = self
+      .request(method, key)
+      .await?
+      .header("x-ms-version", API_VERSION);
+    if !range.is_empty() {
+      request = request.header(RANGE, range);
+    }
+    if let Some(etag) = etag {
+      request = request.header(IF_MATCH, etag);
+    }
+
+    let response = request
+      .send()
+      .await
+      .map_err(|err| AzureError(err.to_string(), key.to_string()))?;
+
+    match response.status() {
+      status if status.is_success() => Ok(response),
+      StatusCode::NOT_FOUND => Err(KeyNotFound(key.to_string())),
+      StatusCode::PRECONDITION_FAILED => Err(StorageError::modified(key)),
+      StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Err(PermissionDenied(format!(
+        "azure denied access for key {}",
+        key
+      ))),
+      status if status.is_server_error() => Err(ServerError(format!(
+        "azure returned {} for key {}",
+        status, key
+      ))),
+      status => Err(AzureError(
+        format!("azure returned {}", status),
+        key.to_string(),
+      )),
+    }
+  }
+}
+
+/// The time before expiry that tokens and keys are refreshed.
+fn refresh_before() -> TimeDelta {
+  TimeDelta::seconds(REFRESH_BEFORE)
+}
+
+/// Format a time as required by the Blob service.
+fn format_time(time: DateTime<Utc>) -> String {
+  time.format("%Y-%m-%dT%H:%M:%SZ").to_string()
+}
+
+/// Sign the string using HMAC-SHA256, returning the base64 encoded signature.
+fn sign(key: &[u8], string_to_sign: &str) -> Result<String> {
+  let mut mac = Hmac::<Sha256>::new_from_slice(key)
+    .map_err(|err| InternalError(format!("invalid signing key: {}", err)))?;
+  mac.update(string_to_sign.as_bytes());
+
+  Ok(general_purpose::STANDARD.encode(mac.finalize().into_bytes()))
+}
+
+/// Get the text of the first XML element with the name.
+fn xml_element(xml: &str, name: &str) -> Option<String> {
+  let start = format!("<{}>", name);
+  let end = format!("</{}>", name);
+
+  let value = xml.split_once(&start)?.1;
+  Some(value.split_once(&end)?.0.to_string())
+}
+
+/// Percent-encode a value, leaving unreserved characters and optionally `/` unchanged.
+fn encode(value: &str, encode_slash: bool) -> String {
+  value
+    .bytes()
+    .map(|byte| match byte {
+      b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+        (byte as char).to_string()
+      }
+      b'/' if !encode_slash => "/".to_string(),
+      byte => format!("%{:02X}", byte),
+    })
+    .collect()
+}
+
+#[async_trait]
+impl StorageMiddleware for AzureStorage {}
+
+#[async_trait]
+impl StorageTrait for AzureStorage {
+  #[instrument(level = "trace", skip(self))]
+  async fn get(&self, key: &str, options: GetOptions<'_>) -> Result<Streamable> {
+    debug!(calling_from = ?self, key, "getting file with key {:?}", key);
+
+    let range = String::from(&BytesRange::from(options.range()));
+    let response = self
+      .send_request(key, Method::GET, &range, options.etag())
+      .await?;
+
+    Ok(Streamable::from_async_read(StreamReader::new(
+      response
+        .bytes_stream()
+        .map_err(|err| ResponseError(format!("reading body from response: {}", err))),
+    )))
+  }
+
+  #[instrument(level = "trace", skip(self))]
+  async fn range_url(&self, key: &str, options: RangeUrlOptions<'_>) -> Result<Url> {
+    debug!(calling_from = ?self, key, "getting url with key {:?}", key);
+
+    let query = self.sas_query(key, Utc::now()).await?;
+    Ok(options.apply(Url::new(format!("{}?{}", self.blob_url(key), query))))
+  }
+
+  #[instrument(level = "trace", skip(self))]
+  async fn head(&self, key: &str, _options: HeadOptions<'_>) -> Result<u64> {
+    let len = self
+      .head_headers(key)
+      .await?
+      .get(CONTENT_LENGTH)
+      .and_then(|length| length.to_str().ok())
+      .and_then(|length| length.parse().ok())
+      .ok_or_else(|| AzureError("failed to get content length".to_string(), key.to_string()))?;
+
+    debug!(calling_from = ?self, key, len, "size of key {:?} is {}", key, len);
+    Ok(len)
+  }
+
+  #[instrument(level = "trace", skip(self))]
+  async fn etag(&self, key: &str, _options: HeadOptions<'_>) -> Result<Option<String>> {
+    let etag = self
+      .head_headers(key)
+      .await?
+      .get(ETAG)
+      .and_then(|etag| etag.to_str().ok())
+      .map(ToString::to_string);
+
+    debug!(calling_from = ?self, key, ?etag, "etag of key {:?} is {:?}", key, etag);
+    Ok(etag)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::future::Future;
+
+  use axum::extract::Request;
+  use axum::middleware::Next;
+  use axum::response::Response;
+  use axum::routing::{get, post};
+  use axum::{middleware, Json, Router};
+  use chrono::TimeZone;
+  use htsget_config::types::{Class, Headers};
+  use tokio::io::AsyncReadExt;
+  use tokio::net::TcpListener;
+  use tower_http::services::ServeDir;
+
+  use crate::local::tests::create_local_test_files;
+  use crate::types::BytesPosition;
+  use crate::StorageError;
+
+  use super::*;
+
+  /// The well-known Azurite development account key.
+  const AZURITE_KEY: &str =
+    "Eby8vdM02xNOcqFlqUwJPLlmEtlCDXJ1OUzFT50uSRZ6IFsuFq2UVErCz4I6tq/K1SZFPTOtr/KBHBeksoGMGw=="; // pragma: allowlist secret
+
+  #[test]
+  fn blob_url() {
+    let storage = azurite_storage("http://127.0.0.1:10000/devstoreaccount1".to_string());
+
+    assert_eq!(
+      storage.blob_url("folder/key name"),
+      "http://127.0.0.1:10000/devstoreaccount1/container/folder/key%20name"
+    );
+  }
+
+  #[test]
+  fn default_endpoint() {
+    let storage = AzureStorage::new(
+      Client::new(),
+      "account".to_string(),
+      "container".to_string(),
+      None,
+      AzureCredentials::from_account_key(AZURITE_KEY).unwrap(),
+      Duration::from_secs(3600),
+    );
+
+    assert_eq!(
+      storage.blob_url("key"),
+      "https://account.blob.core.windows.net/container/key"
+    );
+  }
+
+  #[tokio::test]
+  async fn service_sas() {
+    let storage = azurite_storage("http://127.0.0.1:10000/devstoreaccount1".to_string());
+    let now = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+
+    assert_eq!(
+      storage.sas_query("folder/key name", now).await.unwrap(),
+      "sv=2020-12-06&se=2024-01-01T01%3A00%3A00Z&sr=b&sp=r&\
+      sig=XLojYwFei2odnkf7hjZli0EXQ9%2BHHplcptARUOA3zSg%3D"
+    );
+  }
+
+  #[tokio::test]
+  async fn get_blob() {
+    with_azurite_test_server(|storage| async move {
+      let mut result = String::new();
+      storage
+        .get(
+          "key1",
+          GetOptions::new_with_default_range(&Default::default()),
+        )
+        .await
+        .unwrap()
+        .read_to_string(&mut result)
+        .await
+        .unwrap();
+
+      assert_eq!(result, "value1");
+    })
+    .await;
+  }
+
+  #[tokio::test]
+  async fn get_blob_with_range() {
+    with_azurite_test_server(|storage| async move {
+      let mut result = String::new();
+      storage
+        .get(
+          "folder/key2",
+          GetOptions::new(
+            BytesPosition::new(Some(1), Some(3), None),
+            &Default::default(),
+          ),
+        )
+        .await
+        .unwrap()
+        .read_to_string(&mut result)
+        .await
+        .unwrap();
+
+      assert_eq!(result, "al");
+    })
+    .await;
+  }
+
+  #[tokio::test]
+  async fn get_non_existing_key() {
+    with_azurite_test_server(|storage| async move {
+      let result = storage
+        .get(
+          "non-existing-key",
+          GetOptions::new_with_default_range(&Default::default()),
+        )
+        .await;
+
+      assert!(matches!(result, Err(StorageError::KeyNotFound(_))));
+    })
+    .await;
+  }
+
+  #[tokio::test]
+  async fn get_unauthorized_key() {
+    with_azurite_test_server(|storage| async move {
+      let result = storage
+        .head("unauthorized", HeadOptions::new(&Default::default()))
+        .await;
+
+      assert!(matches!(result, Err(StorageError::PermissionDenied(_))));
+    })
+    .await;
+  }
+
+  #[tokio::test]
+  async fn head_blob() {
+    with_azurite_test_server(|storage| async move {
+      let result = storage
+        .head("key1", HeadOptions::new(&Default::default()))
+        .await;
+
+      assert_eq!(result.unwrap(), 6);
+    })
+    .await;
+  }
+
+  #[tokio::test]
+  async fn range_url_with_range() {
+    with_azurite_test_server(|storage| async move {
+      let result = storage
+        .range_url(
+          "key1",
+          RangeUrlOptions::new(
+            BytesPosition::new(Some(7), Some(10), Some(Class::Body)),
+            &Default::default(),
+          ),
+        )
+        .await
+        .unwrap();
+
+      assert!(result
+        .url
+        .starts_with(&format!("{}?sv=2020-12-06&se=", storage.blob_url("key1"))));
+      assert!(result.url.contains("&sig="));
+      assert_eq!(
+        result.headers,
+        Some(Headers::default().with_header("Range", "bytes=7-9"))
+      );
+      assert_eq!(result.class, Some(Class::Body));
+    })
+    .await;
+  }
+
+  #[tokio::test]
+  async fn managed_identity() {
+    with_test_server(
+      |addr| {
+        AzureCredentials::ManagedIdentity(ManagedIdentity::new(format!("{}/token", addr), None))
+      },
+      |storage| async move {
+        assert_eq!(
+          storage
+            .head("key1", HeadOptions::new(&Default::default()))
+            .await
+            .unwrap(),
+          6
+        );
+
+        let result = storage
+          .range_url(
+            "key1",
+            RangeUrlOptions::new_with_default_range(&Default::default()),
+          )
+          .await
+          .unwrap();
+
+        assert!(result.url.contains("&skoid=oid&sktid=tid&"));
+        assert!(result.url.contains("&sks=b&skv=2020-12-06&sig="));
+      },
+    )
+    .await;
+  }
+
+  fn azurite_storage(endpoint: String) -> AzureStorage {
+    AzureStorage::new(
+      Client::new(),
+      "devstoreaccount1".to_string(),
+      "container".to_string(),
+      Some(endpoint),
+      AzureCredentials::from_account_key(AZURITE_KEY).unwrap(),
+      Duration::from_secs(3600),
+    )
+  }
+
+  async fn with_azurite_test_server<F, Fut>(test: F)
+  where
+    F: FnOnce(AzureStorage) -> Fut,
+    Fut: Future<Output = ()>,
+  {
+    with_test_server(
+      |_| AzureCredentials::from_account_key(AZURITE_KEY).unwrap(),
+      test,
+    )
+    .await;
+  }
+
+  /// Requests must be authorized using either a SAS or a bearer token.
+  async fn test_auth(request: Request, next: Next) -> std::result::Result<Response, StatusCode> {
+    let sas = request
+      .uri()
+      .query()
+      .is_some_and(|query| query.contains("sig="));
+    let bearer = request
+      .headers()
+      .get(AUTHORIZATION)
+      .is_some_and(|header| header == "Bearer token");
+
+    if sas || bearer {
+      Ok(next.run(request).await)
+    } else {
+      Err(StatusCode::FORBIDDEN)
+    }
+  }
+
+  /// Serves blobs under `/devstoreaccount1/container` in the same way as Azurite, along with
+  /// managed identity token and user delegation key endpoints.
+  async fn with_test_server<C, F, Fut>(credentials: C, test: F)
+  where
+    C: FnOnce(&str) -> AzureCredentials,
+    F: FnOnce(AzureStorage) -> Fut,
+    Fut: Future<Output = ()>,
+  {
+    let (_, base_path) = create_local_test_files().await;
+    let router = Router::new()
+      .nest_service(
+        "/devstoreaccount1/container",
+        ServeDir::new(base_path.path()),
+      )
+      .route_layer(middleware::from_fn(test_auth))
+      .route(
+        "/devstoreaccount1/container/unauthorized",
+        get(|| async { StatusCode::UNAUTHORIZED }),
+      )
+      .route(
+        "/token",
+        get(|| async {
+          Json(serde_json::json!({
+            "access_token": "token",
+            "expires_on": (Utc::now() + TimeDelta::hours(1)).timestamp().to_string(),
+          }))
+        }),
+      )
+      .route(
+        "/devstoreaccount1/",
+        post(|| async {
+          "<?xml version=\"1.0\" encoding=\"utf-8\"?><UserDelegationKey>\
+          <SignedOid>oid</SignedOid><SignedTid>tid</SignedTid>\
+          <SignedStart>2024-01-01T00:00:00Z</SignedStart><SignedExpiry>2024-01-02T00:00:00Z</SignedExpiry>\
+          <SignedService>b</SignedService><SignedVersion>2020-12-06</SignedVersion>\
+          <Value>a2V5</Value></UserDelegationKey>"
+        }),
+      );
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = format!("http://{}", listener.local_addr().unwrap());
+
+    tokio::spawn(async move { axum::serve(listener, router.into_make_service()).await });
+
+    test(AzureStorage::new(
+      Client::new(),
+      "devstoreaccount1".to_string(),
+      "container".to_string(),
+      Some(format!("{}/devstoreaccount1", addr)),
+      credentials(&addr),
+      Duration::from_secs(3600),
+    ))
+    .await;
+  }
+}
@@ -0,0 +1,114 @@
+//! A cache of Crypt4GH state which is shared between requests.
+//!
+
+use crate::c4gh::storage::C4GHState;
+use htsget_config::storage::c4gh::C4GHKeys;
+use lru::LruCache;
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug)]
+struct Entries {
+  values: LruCache<String, Arc<C4GHState>>,
+  size: usize,
+}
+
+impl Default for Entries {
+  fn default() -> Self {
+    Self {
+      values: LruCache::unbounded(),
+      size: 0,
+    }
+  }
+}
+
+/// A least recently used cache of the decrypted headers and format header data blocks of C4GH
+/// objects. The cache is bounded by the number of bytes that the cached state holds.
+#[derive(Debug, Clone)]
+pub struct C4GHStateCache {
+  entries: Arc<Mutex<Entries>>,
+  capacity: usize,
+}
+
+impl C4GHStateCache {
+  /// Create a cache holding at most `capacity` bytes. A capacity of zero disables the cache.
+  pub fn new(capacity: usize) -> Self {
+    Self {
+      entries: Default::default(),
+      capacity,
+    }
+  }
+
+  /// Get the cache for a location's keys. The cache is stored with the keys, so it is created
+  /// once for each set of keys, shared between requests and dropped with the keys.
+  pub fn for_keys(keys: &C4GHKeys) -> Self {
+    let capacity = keys.state_cache_size();
+    keys
+      .extension(|| Self::new(capacity))
+      .map(|cache| C4GHStateCache::clone(&cache))
+      .unwrap_or_else(|| Self::new(capacity))
+  }
+
+  /// Get the capacity of the cache in bytes.
+  pub fn capacity(&self) -> usize {
+    self.capacity
+  }
+
+  /// Get the state of the object, marking it as recently used.
+  pub fn get(&self, key: &str) -> Option<Arc<C4GHState>> {
+    self
+      .entries
+      .lock()
+      .unwrap_or_else(|err| err.into_inner())
+      .values
+      .get(key)
+      .cloned()
+  }
+
+  /// Insert the state of the object, evicting the least recently used entries until the cache
+  /// fits within its capacity. State which is larger than the capacity is not cached.
+  pub fn insert(&self, key: String, state: C4GHState) {
+    let size = state.size();
+    if size > self.capacity {
+      return;
+    }
+
+    let mut entries = self.entries.lock().unwrap_or_else(|err| err.into_inner());
+    let entries = &mut *entries;
+
+    if let Some(previous) = entries.values.put(key, Arc::new(state)) {
+      entries.size -= previous.size();
+    }
+    entries.size += size;
+
+    while entries.size > self.capacity {
+      let Some((_, evicted)) = entries.values.pop_lru() else {
+        break;
+      };
+      entries.size -= evicted.size();
+    }
+  }
+
+  /// Get the number of entries in the cache.
+  pub fn len(&self) -> usize {
+    self
+      .entries
+      .lock()
+      .unwrap_or_else(|err| err.into_inner())
+      .values
+      .len()
+  }
+
+  /// Check whether the cache is empty.
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  /// Get the number of bytes held by the cache.
+  pub fn size(&self) -> usize {
+    self
+      .entries
+      .lock()
+      .unwrap_or_else(|err| err.into_inner())
+      .size
+  }
+}
@@ -78,6 +78,7 @@ pub struct EditHeader<'a> {
   clamped_positions: Vec<ClampedPosition>,
   keys: &'a [Keys],
   current_header: &'a DeserializedHeader,
+  keep_existing_packets: bool,
 }
 
 impl<'a> EditHeader<'a> {
@@ -93,19 +94,34 @@ impl<'a> EditHeader<'a> {
       clamped_positions,
       keys,
       current_header,
+      keep_existing_packets: true,
     }
   }
 
-  /// Encrypt the header packet.
-  pub fn encrypt_header_packet(&self, header_packet: Vec<u8>) -> Result<Vec<u8>> {
-    Ok(
-      encrypt(&header_packet, &HashSet::from_iter(self.keys.to_vec()))?
-        .into_iter()
-        .last()
-        .ok_or_else(|| {
-          Crypt4GHError::UnableToEncryptPacket("could not encrypt header packet".to_string())
-        })?,
-    )
+  /// Set whether the packets of the current header are kept in the output header. If they are not
+  /// kept, the header only contains the packets encrypted for the recipients of `keys`.
+  pub fn with_keep_existing_packets(mut self, keep_existing_packets: bool) -> Self {
+    self.keep_existing_packets = keep_existing_packets;
+    self
+  }
+
+  /// Encrypt the header packet for each recipient, returning the length prefixed packets and the
+  /// number of packets.
+  pub fn encrypt_header_packet(&self, header_packet: Vec<u8>) -> Result<(Vec<u8>, u32)> {
+    let packets = encrypt(&header_packet, &HashSet::from_iter(self.keys.to_vec()))?;
+    if packets.is_empty() {
+      return Err(
+        Crypt4GHError::UnableToEncryptPacket("could not encrypt header packet".to_string()).into(),
+      );
+    }
+
+    let n_packets = packets.len() as u32;
+    let bytes = packets
+      .into_iter()
+      .flat_map(|packet| [((packet.len() + 4) as u32).to_le_bytes().to_vec(), packet].concat())
+      .collect();
+
+    Ok((bytes, n_packets))
   }
 
   /// Create the edit lists from the unencrypted byte positions.
@@ -176,14 +192,10 @@ impl<'a> EditHeader<'a> {
     let edit_list_packet =
       make_packet_data_edit_list(edit_list.into_iter().map(|edit| edit as usize).collect());
 
-    let edit_list_bytes = self.encrypt_header_packet(edit_list_packet)?;
-    let edit_list_bytes = [
-      ((edit_list_bytes.len() + 4) as u32).to_le_bytes().to_vec(),
-      edit_list_bytes,
-    ]
-    .concat();
+    let (edit_list_bytes, n_edit_list_packets) = self.encrypt_header_packet(edit_list_packet)?;
 
     let mut header_packets = vec![];
+    let mut n_header_packets = 0;
     for session_key in self.current_header.session_keys.as_slice() {
       let data_enc_packet = make_packet_data_enc(
         0,
@@ -192,20 +204,19 @@ impl<'a> EditHeader<'a> {
           .try_into()
           .map_err(|_| Crypt4GHError::NoValidHeaderPacket)?,
       );
-      let header_packet = self.encrypt_header_packet(data_enc_packet)?;
-      header_packets.push(
-        [
-          ((header_packet.len() + 4) as u32).to_le_bytes().to_vec(),
-          header_packet,
-        ]
-        .concat(),
-      )
+      let (header_packet, n_packets) = self.encrypt_header_packet(data_enc_packet)?;
+      header_packets.push(header_packet);
+      n_header_packets += n_packets;
     }
 
     let header_info = &self.current_header.header_info;
 
-    let mut current_len = header_info.packets_count;
-    current_len += 1 + header_packets.len() as u32;
+    let mut current_len = if self.keep_existing_packets {
+      header_info.packets_count
+    } else {
+      0
+    };
+    current_len += n_edit_list_packets + n_header_packets;
 
     let header_info = HeaderInfo {
       magic_number: header_info.magic_number,
@@ -2,13 +2,23 @@
 //! These serve as wrappers around other `Storage` implementations.
 //!
 
+use crate::metrics::record_key_index;
+use crate::types::BytesPosition;
 use crypt4gh::error::Crypt4GHError;
 use crypt4gh::header::{DecryptedHeaderPackets, HeaderInfo};
 use crypt4gh::{body_decrypt, body_decrypt_parts, header, Keys, WriteInfo};
-use std::cmp::min;
+use pin_project_lite::pin_project;
+use std::cmp::{max, min};
+use std::collections::VecDeque;
 use std::io;
 use std::io::{BufWriter, Cursor, Read};
+use std::pin::Pin;
+use std::slice;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, ReadBuf};
+use tracing::debug;
 
+pub mod cache;
 mod edit;
 pub mod storage;
 
@@ -58,7 +68,8 @@ impl DeserializedHeader {
     }
   }
 
-  /// Grab all the required information from the header.
+  /// Grab all the required information from the header. The keys are tried in order, and the
+  /// index of the key which decrypted the header is recorded as a `key_index` event.
   /// This is more or less directly copied from https://github.com/EGA-archive/crypt4gh-rust/blob/2d41a1770067003bc67ab499841e0def186ed218/src/lib.rs#L283-L314
   pub fn from_buffer<R: Read>(read_buffer: &mut R, keys: &[Keys]) -> Result<Self, Crypt4GHError> {
     // Get header info
@@ -100,10 +111,25 @@ impl DeserializedHeader {
       })
       .collect::<Result<Vec<Vec<u8>>, Crypt4GHError>>()?;
 
-    let DecryptedHeaderPackets {
-      data_enc_packets: session_keys,
-      edit_list_packet,
-    } = header::deconstruct_header_body(encrypted_packets, keys, &None)?;
+    let (
+      key_index,
+      DecryptedHeaderPackets {
+        data_enc_packets: session_keys,
+        edit_list_packet,
+      },
+    ) = keys
+      .iter()
+      .enumerate()
+      .find_map(|(key_index, key)| {
+        header::deconstruct_header_body(encrypted_packets.clone(), slice::from_ref(key), &None)
+          .ok()
+          .filter(|packets| !packets.data_enc_packets.is_empty())
+          .map(|packets| (key_index, packets))
+      })
+      .ok_or(Crypt4GHError::NoValidHeaderPacket)?;
+
+    debug!(key_index, "decrypted Crypt4GH header");
+    record_key_index(key_index);
 
     let header_size = 16 + header_lengths;
 
@@ -119,6 +145,65 @@ impl DeserializedHeader {
   pub fn contains_edit_list(&self) -> bool {
     self.edit_list.is_some()
   }
+
+  /// Get the size of the plaintext after applying the edit list, where `data_size` is the size of
+  /// the decrypted data.
+  pub fn edited_size(&self, data_size: u64) -> u64 {
+    self
+      .kept_ranges(data_size)
+      .iter()
+      .map(|(start, end)| end - start)
+      .sum()
+  }
+
+  /// Map a range of the plaintext onto the ranges of the decrypted data that it is read from. These
+  /// are the same unless there is an edit list. `data_size` is the size of the decrypted data, or
+  /// `u64::MAX` if it is not known.
+  pub fn data_ranges(&self, range: &BytesPosition, data_size: u64) -> Vec<(u64, u64)> {
+    let start = range.start.unwrap_or_default();
+    let end = range.end.unwrap_or(u64::MAX);
+
+    let mut ranges = vec![];
+    let mut offset = 0;
+    for (data_start, data_end) in self.kept_ranges(data_size) {
+      let length = data_end - data_start;
+      let (range_start, range_end) = (max(start, offset), min(end, offset.saturating_add(length)));
+      if range_start < range_end {
+        ranges.push((
+          data_start + range_start - offset,
+          data_start + range_end - offset,
+        ));
+      }
+
+      offset = offset.saturating_add(length);
+    }
+
+    ranges
+  }
+
+  /// Get the ranges of the decrypted data which are kept by the edit list. The edit list
+  /// alternates between bytes to skip and bytes to keep, and keeps the remaining data if it ends
+  /// with a skip.
+  fn kept_ranges(&self, data_size: u64) -> Vec<(u64, u64)> {
+    let Some(edit_list) = &self.edit_list else {
+      return vec![(0, data_size)];
+    };
+
+    let mut position = 0u64;
+    edit_list
+      .chunks(2)
+      .map(|lengths| {
+        position = min(position.saturating_add(lengths[0]), data_size);
+        let start = position;
+        position = match lengths.get(1) {
+          Some(keep) => min(position.saturating_add(*keep), data_size),
+          None => data_size,
+        };
+
+        (start, position)
+      })
+      .collect()
+  }
 }
 
 /// Represents the decrypted data from a C4GH file.
@@ -157,6 +242,135 @@ impl DecryptedData {
   pub fn into_inner(self) -> Vec<u8> {
     self.0
   }
+
+  /// Get the number of decrypted bytes.
+  pub(crate) fn size(&self) -> usize {
+    self.0.len()
+  }
+}
+
+pin_project! {
+  /// Decrypts a stream of Crypt4GH data blocks as it is read, returning only the plaintext within
+  /// the decrypted data ranges. Only one data block is held in memory at a time.
+  pub struct DecryptReader<R> {
+    #[pin]
+    inner: R,
+    session_keys: Vec<Vec<u8>>,
+    ranges: VecDeque<(u64, u64)>,
+    position: u64,
+    block: Vec<u8>,
+    output: Cursor<Vec<u8>>,
+    finished: bool,
+  }
+}
+
+impl<R> DecryptReader<R> {
+  /// Create the reader from the encrypted data blocks, which start at the decrypted data
+  /// `position`, the session keys of the header and the ranges of the decrypted data to return.
+  /// The ranges must be sorted and non-overlapping.
+  pub fn new(inner: R, position: u64, session_keys: Vec<Vec<u8>>, ranges: Vec<(u64, u64)>) -> Self {
+    Self {
+      inner,
+      session_keys,
+      ranges: ranges.into(),
+      position,
+      block: Vec::with_capacity(DATA_BLOCK_SIZE as usize),
+      output: Default::default(),
+      finished: false,
+    }
+  }
+}
+
+impl<R: AsyncRead> AsyncRead for DecryptReader<R> {
+  fn poll_read(
+    self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+    buf: &mut ReadBuf<'_>,
+  ) -> Poll<io::Result<()>> {
+    let mut this = self.project();
+
+    loop {
+      let remaining = &this.output.get_ref()[this.output.position() as usize..];
+      if !remaining.is_empty() {
+        let length = min(remaining.len(), buf.remaining());
+        buf.put_slice(&remaining[..length]);
+        this
+          .output
+          .set_position(this.output.position() + length as u64);
+
+        return Poll::Ready(Ok(()));
+      }
+
+      if *this.finished || this.ranges.is_empty() {
+        return Poll::Ready(Ok(()));
+      }
+
+      // Fill a whole data block before decrypting it, keeping any partial block between polls.
+      let filled = this.block.len();
+      if filled < DATA_BLOCK_SIZE as usize {
+        this.block.resize(DATA_BLOCK_SIZE as usize, 0);
+        let mut read_buf = ReadBuf::new(&mut this.block[filled..]);
+        let poll = this.inner.as_mut().poll_read(cx, &mut read_buf);
+        let read = read_buf.filled().len();
+        this.block.truncate(filled + read);
+
+        ready!(poll)?;
+        if read != 0 {
+          continue;
+        }
+        *this.finished = true;
+      }
+
+      if this.block.is_empty() {
+        continue;
+      }
+
+      let block_start = *this.position;
+      let block_end = block_start + (this.block.len() as u64).saturating_sub(NONCE_SIZE + MAC_SIZE);
+      *this.position = block_end;
+
+      // Blocks before the first range do not need to be decrypted.
+      if this
+        .ranges
+        .front()
+        .is_some_and(|(start, _)| *start >= block_end)
+      {
+        this.block.clear();
+        continue;
+      }
+
+      let mut writer = Cursor::new(vec![]);
+      body_decrypt(
+        &mut this.block.as_slice(),
+        this.session_keys,
+        &mut WriteInfo::new(0, None, &mut writer),
+        0,
+      )
+      .map_err(io::Error::other)?;
+      this.block.clear();
+      let plaintext = writer.into_inner();
+
+      let mut output = vec![];
+      while let Some((start, end)) = this.ranges.front().copied() {
+        if start >= block_end {
+          break;
+        }
+
+        let (slice_start, slice_end) = (max(start, block_start), min(end, block_end));
+        if slice_start < slice_end {
+          output.extend_from_slice(
+            &plaintext[(slice_start - block_start) as usize..(slice_end - block_start) as usize],
+          );
+        }
+
+        if end > block_end {
+          break;
+        }
+        this.ranges.pop_front();
+      }
+      *this.output = Cursor::new(output);
+    }
+  }
 }
 
 /// Convert an encrypted file position to an unencrypted position if the header length is known.
@@ -348,4 +562,28 @@ mod tests {
     let result = unencrypted_clamp_next(pos, 0, to_encrypted_file_size(5485112, 0));
     assert_eq!(result, expected);
   }
+
+  #[test]
+  fn test_data_ranges_edit_list() {
+    let header = DeserializedHeader::new(
+      HeaderInfo {
+        magic_number: *b"crypt4gh",
+        version: 1,
+        packets_count: 0,
+      },
+      vec![],
+      0,
+      Some(vec![10, 5, 20, 5, 10]),
+    );
+
+    assert_eq!(header.edited_size(100), 60);
+    assert_eq!(
+      header.data_ranges(&BytesPosition::new(Some(3), Some(12), None), 100),
+      vec![(13, 15), (35, 40), (50, 52)]
+    );
+    assert_eq!(
+      header.data_ranges(&BytesPosition::default().with_start(58), 100),
+      vec![(98, 100)]
+    );
+  }
 }
@@ -1,32 +1,122 @@
 //! Local Crypt4GH storage access.
 //!
 
+use crate::c4gh::cache::C4GHStateCache;
 use crate::c4gh::edit::{ClampedPosition, EditHeader, UnencryptedPosition};
 use crate::c4gh::{
   to_unencrypted_file_size, unencrypted_clamp, unencrypted_clamp_next, unencrypted_to_data_block,
-  unencrypted_to_next_data_block, DecryptedData, DeserializedHeader,
+  unencrypted_to_next_data_block, DecryptReader, DecryptedData, DeserializedHeader,
+  ENCRYPTED_BLOCK_SIZE,
 };
-use crate::error::StorageError::{InternalError, IoError};
+use crate::error::StorageError::{InternalError, InvalidInput, IoError};
 use crate::error::{Result, StorageError};
-use crate::types::BytesPosition;
+use crate::types::{BytesPosition, BytesRange};
 use crate::{
   BytesPositionOptions, DataBlock, GetOptions, HeadOptions, RangeUrlOptions, StorageMiddleware,
-  StorageTrait, Streamable,
+  StorageTrait, Streamable, UrlFormatter,
 };
 use async_trait::async_trait;
+use base64::engine::general_purpose;
+use base64::Engine;
 use crypt4gh::error::Crypt4GHError;
 use crypt4gh::Keys;
+use hmac::{Hmac, Mac};
+use htsget_config::storage::file::File;
 use htsget_config::types::{Class, Format, Url};
+use http::HeaderMap;
+use sha2::Sha256;
 use std::cmp::min;
 use std::collections::HashMap;
 use std::fmt::{Debug, Formatter};
 use std::io;
 use std::io::{BufReader, Cursor, Read};
+use std::time::{SystemTime, UNIX_EPOCH};
 use tokio::io::AsyncReadExt;
+use tracing::debug;
 
 /// Max C4GH header size in bytes. Supports 50 regular sized encrypted packets. 16 + (108 * 50).
 const MAX_C4GH_HEADER_SIZE: u64 = 5416;
 
+/// The request header which clients use to supply their own Crypt4GH public key. The value is
+/// the base64 encoded public key file, or the base64 encoded raw 32 byte key.
+pub const CLIENT_PUBLIC_KEY_HEADER: &str = "Htsget-Context-Public-Key";
+
+/// The path of the data server endpoint which returns decrypted byte ranges of C4GH objects.
+pub const DECRYPT_PATH: &str = "decrypt";
+
+/// The query parameter of a decrypt ticket which identifies the location keys.
+pub const DECRYPT_KEYS_PARAM: &str = "keys";
+
+/// The query parameter of a decrypt ticket which holds the signature.
+pub const DECRYPT_SIGNATURE_PARAM: &str = "signature";
+
+/// The query parameter of a decrypt ticket which holds the time that the ticket expires, in
+/// seconds since the Unix epoch.
+pub const DECRYPT_EXPIRES_PARAM: &str = "expires";
+
+/// Create the HMAC over a decrypt ticket, which binds the location keys, the key of the object,
+/// the value of the `Range` header and the expiry of the ticket.
+fn decrypt_ticket_mac(
+  secret: &[u8],
+  keys_id: u64,
+  key: &str,
+  range: &str,
+  expires: u64,
+) -> Hmac<Sha256> {
+  let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any size");
+  mac.update(format!("{}\n{}\n{}\n{}", keys_id, key, range, expires).as_bytes());
+  mac
+}
+
+/// Get the current time in seconds since the Unix epoch.
+fn unix_time() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .unwrap_or_default()
+    .as_secs()
+}
+
+/// Sign a decrypt ticket for the object at the key, where `range` is the value of the `Range`
+/// header, or empty if there is no range, and `expires` is the time that the ticket expires in
+/// seconds since the Unix epoch. Returns the URL safe base64 encoded signature.
+pub fn sign_decrypt_ticket(
+  secret: &[u8],
+  keys_id: u64,
+  key: &str,
+  range: &str,
+  expires: u64,
+) -> String {
+  general_purpose::URL_SAFE_NO_PAD.encode(
+    decrypt_ticket_mac(secret, keys_id, key, range, expires)
+      .finalize()
+      .into_bytes(),
+  )
+}
+
+/// Verify the signature of a decrypt ticket created with `sign_decrypt_ticket`, and check that
+/// the ticket has not expired.
+pub fn verify_decrypt_ticket(
+  secret: &[u8],
+  keys_id: u64,
+  key: &str,
+  range: &str,
+  expires: u64,
+  signature: &str,
+) -> bool {
+  expires > unix_time()
+    && general_purpose::URL_SAFE_NO_PAD
+      .decode(signature)
+      .is_ok_and(|signature| {
+        decrypt_ticket_mac(secret, keys_id, key, range, expires)
+          .verify_slice(&signature)
+          .is_ok()
+      })
+}
+
+const PUBLIC_KEY_BEGIN: &str = "-----BEGIN CRYPT4GH PUBLIC KEY-----";
+const PUBLIC_KEY_END: &str = "-----END CRYPT4GH PUBLIC KEY-----";
+const PUBLIC_KEY_SIZE: usize = 32;
+
 /// This represents the state that the C4GHStorage needs to save, like the file sizes and header
 /// sizes.
 #[derive(Debug, Clone)]
@@ -35,22 +125,44 @@ pub struct C4GHState {
   unencrypted_file_size: u64,
   deserialized_header: DeserializedHeader,
   decrypted_data: DecryptedData,
+  fetched_end: u64,
+}
+
+impl C4GHState {
+  /// Get the number of bytes held by the state.
+  pub(crate) fn size(&self) -> usize {
+    self.deserialized_header.header_size as usize + self.decrypted_data.size()
+  }
 }
 
 /// Implementation for the [StorageTrait] trait using the local file system for accessing Crypt4GH
 /// encrypted files. [T] is the type of the server struct, which is used for formatting urls.
 pub struct C4GHStorage {
   keys: Vec<Keys>,
+  recipient_keys: Vec<Keys>,
   inner: Box<dyn StorageTrait + Send + Sync + 'static>,
   state: HashMap<String, C4GHState>,
+  state_cache: Option<C4GHStateCache>,
+  decrypt: Option<File>,
+  keys_id: u64,
+  ticket_secret: Vec<u8>,
+  ticket_expires_in: u64,
+  encrypted_indexes: bool,
 }
 
 impl Clone for C4GHStorage {
   fn clone(&self) -> Self {
     Self {
       keys: self.keys.clone(),
+      recipient_keys: self.recipient_keys.clone(),
       inner: self.inner.clone_box(),
       state: self.state.clone(),
+      state_cache: self.state_cache.clone(),
+      decrypt: self.decrypt.clone(),
+      keys_id: self.keys_id,
+      ticket_secret: self.ticket_secret.clone(),
+      ticket_expires_in: self.ticket_expires_in,
+      encrypted_indexes: self.encrypted_indexes,
     }
   }
 }
@@ -67,24 +179,180 @@ impl C4GHStorage {
     Self::new_box(keys, Box::new(inner))
   }
 
-  /// Create a new value from a boxed storage trait.
+  /// Create a new value from a boxed storage trait. The keys are used both to decrypt headers and
+  /// to re-encrypt them, unless recipient keys are set.
   pub fn new_box(keys: Vec<Keys>, inner: Box<dyn StorageTrait + Send + Sync + 'static>) -> Self {
     Self {
+      recipient_keys: keys.clone(),
       keys,
       inner,
       state: Default::default(),
+      state_cache: None,
+      decrypt: None,
+      keys_id: 0,
+      ticket_secret: vec![],
+      ticket_expires_in: 0,
+      encrypted_indexes: false,
     }
   }
 
+  /// Set the cache used to share state between requests.
+  pub fn with_state_cache(mut self, state_cache: Option<C4GHStateCache>) -> Self {
+    self.state_cache = state_cache;
+    self
+  }
+
+  /// Return plaintext byte ranges, with tickets pointing at the decrypt endpoint of the data
+  /// server rather than the encrypted object.
+  pub fn with_decrypt(mut self, data_server: Option<File>) -> Self {
+    self.decrypt = data_server;
+    self
+  }
+
+  /// Set the id of the location keys, which decrypt tickets are signed for.
+  pub fn with_keys_id(mut self, keys_id: u64) -> Self {
+    self.keys_id = keys_id;
+    self
+  }
+
+  /// Set the secret used to sign decrypt tickets, and the number of seconds they are valid for.
+  pub fn with_ticket_secret(mut self, secret: Vec<u8>, expires_in: u64) -> Self {
+    self.ticket_secret = secret;
+    self.ticket_expires_in = expires_in;
+    self
+  }
+
+  /// Look for an encrypted index at the C4GH key before the plaintext index.
+  pub fn with_encrypted_indexes(mut self, encrypted_indexes: bool) -> Self {
+    self.encrypted_indexes = encrypted_indexes;
+    self
+  }
+
+  /// Set the keys used to re-encrypt headers for recipients.
+  pub fn with_recipient_keys(mut self, recipient_keys: Vec<Keys>) -> Self {
+    self.recipient_keys = recipient_keys;
+    self
+  }
+
   /// Format a C4GH key.
   pub fn format_key(key: &str) -> String {
     format!("{}.c4gh", key)
   }
 
-  /// Get a C4GH object and decrypt it if it is not an index.
+  /// Parse a client public key from the value of the [CLIENT_PUBLIC_KEY_HEADER].
+  pub fn parse_public_key(value: &[u8]) -> Result<Vec<u8>> {
+    let invalid = || InvalidInput(format!("invalid `{}` header", CLIENT_PUBLIC_KEY_HEADER));
+    let decode = |value: &[u8]| {
+      general_purpose::STANDARD
+        .decode(value.trim_ascii())
+        .map_err(|_| invalid())
+    };
+
+    let public_key = decode(value)?;
+    if public_key.len() == PUBLIC_KEY_SIZE {
+      return Ok(public_key);
+    }
+
+    let public_key = String::from_utf8(public_key).map_err(|_| invalid())?;
+    let public_key = public_key
+      .trim()
+      .strip_prefix(PUBLIC_KEY_BEGIN)
+      .and_then(|key| key.strip_suffix(PUBLIC_KEY_END))
+      .ok_or_else(invalid)?;
+
+    let public_key = decode(public_key.as_bytes())?;
+    if public_key.len() != PUBLIC_KEY_SIZE {
+      return Err(invalid());
+    }
+
+    Ok(public_key)
+  }
+
+  /// Get the keys used to re-encrypt the header for a client that supplied their own public key.
+  /// The header is then encrypted for that recipient only, instead of the configured recipients.
+  pub fn client_keys(&self, headers: &HeaderMap) -> Result<Option<Vec<Keys>>> {
+    let Some(public_key) = headers.get(CLIENT_PUBLIC_KEY_HEADER) else {
+      return Ok(None);
+    };
+    let public_key = Self::parse_public_key(public_key.as_bytes())?;
+
+    let key = self
+      .recipient_keys
+      .first()
+      .ok_or_else(|| InternalError("missing Crypt4GH keys".to_string()))?;
+
+    Ok(Some(vec![Keys {
+      method: key.method,
+      privkey: key.privkey.clone(),
+      recipient_pubkey: public_key,
+    }]))
+  }
+
+  /// Read the header from the start of a C4GH stream, returning the deserialized header and the
+  /// remaining stream, which is positioned at the start of the encrypted data.
+  async fn read_header(
+    &self,
+    mut encrypted: Streamable,
+  ) -> Result<(DeserializedHeader, Streamable)> {
+    let mut buf = vec![];
+    (&mut encrypted)
+      .take(MAX_C4GH_HEADER_SIZE)
+      .read_to_end(&mut buf)
+      .await?;
+
+    let mut reader = buf.as_slice();
+    let deserialized_header = DeserializedHeader::from_buffer(&mut reader, &self.keys)?;
+    let body = AsyncReadExt::chain(Cursor::new(reader.to_vec()), encrypted);
+
+    Ok((deserialized_header, Streamable::from_async_read(body)))
+  }
+
+  /// Whether a missing encrypted index should fall back to the plaintext index. Some backends
+  /// deny access rather than report a missing key, e.g. S3 without `s3:ListBucket` permissions.
+  fn is_absent(err: &StorageError) -> bool {
+    matches!(
+      err,
+      StorageError::KeyNotFound(_) | StorageError::PermissionDenied(_)
+    )
+  }
+
+  /// Get an index, decrypting it if encrypted indexes are enabled and there is an encrypted index
+  /// at the C4GH key. Otherwise, the plaintext index is returned. The index is decrypted as it is
+  /// read.
+  pub async fn get_index(&self, key: &str, options: GetOptions<'_>) -> Result<Streamable> {
+    if !self.encrypted_indexes {
+      return self.inner.get(key, options).await;
+    }
+
+    let encrypted = match self
+      .inner
+      .get(
+        &Self::format_key(key),
+        GetOptions::new_with_default_range(options.request_headers())
+          .with_etag(options.etag().map(str::to_string)),
+      )
+      .await
+    {
+      Ok(encrypted) => encrypted,
+      Err(err) if Self::is_absent(&err) => return self.inner.get(key, options).await,
+      Err(err) => return Err(err),
+    };
+
+    let (deserialized_header, body) = self.read_header(encrypted).await?;
+    let ranges = deserialized_header.data_ranges(options.range(), u64::MAX);
+
+    Ok(Streamable::from_async_read(DecryptReader::new(
+      body,
+      0,
+      deserialized_header.session_keys,
+      ranges,
+    )))
+  }
+
+  /// Get a C4GH object and decrypt it. Indexes are decrypted if they are encrypted.
   pub async fn get_object(&self, key: &str, options: GetOptions<'_>) -> Result<Streamable> {
     if Format::is_index(key) {
-      return self.inner.get(key, options).await;
+      return self.get_index(key, options).await;
     }
 
     let data = self
@@ -98,6 +366,127 @@ impl C4GHStorage {
     )))
   }
 
+  /// Decrypt an unencrypted byte range of a C4GH object, returning the plaintext stream and the
+  /// size of the unencrypted object. Only the data blocks which overlap the range are fetched,
+  /// and they are decrypted as the stream is read. If the object contains an edit list, the range
+  /// refers to the plaintext after applying the edit list.
+  pub async fn decrypt_range(
+    &self,
+    key: &str,
+    options: GetOptions<'_>,
+  ) -> Result<(Streamable, u64)> {
+    let range = options.range().clone();
+    self.decrypt_range_with(key, options, |_| range).await
+  }
+
+  /// Decrypt a byte range of a C4GH object like `decrypt_range`, where the range is resolved from
+  /// the size of the unencrypted object. This supports ranges which are relative to the end of
+  /// the object.
+  pub async fn decrypt_range_with(
+    &self,
+    key: &str,
+    options: GetOptions<'_>,
+    resolve: impl FnOnce(u64) -> BytesPosition,
+  ) -> Result<(Streamable, u64)> {
+    let key = Self::format_key(key);
+    let encrypted_file_size = self.inner.head(&key, (&options).into()).await?;
+
+    let header = self
+      .inner
+      .get(
+        &key,
+        options.clone().with_range(
+          BytesPosition::default()
+            .with_start(0)
+            .with_end(min(MAX_C4GH_HEADER_SIZE, encrypted_file_size)),
+        ),
+      )
+      .await?;
+    let (deserialized_header, _) = self.read_header(header).await?;
+    let header_size = deserialized_header.header_size;
+
+    let data_size = to_unencrypted_file_size(encrypted_file_size, header_size);
+    let unencrypted_file_size = deserialized_header.edited_size(data_size);
+
+    let range = resolve(unencrypted_file_size);
+    let ranges = deserialized_header.data_ranges(&range, data_size);
+    let (Some((start, _)), Some((_, end))) = (ranges.first().copied(), ranges.last().copied())
+    else {
+      return Ok((
+        Streamable::from_async_read(Cursor::new(vec![])),
+        unencrypted_file_size,
+      ));
+    };
+
+    // Some storage backends ignore the range end, so the fetched bytes are also limited here.
+    let fetch_start = unencrypted_to_data_block(start, header_size, encrypted_file_size);
+    let fetch_end = unencrypted_to_next_data_block(end - 1, header_size, encrypted_file_size);
+
+    let encrypted = self
+      .inner
+      .get(
+        &key,
+        options.clone().with_range(
+          BytesPosition::default()
+            .with_start(fetch_start)
+            .with_end(fetch_end),
+        ),
+      )
+      .await?
+      .take(fetch_end - fetch_start);
+
+    Ok((
+      Streamable::from_async_read(DecryptReader::new(
+        encrypted,
+        start - start % ENCRYPTED_BLOCK_SIZE,
+        deserialized_header.session_keys,
+        ranges,
+      )),
+      unencrypted_file_size,
+    ))
+  }
+
+  /// Get the end of the encrypted bytes which are fetched to decrypt the object up to the
+  /// unencrypted range end.
+  fn fetch_end(range_end: Option<u64>, header_size: u64, encrypted_file_size: u64) -> u64 {
+    if encrypted_file_size <= MAX_C4GH_HEADER_SIZE {
+      return encrypted_file_size;
+    }
+
+    let end = unencrypted_to_next_data_block(
+      range_end.unwrap_or(encrypted_file_size),
+      header_size,
+      encrypted_file_size,
+    );
+    if end < MAX_C4GH_HEADER_SIZE {
+      encrypted_file_size
+    } else {
+      min(end, encrypted_file_size)
+    }
+  }
+
+  /// Get the key that identifies an object in the state cache.
+  fn cache_key(key: &str, etag: &str, encrypted_file_size: u64) -> String {
+    format!("{}#{}#{}", key, etag, encrypted_file_size)
+  }
+
+  /// Get the cached state of an object, if it contains the data up to the range end.
+  fn cached_state(
+    &self,
+    cache_key: Option<&str>,
+    range_end: Option<u64>,
+    encrypted_file_size: u64,
+  ) -> Option<C4GHState> {
+    let state = self.state_cache.as_ref()?.get(cache_key?)?;
+
+    let fetch_end = Self::fetch_end(
+      range_end,
+      state.deserialized_header.header_size,
+      encrypted_file_size,
+    );
+    (state.fetched_end >= fetch_end).then(|| C4GHState::clone(&state))
+  }
+
   /// Get the size of the unencrypted object and update state.
   pub async fn preprocess_for_state(
     &mut self,
@@ -113,6 +502,23 @@ impl C4GHStorage {
     // Get the file size.
     let encrypted_file_size = self.inner.head(&key, (&options).into()).await?;
 
+    // Reuse the state from a previous request if it contains the required data. The state is
+    // keyed by the etag that the search captured, so objects are only cached if the location
+    // verifies etags, as a modified object with the same size cannot otherwise be detected.
+    let cache_key = options
+      .etag()
+      .filter(|_| self.state_cache.is_some())
+      .map(|etag| Self::cache_key(&key, etag, encrypted_file_size));
+    if let Some(state) =
+      self.cached_state(cache_key.as_deref(), options.range.end, encrypted_file_size)
+    {
+      debug!(key, "using cached Crypt4GH state");
+
+      let unencrypted_file_size = state.unencrypted_file_size;
+      self.state.insert(key, state);
+      return Ok(unencrypted_file_size);
+    }
+
     let mut c4gh_header_options = options.clone();
     c4gh_header_options.range.end = Some(min(MAX_C4GH_HEADER_SIZE, encrypted_file_size));
 
@@ -135,19 +541,14 @@ impl C4GHStorage {
     // Grab remaining bytes after knowing the header size.
     let mut remaining = vec![];
 
+    let fetched_end = Self::fetch_end(
+      options.range.end,
+      deserialized_header.header_size,
+      encrypted_file_size,
+    );
     if encrypted_file_size > MAX_C4GH_HEADER_SIZE {
-      let end = unencrypted_to_next_data_block(
-        options.range.end.unwrap_or(encrypted_file_size),
-        deserialized_header.header_size,
-        encrypted_file_size,
-      );
       options.range.start = Some(MAX_C4GH_HEADER_SIZE);
-
-      if end < MAX_C4GH_HEADER_SIZE {
-        options.range.end = None;
-      } else {
-        options.range.end = Some(min(end, encrypted_file_size));
-      }
+      options.range.end = Some(fetched_end);
 
       self
         .inner
@@ -165,8 +566,12 @@ impl C4GHStorage {
       unencrypted_file_size,
       deserialized_header,
       decrypted_data,
+      fetched_end,
     };
 
+    if let (Some(state_cache), Some(cache_key)) = (&self.state_cache, cache_key) {
+      state_cache.insert(cache_key, state.clone());
+    }
     self.state.insert(key, state);
 
     Ok(unencrypted_file_size)
@@ -224,6 +629,11 @@ impl C4GHStorage {
       encrypted_positions.push(pos);
     }
 
+    // Plaintext ranges are decrypted by the data server, so there is no header to re-encrypt.
+    if self.decrypt.is_some() {
+      return Ok(DataBlock::from_bytes_positions(unencrypted_positions));
+    }
+
     let unencrypted_positions = BytesPosition::merge_all(unencrypted_positions)
       .into_iter()
       .map(|pos| UnencryptedPosition::new(default_start(&pos), default_end(&pos)))
@@ -233,29 +643,36 @@ impl C4GHStorage {
       .map(|pos| ClampedPosition::new(default_start(&pos), default_end(&pos)))
       .collect::<Vec<_>>();
 
+    // The existing header packets cannot be read using a client supplied key, so they are only
+    // kept if the client did not supply their own key.
+    let client_keys = self.client_keys(options.headers)?;
+    let keep_existing_packets = client_keys.is_none();
+    let keys = client_keys.as_deref().unwrap_or(&self.recipient_keys);
+
     let (header_info, reencrypted_bytes, edit_list_packet) = EditHeader::new(
       unencrypted_positions,
       clamped_positions,
-      &self.keys,
+      keys,
       &state.deserialized_header,
     )
+    .with_keep_existing_packets(keep_existing_packets)
     .reencrypt_header()?
     .into_inner();
 
     let header_info_size = header_info.len() as u64;
     let current_header_size = state.deserialized_header.header_size;
-    let mut blocks = vec![
-      DataBlock::Data(header_info, Some(Class::Header)),
-      DataBlock::Range(
+    let mut blocks = vec![DataBlock::Data(header_info, Some(Class::Header))];
+    if keep_existing_packets {
+      blocks.push(DataBlock::Range(
         BytesPosition::default()
           .with_start(header_info_size)
           .with_end(current_header_size),
-      ),
-      DataBlock::Data(
-        [edit_list_packet, reencrypted_bytes].concat(),
-        Some(Class::Header),
-      ),
-    ];
+      ));
+    }
+    blocks.push(DataBlock::Data(
+      [edit_list_packet, reencrypted_bytes].concat(),
+      Some(Class::Header),
+    ));
 
     blocks.extend(DataBlock::from_bytes_positions(BytesPosition::merge_all(
       encrypted_positions,
@@ -288,8 +705,25 @@ impl StorageTrait for C4GHStorage {
     self.get_object(key, options).await
   }
 
-  /// Get a url for the file at key. This refers to the underlying `StorageTrait`.
+  /// Get a url for the file at key. This refers to the underlying `StorageTrait`, or the decrypt
+  /// endpoint of the data server when returning plaintext.
   async fn range_url(&self, key: &str, options: RangeUrlOptions<'_>) -> Result<Url> {
+    if let Some(data_server) = &self.decrypt {
+      let range = String::from(&BytesRange::from(options.range()));
+      let expires = unix_time().saturating_add(self.ticket_expires_in);
+      let url = format!(
+        "{}?{}={}&{}={}&{}={}",
+        data_server.format_url(format!("{}/{}", DECRYPT_PATH, key))?,
+        DECRYPT_KEYS_PARAM,
+        self.keys_id,
+        DECRYPT_EXPIRES_PARAM,
+        expires,
+        DECRYPT_SIGNATURE_PARAM,
+        sign_decrypt_ticket(&self.ticket_secret, self.keys_id, key, &range, expires)
+      );
+      return Ok(options.apply(Url::new(url)));
+    }
+
     self.inner.range_url(&Self::format_key(key), options).await
   }
 
@@ -303,6 +737,51 @@ impl StorageTrait for C4GHStorage {
         .unencrypted_file_size,
     )
   }
+
+  /// Check whether the encrypted file exists in the underlying `StorageTrait`. Indexes can either
+  /// be encrypted, if encrypted indexes are enabled, or plaintext.
+  async fn exists(&self, key: &str, options: HeadOptions<'_>) -> Result<bool> {
+    if Format::is_index(key) {
+      if self.encrypted_indexes {
+        match self
+          .inner
+          .exists(&Self::format_key(key), options.clone())
+          .await
+        {
+          Ok(true) => return Ok(true),
+          Ok(false) => {}
+          Err(err) if Self::is_absent(&err) => {}
+          Err(err) => return Err(err),
+        }
+      }
+
+      return self.inner.exists(key, options).await;
+    }
+
+    self.inner.exists(&Self::format_key(key), options).await
+  }
+
+  /// Get the etag of the encrypted file in the underlying `StorageTrait`. This is the etag of the
+  /// encrypted index if encrypted indexes are enabled and it exists, and otherwise the plaintext
+  /// index.
+  async fn etag(&self, key: &str, options: HeadOptions<'_>) -> Result<Option<String>> {
+    if Format::is_index(key) {
+      if !self.encrypted_indexes {
+        return self.inner.etag(key, options).await;
+      }
+
+      return match self
+        .inner
+        .etag(&Self::format_key(key), options.clone())
+        .await
+      {
+        Err(err) if Self::is_absent(&err) => self.inner.etag(key, options).await,
+        etag => etag,
+      };
+    }
+
+    self.inner.etag(&Self::format_key(key), options).await
+  }
 }
 
 impl From<Crypt4GHError> for StorageError {
@@ -319,10 +798,15 @@ mod tests {
   use crate::s3::tests::with_aws_s3_storage;
   #[cfg(feature = "url")]
   use crate::url::tests::{test_headers, with_url_test_server};
+  use crypt4gh::decrypt;
+  use crypt4gh::keys::{get_private_key, get_public_key};
+  use htsget_config::storage::c4gh::C4GHKeys;
   use htsget_config::types::Headers;
   use htsget_test::c4gh::{encrypt_data, get_decryption_keys};
+  use htsget_test::util::default_dir;
   use http::HeaderMap;
   use std::future::Future;
+  use std::io::BufWriter;
   use std::path::Path;
   use tokio::fs::{read, File};
   use tokio::io::AsyncWriteExt;
@@ -485,6 +969,544 @@ mod tests {
     .await;
   }
 
+  #[tokio::test]
+  async fn test_postprocess_with_client_public_key() {
+    with_local_c4gh_storage(|mut storage| async move {
+      let public_key = read(default_dir().join("data/c4gh/keys/bob.pub"))
+        .await
+        .unwrap();
+      let mut headers = HeaderMap::default();
+      headers.insert(
+        CLIENT_PUBLIC_KEY_HEADER,
+        general_purpose::STANDARD
+          .encode(public_key)
+          .parse()
+          .unwrap(),
+      );
+
+      let options = GetOptions::new_with_default_range(&headers);
+      storage.preprocess("folder/key", options).await.unwrap();
+
+      let blocks = storage
+        .postprocess(
+          "folder/key",
+          BytesPositionOptions::new(
+            vec![BytesPosition::default().with_start(0).with_end(6)],
+            &headers,
+          ),
+        )
+        .await
+        .unwrap();
+      assert_eq!(blocks.len(), 3);
+
+      let mut encrypted = vec![];
+      for block in blocks {
+        match block {
+          DataBlock::Data(data, _) => encrypted.extend(data),
+          DataBlock::Range(range) => {
+            storage
+              .inner
+              .get("folder/key.c4gh", GetOptions::new(range, &headers))
+              .await
+              .unwrap()
+              .read_to_end(&mut encrypted)
+              .await
+              .unwrap();
+          }
+        }
+      }
+
+      let private_key = get_private_key(
+        default_dir().join("data/c4gh/keys/bob.sec"),
+        Ok("".to_string()),
+      )
+      .unwrap();
+      let mut writer = BufWriter::new(Cursor::new(vec![]));
+      decrypt(
+        &[Keys {
+          method: 0,
+          privkey: private_key,
+          recipient_pubkey: vec![],
+        }],
+        &mut BufReader::new(Cursor::new(encrypted)),
+        &mut writer,
+        0,
+        None,
+        &None,
+      )
+      .unwrap();
+
+      assert_eq!(writer.into_inner().unwrap().into_inner(), b"value1");
+    })
+    .await;
+  }
+
+  #[tokio::test]
+  async fn test_postprocess_with_invalid_client_public_key() {
+    with_local_c4gh_storage(|mut storage| async move {
+      let mut headers = HeaderMap::default();
+      headers.insert(CLIENT_PUBLIC_KEY_HEADER, "invalid".parse().unwrap());
+
+      let options = GetOptions::new_with_default_range(&headers);
+      storage.preprocess("folder/key", options).await.unwrap();
+
+      let result = storage
+        .postprocess(
+          "folder/key",
+          BytesPositionOptions::new(
+            vec![BytesPosition::default().with_start(0).with_end(6)],
+            &headers,
+          ),
+        )
+        .await;
+      assert!(matches!(result, Err(InvalidInput(_))));
+    })
+    .await;
+  }
+
+  #[tokio::test]
+  async fn test_preprocess_with_rotated_keys() {
+    with_local_storage(|storage, base_path| async move {
+      create_encrypted_files(&base_path).await;
+
+      let mut keys = vec![Keys {
+        method: 0,
+        privkey: get_private_key(
+          default_dir().join("data/c4gh/keys/alice.sec"),
+          Ok("".to_string()),
+        )
+        .unwrap(),
+        recipient_pubkey: vec![],
+      }];
+      keys.extend(get_decryption_keys().await);
+
+      let mut storage = C4GHStorage::new(keys, storage);
+      test_preprocess(&mut storage, "folder/key", &Default::default()).await;
+    })
+    .await;
+  }
+
+  #[tokio::test]
+  async fn test_postprocess_with_multiple_recipients() {
+    with_local_c4gh_storage(|storage| async move {
+      let public_key = get_public_key(default_dir().join("data/c4gh/keys/bob.pub")).unwrap();
+      let mut recipient_keys = storage.recipient_keys.clone();
+      recipient_keys.push(Keys {
+        recipient_pubkey: public_key,
+        ..recipient_keys[0].clone()
+      });
+      let mut storage = storage.with_recipient_keys(recipient_keys);
+
+      let headers = HeaderMap::default();
+      let options = GetOptions::new_with_default_range(&headers);
+      storage.preprocess("folder/key", options).await.unwrap();
+
+      let blocks = storage
+        .postprocess(
+          "folder/key",
+          BytesPositionOptions::new(
+            vec![BytesPosition::default().with_start(0).with_end(6)],
+            &headers,
+          ),
+        )
+        .await
+        .unwrap();
+
+      // The existing packet, and an edit list and data encryption packet for each recipient.
+      assert_eq!(
+        blocks[0],
+        DataBlock::Data(
+          vec![99, 114, 121, 112, 116, 52, 103, 104, 1, 0, 0, 0, 5, 0, 0, 0],
+          Some(Class::Header)
+        )
+      );
+    })
+    .await;
+  }
+
+  #[cfg(feature = "aws")]
+  #[tokio::test]
+  async fn test_preprocess_with_state_cache() {
+    with_aws_s3_storage(|storage, base_path| async move {
+      create_encrypted_files(&base_path).await;
+
+      let state_cache = C4GHStateCache::new(1024 * 1024);
+      let keys = get_decryption_keys().await;
+      let headers = HeaderMap::default();
+      let etag = |storage: C4GHStorage| async move {
+        storage
+          .etag("folder/key", HeadOptions::new(&HeaderMap::default()))
+          .await
+          .unwrap()
+      };
+
+      let mut first =
+        C4GHStorage::new(keys.clone(), storage.clone()).with_state_cache(Some(state_cache.clone()));
+      let first_etag = etag(first.clone()).await;
+      assert!(first_etag.is_some());
+      first
+        .preprocess(
+          "folder/key",
+          GetOptions::new_with_default_range(&headers).with_etag(first_etag.clone()),
+        )
+        .await
+        .unwrap();
+      assert_eq!(state_cache.len(), 1);
+
+      let mut second =
+        C4GHStorage::new(keys.clone(), storage.clone()).with_state_cache(Some(state_cache.clone()));
+      second
+        .preprocess(
+          "folder/key",
+          GetOptions::new_with_default_range(&headers).with_etag(first_etag),
+        )
+        .await
+        .unwrap();
+      assert_eq!(state_cache.len(), 1);
+
+      // Overwrite the object with the same size, which changes the etag so the cached state is
+      // not used.
+      let path = base_path.join("folder/key.c4gh");
+      let size = read(&path).await.unwrap().len();
+      File::create(&path)
+        .await
+        .unwrap()
+        .write_all(&vec![0; size])
+        .await
+        .unwrap();
+
+      let mut third = C4GHStorage::new(keys, storage).with_state_cache(Some(state_cache));
+      let third_etag = etag(third.clone()).await;
+      assert!(third
+        .preprocess(
+          "folder/key",
+          GetOptions::new_with_default_range(&headers).with_etag(third_etag)
+        )
+        .await
+        .is_err());
+    })
+    .await;
+  }
+
+  #[tokio::test]
+  async fn test_preprocess_without_etag_is_not_cached() {
+    with_local_storage(|storage, base_path| async move {
+      create_encrypted_files(&base_path).await;
+
+      let state_cache = C4GHStateCache::new(1024 * 1024);
+      let mut storage = C4GHStorage::new(get_decryption_keys().await, storage)
+        .with_state_cache(Some(state_cache.clone()));
+      test_preprocess(&mut storage, "folder/key", &Default::default()).await;
+
+      assert!(state_cache.is_empty());
+    })
+    .await;
+  }
+
+  #[tokio::test]
+  async fn test_state_cache_evicts_least_recently_used() {
+    with_local_c4gh_storage(|mut storage| async move {
+      storage
+        .preprocess(
+          "folder/key",
+          GetOptions::new_with_default_range(&Default::default()),
+        )
+        .await
+        .unwrap();
+      let state = storage.state.get("folder/key.c4gh").unwrap().clone();
+      let size = state.size();
+
+      let cache = C4GHStateCache::new(size * 2);
+      cache.insert("a".to_string(), state.clone());
+      cache.insert("b".to_string(), state.clone());
+
+      assert!(cache.get("a").is_some());
+      cache.insert("c".to_string(), state.clone());
+
+      assert_eq!(cache.len(), 2);
+      assert_eq!(cache.size(), size * 2);
+      assert!(cache.get("b").is_none());
+      assert!(cache.get("a").is_some());
+      assert!(cache.get("c").is_some());
+
+      let disabled = C4GHStateCache::new(0);
+      disabled.insert("a".to_string(), state);
+      assert!(disabled.is_empty());
+    })
+    .await;
+  }
+
+  #[tokio::test]
+  async fn test_state_cache_is_shared_by_key_clones() {
+    with_local_c4gh_storage(|mut storage| async move {
+      storage
+        .preprocess(
+          "folder/key",
+          GetOptions::new_with_default_range(&Default::default()),
+        )
+        .await
+        .unwrap();
+      let state = storage.state.get("folder/key.c4gh").unwrap().clone();
+
+      let keys = C4GHKeys::from_join_handle(tokio::spawn(async {
+        Err(htsget_config::error::Error::ParseError("keys".to_string()))
+      }));
+      C4GHStateCache::for_keys(&keys).insert("a".to_string(), state);
+
+      assert_eq!(C4GHStateCache::for_keys(&keys.clone()).len(), 1);
+      assert!(
+        C4GHStateCache::for_keys(&C4GHKeys::from_join_handle(tokio::spawn(async {
+          Err(htsget_config::error::Error::ParseError("keys".to_string()))
+        })))
+        .is_empty()
+      );
+    })
+    .await;
+  }
+
+  #[tokio::test]
+  async fn test_get_encrypted_index() {
+    with_local_storage(|storage, base_path| async move {
+      File::create(base_path.join("folder/index.bam.bai.c4gh"))
+        .await
+        .unwrap()
+        .write_all(&encrypt_data(b"encrypted index"))
+        .await
+        .unwrap();
+      File::create(base_path.join("folder/plaintext.bam.bai"))
+        .await
+        .unwrap()
+        .write_all(b"plaintext index")
+        .await
+        .unwrap();
+
+      let storage =
+        C4GHStorage::new(get_decryption_keys().await, storage).with_encrypted_indexes(true);
+      let headers = HeaderMap::default();
+
+      for (key, range, expected) in [
+        (
+          "folder/index.bam.bai",
+          BytesPosition::default(),
+          &b"encrypted index"[..],
+        ),
+        (
+          "folder/index.bam.bai",
+          BytesPosition::default().with_start(10).with_end(15),
+          &b"index"[..],
+        ),
+        (
+          "folder/plaintext.bam.bai",
+          BytesPosition::default(),
+          &b"plaintext index"[..],
+        ),
+      ] {
+        assert!(storage
+          .exists(key, HeadOptions::new(&headers))
+          .await
+          .unwrap());
+
+        let mut index = vec![];
+        storage
+          .get(key, GetOptions::new(range, &headers))
+          .await
+          .unwrap()
+          .read_to_end(&mut index)
+          .await
+          .unwrap();
+        assert_eq!(index, expected);
+      }
+
+      assert!(!storage
+        .exists("folder/missing.bam.bai", HeadOptions::new(&headers))
+        .await
+        .unwrap());
+    })
+    .await;
+  }
+
+  #[tokio::test]
+  async fn test_get_index_without_encrypted_indexes() {
+    with_local_storage(|storage, base_path| async move {
+      File::create(base_path.join("folder/index.bam.bai.c4gh"))
+        .await
+        .unwrap()
+        .write_all(&encrypt_data(b"encrypted index"))
+        .await
+        .unwrap();
+      File::create(base_path.join("folder/index.bam.bai"))
+        .await
+        .unwrap()
+        .write_all(b"plaintext index")
+        .await
+        .unwrap();
+
+      let storage = C4GHStorage::new(get_decryption_keys().await, storage);
+      let headers = HeaderMap::default();
+
+      let mut index = vec![];
+      storage
+        .get(
+          "folder/index.bam.bai",
+          GetOptions::new(BytesPosition::default(), &headers),
+        )
+        .await
+        .unwrap()
+        .read_to_end(&mut index)
+        .await
+        .unwrap();
+      assert_eq!(index, b"plaintext index");
+    })
+    .await;
+  }
+
+  #[tokio::test]
+  async fn test_decrypt_range() {
+    with_local_storage(|storage, base_path| async move {
+      let data: Vec<u8> = (0..200000).map(|i| (i % 251) as u8).collect();
+      File::create(base_path.join("folder/large.c4gh"))
+        .await
+        .unwrap()
+        .write_all(&encrypt_data(&data))
+        .await
+        .unwrap();
+
+      let storage = C4GHStorage::new(get_decryption_keys().await, storage);
+      let headers = HeaderMap::default();
+
+      for (start, end) in [
+        (None, None),
+        (Some(10), Some(20)),
+        (Some(65530), Some(65540)),
+        (Some(70000), Some(140000)),
+        (Some(150000), None),
+        (Some(199990), Some(300000)),
+        (Some(300000), None),
+      ] {
+        let (mut stream, size) = storage
+          .decrypt_range(
+            "folder/large",
+            GetOptions::new(BytesPosition::new(start, end, None), &headers),
+          )
+          .await
+          .unwrap();
+        let mut decrypted = vec![];
+        stream.read_to_end(&mut decrypted).await.unwrap();
+
+        let start = min(start.unwrap_or_default(), 200000) as usize;
+        let end = min(end.unwrap_or(200000), 200000) as usize;
+        assert_eq!(size, 200000);
+        assert_eq!(decrypted, &data[start..end]);
+      }
+    })
+    .await;
+  }
+
+  #[tokio::test]
+  async fn test_postprocess_decrypt_mode() {
+    with_local_c4gh_storage(|storage| async move {
+      let mut storage = storage
+        .with_decrypt(Some(htsget_config::storage::file::File::default()))
+        .with_ticket_secret(b"secret".to_vec(), 60);
+      let headers = HeaderMap::default();
+      storage
+        .preprocess("folder/key", GetOptions::new_with_default_range(&headers))
+        .await
+        .unwrap();
+
+      let blocks = storage
+        .postprocess(
+          "folder/key",
+          BytesPositionOptions::new(
+            vec![BytesPosition::default().with_start(0).with_end(6)],
+            &headers,
+          ),
+        )
+        .await
+        .unwrap();
+      assert_eq!(
+        blocks,
+        vec![DataBlock::Range(BytesPosition::new(Some(0), Some(6), None))]
+      );
+
+      let url = storage
+        .range_url(
+          "folder/key",
+          RangeUrlOptions::new(BytesPosition::new(Some(0), Some(6), None), &headers),
+        )
+        .await
+        .unwrap();
+      let (ticket, signature) = url.url.split_once("&signature=").unwrap();
+      let (ticket, expires) = ticket.split_once("&expires=").unwrap();
+      let expires = expires.parse::<u64>().unwrap();
+      assert_eq!(ticket, "http://127.0.0.1:8081/decrypt/folder/key?keys=0");
+      assert!(expires > unix_time() && expires <= unix_time() + 60);
+      assert_eq!(
+        signature,
+        sign_decrypt_ticket(b"secret", 0, "folder/key", "bytes=0-5", expires)
+      );
+
+      assert!(verify_decrypt_ticket(
+        b"secret",
+        0,
+        "folder/key",
+        "bytes=0-5",
+        expires,
+        signature
+      ));
+      assert!(!verify_decrypt_ticket(
+        b"secret",
+        0,
+        "folder/key",
+        "bytes=0-6",
+        expires,
+        signature
+      ));
+      assert!(!verify_decrypt_ticket(
+        b"other",
+        0,
+        "folder/key",
+        "bytes=0-5",
+        expires,
+        signature
+      ));
+      assert!(!verify_decrypt_ticket(
+        b"secret",
+        0,
+        "folder/key",
+        "bytes=0-5",
+        unix_time() - 1,
+        &sign_decrypt_ticket(b"secret", 0, "folder/key", "bytes=0-5", unix_time() - 1)
+      ));
+      assert_eq!(
+        url.headers,
+        Some(Headers::default().with_header("Range", "bytes=0-5"))
+      );
+    })
+    .await;
+  }
+
+  #[test]
+  fn test_parse_public_key() {
+    let raw = general_purpose::STANDARD.encode([1; PUBLIC_KEY_SIZE]);
+    assert_eq!(
+      C4GHStorage::parse_public_key(raw.as_bytes()).unwrap(),
+      vec![1; PUBLIC_KEY_SIZE]
+    );
+
+    let file = general_purpose::STANDARD.encode(format!(
+      "{}\n{}\n{}\n",
+      PUBLIC_KEY_BEGIN, raw, PUBLIC_KEY_END
+    ));
+    assert_eq!(
+      C4GHStorage::parse_public_key(file.as_bytes()).unwrap(),
+      vec![1; PUBLIC_KEY_SIZE]
+    );
+
+    let short = general_purpose::STANDARD.encode([1; 16]);
+    assert!(C4GHStorage::parse_public_key(short.as_bytes()).is_err());
+  }
+
   async fn test_preprocess(storage: &mut C4GHStorage, key: &str, headers: &HeaderMap) {
     storage
       .preprocess(key, GetOptions::new_with_default_range(headers))
@@ -0,0 +1,352 @@
+//! Module providing CDN URLs for tickets, signed using CloudFront canned policies.
+//!
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::{Debug, Formatter};
+use std::fs;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use base64::engine::general_purpose;
+use base64::Engine;
+use htsget_config::storage::s3::{Cdn as CdnConfig, CdnSigning};
+use htsget_config::types::Headers;
+use rsa::pkcs1::DecodeRsaPrivateKey;
+use rsa::pkcs1v15::SigningKey;
+use rsa::pkcs8::DecodePrivateKey;
+use rsa::signature::{SignatureEncoding, Signer};
+use rsa::RsaPrivateKey;
+use sha1::Sha1;
+
+use crate::error::{Result, StorageError};
+use crate::Url;
+
+/// The key used to sign CDN URLs.
+#[derive(Clone)]
+struct CdnSigner {
+  key_pair_id: String,
+  private_key: RsaPrivateKey,
+  signing: CdnSigning,
+}
+
+impl Debug for CdnSigner {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    f.debug_struct("CdnSigner")
+      .field("key_pair_id", &self.key_pair_id)
+      .field("signing", &self.signing)
+      .finish_non_exhaustive()
+  }
+}
+
+/// Rewrites ticket URLs to point at a CDN, optionally signing them.
+#[derive(Debug, Clone)]
+pub struct Cdn {
+  domain: String,
+  signer: Option<CdnSigner>,
+}
+
+impl Cdn {
+  /// Create a CDN which returns unsigned URLs.
+  pub fn new(domain: String) -> Self {
+    Self {
+      domain,
+      signer: None,
+    }
+  }
+
+  /// Sign URLs using the key pair id and private key.
+  pub fn with_signer(
+    mut self,
+    key_pair_id: String,
+    private_key: RsaPrivateKey,
+    signing: CdnSigning,
+  ) -> Self {
+    self.signer = Some(CdnSigner {
+      key_pair_id,
+      private_key,
+      signing,
+    });
+    self
+  }
+
+  /// Create a CDN from the config, reading the private key if it is set. The CDN is only created
+  /// once for each config and then shared between requests, so the private key is not re-read.
+  pub fn from_config(cdn: &CdnConfig) -> Result<Self> {
+    static SHARED: LazyLock<Mutex<HashMap<CdnConfig, Cdn>>> = LazyLock::new(Default::default);
+
+    if let Some(shared) = SHARED
+      .lock()
+      .ok()
+      .and_then(|shared| shared.get(cdn).cloned())
+    {
+      return Ok(shared);
+    }
+
+    let shared = Self::read_config(cdn)?;
+    if let Ok(mut cache) = SHARED.lock() {
+      cache.insert(cdn.clone(), shared.clone());
+    }
+
+    Ok(shared)
+  }
+
+  /// Read the CDN config, parsing the private key.
+  fn read_config(cdn: &CdnConfig) -> Result<Self> {
+    let storage = Self::new(cdn.domain().to_string());
+
+    match (cdn.key_pair_id(), cdn.private_key()) {
+      (Some(key_pair_id), Some(private_key)) => {
+        let pem = fs::read_to_string(private_key).map_err(|err| {
+          StorageError::IoError(
+            format!("failed to read CDN private key `{}`", private_key.display()),
+            err,
+          )
+        })?;
+        let private_key = RsaPrivateKey::from_pkcs1_pem(&pem)
+          .or_else(|_| RsaPrivateKey::from_pkcs8_pem(&pem))
+          .map_err(|err| StorageError::InvalidInput(format!("invalid CDN private key: {}", err)))?;
+
+        Ok(storage.with_signer(key_pair_id.to_string(), private_key, cdn.signing()))
+      }
+      (None, None) => Ok(storage),
+      _ => Err(StorageError::InvalidInput(
+        "both the CDN key pair id and private key must be set".to_string(),
+      )),
+    }
+  }
+
+  /// Get the unsigned URL of the object on the CDN, pinning the version if it is set.
+  pub fn object_url(&self, key: &str, version_id: Option<&str>) -> Result<url::Url> {
+    let mut url = url::Url::parse(&self.domain)
+      .map_err(|err| StorageError::UrlParseError(format!("invalid CDN domain: {}", err)))?;
+    url
+      .path_segments_mut()
+      .map_err(|_| StorageError::UrlParseError("invalid CDN domain".to_string()))?
+      .pop_if_empty()
+      .extend(key.split('/'));
+
+    if let Some(version_id) = version_id {
+      url.query_pairs_mut().append_pair("versionId", version_id);
+    }
+
+    Ok(url)
+  }
+
+  /// Get the canned policy for the resource which expires at the epoch time.
+  fn canned_policy(resource: &str, expires: u64) -> String {
+    format!(
+      r#"{{"Statement":[{{"Resource":"{}","Condition":{{"DateLessThan":{{"AWS:EpochTime":{}}}}}}}]}}"#,
+      resource, expires
+    )
+  }
+
+  /// Sign the policy, encoding it using the CloudFront URL-safe base64 variant.
+  fn sign(private_key: &RsaPrivateKey, policy: &str) -> String {
+    let signature = SigningKey::<Sha1>::new(private_key.clone()).sign(policy.as_bytes());
+
+    general_purpose::STANDARD
+      .encode(signature.to_bytes())
+      .replace('+', "-")
+      .replace('=', "_")
+      .replace('/', "~")
+  }
+
+  /// Get the ticket URL of the object, signing it with a canned policy that expires after
+  /// `expires_in` if there is a signer.
+  pub fn url(&self, key: &str, version_id: Option<&str>, expires_in: Duration) -> Result<Url> {
+    let expires = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .map_err(|err| StorageError::InternalError(err.to_string()))?
+      .saturating_add(expires_in)
+      .as_secs();
+
+    self.url_expiring_at(key, version_id, expires)
+  }
+
+  /// Get the ticket URL of the object, which expires at the epoch time.
+  pub fn url_expiring_at(&self, key: &str, version_id: Option<&str>, expires: u64) -> Result<Url> {
+    let mut url = self.object_url(key, version_id)?;
+
+    let Some(signer) = &self.signer else {
+      return Ok(Url::new(url.to_string()));
+    };
+
+    let signature = Self::sign(
+      &signer.private_key,
+      &Self::canned_policy(url.as_str(), expires),
+    );
+
+    match signer.signing {
+      CdnSigning::Url => {
+        url
+          .query_pairs_mut()
+          .append_pair("Expires", &expires.to_string())
+          .append_pair("Signature", &signature)
+          .append_pair("Key-Pair-Id", &signer.key_pair_id);
+
+        Ok(Url::new(url.to_string()))
+      }
+      CdnSigning::Cookie => Ok(Url::new(url.to_string()).with_headers(
+        Headers::default().with_header(
+          "Cookie",
+          format!(
+            "CloudFront-Expires={}; CloudFront-Signature={}; CloudFront-Key-Pair-Id={}",
+            expires, signature, signer.key_pair_id
+          ),
+        ),
+      )),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use rsa::pkcs1v15::{Signature, VerifyingKey};
+  use rsa::pkcs8::{EncodePrivateKey, LineEnding};
+  use rsa::rand_core::OsRng;
+  use rsa::signature::Verifier;
+  use tempfile::TempDir;
+
+  use super::*;
+
+  const EXPIRES: u64 = 1704067200;
+
+  fn test_private_key() -> RsaPrivateKey {
+    RsaPrivateKey::new(&mut OsRng, 2048).unwrap()
+  }
+
+  fn verify(private_key: &RsaPrivateKey, resource: &str, signature: &str) {
+    let signature = general_purpose::STANDARD
+      .decode(
+        signature
+          .replace('-', "+")
+          .replace('_', "=")
+          .replace('~', "/"),
+      )
+      .unwrap();
+
+    VerifyingKey::<Sha1>::new(private_key.to_public_key())
+      .verify(
+        Cdn::canned_policy(resource, EXPIRES).as_bytes(),
+        &Signature::try_from(signature.as_slice()).unwrap(),
+      )
+      .unwrap();
+  }
+
+  #[test]
+  fn unsigned_url() {
+    let cdn = Cdn::new("https://cdn.example.com".to_string());
+
+    assert_eq!(
+      cdn
+        .url_expiring_at("folder/key name", None, EXPIRES)
+        .unwrap(),
+      Url::new("https://cdn.example.com/folder/key%20name")
+    );
+  }
+
+  #[test]
+  fn unsigned_url_with_version() {
+    let cdn = Cdn::new("https://cdn.example.com/".to_string());
+
+    assert_eq!(
+      cdn
+        .url_expiring_at("key", Some("version"), EXPIRES)
+        .unwrap(),
+      Url::new("https://cdn.example.com/key?versionId=version")
+    );
+  }
+
+  #[test]
+  fn signed_url() {
+    let private_key = test_private_key();
+    let cdn = Cdn::new("https://cdn.example.com".to_string()).with_signer(
+      "K2JCJMDEHXQW5F".to_string(),
+      private_key.clone(),
+      CdnSigning::Url,
+    );
+
+    let result = cdn.url_expiring_at("key", None, EXPIRES).unwrap();
+    let url = url::Url::parse(&result.url).unwrap();
+    let params: Vec<_> = url.query_pairs().into_owned().collect();
+
+    assert_eq!(params[0], ("Expires".to_string(), EXPIRES.to_string()));
+    assert_eq!(
+      params[2],
+      ("Key-Pair-Id".to_string(), "K2JCJMDEHXQW5F".to_string())
+    );
+    assert_eq!(result.headers, None);
+
+    verify(&private_key, "https://cdn.example.com/key", &params[1].1);
+  }
+
+  #[test]
+  fn signed_cookies() {
+    let private_key = test_private_key();
+    let cdn = Cdn::new("https://cdn.example.com".to_string()).with_signer(
+      "K2JCJMDEHXQW5F".to_string(),
+      private_key.clone(),
+      CdnSigning::Cookie,
+    );
+
+    let result = cdn.url_expiring_at("key", None, EXPIRES).unwrap();
+    assert_eq!(result.url, "https://cdn.example.com/key");
+
+    let cookie = result
+      .headers
+      .unwrap()
+      .as_ref_inner()
+      .get("Cookie")
+      .unwrap()
+      .to_string();
+    let cookies: Vec<_> = cookie
+      .split("; ")
+      .map(|cookie| cookie.split_once('=').unwrap())
+      .collect();
+
+    assert_eq!(
+      cookies[0],
+      ("CloudFront-Expires", EXPIRES.to_string().as_str())
+    );
+    assert_eq!(cookies[2], ("CloudFront-Key-Pair-Id", "K2JCJMDEHXQW5F"));
+
+    verify(&private_key, "https://cdn.example.com/key", cookies[1].1);
+  }
+
+  #[test]
+  fn from_config() {
+    let tmp = TempDir::new().unwrap();
+    let path = tmp.path().join("private_key.pem");
+    fs::write(
+      &path,
+      test_private_key()
+        .to_pkcs8_pem(LineEnding::LF)
+        .unwrap()
+        .as_bytes(),
+    )
+    .unwrap();
+
+    let config = CdnConfig::new("https://cdn.example.com".to_string()).with_signing(
+      "K2JCJMDEHXQW5F".to_string(),
+      path.clone(),
+      CdnSigning::Cookie,
+    );
+    let cdn = Cdn::from_config(&config).unwrap();
+    assert!(cdn.signer.is_some());
+
+    // The private key is only read once for each config.
+    fs::remove_file(&path).unwrap();
+    let cdn = Cdn::from_config(&config).unwrap();
+    assert!(cdn.signer.is_some());
+
+    let result = Cdn::from_config(
+      &CdnConfig::new("https://cdn.example.com".to_string()).with_signing(
+        "K2JCJMDEHXQW5F".to_string(),
+        tmp.path().join("missing.pem"),
+        CdnSigning::Url,
+      ),
+    );
+    assert!(matches!(result, Err(StorageError::IoError(_, _))));
+  }
+}
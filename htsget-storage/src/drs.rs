@@ -0,0 +1,756 @@
+//! Storage which resolves objects using a GA4GH Data Repository Service (DRS).
+//!
+
+use std::fmt;
+use std::fmt::{Debug, Formatter};
+use std::str::FromStr;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use http::header::RETRY_AFTER;
+use http::{HeaderMap, HeaderName, HeaderValue, Method, Uri};
+use reqwest::{Client, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use tracing::{debug, instrument};
+
+pub use htsget_config::storage::drs::DrsAccess;
+use htsget_config::storage::drs::{DrsAccessKey, DrsObjectCache};
+use htsget_config::types::Format;
+
+use crate::url::UrlStorage;
+use crate::StorageError::{
+  KeyNotFound, PermissionDenied, ResponseError, ServerError, Unavailable, UrlParseError,
+};
+use crate::{
+  GetOptions, HeadOptions, RangeUrlOptions, Result, StorageMiddleware, StorageTrait, Streamable,
+  Url as HtsGetUrl, C4GH_FILE_ENDING,
+};
+
+/// The path of the DRS objects endpoint relative to the server url.
+const DRS_OBJECTS_PATH: [&str; 4] = ["ga4gh", "drs", "v1", "objects"];
+
+/// The scheme of DRS URIs.
+const DRS_SCHEME: &str = "drs://";
+
+/// A DRS object returned by the `/objects/{object_id}` endpoint.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DrsObject {
+  id: String,
+  size: u64,
+  #[serde(default)]
+  access_methods: Vec<AccessMethod>,
+}
+
+/// A method used to access the bytes of a DRS object.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AccessMethod {
+  #[serde(rename = "type")]
+  access_type: String,
+  access_url: Option<AccessUrl>,
+  access_id: Option<String>,
+}
+
+/// A URL which can be used to fetch the bytes of a DRS object.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AccessUrl {
+  url: String,
+  #[serde(default)]
+  headers: Vec<String>,
+}
+
+/// A storage struct which resolves keys using a DRS server, and fetches data from the
+/// resulting access urls.
+#[derive(Clone)]
+pub struct DrsStorage {
+  client: Client,
+  url: Uri,
+  index_suffix: Option<String>,
+  forward_headers: bool,
+  header_allowlist: Vec<String>,
+  header_blacklist: Vec<String>,
+  objects: DrsObjectCache,
+  cache_ttl: Duration,
+}
+
+impl Debug for DrsStorage {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    f.debug_struct("DrsStorage")
+      .field("url", &self.url)
+      .field("index_suffix", &self.index_suffix)
+      .field("forward_headers", &self.forward_headers)
+      .field("header_allowlist", &self.header_allowlist)
+      .field("header_blacklist", &self.header_blacklist)
+      .field("cache_ttl", &self.cache_ttl)
+      .finish()
+  }
+}
+
+impl DrsStorage {
+  /// Construct a new DrsStorage.
+  pub fn new(
+    client: Client,
+    url: Uri,
+    index_suffix: Option<String>,
+    forward_headers: bool,
+    header_allowlist: Vec<String>,
+    header_blacklist: Vec<String>,
+  ) -> Self {
+    Self {
+      client,
+      url,
+      index_suffix,
+      forward_headers,
+      header_allowlist,
+      header_blacklist,
+      objects: Default::default(),
+      cache_ttl: Duration::ZERO,
+    }
+  }
+
+  /// Share resolved objects between storages using the cache, keeping them for the ttl.
+  pub fn with_cache(mut self, objects: DrsObjectCache, cache_ttl: Duration) -> Self {
+    self.objects = objects;
+    self.cache_ttl = cache_ttl;
+    self
+  }
+
+  /// Get the DRS id of the object represented by the key. The data file maps to the htsget id,
+  /// and index files map to either the id with the `index_suffix` or the index file name.
+  pub fn drs_id_from_key(&self, key: &str) -> String {
+    let key = key.strip_suffix(C4GH_FILE_ENDING).unwrap_or(key);
+
+    for format in [Format::Bam, Format::Cram, Format::Vcf, Format::Bcf] {
+      if let Some(id) = key.strip_suffix(format.index_file_ending()) {
+        return match &self.index_suffix {
+          Some(suffix) => format!("{id}{suffix}"),
+          None => key.to_string(),
+        };
+      }
+
+      if let Ok(gzi_ending) = format.gzi_index_file_ending() {
+        if key.ends_with(gzi_ending) {
+          return key.to_string();
+        }
+      }
+
+      if let Some(id) = key.strip_suffix(format.file_ending()) {
+        return id.to_string();
+      }
+    }
+
+    key.to_string()
+  }
+
+  /// Get the url of the DRS object endpoint for the DRS id. This accounts for ids which are
+  /// `drs://<host>/<object_id>` URIs by calling the host directly.
+  pub fn object_url(&self, drs_id: &str) -> Result<url::Url> {
+    let (base, object_id) = match drs_id.strip_prefix(DRS_SCHEME) {
+      Some(uri) => {
+        let (host, object_id) = uri
+          .split_once('/')
+          .ok_or_else(|| UrlParseError(format!("invalid DRS uri: {}", drs_id)))?;
+        (format!("https://{}", host), object_id)
+      }
+      None => (self.url.to_string(), drs_id),
+    };
+
+    let mut url = url::Url::parse(&base).map_err(|err| UrlParseError(err.to_string()))?;
+    url
+      .path_segments_mut()
+      .map_err(|_| UrlParseError(format!("DRS url cannot be a base: {}", base)))?
+      .pop_if_empty()
+      .extend(DRS_OBJECTS_PATH)
+      .push(object_id);
+
+    Ok(url)
+  }
+
+  /// Get the allowlisted headers which are forwarded to the DRS server, excluding blacklisted
+  /// headers. No headers are forwarded if forwarding is disabled.
+  pub fn forwarded_headers(&self, headers: &HeaderMap) -> HeaderMap {
+    let mut forwarded = HeaderMap::default();
+    if !self.forward_headers {
+      return forwarded;
+    }
+
+    for allowed_header in &self.header_allowlist {
+      if self
+        .header_blacklist
+        .iter()
+        .any(|blacklisted| blacklisted.eq_ignore_ascii_case(allowed_header))
+      {
+        continue;
+      }
+
+      let Ok(name) = HeaderName::from_str(allowed_header) else {
+        continue;
+      };
+      for value in headers.get_all(&name) {
+        forwarded.append(name.clone(), value.clone());
+      }
+    }
+
+    forwarded
+  }
+
+  /// Get the cache key of the access for a DRS object url and its forwarded headers.
+  fn access_key(object_url: &url::Url, forwarded: &HeaderMap) -> DrsAccessKey {
+    let mut headers: Vec<_> = forwarded
+      .iter()
+      .map(|(name, value)| (name.to_string(), value.clone()))
+      .collect();
+    headers.sort();
+
+    (object_url.to_string(), headers)
+  }
+
+  /// Send a request to the DRS server and deserialize the response.
+  async fn send_request<T: DeserializeOwned>(
+    &self,
+    url: url::Url,
+    headers: &HeaderMap,
+  ) -> Result<T> {
+    let response = self
+      .client
+      .request(Method::GET, url.clone())
+      .headers(headers.clone())
+      .send()
+      .await
+      .map_err(|err| ServerError(format!("sending request to DRS url {}: {}", url, err)))?;
+
+    let status = response.status();
+    match status {
+      StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+        return Err(PermissionDenied(format!(
+          "DRS server returned {} for {}",
+          status, url
+        )))
+      }
+      StatusCode::NOT_FOUND => return Err(KeyNotFound(url.to_string())),
+      status if status.is_client_error() => {
+        return Err(ResponseError(format!(
+          "DRS server returned {} for {}",
+          status, url
+        )))
+      }
+      status if status.is_server_error() => {
+        return Err(ServerError(format!(
+          "DRS server returned {} for {}",
+          status, url
+        )))
+      }
+      _ => {}
+    }
+
+    if status == StatusCode::ACCEPTED {
+      // The DRS server is still staging the object, and the client should retry.
+      let retry_after = response
+        .headers()
+        .get(RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse().ok());
+
+      return Err(Unavailable(
+        format!("DRS object is not ready for {}", url),
+        retry_after,
+      ));
+    }
+
+    let body = response
+      .bytes()
+      .await
+      .map_err(|err| ResponseError(format!("reading DRS response body: {}", err)))?;
+
+    serde_json::from_slice(&body)
+      .map_err(|err| ResponseError(format!("invalid DRS response from {}: {}", url, err)))
+  }
+
+  /// Parse DRS headers, which are formatted as `<name>: <value>` strings.
+  pub fn parse_headers(headers: &[String]) -> Result<HeaderMap> {
+    headers
+      .iter()
+      .try_fold(HeaderMap::default(), |mut acc, header| {
+        let (name, value) = header
+          .split_once(':')
+          .ok_or_else(|| ResponseError(format!("invalid DRS header: {}", header)))?;
+
+        acc.append(
+          HeaderName::from_str(name.trim())
+            .map_err(|err| ResponseError(format!("invalid DRS header name: {}", err)))?,
+          HeaderValue::from_str(value.trim())
+            .map_err(|err| ResponseError(format!("invalid DRS header value: {}", err)))?,
+        );
+
+        Ok(acc)
+      })
+  }
+
+  /// Resolve the key into its access url and headers by calling the DRS server. Results are
+  /// cached for the cache ttl, separately for each set of forwarded headers.
+  #[instrument(level = "trace", skip(self, headers))]
+  pub async fn resolve(&self, key: &str, headers: &HeaderMap) -> Result<DrsAccess> {
+    let headers = self.forwarded_headers(headers);
+    let drs_id = self.drs_id_from_key(key);
+    let object_url = self.object_url(&drs_id)?;
+
+    let access_key = Self::access_key(&object_url, &headers);
+    if let Some(access) = self.objects.get(&access_key) {
+      return Ok(access);
+    }
+
+    let object: DrsObject = self.send_request(object_url.clone(), &headers).await?;
+
+    // Prefer methods which are fetchable over HTTP.
+    let access_method = object
+      .access_methods
+      .iter()
+      .find(|method| method.access_type == "https" || method.access_type == "http")
+      .or_else(|| object.access_methods.first())
+      .ok_or_else(|| KeyNotFound(format!("no access methods for DRS object {}", object.id)))?;
+
+    let access_url = match (&access_method.access_url, &access_method.access_id) {
+      (Some(access_url), _) => access_url.clone(),
+      (None, Some(access_id)) => {
+        let mut access_url = object_url;
+        access_url
+          .path_segments_mut()
+          .map_err(|_| UrlParseError("DRS url cannot be a base".to_string()))?
+          .extend(["access", access_id.as_str()]);
+
+        self.send_request(access_url, &headers).await?
+      }
+      (None, None) => {
+        return Err(ResponseError(format!(
+          "DRS object {} has no access url or access id",
+          object.id
+        )))
+      }
+    };
+
+    let access = DrsAccess::new(
+      access_url
+        .url
+        .parse()
+        .map_err(|err: http::uri::InvalidUri| UrlParseError(err.to_string()))?,
+      Self::parse_headers(&access_url.headers)?,
+      object.size,
+    );
+
+    debug!(calling_from = ?self, key, drs_id, url = ?access.url(), "resolved DRS object {:?}", drs_id);
+
+    self
+      .objects
+      .insert(access_key, access.clone(), self.cache_ttl);
+
+    Ok(access)
+  }
+
+  /// Create a url storage which fetches data from the resolved access url.
+  fn url_storage(&self, access: &DrsAccess) -> UrlStorage {
+    UrlStorage::new(
+      self.client.clone(),
+      access.url().clone(),
+      access.url().clone(),
+      true,
+      vec![],
+    )
+  }
+}
+
+#[async_trait]
+impl StorageMiddleware for DrsStorage {}
+
+#[async_trait]
+impl StorageTrait for DrsStorage {
+  #[instrument(level = "trace", skip(self))]
+  async fn get(&self, key: &str, options: GetOptions<'_>) -> Result<Streamable> {
+    let access = self.resolve(key, options.request_headers()).await?;
+    let options = GetOptions::new(options.range().clone(), access.headers())
+      .with_etag(options.etag().map(ToString::to_string));
+
+    self.url_storage(&access).get("", options).await
+  }
+
+  #[instrument(level = "trace", skip(self))]
+  async fn range_url(&self, key: &str, options: RangeUrlOptions<'_>) -> Result<HtsGetUrl> {
+    let access = self.resolve(key, options.response_headers()).await?;
+    let options = RangeUrlOptions::new(options.range().clone(), access.headers());
+
+    self.url_storage(&access).format_url("", options)
+  }
+
+  #[instrument(level = "trace", skip(self))]
+  async fn head(&self, key: &str, options: HeadOptions<'_>) -> Result<u64> {
+    let access = self.resolve(key, options.request_headers()).await?;
+
+    debug!(calling_from = ?self, key, len = access.size(), "size of key {:?} is {}", key, access.size());
+    Ok(access.size())
+  }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+  use std::future::Future;
+  use std::path::Path;
+
+  use axum::extract::{Path as AxumPath, State};
+  use axum::response::{IntoResponse, Response};
+  use axum::routing::get;
+  use axum::{Json, Router};
+  use http::header::AUTHORIZATION;
+  use serde_json::{json, Value};
+  use tempfile::TempDir;
+  use tokio::io::AsyncReadExt;
+  use tokio::net::TcpListener;
+  use tower_http::services::ServeDir;
+
+  use htsget_config::types::Headers;
+
+  use crate::types::BytesPosition;
+
+  use super::*;
+
+  #[test]
+  fn drs_id_from_key() {
+    let storage = test_storage("https://example.com".to_string(), None);
+
+    assert_eq!(storage.drs_id_from_key("sample.bam"), "sample");
+    assert_eq!(storage.drs_id_from_key("sample.bam.c4gh"), "sample");
+    assert_eq!(storage.drs_id_from_key("sample.vcf.gz"), "sample");
+    assert_eq!(storage.drs_id_from_key("sample.bam.bai"), "sample.bam.bai");
+    assert_eq!(
+      storage.drs_id_from_key("sample.vcf.gz.gzi"),
+      "sample.vcf.gz.gzi"
+    );
+  }
+
+  #[test]
+  fn drs_id_from_key_index_suffix() {
+    let storage = test_storage(
+      "https://example.com".to_string(),
+      Some("-index".to_string()),
+    );
+
+    assert_eq!(storage.drs_id_from_key("sample.bam"), "sample");
+    assert_eq!(storage.drs_id_from_key("sample.bam.bai"), "sample-index");
+    assert_eq!(storage.drs_id_from_key("sample.vcf.gz.tbi"), "sample-index");
+  }
+
+  #[test]
+  fn object_url() {
+    let storage = test_storage("https://example.com/".to_string(), None);
+
+    assert_eq!(
+      storage.object_url("sample").unwrap().as_str(),
+      "https://example.com/ga4gh/drs/v1/objects/sample"
+    );
+    assert_eq!(
+      storage.object_url("folder/sample").unwrap().as_str(),
+      "https://example.com/ga4gh/drs/v1/objects/folder%2Fsample"
+    );
+  }
+
+  #[test]
+  fn object_url_drs_uri() {
+    let storage = test_storage("https://example.com".to_string(), None);
+
+    assert_eq!(
+      storage
+        .object_url("drs://drs.example.org/sample")
+        .unwrap()
+        .as_str(),
+      "https://drs.example.org/ga4gh/drs/v1/objects/sample"
+    );
+    assert!(storage.object_url("drs://drs.example.org").is_err());
+  }
+
+  #[test]
+  fn parse_headers() {
+    let headers = DrsStorage::parse_headers(&["Authorization: Bearer token".to_string()]).unwrap();
+
+    assert_eq!(headers.get(AUTHORIZATION).unwrap(), "Bearer token");
+    assert!(DrsStorage::parse_headers(&["invalid".to_string()]).is_err());
+  }
+
+  #[tokio::test]
+  async fn head_access_url() {
+    with_drs_test_server(|storage, _| async move {
+      let headers = HeaderMap::default();
+      let options = HeadOptions::new(&headers);
+
+      assert_eq!(storage.head("sample.bam", options).await.unwrap(), 6);
+    })
+    .await;
+  }
+
+  #[tokio::test]
+  async fn get_access_url() {
+    with_drs_test_server(|storage, _| async move {
+      let headers = HeaderMap::default();
+      let options = GetOptions::new_with_default_range(&headers);
+
+      let mut reader = storage.get("sample.bam", options).await.unwrap();
+      let mut response = String::new();
+      reader.read_to_string(&mut response).await.unwrap();
+
+      assert_eq!(response, "value1");
+    })
+    .await;
+  }
+
+  #[tokio::test]
+  async fn get_access_id() {
+    with_drs_test_server(|storage, _| async move {
+      let headers = HeaderMap::default();
+      let options = GetOptions::new_with_default_range(&headers);
+
+      let mut reader = storage.get("sample.bam.bai", options).await.unwrap();
+      let mut response = String::new();
+      reader.read_to_string(&mut response).await.unwrap();
+
+      assert_eq!(response, "value2");
+    })
+    .await;
+  }
+
+  #[tokio::test]
+  async fn get_not_found() {
+    with_drs_test_server(|storage, _| async move {
+      let headers = HeaderMap::default();
+      let options = GetOptions::new_with_default_range(&headers);
+
+      assert!(matches!(
+        storage.get("missing.bam", options).await,
+        Err(KeyNotFound(_))
+      ));
+    })
+    .await;
+  }
+
+  #[tokio::test]
+  async fn get_accepted_is_unavailable() {
+    with_drs_test_server(|storage, _| async move {
+      let headers = HeaderMap::default();
+      let options = HeadOptions::new(&headers);
+
+      assert!(matches!(
+        storage.head("staging.bam", options).await,
+        Err(Unavailable(_, Some(30)))
+      ));
+    })
+    .await;
+  }
+
+  #[test]
+  fn forwarded_headers_allowlist() {
+    let mut storage = test_storage("https://example.com".to_string(), None);
+    storage.header_blacklist = vec!["authorization".to_string()];
+
+    let mut headers = HeaderMap::default();
+    headers.insert(AUTHORIZATION, HeaderValue::from_static("Bearer reader"));
+    headers.insert("Cookie", HeaderValue::from_static("session"));
+
+    assert!(storage.forwarded_headers(&headers).is_empty());
+
+    storage.header_blacklist = vec![];
+    let forwarded = storage.forwarded_headers(&headers);
+    assert_eq!(forwarded.len(), 1);
+    assert_eq!(forwarded.get(AUTHORIZATION).unwrap(), "Bearer reader");
+  }
+
+  #[tokio::test]
+  async fn resolve_cached_by_authorization() {
+    with_drs_test_server(|storage, _| async move {
+      let mut headers = HeaderMap::default();
+      headers.insert(AUTHORIZATION, HeaderValue::from_static("Bearer reader"));
+      assert_eq!(
+        storage
+          .head("private.bam", HeadOptions::new(&headers))
+          .await
+          .unwrap(),
+        6
+      );
+
+      let headers = HeaderMap::default();
+      assert!(matches!(
+        storage
+          .head("private.bam", HeadOptions::new(&headers))
+          .await,
+        Err(PermissionDenied(_))
+      ));
+    })
+    .await;
+  }
+
+  #[tokio::test]
+  async fn resolve_shares_cache() {
+    with_drs_test_server(|storage, url| async move {
+      let objects = DrsObjectCache::default();
+      let storage = storage.with_cache(objects.clone(), Duration::from_secs(60));
+      let headers = HeaderMap::default();
+      storage
+        .head("sample.bam", HeadOptions::new(&headers))
+        .await
+        .unwrap();
+
+      let object_url = storage.object_url("sample").unwrap();
+      let access = objects
+        .get(&DrsStorage::access_key(&object_url, &HeaderMap::default()))
+        .unwrap();
+      assert_eq!(
+        access.url().to_string(),
+        format!("{}/assets/sample.bam", url)
+      );
+
+      // A storage created for another request reuses the resolved object.
+      let other = test_storage(url, None).with_cache(objects.clone(), Duration::from_secs(60));
+      assert_eq!(
+        other.resolve("sample.bam", &headers).await.unwrap().size(),
+        6
+      );
+
+      let uncached = DrsObjectCache::default();
+      uncached.insert(("key".to_string(), vec![]), access, Duration::ZERO);
+      assert!(uncached.get(&("key".to_string(), vec![])).is_none());
+    })
+    .await;
+  }
+
+  #[tokio::test]
+  async fn server_errors() {
+    with_drs_test_server(|storage, _| async move {
+      let headers = HeaderMap::default();
+      assert!(matches!(
+        storage.head("broken.bam", HeadOptions::new(&headers)).await,
+        Err(ServerError(_))
+      ));
+
+      let storage = test_storage("http://127.0.0.1:1".to_string(), None);
+      assert!(matches!(
+        storage.head("sample.bam", HeadOptions::new(&headers)).await,
+        Err(ServerError(_))
+      ));
+    })
+    .await;
+  }
+
+  #[tokio::test]
+  async fn range_url_access_headers() {
+    with_drs_test_server(|storage, url| async move {
+      let headers = HeaderMap::default();
+      let options = RangeUrlOptions::new(BytesPosition::new(Some(0), Some(3), None), &headers);
+
+      assert_eq!(
+        storage.range_url("sample.bam.bai", options).await.unwrap(),
+        HtsGetUrl::new(format!("{}/assets/sample.bam.bai", url)).with_headers(
+          Headers::default()
+            .with_header(AUTHORIZATION.as_str(), "secret")
+            .with_header("Range", "bytes=0-2")
+        )
+      );
+    })
+    .await;
+  }
+
+  fn test_storage(url: String, index_suffix: Option<String>) -> DrsStorage {
+    DrsStorage::new(
+      Client::new(),
+      Uri::from_str(&url).unwrap(),
+      index_suffix,
+      true,
+      vec!["Authorization".to_string()],
+      vec![],
+    )
+  }
+
+  async fn drs_object(
+    State(url): State<String>,
+    AxumPath(id): AxumPath<String>,
+    headers: HeaderMap,
+  ) -> Response {
+    match id.as_str() {
+      "sample" => Json(json!({
+        "id": "sample",
+        "self_uri": "drs://localhost/sample",
+        "size": 6,
+        "access_methods": [{
+          "type": "https",
+          "access_url": { "url": format!("{}/assets/sample.bam", url) }
+        }]
+      }))
+      .into_response(),
+      "sample.bam.bai" => Json(json!({
+        "id": "sample.bam.bai",
+        "self_uri": "drs://localhost/sample.bam.bai",
+        "size": 6,
+        "access_methods": [{ "type": "https", "access_id": "access1" }]
+      }))
+      .into_response(),
+      "staging" => (StatusCode::ACCEPTED, [(RETRY_AFTER, "30")]).into_response(),
+      "broken" => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+      "private" => match headers.get(AUTHORIZATION).map(HeaderValue::as_bytes) {
+        Some(b"Bearer reader") => Json(json!({
+          "id": "private",
+          "self_uri": "drs://localhost/private",
+          "size": 6,
+          "access_methods": [{
+            "type": "https",
+            "access_url": { "url": format!("{}/assets/sample.bam", url) }
+          }]
+        }))
+        .into_response(),
+        _ => StatusCode::FORBIDDEN.into_response(),
+      },
+      _ => StatusCode::NOT_FOUND.into_response(),
+    }
+  }
+
+  async fn drs_access(
+    State(url): State<String>,
+    AxumPath((id, access_id)): AxumPath<(String, String)>,
+  ) -> std::result::Result<Json<Value>, StatusCode> {
+    if access_id != "access1" {
+      return Err(StatusCode::NOT_FOUND);
+    }
+
+    Ok(Json(json!({
+      "url": format!("{}/assets/{}", url, id),
+      "headers": ["Authorization: secret"]
+    })))
+  }
+
+  async fn write_test_files(path: &Path) {
+    tokio::fs::write(path.join("sample.bam"), b"value1")
+      .await
+      .unwrap();
+    tokio::fs::write(path.join("sample.bam.bai"), b"value2")
+      .await
+      .unwrap();
+  }
+
+  pub(crate) async fn with_drs_test_server<F, Fut>(test: F)
+  where
+    F: FnOnce(DrsStorage, String) -> Fut,
+    Fut: Future<Output = ()>,
+  {
+    let base_path = TempDir::new().unwrap();
+    write_test_files(base_path.path()).await;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+
+    let router = Router::new()
+      .route("/ga4gh/drs/v1/objects/:id", get(drs_object))
+      .route(
+        "/ga4gh/drs/v1/objects/:id/access/:access_id",
+        get(drs_access),
+      )
+      .with_state(url.clone())
+      .nest_service("/assets", ServeDir::new(base_path.path()));
+
+    tokio::spawn(async move { axum::serve(listener, router.into_make_service()).await });
+
+    test(test_storage(url.clone(), None), url).await;
+  }
+}
@@ -19,6 +19,9 @@ pub enum StorageError {
   #[error("key not found in storage: `{0}`")]
   KeyNotFound(String),
 
+  #[error("permission denied: {0}")]
+  PermissionDenied(String),
+
   #[error("{0}: {1}")]
   IoError(String, io::Error),
 
@@ -40,14 +43,44 @@ pub enum StorageError {
   #[error("response error: {0}")]
   ResponseError(String),
 
+  #[error("unavailable: {0}")]
+  Unavailable(String, Option<u64>),
+
   #[cfg(feature = "aws")]
   #[error("aws error: {0}, with key: `{1}`")]
   AwsS3Error(String, String),
 
+  #[cfg(feature = "gcp")]
+  #[error("gcs error: {0}, with key: `{1}`")]
+  GcsError(String, String),
+
+  #[cfg(feature = "azure")]
+  #[error("azure error: {0}, with key: `{1}`")]
+  AzureError(String, String),
+
   #[error("parsing url: {0}")]
   UrlParseError(String),
 }
 
+/// The number of seconds after which a request can be retried if an object was modified during
+/// the search. The new version of the object is used by the next search, so this is short.
+pub const MODIFIED_RETRY_AFTER: u64 = 1;
+
+impl StorageError {
+  /// The error returned when a read fails its `If-Match` precondition because the object
+  /// was modified.
+  #[cfg(any(feature = "aws", feature = "gcp", feature = "azure", feature = "url"))]
+  pub(crate) fn modified(key: &str) -> Self {
+    Self::Unavailable(
+      format!(
+        "`{}` was modified during the search, retry the request",
+        key
+      ),
+      Some(MODIFIED_RETRY_AFTER),
+    )
+  }
+}
+
 impl From<StorageError> for HtsGetError {
   fn from(err: StorageError) -> Self {
     match err {
@@ -55,13 +88,19 @@ impl From<StorageError> for HtsGetError {
       err @ (StorageError::KeyNotFound(_)
       | StorageError::InvalidKey(_)
       | StorageError::ResponseError(_)) => Self::NotFound(err.to_string()),
+      err @ StorageError::PermissionDenied(_) => Self::PermissionDenied(err.to_string()),
       err @ StorageError::IoError(_, _) => Self::IoError(err.to_string()),
       err @ (StorageError::ServerError(_)
       | StorageError::InvalidUri(_)
       | StorageError::InvalidAddress(_)
       | StorageError::InternalError(_)) => Self::InternalError(err.to_string()),
+      StorageError::Unavailable(err, retry_after) => Self::Unavailable(err, retry_after),
       #[cfg(feature = "aws")]
       err @ StorageError::AwsS3Error(_, _) => Self::IoError(err.to_string()),
+      #[cfg(feature = "gcp")]
+      err @ StorageError::GcsError(_, _) => Self::IoError(err.to_string()),
+      #[cfg(feature = "azure")]
+      err @ StorageError::AzureError(_, _) => Self::IoError(err.to_string()),
       err @ StorageError::UrlParseError(_) => Self::ParseError(err.to_string()),
     }
   }
@@ -98,4 +137,16 @@ mod tests {
     let result = HtsGetError::from(StorageError::InvalidKey("error".to_string()));
     assert!(matches!(result, HtsGetError::NotFound(_)));
   }
+
+  #[test]
+  fn htsget_error_from_storage_permission_denied() {
+    let result = HtsGetError::from(StorageError::PermissionDenied("error".to_string()));
+    assert!(matches!(result, HtsGetError::PermissionDenied(_)));
+  }
+
+  #[test]
+  fn htsget_error_from_storage_unavailable() {
+    let result = HtsGetError::from(StorageError::Unavailable("error".to_string(), Some(60)));
+    assert!(matches!(result, HtsGetError::Unavailable(message, Some(60)) if message == "error"));
+  }
 }
@@ -0,0 +1,642 @@
+//! Module providing an implementation for the [StorageTrait] trait using Google Cloud Storage.
+//!
+
+use std::collections::HashMap;
+use std::env;
+use std::fmt::{Debug, Formatter};
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+use std::{fmt, fs};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures_util::TryStreamExt;
+use http::header::{CONTENT_LENGTH, ETAG, IF_MATCH, RANGE};
+use http::{HeaderMap, Method, StatusCode, Uri};
+use reqwest::Client;
+use rsa::pkcs1v15::SigningKey;
+use rsa::pkcs8::DecodePrivateKey;
+use rsa::signature::{SignatureEncoding, Signer};
+use rsa::RsaPrivateKey;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tokio_util::io::StreamReader;
+use tracing::{debug, instrument};
+
+use crate::types::{BytesRange, HeadCache};
+use crate::StorageError::{
+  GcsError, InternalError, KeyNotFound, PermissionDenied, ResponseError, ServerError,
+};
+use crate::{
+  GetOptions, HeadOptions, RangeUrlOptions, Result, StorageError, StorageMiddleware, StorageTrait,
+  Streamable, Url,
+};
+
+/// The default public GCS endpoint.
+pub const DEFAULT_ENDPOINT: &str = "https://storage.googleapis.com";
+/// The signing algorithm used for V4 signed URLs.
+const SIGNING_ALGORITHM: &str = "GOOG4-RSA-SHA256";
+/// The environment variable used to find service account credentials.
+const CREDENTIALS_ENV: &str = "GOOGLE_APPLICATION_CREDENTIALS";
+
+/// The endpoint and credentials path that shared clients are keyed by.
+type SharedKey = (Option<String>, Option<PathBuf>);
+/// A client and service account which are shared between requests.
+type Shared = (Client, Option<ServiceAccount>);
+
+/// The fields of a service account JSON key file that are used for signing.
+#[derive(Deserialize)]
+struct ServiceAccountKey {
+  client_email: String,
+  private_key: String,
+}
+
+/// A service account used to sign URLs.
+#[derive(Clone)]
+pub struct ServiceAccount {
+  client_email: String,
+  private_key: RsaPrivateKey,
+}
+
+impl Debug for ServiceAccount {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    f.debug_struct("ServiceAccount")
+      .field("client_email", &self.client_email)
+      .finish()
+  }
+}
+
+impl ServiceAccount {
+  /// Create a new service account.
+  pub fn new(client_email: String, private_key: RsaPrivateKey) -> Self {
+    Self {
+      client_email,
+      private_key,
+    }
+  }
+
+  /// Read a service account from the contents of a JSON key file.
+  pub fn from_json(json: &[u8]) -> Result<Self> {
+    let key: ServiceAccountKey = serde_json::from_slice(json)
+      .map_err(|err| InternalError(format!("failed to parse service account: {}", err)))?;
+    let private_key = RsaPrivateKey::from_pkcs8_pem(&key.private_key).map_err(|err| {
+      InternalError(format!(
+        "failed to parse service account private key: {}",
+        err
+      ))
+    })?;
+
+    Ok(Self::new(key.client_email, private_key))
+  }
+
+  /// Read a service account from a JSON key file.
+  pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+    let json = fs::read(path.as_ref()).map_err(|err| {
+      InternalError(format!(
+        "failed to read service account {}: {}",
+        path.as_ref().display(),
+        err
+      ))
+    })?;
+
+    Self::from_json(&json)
+  }
+
+  /// Get the client email.
+  pub fn client_email(&self) -> &str {
+    &self.client_email
+  }
+}
+
+/// Implementation for the [StorageTrait] trait using Google Cloud Storage. Objects are accessed
+/// using V4 signed URLs if a service account is present, and unsigned URLs otherwise, which
+/// allows using public buckets or an emulator.
+#[derive(Debug, Clone)]
+pub struct GcsStorage {
+  client: Client,
+  bucket: String,
+  endpoint: String,
+  service_account: Option<ServiceAccount>,
+  expires_in: Duration,
+  heads: HeadCache<HeaderMap>,
+}
+
+impl GcsStorage {
+  /// Create a new GCS storage.
+  pub fn new(
+    client: Client,
+    bucket: String,
+    endpoint: Option<String>,
+    service_account: Option<ServiceAccount>,
+    expires_in: Duration,
+  ) -> Self {
+    Self {
+      client,
+      bucket,
+      endpoint: endpoint
+        .unwrap_or_else(|| DEFAULT_ENDPOINT.to_string())
+        .trim_end_matches('/')
+        .to_string(),
+      service_account,
+      expires_in,
+      heads: Default::default(),
+    }
+  }
+
+  /// Create a new GCS storage, reading the service account from the credentials path, or
+  /// from the `GOOGLE_APPLICATION_CREDENTIALS` environment variable if it is not set.
+  pub fn new_with_default_config(
+    bucket: String,
+    endpoint: Option<String>,
+    credentials: Option<&Path>,
+    expires_in: Duration,
+  ) -> Result<Self> {
+    let credentials = credentials
+      .map(Path::to_path_buf)
+      .or_else(|| env::var_os(CREDENTIALS_ENV).map(PathBuf::from));
+    let (client, service_account) = Self::shared(endpoint.clone(), credentials)?;
+
+    Ok(Self::new(
+      client,
+      bucket,
+      endpoint,
+      service_account,
+      expires_in,
+    ))
+  }
+
+  /// Get the client and service account for an endpoint and credentials path. These are only
+  /// created once and then shared between requests, so credentials are not re-read each time.
+  fn shared(endpoint: Option<String>, credentials: Option<PathBuf>) -> Result<Shared> {
+    static SHARED: LazyLock<Mutex<HashMap<SharedKey, Shared>>> = LazyLock::new(Default::default);
+
+    let key = (endpoint, credentials);
+    if let Some(shared) = SHARED
+      .lock()
+      .ok()
+      .and_then(|shared| shared.get(&key).cloned())
+    {
+      return Ok(shared);
+    }
+
+    let service_account = key.1.as_ref().map(ServiceAccount::from_file).transpose()?;
+    let shared = (Client::new(), service_account);
+    if let Ok(mut cache) = SHARED.lock() {
+      cache.insert(key, shared.clone());
+    }
+
+    Ok(shared)
+  }
+
+  /// Get the percent-encoded path of the object.
+  fn resource_path(&self, key: &str) -> String {
+    format!("/{}/{}", encode(&self.bucket, true), encode(key, false))
+  }
+
+  /// Get the unsigned URL of the object.
+  pub fn object_url(&self, key: &str) -> String {
+    format!("{}{}", self.endpoint, self.resource_path(key))
+  }
+
+  /// Get the canonical query string and the string to sign for a V4 signed URL.
+  fn string_to_sign(
+    &self,
+    service_account: &ServiceAccount,
+    method: &Method,
+    key: &str,
+    now: DateTime<Utc>,
+  ) -> Result<(String, String)> {
+    let host = self
+      .endpoint
+      .parse::<Uri>()
+      .ok()
+      .and_then(|uri| uri.authority().map(|authority| authority.to_string()))
+      .ok_or_else(|| InternalError(format!("invalid gcs endpoint: {}", self.endpoint)))?;
+
+    let datetime = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let scope = format!("{}/auto/storage/goog4_request", now.format("%Y%m%d"));
+
+    // Query parameters must be sorted by name.
+    let query = [
+      ("X-Goog-Algorithm", SIGNING_ALGORITHM.to_string()),
+      (
+        "X-Goog-Credential",
+        format!("{}/{}", service_account.client_email, scope),
+      ),
+      ("X-Goog-Date", datetime.clone()),
+      ("X-Goog-Expires", self.expires_in.as_secs().to_string()),
+      ("X-Goog-SignedHeaders", "host".to_string()),
+    ];
+    let canonical_query = query
+      .iter()
+      .map(|(name, value)| format!("{}={}", encode(name, true), encode(value, true)))
+      .collect::<Vec<_>>()
+      .join("&");
+
+    let canonical_request = format!(
+      "{}\n{}\n{}\nhost:{}\n\nhost\nUNSIGNED-PAYLOAD",
+      method,
+      self.resource_path(key),
+      canonical_query,
+      host
+    );
+    let string_to_sign = format!(
+      "{}\n{}\n{}\n{}",
+      SIGNING_ALGORITHM,
+      datetime,
+      scope,
+      hex::encode(Sha256::digest(canonical_request.as_bytes()))
+    );
+
+    Ok((canonical_query, string_to_sign))
+  }
+
+  /// Get a URL for the object which is signed using the service account, if it is present.
+  pub fn signed_url(&self, method: &Method, key: &str, now: DateTime<Utc>) -> Result<String> {
+    let Some(service_account) = &self.service_account else {
+      return Ok(self.object_url(key));
+    };
+
+    let (canonical_query, string_to_sign) =
+      self.string_to_sign(service_account, method, key, now)?;
+    let signature = SigningKey::<Sha256>::new(service_account.private_key.clone())
+      .sign(string_to_sign.as_bytes());
+
+    Ok(format!(
+      "{}?{}&X-Goog-Signature={}",
+      self.object_url(key),
+      canonical_query,
+      hex::encode(signature.to_bytes())
+    ))
+  }
+
+  /// Get the response headers of a head request for the key, reusing the headers of an earlier
+  /// head request for the key. This is used for the size and etag of objects, so that both come
+  /// from one request.
+  async fn head_headers(&self, key: &str) -> Result<HeaderMap> {
+    self
+      .heads
+      .get_or_try_insert(key, || async {
+        Ok(
+          self
+            .send_request(key, Method::HEAD, "", None)
+            .await?
+            .headers()
+            .clone(),
+        )
+      })
+      .await
+  }
+
+  /// Send a request to GCS, mapping error statuses.
+  async fn send_request(
+    &self,
+    key: &str,
+    method: Method,
+    range: &str,
+    etag: Option<&str>,
+  ) -> Result<reqwest::Response> {
+    let url = self.signed_url(&method, key, Utc::now())?;
+
+    let mut request = self.client.request(method, url);
+    if !range.is_empty() {
+      request = request.header(RANGE, range);
+    }
+    if let Some(etag) = etag {
+      request = request.header(IF_MATCH, etag);
+    }
+
+    let response = request
+      .send()
+      .await
+      .map_err(|err| GcsError(err.to_string(), key.to_string()))?;
+
+    match response.status() {
+      status if status.is_success() => Ok(response),
+      StatusCode::NOT_FOUND => Err(KeyNotFound(key.to_string())),
+      StatusCode::PRECONDITION_FAILED => Err(StorageError::modified(key)),
+      StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Err(PermissionDenied(format!(
+        "gcs denied access for key {}",
+        key
+      ))),
+      status if status.is_server_error() => Err(ServerError(format!(
+        "gcs returned {} for key {}",
+        status, key
+      ))),
+      status => Err(GcsError(
+        format!("gcs returned {}", status),
+        key.to_string(),
+      )),
+    }
+  }
+}
+
+/// Percent-encode a value, leaving unreserved characters and optionally `/` unchanged.
+fn encode(value: &str, encode_slash: bool) -> String {
+  value
+    .bytes()
+    .map(|byte| match byte {
+      b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+        (byte as char).to_string()
+      }
+      b'/' if !encode_slash => "/".to_string(),
+      byte => format!("%{:02X}", byte),
+    })
+    .collect()
+}
+
+#[async_trait]
+impl StorageMiddleware for GcsStorage {}
+
+#[async_trait]
+impl StorageTrait for GcsStorage {
+  #[instrument(level = "trace", skip(self))]
+  async fn get(&self, key: &str, options: GetOptions<'_>) -> Result<Streamable> {
+    debug!(calling_from = ?self, key, "getting file with key {:?}", key);
+
+    let range = String::from(&BytesRange::from(options.range()));
+    let response = self
+      .send_request(key, Method::GET, &range, options.etag())
+      .await?;
+
+    Ok(Streamable::from_async_read(StreamReader::new(
+      response
+        .bytes_stream()
+        .map_err(|err| ResponseError(format!("reading body from response: {}", err))),
+    )))
+  }
+
+  #[instrument(level = "trace", skip(self))]
+  async fn range_url(&self, key: &str, options: RangeUrlOptions<'_>) -> Result<Url> {
+    debug!(calling_from = ?self, key, "getting url with key {:?}", key);
+
+    let url = self.signed_url(&Method::GET, key, Utc::now())?;
+    Ok(options.apply(Url::new(url)))
+  }
+
+  #[instrument(level = "trace", skip(self))]
+  async fn head(&self, key: &str, _options: HeadOptions<'_>) -> Result<u64> {
+    let len = self
+      .head_headers(key)
+      .await?
+      .get(CONTENT_LENGTH)
+      .and_then(|length| length.to_str().ok())
+      .and_then(|length| length.parse().ok())
+      .ok_or_else(|| GcsError("failed to get content length".to_string(), key.to_string()))?;
+
+    debug!(calling_from = ?self, key, len, "size of key {:?} is {}", key, len);
+    Ok(len)
+  }
+
+  #[instrument(level = "trace", skip(self))]
+  async fn etag(&self, key: &str, _options: HeadOptions<'_>) -> Result<Option<String>> {
+    let etag = self
+      .head_headers(key)
+      .await?
+      .get(ETAG)
+      .and_then(|etag| etag.to_str().ok())
+      .map(ToString::to_string);
+
+    debug!(calling_from = ?self, key, ?etag, "etag of key {:?} is {:?}", key, etag);
+    Ok(etag)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::future::Future;
+
+  use axum::routing::get;
+  use axum::Router;
+  use chrono::TimeZone;
+  use htsget_config::types::{Class, Headers};
+  use rsa::pkcs1v15::{Signature, VerifyingKey};
+  use rsa::pkcs8::{EncodePrivateKey, LineEnding};
+  use rsa::rand_core::OsRng;
+  use rsa::signature::Verifier;
+  use tokio::io::AsyncReadExt;
+  use tokio::net::TcpListener;
+  use tower_http::services::ServeDir;
+
+  use crate::local::tests::create_local_test_files;
+  use crate::types::BytesPosition;
+  use crate::StorageError;
+
+  use super::*;
+
+  #[test]
+  fn object_url() {
+    let storage = GcsStorage::new(
+      Client::new(),
+      "bucket".to_string(),
+      None,
+      None,
+      Duration::from_secs(3600),
+    );
+
+    assert_eq!(
+      storage.object_url("folder/key name"),
+      "https://storage.googleapis.com/bucket/folder/key%20name"
+    );
+  }
+
+  #[test]
+  fn unsigned_url_without_service_account() {
+    let storage = GcsStorage::new(
+      Client::new(),
+      "bucket".to_string(),
+      Some("http://127.0.0.1:4443/".to_string()),
+      None,
+      Duration::from_secs(3600),
+    );
+
+    assert_eq!(
+      storage.signed_url(&Method::GET, "key", Utc::now()).unwrap(),
+      "http://127.0.0.1:4443/bucket/key"
+    );
+  }
+
+  #[test]
+  fn signed_url() {
+    let service_account = test_service_account();
+    let storage = GcsStorage::new(
+      Client::new(),
+      "bucket".to_string(),
+      None,
+      Some(service_account.clone()),
+      Duration::from_secs(3600),
+    );
+    let now = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+
+    let result = storage
+      .signed_url(&Method::GET, "folder/key name", now)
+      .unwrap();
+    let (url, signature) = result.split_once("&X-Goog-Signature=").unwrap();
+
+    assert_eq!(
+      url,
+      "https://storage.googleapis.com/bucket/folder/key%20name?\
+      X-Goog-Algorithm=GOOG4-RSA-SHA256&\
+      X-Goog-Credential=htsget-test%40htsget-test.iam.gserviceaccount.com%2F20240101%2Fauto%2Fstorage%2Fgoog4_request&\
+      X-Goog-Date=20240101T000000Z&\
+      X-Goog-Expires=3600&\
+      X-Goog-SignedHeaders=host"
+    );
+
+    let (_, string_to_sign) = storage
+      .string_to_sign(&service_account, &Method::GET, "folder/key name", now)
+      .unwrap();
+    let signature = Signature::try_from(hex::decode(signature).unwrap().as_slice()).unwrap();
+
+    VerifyingKey::<Sha256>::new(service_account.private_key.to_public_key())
+      .verify(string_to_sign.as_bytes(), &signature)
+      .unwrap();
+  }
+
+  #[tokio::test]
+  async fn get_object() {
+    with_gcs_test_server(|storage| async move {
+      let mut result = String::new();
+      storage
+        .get(
+          "key1",
+          GetOptions::new_with_default_range(&Default::default()),
+        )
+        .await
+        .unwrap()
+        .read_to_string(&mut result)
+        .await
+        .unwrap();
+
+      assert_eq!(result, "value1");
+    })
+    .await;
+  }
+
+  #[tokio::test]
+  async fn get_object_with_range() {
+    with_gcs_test_server(|storage| async move {
+      let mut result = String::new();
+      storage
+        .get(
+          "folder/key2",
+          GetOptions::new(
+            BytesPosition::new(Some(1), Some(3), None),
+            &Default::default(),
+          ),
+        )
+        .await
+        .unwrap()
+        .read_to_string(&mut result)
+        .await
+        .unwrap();
+
+      assert_eq!(result, "al");
+    })
+    .await;
+  }
+
+  #[tokio::test]
+  async fn get_non_existing_key() {
+    with_gcs_test_server(|storage| async move {
+      let result = storage
+        .get(
+          "non-existing-key",
+          GetOptions::new_with_default_range(&Default::default()),
+        )
+        .await;
+
+      assert!(matches!(result, Err(StorageError::KeyNotFound(_))));
+    })
+    .await;
+  }
+
+  #[tokio::test]
+  async fn get_unauthorized_key() {
+    with_gcs_test_server(|storage| async move {
+      let result = storage
+        .head("unauthorized", HeadOptions::new(&Default::default()))
+        .await;
+
+      assert!(matches!(result, Err(StorageError::PermissionDenied(_))));
+    })
+    .await;
+  }
+
+  #[tokio::test]
+  async fn head_object() {
+    with_gcs_test_server(|storage| async move {
+      let result = storage
+        .head("key1", HeadOptions::new(&Default::default()))
+        .await;
+
+      assert_eq!(result.unwrap(), 6);
+    })
+    .await;
+  }
+
+  #[tokio::test]
+  async fn range_url_with_range() {
+    with_gcs_test_server(|storage| async move {
+      let result = storage
+        .range_url(
+          "key1",
+          RangeUrlOptions::new(
+            BytesPosition::new(Some(7), Some(10), Some(Class::Body)),
+            &Default::default(),
+          ),
+        )
+        .await
+        .unwrap();
+
+      assert_eq!(
+        result,
+        Url::new(storage.object_url("key1"))
+          .with_headers(Headers::default().with_header("Range", "bytes=7-9"))
+          .with_class(Class::Body)
+      );
+    })
+    .await;
+  }
+
+  fn test_service_account() -> ServiceAccount {
+    let private_key = RsaPrivateKey::new(&mut OsRng, 1024).unwrap();
+    let json = serde_json::json!({
+      "type": "service_account",
+      "client_email": "htsget-test@htsget-test.iam.gserviceaccount.com",
+      "private_key": private_key.to_pkcs8_pem(LineEnding::LF).unwrap().as_str(),
+    });
+
+    ServiceAccount::from_json(json.to_string().as_bytes()).unwrap()
+  }
+
+  /// Serves objects under `/bucket` in the same way as a GCS emulator.
+  async fn with_gcs_test_server<F, Fut>(test: F)
+  where
+    F: FnOnce(GcsStorage) -> Fut,
+    Fut: Future<Output = ()>,
+  {
+    let (_, base_path) = create_local_test_files().await;
+    let router = Router::new()
+      .route(
+        "/bucket/unauthorized",
+        get(|| async { StatusCode::UNAUTHORIZED }),
+      )
+      .nest_service("/bucket", ServeDir::new(base_path.path()));
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move { axum::serve(listener, router.into_make_service()).await });
+
+    test(GcsStorage::new(
+      Client::new(),
+      "bucket".to_string(),
+      Some(format!("http://{}", addr)),
+      None,
+      Duration::from_secs(3600),
+    ))
+    .await;
+  }
+}
@@ -6,12 +6,23 @@ pub use htsget_config::types::{
   Class, Format, Headers, HtsGetError, JsonResponse, Query, Response, Url,
 };
 
+#[cfg(feature = "azure")]
+use crate::azure::AzureStorage;
+#[cfg(feature = "experimental")]
+use crate::c4gh::cache::C4GHStateCache;
 #[cfg(feature = "experimental")]
 use crate::c4gh::storage::C4GHStorage;
+#[cfg(feature = "url")]
+use crate::drs::DrsStorage;
 use crate::error::Result;
 use crate::error::StorageError;
 use crate::error::StorageError::InvalidKey;
+#[cfg(feature = "gcp")]
+use crate::gcs::GcsStorage;
 use crate::local::FileStorage;
+use crate::metrics::{record_bytes, record_operation, CountBytes};
+#[cfg(feature = "replicas")]
+use crate::replicas::{Replica, ReplicaStorage};
 #[cfg(feature = "aws")]
 use crate::s3::S3Storage;
 use crate::types::{BytesPositionOptions, DataBlock, GetOptions, HeadOptions, RangeUrlOptions};
@@ -23,27 +34,46 @@ use base64::Engine;
 use cfg_if::cfg_if;
 use htsget_config::storage;
 #[cfg(feature = "experimental")]
-use htsget_config::storage::c4gh::C4GHKeys;
+use htsget_config::storage::c4gh::{C4GHKeys, C4GHMode};
 use htsget_config::types::Scheme;
 use http::uri;
 use pin_project_lite::pin_project;
 use std::fmt;
 use std::fmt::{Debug, Formatter};
+use std::future::Future;
 use std::path::Path;
 use std::pin::Pin;
 use std::task::{Context, Poll};
+#[cfg(any(feature = "gcp", feature = "azure"))]
+use std::time::Duration;
+use std::time::Instant;
 use tokio::io::{AsyncRead, ReadBuf};
 
+#[cfg(feature = "azure")]
+pub mod azure;
 #[cfg(feature = "experimental")]
 pub mod c4gh;
+#[cfg(feature = "aws")]
+pub mod cdn;
+#[cfg(feature = "url")]
+pub mod drs;
 pub mod error;
+#[cfg(feature = "gcp")]
+pub mod gcs;
 pub mod local;
+pub mod metrics;
+#[cfg(feature = "replicas")]
+pub mod replicas;
 #[cfg(feature = "aws")]
 pub mod s3;
 pub mod types;
 #[cfg(feature = "url")]
 pub mod url;
 
+/// The C4GH file ending, which encrypted objects are stored with.
+#[cfg(any(feature = "aws", feature = "url"))]
+pub(crate) const C4GH_FILE_ENDING: &str = ".c4gh";
+
 pin_project! {
   /// A Streamable type represents any AsyncRead data used by `StorageTrait`.
   pub struct Streamable {
@@ -71,9 +101,11 @@ impl AsyncRead for Streamable {
   }
 }
 
-/// The top-level storage type is created from any `StorageTrait`.
+/// The top-level storage type is created from any `StorageTrait`. Operations are recorded as
+/// metrics if the storage has a backend label.
 pub struct Storage {
   inner: Box<dyn StorageTrait + Send + Sync + 'static>,
+  backend: Option<&'static str>,
 }
 
 impl Storage {
@@ -81,12 +113,24 @@ impl Storage {
   pub fn into_inner(self) -> Box<dyn StorageTrait + Send + Sync> {
     self.inner
   }
+
+  /// Set the backend label used when recording metrics.
+  pub fn with_backend(mut self, backend: &'static str) -> Self {
+    self.backend = Some(backend);
+    self
+  }
+
+  /// Get the backend label used when recording metrics.
+  pub fn backend(&self) -> Option<&'static str> {
+    self.backend
+  }
 }
 
 impl Clone for Storage {
   fn clone(&self) -> Self {
     Self {
       inner: self.inner.clone_box(),
+      backend: self.backend,
     }
   }
 }
@@ -115,15 +159,54 @@ impl StorageMiddleware for Storage {
 #[async_trait]
 impl StorageTrait for Storage {
   async fn get(&self, key: &str, options: GetOptions<'_>) -> Result<Streamable> {
-    self.inner.get(key, options).await
+    let Some(backend) = self.backend else {
+      return self.inner.get(key, options).await;
+    };
+
+    let start = Instant::now();
+    let result = self.inner.get(key, options).await;
+    record_operation(backend, "get", &result, start);
+
+    result.map(|streamable| Streamable::from_async_read(CountBytes::new(streamable, backend)))
   }
 
   async fn range_url(&self, key: &str, options: RangeUrlOptions<'_>) -> Result<Url> {
-    self.inner.range_url(key, options).await
+    let Some(backend) = self.backend else {
+      return self.inner.range_url(key, options).await;
+    };
+
+    let range = options.range().clone();
+    let start = Instant::now();
+    let result = self.inner.range_url(key, options).await;
+    record_operation(backend, "range_url", &result, start);
+
+    if let (Ok(_), Some(range_start), Some(range_end)) =
+      (&result, range.get_start(), range.get_end())
+    {
+      record_bytes(backend, "range_url", range_end.saturating_sub(range_start));
+    }
+
+    result
   }
 
   async fn head(&self, key: &str, options: HeadOptions<'_>) -> Result<u64> {
-    self.inner.head(key, options).await
+    let Some(backend) = self.backend else {
+      return self.inner.head(key, options).await;
+    };
+
+    let start = Instant::now();
+    let result = self.inner.head(key, options).await;
+    record_operation(backend, "head", &result, start);
+
+    result
+  }
+
+  async fn exists(&self, key: &str, options: HeadOptions<'_>) -> Result<bool> {
+    self.inner.exists(key, options).await
+  }
+
+  async fn etag(&self, key: &str, options: HeadOptions<'_>) -> Result<Option<String>> {
+    self.inner.etag(key, options).await
   }
 
   fn data_url(&self, data: Vec<u8>, class: Option<Class>) -> Url {
@@ -135,27 +218,101 @@ impl Storage {
   #[cfg(feature = "experimental")]
   /// Wrap an existing storage with C4GH storage
   pub async fn from_c4gh_keys(keys: Option<&C4GHKeys>, storage: Storage) -> Result<Storage> {
+    Self::from_c4gh_keys_with_data_server(keys, storage, None).await
+  }
+
+  #[cfg(feature = "experimental")]
+  /// Wrap an existing storage with C4GH storage, using the data server to return plaintext if
+  /// the keys are in the decrypt mode.
+  pub async fn from_c4gh_keys_with_data_server(
+    keys: Option<&C4GHKeys>,
+    storage: Storage,
+    data_server: Option<&storage::file::File>,
+  ) -> Result<Storage> {
     if let Some(keys) = keys {
-      Ok(Storage::new(C4GHStorage::new_box(
-        keys
-          .clone()
-          .keys()
-          .await
-          .map_err(|err| StorageError::InternalError(err.to_string()))?,
-        storage.into_inner(),
-      )))
+      let decrypt = match keys.mode() {
+        C4GHMode::Reencrypt => None,
+        C4GHMode::Decrypt => Some(data_server.cloned().ok_or_else(|| {
+          StorageError::InvalidInput(
+            "the Crypt4GH decrypt mode requires a `File` backend served by the data server"
+              .to_string(),
+          )
+        })?),
+      };
+
+      let (ticket_secret, ticket_expires_in) = match (&decrypt, keys.decrypt_ticket()) {
+        (None, _) => (vec![], 0),
+        (Some(_), Some(ticket)) => (
+          ticket
+            .read_secret()
+            .await
+            .map_err(|err| StorageError::InternalError(err.to_string()))?
+            .into_bytes(),
+          ticket.expires_in(),
+        ),
+        (Some(_), None) => {
+          return Err(StorageError::InvalidInput(
+            "the Crypt4GH decrypt mode requires a `decrypt_ticket` secret".to_string(),
+          ))
+        }
+      };
+
+      let (decryption_keys, recipient_keys) = keys
+        .clone()
+        .key_set()
+        .await
+        .map_err(|err| StorageError::InternalError(err.to_string()))?
+        .into_inner();
+
+      Ok(Storage::new(
+        C4GHStorage::new_box(decryption_keys, Box::new(storage))
+          .with_recipient_keys(recipient_keys)
+          .with_state_cache(Some(C4GHStateCache::for_keys(keys)))
+          .with_decrypt(decrypt)
+          .with_keys_id(keys.id())
+          .with_ticket_secret(ticket_secret, ticket_expires_in)
+          .with_encrypted_indexes(keys.encrypted_indexes()),
+      ))
     } else {
       Ok(storage)
     }
   }
 
+  /// Create from any backend config. This is boxed because a replica is itself a backend.
+  pub fn from_backend(
+    backend: &storage::Backend,
+  ) -> Pin<Box<dyn Future<Output = Result<Storage>> + Send + '_>> {
+    Box::pin(async move {
+      match backend {
+        storage::Backend::File(file) => Self::from_file(file).await,
+        #[cfg(feature = "aws")]
+        storage::Backend::S3(s3) => Self::from_s3(s3).await,
+        #[cfg(feature = "gcp")]
+        storage::Backend::Gcs(gcs) => Self::from_gcs(gcs).await,
+        #[cfg(feature = "azure")]
+        storage::Backend::Azure(azure) => Self::from_azure(azure).await,
+        #[cfg(feature = "url")]
+        storage::Backend::Url(url) => Self::from_url(url).await,
+        #[cfg(feature = "url")]
+        storage::Backend::Drs(drs) => Self::from_drs(drs).await,
+        #[cfg(feature = "replicas")]
+        storage::Backend::Replicas(replicas) => Self::from_replicas(replicas).await,
+        _ => Err(StorageError::InvalidInput(format!(
+          "unsupported backend: {}",
+          backend.kind()
+        ))),
+      }
+    })
+  }
+
   /// Create from local storage config.
   pub async fn from_file(file: &storage::file::File) -> Result<Storage> {
-    let storage = Storage::new(FileStorage::new(file.local_path(), file.clone())?);
+    let storage =
+      Storage::new(FileStorage::new(file.local_path(), file.clone())?).with_backend("file");
 
     cfg_if! {
       if #[cfg(feature = "experimental")] {
-        Self::from_c4gh_keys(file.keys(), storage).await
+        Self::from_c4gh_keys_with_data_server(file.keys(), storage, Some(file)).await
       } else {
         Ok(storage)
       }
@@ -165,14 +322,7 @@ impl Storage {
   /// Create from s3 config.
   #[cfg(feature = "aws")]
   pub async fn from_s3(s3: &storage::s3::S3) -> Result<Storage> {
-    let storage = Storage::new(
-      S3Storage::new_with_default_config(
-        s3.bucket().to_string(),
-        s3.endpoint().map(str::to_string),
-        s3.path_style(),
-      )
-      .await,
-    );
+    let storage = Storage::new(S3Storage::new_with_config(s3).await?).with_backend("s3");
 
     cfg_if! {
       if #[cfg(feature = "experimental")] {
@@ -183,16 +333,79 @@ impl Storage {
     }
   }
 
+  /// Create from gcs config.
+  #[cfg(feature = "gcp")]
+  pub async fn from_gcs(gcs: &storage::gcs::Gcs) -> Result<Storage> {
+    let storage = Storage::new(GcsStorage::new_with_default_config(
+      gcs.bucket().to_string(),
+      gcs.endpoint().map(str::to_string),
+      gcs.credentials(),
+      Duration::from_secs(gcs.expires_in()),
+    )?)
+    .with_backend("gcs");
+
+    cfg_if! {
+      if #[cfg(feature = "experimental")] {
+        Self::from_c4gh_keys(gcs.keys(), storage).await
+      } else {
+        Ok(storage)
+      }
+    }
+  }
+
+  /// Create from azure config.
+  #[cfg(feature = "azure")]
+  pub async fn from_azure(azure: &storage::azure::Azure) -> Result<Storage> {
+    let storage = Storage::new(AzureStorage::new_with_default_config(
+      azure.account().to_string(),
+      azure.container().to_string(),
+      azure.endpoint().map(str::to_string),
+      azure.account_key(),
+      azure.identity_endpoint(),
+      Duration::from_secs(azure.expires_in()),
+    )?)
+    .with_backend("azure");
+
+    cfg_if! {
+      if #[cfg(feature = "experimental")] {
+        Self::from_c4gh_keys(azure.keys(), storage).await
+      } else {
+        Ok(storage)
+      }
+    }
+  }
+
   /// Create from url config.
   #[cfg(feature = "url")]
   pub async fn from_url(url: &storage::url::Url) -> Result<Storage> {
-    let storage = Storage::new(UrlStorage::new(
-      url.client_cloned(),
-      url.url().clone(),
-      url.response_url().clone(),
-      url.forward_headers(),
-      url.header_blacklist().to_vec(),
-    ));
+    let size_manifest = url
+      .size_manifest()
+      .map(UrlStorage::read_size_manifest)
+      .transpose()?
+      .unwrap_or_default();
+
+    let storage = Storage::new(
+      UrlStorage::new(
+        url.client_cloned(),
+        url.url().clone(),
+        url.response_url().clone(),
+        url.forward_headers(),
+        url.header_blacklist().to_vec(),
+      )
+      .with_retries(url.retries(), url.retry_backoff())
+      .with_circuit_breaker(
+        url.breaker().clone(),
+        url.failure_threshold(),
+        url.cooldown(),
+      )
+      .with_size_discovery(url.size_discovery())
+      .with_sizes(url.sizes().clone())
+      .with_size_manifest(size_manifest)
+      .with_header_allowlist(url.header_allowlist().map(<[String]>::to_vec))
+      .with_headers(url.headers().clone())
+      .with_auth(url.auth().cloned(), url.token_cache().clone()),
+    )
+    .with_backend("url");
 
     cfg_if! {
       if #[cfg(feature = "experimental")] {
@@ -203,9 +416,65 @@ impl Storage {
     }
   }
 
+  /// Create from drs config.
+  #[cfg(feature = "url")]
+  pub async fn from_drs(drs: &storage::drs::Drs) -> Result<Storage> {
+    let storage = Storage::new(
+      DrsStorage::new(
+        drs.client_cloned(),
+        drs.url().clone(),
+        drs.index_suffix().map(str::to_string),
+        drs.forward_headers(),
+        drs.header_allowlist().to_vec(),
+        drs.header_blacklist().to_vec(),
+      )
+      .with_cache(drs.objects().clone(), drs.cache_ttl()),
+    )
+    .with_backend("drs");
+
+    cfg_if! {
+      if #[cfg(feature = "experimental")] {
+        Self::from_c4gh_keys(drs.keys(), storage).await
+      } else {
+        Ok(storage)
+      }
+    }
+  }
+
+  /// Create from replicas config.
+  #[cfg(feature = "replicas")]
+  pub async fn from_replicas(replicas: &storage::replicas::Replicas) -> Result<Storage> {
+    let mut replica_storage = Vec::with_capacity(replicas.replicas().len());
+    for replica in replicas.replicas() {
+      replica_storage.push(Replica::new(
+        Self::from_backend(replica.backend()).await?,
+        replica.region().map(str::to_string),
+      ));
+    }
+
+    let storage = Storage::new(ReplicaStorage::new(
+      replica_storage,
+      replicas.policy(),
+      replicas.region_header().to_string(),
+      replicas.timeout(),
+      replicas.failure_threshold(),
+      replicas.cooldown(),
+      replicas.health().clone(),
+    ));
+
+    cfg_if! {
+      if #[cfg(feature = "experimental")] {
+        Self::from_c4gh_keys(replicas.keys(), storage).await
+      } else {
+        Ok(storage)
+      }
+    }
+  }
+
   pub fn new(inner: impl StorageTrait + Send + Sync + 'static) -> Self {
     Self {
       inner: Box::new(inner),
+      backend: None,
     }
   }
 }
@@ -224,6 +493,21 @@ pub trait StorageTrait: StorageMiddleware + StorageClone {
   /// Get the size of the object represented by the key.
   async fn head(&self, key: &str, options: HeadOptions<'_>) -> Result<u64>;
 
+  /// Check whether the object represented by the key exists.
+  async fn exists(&self, key: &str, options: HeadOptions<'_>) -> Result<bool> {
+    match self.head(key, options).await {
+      Ok(_) => Ok(true),
+      Err(StorageError::KeyNotFound(_)) => Ok(false),
+      Err(err) => Err(err),
+    }
+  }
+
+  /// Get a value which identifies the current version of the object represented by the key,
+  /// such as an ETag or generation. Returns `None` if the storage cannot identify object versions.
+  async fn etag(&self, _key: &str, _options: HeadOptions<'_>) -> Result<Option<String>> {
+    Ok(None)
+  }
+
   /// Get the url of the object using an inline data uri.
   fn data_url(&self, data: Vec<u8>, class: Option<Class>) -> Url {
     Url::new(format!(
@@ -0,0 +1,90 @@
+//! Metrics for storage operations.
+//!
+
+use crate::error::Result;
+use metrics::{counter, histogram};
+use pin_project_lite::pin_project;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Instant;
+use tokio::io::{AsyncRead, ReadBuf};
+
+/// The total number of storage operations.
+pub const STORAGE_OPERATIONS_TOTAL: &str = "htsget_storage_operations_total";
+/// The duration of storage operations in seconds.
+pub const STORAGE_OPERATION_DURATION_SECONDS: &str = "htsget_storage_operation_duration_seconds";
+/// The total number of bytes read by `get`, or covered by the ranges of `range_url`.
+pub const STORAGE_BYTES_TOTAL: &str = "htsget_storage_bytes_total";
+
+/// The total number of Crypt4GH headers decrypted, by the index of the private key that matched.
+pub const C4GH_HEADERS_DECRYPTED_TOTAL: &str = "htsget_c4gh_headers_decrypted_total";
+
+/// Record a storage operation which started at `start`.
+pub(crate) fn record_operation<T>(
+  backend: &'static str,
+  operation: &'static str,
+  result: &Result<T>,
+  start: Instant,
+) {
+  let status = if result.is_ok() { "ok" } else { "error" };
+
+  counter!(
+    STORAGE_OPERATIONS_TOTAL,
+    "backend" => backend,
+    "operation" => operation,
+    "status" => status
+  )
+  .increment(1);
+  histogram!(
+    STORAGE_OPERATION_DURATION_SECONDS,
+    "backend" => backend,
+    "operation" => operation,
+    "status" => status
+  )
+  .record(start.elapsed().as_secs_f64());
+}
+
+/// Record the bytes used by a storage operation.
+pub(crate) fn record_bytes(backend: &'static str, operation: &'static str, bytes: u64) {
+  counter!(STORAGE_BYTES_TOTAL, "backend" => backend, "operation" => operation).increment(bytes);
+}
+
+/// Record a decrypted Crypt4GH header, labelled by the index of the private key that matched.
+#[cfg(feature = "experimental")]
+pub(crate) fn record_key_index(key_index: usize) {
+  counter!(C4GH_HEADERS_DECRYPTED_TOTAL, "key_index" => key_index.to_string()).increment(1);
+}
+
+pin_project! {
+  /// Counts the bytes read from a storage `get`.
+  pub(crate) struct CountBytes<R> {
+    #[pin]
+    inner: R,
+    backend: &'static str,
+  }
+}
+
+impl<R> CountBytes<R> {
+  /// Count the bytes read from the inner reader.
+  pub(crate) fn new(inner: R, backend: &'static str) -> Self {
+    Self { inner, backend }
+  }
+}
+
+impl<R: AsyncRead> AsyncRead for CountBytes<R> {
+  fn poll_read(
+    self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+    buf: &mut ReadBuf<'_>,
+  ) -> Poll<std::io::Result<()>> {
+    let this = self.project();
+    let before = buf.filled().len();
+    let poll = this.inner.poll_read(cx, buf);
+
+    if let Poll::Ready(Ok(())) = poll {
+      record_bytes(this.backend, "get", (buf.filled().len() - before) as u64);
+    }
+
+    poll
+  }
+}
@@ -0,0 +1,594 @@
+//! Module providing an implementation for the [StorageTrait] trait which fails over between
+//! multiple replicated storage backends.
+//!
+
+use crate::error::{Result, StorageError};
+use crate::{
+  GetOptions, HeadOptions, RangeUrlOptions, Storage, StorageMiddleware, StorageTrait, Streamable,
+  Url,
+};
+use async_trait::async_trait;
+use htsget_config::storage::replicas::{ReplicaHealth, ReplicaPolicy};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::timeout;
+use tracing::{debug, instrument, warn};
+
+/// A storage replica with an optional region.
+#[derive(Debug, Clone)]
+pub struct Replica {
+  storage: Storage,
+  region: Option<String>,
+}
+
+impl Replica {
+  /// Create a new replica.
+  pub fn new(storage: Storage, region: Option<String>) -> Self {
+    Self { storage, region }
+  }
+}
+
+/// Implementation for the [StorageTrait] trait which reads from the healthiest replica and fails
+/// over to the next one on errors or timeouts.
+#[derive(Debug, Clone)]
+pub struct ReplicaStorage {
+  replicas: Vec<Replica>,
+  policy: ReplicaPolicy,
+  region_header: String,
+  timeout: Duration,
+  failure_threshold: u64,
+  cooldown: Duration,
+  health: ReplicaHealth,
+  contains: Arc<Mutex<HashMap<(usize, String), bool>>>,
+}
+
+impl ReplicaStorage {
+  /// Create a new replica storage.
+  pub fn new(
+    replicas: Vec<Replica>,
+    policy: ReplicaPolicy,
+    region_header: String,
+    timeout: Duration,
+    failure_threshold: u64,
+    cooldown: Duration,
+    health: ReplicaHealth,
+  ) -> Self {
+    Self {
+      replicas,
+      policy,
+      region_header,
+      timeout,
+      failure_threshold,
+      cooldown,
+      health,
+      contains: Default::default(),
+    }
+  }
+
+  /// Get the indices of the replicas in the order that they should be tried. Healthy replicas
+  /// come first, and unhealthy replicas are kept as a last resort.
+  fn ordered(&self) -> Vec<usize> {
+    let (mut healthy, unhealthy): (Vec<_>, Vec<_>) =
+      (0..self.replicas.len()).partition(|index| self.health.is_healthy(*index));
+
+    healthy.extend(unhealthy);
+    healthy
+  }
+
+  /// Select the replica used for tickets according to the policy.
+  fn select(&self, options: &RangeUrlOptions<'_>) -> Option<usize> {
+    let ordered = self.ordered();
+    let healthy: Vec<_> = ordered
+      .iter()
+      .copied()
+      .filter(|index| self.health.is_healthy(*index))
+      .collect();
+    let candidates = if healthy.is_empty() {
+      &ordered
+    } else {
+      &healthy
+    };
+
+    match self.policy {
+      ReplicaPolicy::PrimaryFirst => candidates.first().copied(),
+      ReplicaPolicy::RoundRobin => {
+        if candidates.is_empty() {
+          None
+        } else {
+          Some(candidates[self.health.next_index() % candidates.len()])
+        }
+      }
+      ReplicaPolicy::Region => options
+        .response_headers()
+        .get(&self.region_header)
+        .and_then(|region| region.to_str().ok())
+        .and_then(|region| {
+          candidates
+            .iter()
+            .copied()
+            .find(|index| self.replicas[*index].region.as_deref() == Some(region))
+        })
+        .or_else(|| candidates.first().copied()),
+    }
+  }
+
+  /// Check whether the replica contains the key. The result is remembered for the lifetime of
+  /// this storage, so that each ticket does not need another request.
+  async fn replica_contains(&self, index: usize, key: &str, options: HeadOptions<'_>) -> bool {
+    let cache_key = (index, key.to_string());
+    if let Some(contains) = self
+      .contains
+      .lock()
+      .ok()
+      .and_then(|contains| contains.get(&cache_key).copied())
+    {
+      return contains;
+    }
+
+    let contains = match timeout(
+      self.timeout,
+      self.replicas[index].storage.exists(key, options),
+    )
+    .await
+    {
+      Ok(Ok(contains)) => contains,
+      Ok(Err(err)) => {
+        warn!(index, key, %err, "replica failed");
+        self
+          .health
+          .record_failure(index, self.failure_threshold, self.cooldown);
+        false
+      }
+      Err(_) => {
+        warn!(index, key, "replica timed out");
+        self
+          .health
+          .record_failure(index, self.failure_threshold, self.cooldown);
+        false
+      }
+    };
+
+    if let Ok(mut cache) = self.contains.lock() {
+      cache.insert(cache_key, contains);
+    }
+
+    contains
+  }
+
+  /// Run an operation on each replica in order until one succeeds, recording the health of
+  /// replicas along the way. The timeout only covers the operation itself, so for `get` it
+  /// bounds obtaining the stream, not reading it.
+  async fn failover<'a, T, F, Fut>(&'a self, key: &str, operation: F) -> Result<T>
+  where
+    F: Fn(&'a Storage) -> Fut,
+    Fut: Future<Output = Result<T>>,
+  {
+    let mut last_err = StorageError::KeyNotFound(key.to_string());
+
+    for index in self.ordered() {
+      match timeout(self.timeout, operation(&self.replicas[index].storage)).await {
+        Ok(Ok(value)) => {
+          self.health.record_success(index);
+          return Ok(value);
+        }
+        Ok(Err(StorageError::KeyNotFound(err))) => {
+          // A missing key is not a replica failure, it may not have been replicated yet.
+          debug!(index, key, "key not found in replica");
+          last_err = StorageError::KeyNotFound(err);
+        }
+        Ok(Err(err)) => {
+          warn!(index, key, %err, "replica failed");
+          self
+            .health
+            .record_failure(index, self.failure_threshold, self.cooldown);
+          last_err = err;
+        }
+        Err(_) => {
+          warn!(index, key, "replica timed out");
+          self
+            .health
+            .record_failure(index, self.failure_threshold, self.cooldown);
+          last_err = StorageError::ServerError(format!("replica timed out for key: {key}"));
+        }
+      }
+    }
+
+    Err(last_err)
+  }
+}
+
+#[async_trait]
+impl StorageMiddleware for ReplicaStorage {}
+
+#[async_trait]
+impl StorageTrait for ReplicaStorage {
+  #[instrument(level = "trace", skip(self))]
+  async fn get(&self, key: &str, options: GetOptions<'_>) -> Result<Streamable> {
+    self
+      .failover(key, |storage| storage.get(key, options.clone()))
+      .await
+  }
+
+  #[instrument(level = "trace", skip(self))]
+  async fn range_url(&self, key: &str, options: RangeUrlOptions<'_>) -> Result<Url> {
+    let selected = self
+      .select(&options)
+      .ok_or_else(|| StorageError::InternalError("no replicas configured".to_string()))?;
+
+    // Fall back to the other replicas if the selected one does not contain the key yet.
+    let candidates = [selected].into_iter().chain(
+      self
+        .ordered()
+        .into_iter()
+        .filter(|index| *index != selected),
+    );
+    for index in candidates {
+      if self
+        .replica_contains(index, key, HeadOptions::new(options.response_headers()))
+        .await
+      {
+        debug!(index, key, "selected replica for tickets");
+        return self.replicas[index].storage.range_url(key, options).await;
+      }
+
+      debug!(index, key, "key not found in replica");
+    }
+
+    Err(StorageError::KeyNotFound(key.to_string()))
+  }
+
+  #[instrument(level = "trace", skip(self))]
+  async fn head(&self, key: &str, options: HeadOptions<'_>) -> Result<u64> {
+    self
+      .failover(key, |storage| storage.head(key, options.clone()))
+      .await
+  }
+
+  #[instrument(level = "trace", skip(self))]
+  async fn exists(&self, key: &str, options: HeadOptions<'_>) -> Result<bool> {
+    self
+      .failover(key, |storage| {
+        let options = options.clone();
+        async move {
+          match storage.exists(key, options).await? {
+            true => Ok(true),
+            false => Err(StorageError::KeyNotFound(key.to_string())),
+          }
+        }
+      })
+      .await
+      .or_else(|err| match err {
+        StorageError::KeyNotFound(_) => Ok(false),
+        err => Err(err),
+      })
+  }
+
+  #[instrument(level = "trace", skip(self))]
+  async fn etag(&self, key: &str, options: HeadOptions<'_>) -> Result<Option<String>> {
+    self
+      .failover(key, |storage| storage.etag(key, options.clone()))
+      .await
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::local::FileStorage;
+  use crate::types::BytesPosition;
+  use htsget_config::storage::file::File;
+  use http::{HeaderMap, HeaderValue};
+  use std::fs;
+  use tempfile::TempDir;
+  use tokio::io::AsyncReadExt;
+
+  #[tokio::test]
+  async fn get_fails_over_to_replica() {
+    with_replicas(ReplicaPolicy::PrimaryFirst, |storage, _| async move {
+      let mut result = String::new();
+      storage
+        .get(
+          "replicated",
+          GetOptions::new_with_default_range(&Default::default()),
+        )
+        .await
+        .unwrap()
+        .read_to_string(&mut result)
+        .await
+        .unwrap();
+
+      assert_eq!(result, "secondary");
+    })
+    .await;
+  }
+
+  #[tokio::test]
+  async fn get_fails_over_on_replica_error() {
+    let secondary = TempDir::new().unwrap();
+    let (storage, health) = with_failing_primary(&secondary, None);
+
+    let mut result = String::new();
+    storage
+      .get(
+        "key",
+        GetOptions::new_with_default_range(&Default::default()),
+      )
+      .await
+      .unwrap()
+      .read_to_string(&mut result)
+      .await
+      .unwrap();
+
+    assert_eq!(result, "secondary");
+    assert!(!health.is_healthy(0));
+  }
+
+  #[tokio::test]
+  async fn get_fails_over_on_replica_timeout() {
+    let secondary = TempDir::new().unwrap();
+    let (storage, health) = with_failing_primary(&secondary, Some(Duration::from_secs(5)));
+
+    let mut result = String::new();
+    storage
+      .get(
+        "key",
+        GetOptions::new_with_default_range(&Default::default()),
+      )
+      .await
+      .unwrap()
+      .read_to_string(&mut result)
+      .await
+      .unwrap();
+
+    assert_eq!(result, "secondary");
+    assert!(!health.is_healthy(0));
+  }
+
+  #[tokio::test]
+  async fn head_fails_over_to_replica() {
+    with_replicas(ReplicaPolicy::PrimaryFirst, |storage, _| async move {
+      let result = storage
+        .head("replicated", HeadOptions::new(&Default::default()))
+        .await;
+
+      assert_eq!(result.unwrap(), 9);
+    })
+    .await;
+  }
+
+  #[tokio::test]
+  async fn exists_in_any_replica() {
+    with_replicas(ReplicaPolicy::PrimaryFirst, |storage, _| async move {
+      assert!(storage
+        .exists("replicated", HeadOptions::new(&Default::default()))
+        .await
+        .unwrap());
+      assert!(!storage
+        .exists("missing", HeadOptions::new(&Default::default()))
+        .await
+        .unwrap());
+    })
+    .await;
+  }
+
+  #[tokio::test]
+  async fn range_url_primary_first() {
+    with_replicas(ReplicaPolicy::PrimaryFirst, |storage, _| async move {
+      let result = range_url(&storage, &Default::default()).await;
+      assert_eq!(result, "http://127.0.0.1:8081/key");
+    })
+    .await;
+  }
+
+  #[tokio::test]
+  async fn range_url_falls_back_to_replica_with_key() {
+    with_replicas(ReplicaPolicy::PrimaryFirst, |storage, health| async move {
+      let result = storage
+        .range_url(
+          "replicated",
+          RangeUrlOptions::new(BytesPosition::default(), &Default::default()),
+        )
+        .await
+        .unwrap();
+      assert_eq!(result.url, "http://127.0.0.1:8082/replicated");
+      assert!(health.is_healthy(0));
+
+      assert!(matches!(
+        storage
+          .range_url(
+            "missing",
+            RangeUrlOptions::new(BytesPosition::default(), &Default::default()),
+          )
+          .await,
+        Err(StorageError::KeyNotFound(_))
+      ));
+    })
+    .await;
+  }
+
+  #[tokio::test]
+  async fn etag_fails_over_to_replica() {
+    let secondary = TempDir::new().unwrap();
+    let (storage, health) = with_failing_primary(&secondary, None);
+
+    assert_eq!(
+      storage
+        .etag("key", HeadOptions::new(&Default::default()))
+        .await
+        .unwrap(),
+      None
+    );
+    assert!(!health.is_healthy(0));
+  }
+
+  #[tokio::test]
+  async fn range_url_skips_unhealthy_replica() {
+    with_replicas(ReplicaPolicy::PrimaryFirst, |storage, health| async move {
+      health.record_failure(0, 1, Duration::from_secs(30));
+
+      let result = range_url(&storage, &Default::default()).await;
+      assert_eq!(result, "http://127.0.0.1:8082/key");
+    })
+    .await;
+  }
+
+  #[tokio::test]
+  async fn range_url_round_robin() {
+    with_replicas(ReplicaPolicy::RoundRobin, |storage, _| async move {
+      let first = range_url(&storage, &Default::default()).await;
+      let second = range_url(&storage.clone(), &Default::default()).await;
+
+      assert_eq!(first, "http://127.0.0.1:8081/key");
+      assert_eq!(second, "http://127.0.0.1:8082/key");
+    })
+    .await;
+  }
+
+  #[tokio::test]
+  async fn range_url_region() {
+    with_replicas(ReplicaPolicy::Region, |storage, _| async move {
+      let mut headers = HeaderMap::new();
+      headers.insert("x-htsget-region", HeaderValue::from_static("secondary"));
+
+      let result = range_url(&storage, &headers).await;
+      assert_eq!(result, "http://127.0.0.1:8082/key");
+
+      headers.insert("x-htsget-region", HeaderValue::from_static("unknown"));
+
+      let result = range_url(&storage, &headers).await;
+      assert_eq!(result, "http://127.0.0.1:8081/key");
+    })
+    .await;
+  }
+
+  async fn range_url(storage: &ReplicaStorage, headers: &HeaderMap) -> String {
+    storage
+      .range_url(
+        "key",
+        RangeUrlOptions::new(BytesPosition::default(), headers),
+      )
+      .await
+      .unwrap()
+      .url
+  }
+
+  /// A storage which always fails, either with an error or by not responding within the delay.
+  #[derive(Debug, Clone)]
+  struct FailingStorage {
+    delay: Option<Duration>,
+  }
+
+  impl FailingStorage {
+    async fn fail<T>(&self) -> Result<T> {
+      if let Some(delay) = self.delay {
+        tokio::time::sleep(delay).await;
+      }
+
+      Err(StorageError::ServerError("replica failed".to_string()))
+    }
+  }
+
+  #[async_trait]
+  impl StorageMiddleware for FailingStorage {}
+
+  #[async_trait]
+  impl StorageTrait for FailingStorage {
+    async fn get(&self, _key: &str, _options: GetOptions<'_>) -> Result<Streamable> {
+      self.fail().await
+    }
+
+    async fn range_url(&self, _key: &str, _options: RangeUrlOptions<'_>) -> Result<Url> {
+      self.fail().await
+    }
+
+    async fn head(&self, _key: &str, _options: HeadOptions<'_>) -> Result<u64> {
+      self.fail().await
+    }
+    async fn etag(&self, _key: &str, _options: HeadOptions<'_>) -> Result<Option<String>> {
+      self.fail().await
+    }
+  }
+
+  fn with_failing_primary(
+    secondary: &TempDir,
+    delay: Option<Duration>,
+  ) -> (ReplicaStorage, ReplicaHealth) {
+    fs::write(secondary.path().join("key"), "secondary").unwrap();
+
+    let health = ReplicaHealth::default();
+    let storage = ReplicaStorage::new(
+      vec![
+        Replica::new(Storage::new(FailingStorage { delay }), None),
+        Replica::new(
+          Storage::new(
+            FileStorage::new(
+              secondary.path(),
+              File::new(
+                htsget_config::types::Scheme::Http,
+                http::uri::Authority::from_static("127.0.0.1:8082"),
+                "secondary".to_string(),
+              ),
+            )
+            .unwrap(),
+          ),
+          None,
+        ),
+      ],
+      ReplicaPolicy::PrimaryFirst,
+      "x-htsget-region".to_string(),
+      Duration::from_millis(100),
+      1,
+      Duration::from_secs(30),
+      health.clone(),
+    );
+
+    (storage, health)
+  }
+
+  async fn with_replicas<F, Fut>(policy: ReplicaPolicy, test: F)
+  where
+    F: FnOnce(ReplicaStorage, ReplicaHealth) -> Fut,
+    Fut: Future<Output = ()>,
+  {
+    let primary = TempDir::new().unwrap();
+    let secondary = TempDir::new().unwrap();
+    fs::write(primary.path().join("key"), "primary").unwrap();
+    fs::write(secondary.path().join("key"), "secondary").unwrap();
+    fs::write(secondary.path().join("replicated"), "secondary").unwrap();
+
+    let replica = |dir: &TempDir, authority: &'static str, region: &str| {
+      Replica::new(
+        Storage::new(
+          FileStorage::new(
+            dir.path(),
+            File::new(
+              htsget_config::types::Scheme::Http,
+              http::uri::Authority::from_static(authority),
+              dir.path().to_string_lossy().to_string(),
+            ),
+          )
+          .unwrap(),
+        ),
+        Some(region.to_string()),
+      )
+    };
+
+    let health = ReplicaHealth::default();
+    let storage = ReplicaStorage::new(
+      vec![
+        replica(&primary, "127.0.0.1:8081", "primary"),
+        replica(&secondary, "127.0.0.1:8082", "secondary"),
+      ],
+      policy,
+      "x-htsget-region".to_string(),
+      Duration::from_secs(5),
+      3,
+      Duration::from_secs(30),
+      health.clone(),
+    );
+
+    test(storage, health).await;
+  }
+}
@@ -1,36 +1,88 @@
 //! Module providing an implementation for the [StorageTrait] trait using Amazon's S3 object storage service.
 //!
 
-use std::fmt::Debug;
-use std::io;
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::{Debug, Formatter};
 use std::io::ErrorKind::Other;
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
+use std::sync::{Arc, LazyLock, Mutex};
 use std::task::{Context, Poll};
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
+use std::{fs, io};
 
 use async_trait::async_trait;
+use aws_config::sts::AssumeRoleProvider;
 use aws_config::BehaviorVersion;
-use aws_sdk_s3::error::{DisplayErrorContext, SdkError};
+#[cfg(feature = "otel")]
+use aws_sdk_s3::config::interceptors::BeforeTransmitInterceptorContextMut;
+#[cfg(feature = "otel")]
+use aws_sdk_s3::config::{ConfigBag, Intercept, RuntimeComponents, SharedInterceptor};
+use aws_sdk_s3::config::{Credentials, Region, SharedCredentialsProvider};
+#[cfg(feature = "otel")]
+use aws_sdk_s3::error::BoxError;
+use aws_sdk_s3::error::{DisplayErrorContext, ProvideErrorMetadata, SdkError};
 use aws_sdk_s3::operation::get_object::builders::GetObjectFluentBuilder;
 use aws_sdk_s3::operation::get_object::GetObjectError;
+use aws_sdk_s3::operation::head_object::builders::HeadObjectFluentBuilder;
 use aws_sdk_s3::operation::head_object::{HeadObjectError, HeadObjectOutput};
 use aws_sdk_s3::presigning::PresigningConfig;
 use aws_sdk_s3::primitives::ByteStream;
-use aws_sdk_s3::types::StorageClass;
+use aws_sdk_s3::types::{GlacierJobParameters, RequestPayer, RestoreRequest, StorageClass, Tier};
 use aws_sdk_s3::Client;
+use base64::engine::general_purpose;
+use base64::Engine;
 use bytes::Bytes;
 use futures::Stream;
+#[cfg(feature = "otel")]
+use htsget_config::config::telemetry::trace_context;
+use htsget_config::storage::s3::{Restore, RestoreTier, S3};
+use md5::{Digest, Md5};
 use pin_project_lite::pin_project;
 use tokio_util::io::StreamReader;
 use tracing::instrument;
 use tracing::{debug, warn};
 
 use super::{GetOptions, RangeUrlOptions, Result};
+use crate::cdn::Cdn;
 use crate::s3::Retrieval::{Delayed, Immediate};
-use crate::types::{BytesPosition, BytesRange};
-use crate::StorageError::{AwsS3Error, IoError, KeyNotFound};
-use crate::{HeadOptions, StorageError, StorageMiddleware, StorageTrait};
-use crate::{Streamable, Url};
+use crate::types::{BytesPosition, BytesRange, HeadCache};
+use crate::StorageError::{
+  AwsS3Error, InvalidInput, IoError, KeyNotFound, PermissionDenied, Unavailable,
+};
+use crate::{HeadOptions, StorageError, StorageMiddleware, StorageTrait, C4GH_FILE_ENDING};
+use crate::{Headers, Streamable, Url};
+
+/// The location config values which determine the S3 client.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct ClientKey {
+  profile: Option<String>,
+  region: Option<String>,
+  access_key_id: Option<String>,
+  secret_access_key: Option<String>,
+  session_token: Option<String>,
+  role_arn: Option<String>,
+  external_id: Option<String>,
+  endpoint: Option<String>,
+  path_style: bool,
+}
+
+impl ClientKey {
+  fn new(s3: &S3) -> Self {
+    Self {
+      profile: s3.profile().map(str::to_string),
+      region: s3.region().map(str::to_string),
+      access_key_id: s3.access_key_id().map(str::to_string),
+      secret_access_key: s3.secret_access_key().map(str::to_string),
+      session_token: s3.session_token().map(str::to_string),
+      role_arn: s3.role_arn().map(str::to_string),
+      external_id: s3.external_id().map(str::to_string),
+      endpoint: s3.endpoint().map(str::to_string),
+      path_style: s3.path_style(),
+    }
+  }
+}
 
 /// Represents data classes that can be retrieved immediately or after a delay.
 /// Specifically, Glacier Flexible, Glacier Deep Archive, and Intelligent Tiering archive
@@ -41,19 +93,191 @@ pub enum Retrieval {
   Delayed(StorageClass),
 }
 
+/// A version manifest, which maps keys to object versions.
+type Manifest = HashMap<String, String>;
+/// A cached version manifest, with the modification time and size it was read at.
+type CachedManifest = ((SystemTime, u64), Arc<Manifest>);
+
+/// A customer-provided key used for SSE-C encrypted objects.
+#[derive(Clone)]
+pub struct SseCustomerKey {
+  key: String,
+  key_md5: String,
+}
+
+impl Debug for SseCustomerKey {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    f.debug_struct("SseCustomerKey")
+      .field("key_md5", &self.key_md5)
+      .finish_non_exhaustive()
+  }
+}
+
+impl SseCustomerKey {
+  /// The only algorithm supported by SSE-C.
+  pub const ALGORITHM: &'static str = "AES256";
+
+  /// Create a customer key from a base64 encoded 256-bit key.
+  pub fn new(key: String) -> Result<Self> {
+    let decoded = general_purpose::STANDARD
+      .decode(&key)
+      .map_err(|err| InvalidInput(format!("invalid SSE-C customer key: {}", err)))?;
+    if decoded.len() != 32 {
+      return Err(InvalidInput(
+        "SSE-C customer key must be 256 bits".to_string(),
+      ));
+    }
+
+    Ok(Self {
+      key,
+      key_md5: general_purpose::STANDARD.encode(Md5::digest(&decoded)),
+    })
+  }
+
+  /// Get the base64 encoded MD5 digest of the key.
+  pub fn key_md5(&self) -> &str {
+    &self.key_md5
+  }
+}
+
+/// Adds the trace context of the current span to S3 requests. This runs after signing so that
+/// presigned URLs never depend on the trace headers.
+#[cfg(feature = "otel")]
+#[derive(Debug)]
+struct TraceContextInterceptor;
+
+#[cfg(feature = "otel")]
+impl Intercept for TraceContextInterceptor {
+  fn name(&self) -> &'static str {
+    "TraceContextInterceptor"
+  }
+
+  fn modify_before_transmit(
+    &self,
+    context: &mut BeforeTransmitInterceptorContextMut<'_>,
+    _runtime_components: &RuntimeComponents,
+    _cfg: &mut ConfigBag,
+  ) -> std::result::Result<(), BoxError> {
+    let headers = context.request_mut().headers_mut();
+    for (name, value) in trace_context() {
+      headers.try_insert(name, value)?;
+    }
+
+    Ok(())
+  }
+}
+
 /// Implementation for the [StorageTrait] trait utilising data from an S3 bucket.
 #[derive(Debug, Clone)]
 pub struct S3Storage {
   client: Client,
   bucket: String,
+  restore: Option<Restore>,
+  expires_in: Duration,
+  requester_pays: bool,
+  sse_customer_key: Option<SseCustomerKey>,
+  versions: HashMap<String, String>,
+  version_manifest: Arc<Manifest>,
+  cdn: Option<Cdn>,
+  heads: HeadCache<HeadObjectOutput>,
 }
 
 impl S3Storage {
-  // Allow the user to set this?
+  /// The default number of seconds that presigned URLs are valid for.
   pub const PRESIGNED_REQUEST_EXPIRY: u64 = 1000;
 
   pub fn new(client: Client, bucket: String) -> Self {
-    S3Storage { client, bucket }
+    S3Storage {
+      client,
+      bucket,
+      restore: None,
+      expires_in: Duration::from_secs(Self::PRESIGNED_REQUEST_EXPIRY),
+      requester_pays: false,
+      sse_customer_key: None,
+      versions: HashMap::new(),
+      version_manifest: Default::default(),
+      cdn: None,
+      heads: Default::default(),
+    }
+  }
+
+  /// Set how long presigned URLs are valid for.
+  pub fn with_expires_in(mut self, expires_in: Duration) -> Self {
+    self.expires_in = expires_in;
+    self
+  }
+
+  /// Set whether requests are made to a requester pays bucket.
+  pub fn with_requester_pays(mut self, requester_pays: bool) -> Self {
+    self.requester_pays = requester_pays;
+    self
+  }
+
+  /// Set the customer key used to read SSE-C encrypted objects.
+  pub fn with_sse_customer_key(mut self, sse_customer_key: Option<SseCustomerKey>) -> Self {
+    self.sse_customer_key = sse_customer_key;
+    self
+  }
+
+  /// Set the object versions that are pinned by key. Requests for these keys use the `VersionId`,
+  /// and presigned URLs embed the version.
+  pub fn with_versions(mut self, versions: HashMap<String, String>) -> Self {
+    self.versions = versions;
+    self
+  }
+
+  /// Set the object versions read from a version manifest. Versions set using `with_versions`
+  /// take precedence over these.
+  pub fn with_version_manifest(mut self, version_manifest: Arc<Manifest>) -> Self {
+    self.version_manifest = version_manifest;
+    self
+  }
+
+  /// Read a JSON version manifest which maps keys to object versions. Manifests are cached and
+  /// only read again when their modification time or size changes.
+  pub fn read_version_manifest(path: &Path) -> Result<Arc<Manifest>> {
+    static MANIFESTS: LazyLock<Mutex<HashMap<PathBuf, CachedManifest>>> =
+      LazyLock::new(Default::default);
+
+    let io_err = |err| {
+      IoError(
+        format!("failed to read version manifest `{}`", path.display()),
+        err,
+      )
+    };
+    let metadata = fs::metadata(path).map_err(io_err)?;
+    let modified = (metadata.modified().map_err(io_err)?, metadata.len());
+
+    if let Some(manifest) = MANIFESTS.lock().ok().and_then(|manifests| {
+      manifests
+        .get(path)
+        .filter(|(cached, _)| *cached == modified)
+        .map(|(_, manifest)| manifest.clone())
+    }) {
+      return Ok(manifest);
+    }
+
+    let manifest: Arc<Manifest> = serde_json::from_slice(&fs::read(path).map_err(io_err)?)
+      .map(Arc::new)
+      .map_err(|err| InvalidInput(format!("invalid version manifest: {}", err)))?;
+    if let Ok(mut manifests) = MANIFESTS.lock() {
+      manifests.insert(path.to_path_buf(), (modified, manifest.clone()));
+    }
+
+    Ok(manifest)
+  }
+
+  /// Set the CDN that ticket URLs point to. Indexes and other reads still use the origin bucket.
+  pub fn with_cdn(mut self, cdn: Option<Cdn>) -> Self {
+    self.cdn = cdn;
+    self
+  }
+
+  /// Set the config used to restore archived objects. If this is not set, archived objects are
+  /// reported as unavailable without requesting a restore.
+  pub fn with_restore(mut self, restore: Option<Restore>) -> Self {
+    self.restore = restore;
+    self
   }
 
   pub async fn new_with_default_config(
@@ -65,6 +289,8 @@ impl S3Storage {
     let mut s3_config_builder = aws_sdk_s3::config::Builder::from(&sdk_config);
     s3_config_builder.set_endpoint_url(endpoint); // For local S3 storage, i.e: Minio
     s3_config_builder.set_force_path_style(Some(path_style));
+    #[cfg(feature = "otel")]
+    s3_config_builder.push_interceptor(SharedInterceptor::new(TraceContextInterceptor));
 
     let client = s3_config_builder.build();
     let s3_client = Client::from_conf(client);
@@ -72,77 +298,325 @@ impl S3Storage {
     S3Storage::new(s3_client, bucket)
   }
 
-  /// Return an S3 pre-signed URL of the key. This function does not check that the key exists,
-  /// so this should be checked before calling it.
-  pub async fn s3_presign_url<K: AsRef<str> + Send>(
-    &self,
-    key: K,
-    range: &BytesPosition,
-  ) -> Result<String> {
-    let response = self
+  /// Create a new S3 storage using the credentials, region and signing options of the location
+  /// config. Static credentials take precedence over the profile, and if a role is set, it is
+  /// assumed using the resolved credentials.
+  pub async fn new_with_config(s3: &S3) -> Result<Self> {
+    let client = Self::shared_client(s3).await?;
+
+    let sse_customer_key = s3
+      .sse_customer_key()
+      .map(|key| SseCustomerKey::new(key.to_string()))
+      .transpose()?;
+
+    let version_manifest = s3
+      .version_manifest()
+      .map(Self::read_version_manifest)
+      .transpose()?
+      .unwrap_or_default();
+
+    let cdn = s3.cdn().map(Cdn::from_config).transpose()?;
+
+    Ok(
+      S3Storage::new(client, s3.bucket().to_string())
+        .with_restore(s3.restore().cloned())
+        .with_expires_in(Duration::from_secs(s3.expires_in()))
+        .with_requester_pays(s3.requester_pays())
+        .with_sse_customer_key(sse_customer_key)
+        .with_versions(s3.versions().clone())
+        .with_version_manifest(version_manifest)
+        .with_cdn(cdn),
+    )
+  }
+
+  /// Get the client for the credentials, region, role and endpoint of the location config. Clients
+  /// are only created once and then shared between requests, so that credentials and assumed
+  /// roles are not resolved again for each request.
+  async fn shared_client(s3: &S3) -> Result<Client> {
+    static SHARED: LazyLock<Mutex<HashMap<ClientKey, Client>>> = LazyLock::new(Default::default);
+
+    let key = ClientKey::new(s3);
+    if let Some(client) = SHARED
+      .lock()
+      .ok()
+      .and_then(|shared| shared.get(&key).cloned())
+    {
+      return Ok(client);
+    }
+
+    let mut loader = aws_config::defaults(BehaviorVersion::latest());
+    if let Some(profile) = s3.profile() {
+      loader = loader.profile_name(profile);
+    }
+    if let Some(region) = s3.region() {
+      loader = loader.region(Region::new(region.to_string()));
+    }
+    match (s3.access_key_id(), s3.secret_access_key()) {
+      (Some(access_key_id), Some(secret_access_key)) => {
+        loader = loader.credentials_provider(Credentials::new(
+          access_key_id,
+          secret_access_key,
+          s3.session_token().map(str::to_string),
+          None,
+          "htsget-config",
+        ));
+      }
+      (None, None) => {}
+      _ => {
+        return Err(InvalidInput(
+          "both `access_key_id` and `secret_access_key` must be set".to_string(),
+        ))
+      }
+    }
+    let sdk_config = loader.load().await;
+
+    let mut s3_config_builder = aws_sdk_s3::config::Builder::from(&sdk_config);
+    if let Some(role_arn) = s3.role_arn() {
+      let mut provider = AssumeRoleProvider::builder(role_arn).session_name("htsget-rs");
+      if let Some(external_id) = s3.external_id() {
+        provider = provider.external_id(external_id);
+      }
+
+      s3_config_builder.set_credentials_provider(Some(SharedCredentialsProvider::new(
+        provider.configure(&sdk_config).build().await,
+      )));
+    }
+    s3_config_builder.set_endpoint_url(s3.endpoint().map(str::to_string)); // For local S3 storage, i.e: Minio
+    s3_config_builder.set_force_path_style(Some(s3.path_style()));
+    #[cfg(feature = "otel")]
+    s3_config_builder.push_interceptor(SharedInterceptor::new(TraceContextInterceptor));
+
+    let client = Client::from_conf(s3_config_builder.build());
+    if let Ok(mut shared) = SHARED.lock() {
+      shared.insert(key, client.clone());
+    }
+
+    Ok(client)
+  }
+
+  fn request_payer(&self) -> Option<RequestPayer> {
+    self.requester_pays.then_some(RequestPayer::Requester)
+  }
+
+  /// Get the pinned version of the key. Versions set directly take precedence over the manifest.
+  /// An encrypted key uses the version pinned for the key without the C4GH file ending, if the
+  /// encrypted key itself has no pinned version.
+  fn version_id(&self, key: &str) -> Option<String> {
+    let version = |key: &str| {
+      self
+        .versions
+        .get(key)
+        .or_else(|| self.version_manifest.get(key))
+        .cloned()
+    };
+
+    version(key).or_else(|| key.strip_suffix(C4GH_FILE_ENDING).and_then(version))
+  }
+
+  /// Create a get object request, with the version, request payer and customer key set.
+  fn get_object(&self, key: &str) -> GetObjectFluentBuilder {
+    let sse = self.sse_customer_key.as_ref();
+
+    self
       .client
       .get_object()
       .bucket(&self.bucket)
-      .key(key.as_ref());
-    let response = Self::apply_range(response, range);
-    Ok(
-      response
-        .presigned(
-          PresigningConfig::expires_in(Duration::from_secs(Self::PRESIGNED_REQUEST_EXPIRY))
-            .map_err(|err| AwsS3Error(err.to_string(), key.as_ref().to_string()))?,
-        )
-        .await
-        .map_err(|err| Self::map_get_error(key, err))?
-        .uri()
-        .to_string(),
-    )
+      .key(key)
+      .set_version_id(self.version_id(key))
+      .set_request_payer(self.request_payer())
+      .set_sse_customer_algorithm(sse.map(|_| SseCustomerKey::ALGORITHM.to_string()))
+      .set_sse_customer_key(sse.map(|sse| sse.key.clone()))
+      .set_sse_customer_key_md5(sse.map(|sse| sse.key_md5.clone()))
   }
 
-  async fn s3_head<K: AsRef<str> + Send>(&self, key: K) -> Result<HeadObjectOutput> {
+  /// Create a head object request, with the version, request payer and customer key set.
+  fn head_object(&self, key: &str) -> HeadObjectFluentBuilder {
+    let sse = self.sse_customer_key.as_ref();
+
     self
       .client
       .head_object()
       .bucket(&self.bucket)
-      .key(key.as_ref())
-      .send()
+      .key(key)
+      .set_version_id(self.version_id(key))
+      .set_request_payer(self.request_payer())
+      .set_sse_customer_algorithm(sse.map(|_| SseCustomerKey::ALGORITHM.to_string()))
+      .set_sse_customer_key(sse.map(|sse| sse.key.clone()))
+      .set_sse_customer_key_md5(sse.map(|sse| sse.key_md5.clone()))
+  }
+
+  /// Return an S3 pre-signed URL of the key. This function does not check that the key exists,
+  /// so this should be checked before calling it. Signed headers which the client must send,
+  /// such as the request payer and customer key, are included in the URL headers.
+  pub async fn s3_presign_url<K: AsRef<str> + Send>(
+    &self,
+    key: K,
+    range: &BytesPosition,
+  ) -> Result<Url> {
+    let response = Self::apply_range(self.get_object(key.as_ref()), range);
+    let request = response
+      .presigned(
+        PresigningConfig::expires_in(self.expires_in)
+          .map_err(|err| AwsS3Error(err.to_string(), key.as_ref().to_string()))?,
+      )
       .await
-      .map_err(|err| {
-        warn!("S3 error: {}", DisplayErrorContext(&err));
+      .map_err(|err| Self::map_get_error(key, err))?;
 
-        let err = err.into_service_error();
-        if let HeadObjectError::NotFound(_) = err {
-          KeyNotFound(key.as_ref().to_string())
-        } else {
-          AwsS3Error(err.to_string(), key.as_ref().to_string())
-        }
+    let headers = request
+      .headers()
+      .filter(|(name, _)| {
+        name.starts_with("x-amz-server-side-encryption-customer-") || *name == "x-amz-request-payer"
       })
+      .fold(Headers::default(), |headers, (name, value)| {
+        headers.with_header(name, value)
+      });
+
+    Ok(Url::new(request.uri()).with_headers(headers))
+  }
+
+  async fn s3_head<K: AsRef<str> + Send>(&self, key: K) -> Result<HeadObjectOutput> {
+    self.head_object(key.as_ref()).send().await.map_err(|err| {
+      warn!("S3 error: {}", DisplayErrorContext(&err));
+
+      // A missing key returns 403 rather than 404 without `s3:ListBucket` permissions.
+      let forbidden = err
+        .raw_response()
+        .is_some_and(|response| response.status().as_u16() == 403);
+      let err = err.into_service_error();
+      if let HeadObjectError::NotFound(_) = err {
+        KeyNotFound(key.as_ref().to_string())
+      } else if forbidden {
+        PermissionDenied(format!("access denied for key {}", key.as_ref()))
+      } else {
+        AwsS3Error(err.to_string(), key.as_ref().to_string())
+      }
+    })
+  }
+
+  /// Get the head output of the key, reusing the output of an earlier head request for the key.
+  /// This is used for the size and etag of objects, so that both come from one request.
+  async fn cached_head(&self, key: &str) -> Result<HeadObjectOutput> {
+    self
+      .heads
+      .get_or_try_insert(key, || self.s3_head(key))
+      .await
   }
 
   /// Returns the retrieval type of the object stored with the key.
   #[instrument(level = "trace", skip_all, ret)]
   pub async fn get_retrieval_type<K: AsRef<str> + Send>(&self, key: K) -> Result<Retrieval> {
-    let head = self.s3_head(key.as_ref()).await?;
-    Ok(
-      // Default is Standard.
-      match head.storage_class.unwrap_or(StorageClass::Standard) {
-        class @ (StorageClass::DeepArchive | StorageClass::Glacier) => {
-          Self::check_restore_header(head.restore, class)
-        }
-        class @ StorageClass::IntelligentTiering => {
-          if head.archive_status.is_some() {
-            // Not sure if this check is necessary for the archived intelligent tiering classes but
-            // it shouldn't hurt.
-            Self::check_restore_header(head.restore, class)
-          } else {
-            Immediate(class)
-          }
+    Ok(Self::retrieval_type(&self.s3_head(key.as_ref()).await?))
+  }
+
+  /// Returns the retrieval type from the head output of an object.
+  fn retrieval_type(head: &HeadObjectOutput) -> Retrieval {
+    // Default is Standard.
+    match head.storage_class.clone().unwrap_or(StorageClass::Standard) {
+      class @ (StorageClass::DeepArchive | StorageClass::Glacier) => {
+        Self::check_restore_header(head.restore.as_deref(), class)
+      }
+      class @ StorageClass::IntelligentTiering => {
+        if head.archive_status.is_some() {
+          // Not sure if this check is necessary for the archived intelligent tiering classes but
+          // it shouldn't hurt.
+          Self::check_restore_header(head.restore.as_deref(), class)
+        } else {
+          Immediate(class)
         }
-        class => Immediate(class),
+      }
+      class => Immediate(class),
+    }
+  }
+
+  /// Check that the object can be retrieved immediately. If it cannot, an unavailable error is
+  /// returned containing the restore status, after requesting a restore if one is configured.
+  async fn check_retrieval(&self, key: &str, head: &HeadObjectOutput) -> Result<()> {
+    let Delayed(class) = Self::retrieval_type(head) else {
+      return Ok(());
+    };
+
+    Err(self.archived(key, class, head.restore.is_some()).await)
+  }
+
+  /// Get the unavailable error for an archived object, requesting a restore if one is configured
+  /// and the object is not already being restored.
+  async fn archived(&self, key: &str, class: StorageClass, restoring: bool) -> StorageError {
+    let (status, retry_after) = match (restoring, &self.restore) {
+      // A restore header on a delayed object means that the restore is ongoing.
+      (true, restore) => (
+        "restore in progress",
+        Some(restore.clone().unwrap_or_default().retry_after()),
+      ),
+      (false, Some(restore)) => match self.restore_object(key, &class, restore).await {
+        Ok(status) => (status, Some(restore.retry_after())),
+        Err(err) => return err,
       },
+      (false, None) => ("object has not been restored", None),
+    };
+
+    Unavailable(
+      format!(
+        "object `{}` is archived in `{}`: {}",
+        key,
+        class.as_str(),
+        status
+      ),
+      retry_after,
     )
   }
 
-  fn check_restore_header(restore_header: Option<String>, class: StorageClass) -> Retrieval {
+  /// Request a restore of an archived object, returning the restore status.
+  async fn restore_object(
+    &self,
+    key: &str,
+    class: &StorageClass,
+    restore: &Restore,
+  ) -> Result<&'static str> {
+    let mut request = RestoreRequest::builder();
+    // Objects in the intelligent tiering archive tiers are restored without days or a tier.
+    if *class != StorageClass::IntelligentTiering {
+      let tier = match restore.tier() {
+        RestoreTier::Standard => Tier::Standard,
+        RestoreTier::Bulk => Tier::Bulk,
+        RestoreTier::Expedited => Tier::Expedited,
+      };
+
+      request = request.days(restore.days()).glacier_job_parameters(
+        GlacierJobParameters::builder()
+          .tier(tier)
+          .build()
+          .map_err(|err| AwsS3Error(err.to_string(), key.to_string()))?,
+      );
+    }
+
+    let response = self
+      .client
+      .restore_object()
+      .bucket(&self.bucket)
+      .key(key)
+      .set_version_id(self.version_id(key))
+      .set_request_payer(self.request_payer())
+      .restore_request(request.build())
+      .send()
+      .await;
+
+    match response {
+      Ok(_) => {
+        debug!(key, "requested restore of key {:?}", key);
+        Ok("restore requested")
+      }
+      Err(err) if err.code() == Some("RestoreAlreadyInProgress") => Ok("restore in progress"),
+      Err(err) => {
+        warn!("S3 error: {}", DisplayErrorContext(&err));
+        Err(AwsS3Error(
+          err.into_service_error().to_string(),
+          key.to_string(),
+        ))
+      }
+    }
+  }
+
+  fn check_restore_header(restore_header: Option<&str>, class: StorageClass) -> Retrieval {
     if let Some(restore) = restore_header {
       if restore.contains("ongoing-request=\"false\"") {
         return Immediate(class);
@@ -166,26 +640,32 @@ impl S3Storage {
     key: K,
     options: GetOptions<'_>,
   ) -> Result<ByteStream> {
-    if let Delayed(class) = self.get_retrieval_type(key.as_ref()).await? {
-      return Err(AwsS3Error(
-        format!("cannot retrieve object immediately, class is `{class:?}`"),
-        key.as_ref().to_string(),
-      ));
-    }
+    let response = Self::apply_range(self.get_object(key.as_ref()), options.range())
+      .set_if_match(options.etag().map(ToString::to_string))
+      .send()
+      .await;
 
-    let response = self
-      .client
-      .get_object()
-      .bucket(&self.bucket)
-      .key(key.as_ref());
-    let response = Self::apply_range(response, options.range());
-    Ok(
-      response
-        .send()
-        .await
-        .map_err(|err| Self::map_get_error(key, err))?
-        .body,
-    )
+    match response {
+      Ok(output) => Ok(output.body),
+      Err(err)
+        if err
+          .raw_response()
+          .is_some_and(|response| response.status().as_u16() == 412) =>
+      {
+        Err(StorageError::modified(key.as_ref()))
+      }
+      // Archived objects are only detected when getting them, which avoids a head request.
+      Err(err) => match err.as_service_error() {
+        Some(GetObjectError::InvalidObjectState(state)) => {
+          let class = state
+            .storage_class()
+            .cloned()
+            .unwrap_or(StorageClass::Glacier);
+          Err(self.archived(key.as_ref(), class, false).await)
+        }
+        _ => Err(Self::map_get_error(key, err)),
+      },
+    }
   }
 
   async fn create_stream_reader<K: AsRef<str> + Send>(
@@ -256,12 +736,15 @@ impl StorageTrait for S3Storage {
     ))
   }
 
-  /// Return an S3 pre-signed htsget URL. This function does not check that the key exists, so this
-  /// should be checked before calling it.
+  /// Return an S3 pre-signed htsget URL, or a CDN URL if a CDN is set. This function does not check
+  /// that the key exists, so this should be checked before calling it.
   #[instrument(level = "trace", skip(self))]
   async fn range_url(&self, key: &str, options: RangeUrlOptions<'_>) -> Result<Url> {
-    let presigned_url = self.s3_presign_url(key, options.range()).await?;
-    let url = options.apply(Url::new(presigned_url));
+    let url = match &self.cdn {
+      Some(cdn) => cdn.url(key, self.version_id(key).as_deref(), self.expires_in)?,
+      None => self.s3_presign_url(key, options.range()).await?,
+    };
+    let url = options.apply(url);
 
     debug!(calling_from = ?self, key, ?url, "getting url with key {:?}", key);
     Ok(url)
@@ -270,7 +753,8 @@ impl StorageTrait for S3Storage {
   /// Returns the size of the S3 object in bytes.
   #[instrument(level = "trace", skip(self))]
   async fn head(&self, key: &str, _options: HeadOptions<'_>) -> Result<u64> {
-    let head = self.s3_head(key).await?;
+    let head = self.cached_head(key).await?;
+    self.check_retrieval(key, &head).await?;
 
     let content_length = head
       .content_length()
@@ -286,21 +770,37 @@ impl StorageTrait for S3Storage {
     debug!(calling_from = ?self, key, len, "size of key {:?} is {}", key, len);
     Ok(len)
   }
+
+  /// Returns the ETag of the S3 object.
+  #[instrument(level = "trace", skip(self))]
+  async fn etag(&self, key: &str, _options: HeadOptions<'_>) -> Result<Option<String>> {
+    let etag = self.cached_head(key).await?.e_tag;
+
+    debug!(calling_from = ?self, key, ?etag, "etag of key {:?} is {:?}", key, etag);
+    Ok(etag)
+  }
 }
 
 #[cfg(test)]
 pub(crate) mod tests {
+  use std::collections::HashMap;
+  use std::fs;
   use std::future::Future;
   use std::path::{Path, PathBuf};
+  use std::sync::{Arc, Mutex};
+  use std::time::Duration;
 
-  use htsget_test::aws_mocks::with_s3_test_server;
+  use htsget_config::storage::s3::{Restore, RestoreTier};
+  use htsget_test::aws_mocks::{with_s3_archived_test_server, with_s3_test_server, RestoreState};
+  use tempfile::TempDir;
 
+  use crate::cdn::Cdn;
   use crate::local::tests::create_local_test_files;
-  use crate::s3::S3Storage;
+  use crate::s3::{Retrieval, S3Storage, SseCustomerKey};
   use crate::types::BytesPosition;
-  use crate::Headers;
   use crate::{GetOptions, RangeUrlOptions, StorageTrait};
   use crate::{HeadOptions, StorageError};
+  use crate::{Headers, Url};
 
   pub(crate) async fn with_aws_s3_storage_fn<F, Fut>(test: F, folder_name: String, base_path: &Path)
   where
@@ -322,6 +822,24 @@ pub(crate) mod tests {
     with_aws_s3_storage_fn(test, folder_name, base_path.path()).await;
   }
 
+  async fn with_archived_s3_storage<F, Fut>(state: RestoreState, restore: Option<Restore>, test: F)
+  where
+    F: FnOnce(S3Storage, Arc<Mutex<HashMap<String, RestoreState>>>) -> Fut,
+    Fut: Future<Output = ()>,
+  {
+    let (folder_name, base_path) = create_local_test_files().await;
+    let archived = Arc::new(Mutex::new(HashMap::from([("key2".to_string(), state)])));
+
+    with_s3_archived_test_server(base_path.path(), archived.clone(), |client| async move {
+      test(
+        S3Storage::new(client, folder_name).with_restore(restore),
+        archived,
+      )
+      .await;
+    })
+    .await;
+  }
+
   #[tokio::test]
   async fn existing_key() {
     with_aws_s3_storage(|storage, _| async move {
@@ -345,7 +863,7 @@ pub(crate) mod tests {
           GetOptions::new_with_default_range(&Default::default()),
         )
         .await;
-      assert!(matches!(result, Err(StorageError::AwsS3Error(_, _))));
+      assert!(matches!(result, Err(StorageError::KeyNotFound(_))));
     })
     .await;
   }
@@ -420,6 +938,157 @@ pub(crate) mod tests {
     .await;
   }
 
+  #[tokio::test]
+  async fn url_with_cdn() {
+    with_aws_s3_storage(|storage, _| async move {
+      let storage = storage
+        .with_versions(HashMap::from_iter(vec![(
+          "key2".to_string(),
+          "version".to_string(),
+        )]))
+        .with_cdn(Some(Cdn::new("https://cdn.example.com".to_string())));
+
+      let result = storage
+        .range_url(
+          "key2",
+          RangeUrlOptions::new(
+            BytesPosition::new(Some(7), Some(9), None),
+            &Default::default(),
+          ),
+        )
+        .await
+        .unwrap();
+      assert_eq!(
+        result,
+        Url::new("https://cdn.example.com/key2?versionId=version")
+          .with_headers(Headers::default().with_header("Range", "bytes=7-8"))
+      );
+    })
+    .await;
+  }
+
+  #[tokio::test]
+  async fn url_with_signing_options() {
+    with_aws_s3_storage(|storage, _| async move {
+      let storage = storage
+        .with_expires_in(Duration::from_secs(60))
+        .with_requester_pays(true)
+        .with_sse_customer_key(Some(
+          SseCustomerKey::new("AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=".to_string()).unwrap(),
+        ));
+
+      let result = storage
+        .range_url(
+          "key2",
+          RangeUrlOptions::new(
+            BytesPosition::new(Some(7), Some(9), None),
+            &Default::default(),
+          ),
+        )
+        .await
+        .unwrap();
+      assert!(result.url.contains("Amz-Expires=60"));
+      assert_eq!(
+        result.headers,
+        Some(
+          Headers::default()
+            .with_header("x-amz-request-payer", "requester")
+            .with_header("x-amz-server-side-encryption-customer-algorithm", "AES256")
+            .with_header(
+              "x-amz-server-side-encryption-customer-key",
+              "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA="
+            )
+            .with_header(
+              "x-amz-server-side-encryption-customer-key-md5",
+              "cLyPS3KoaSFGi/joRB3OUQ=="
+            )
+            .with_header("Range", "bytes=7-8")
+        )
+      );
+    })
+    .await;
+  }
+
+  #[tokio::test]
+  async fn url_with_version() {
+    with_aws_s3_storage(|storage, _| async move {
+      let storage =
+        storage.with_versions(HashMap::from([("key2".to_string(), "version".to_string())]));
+
+      let result = storage
+        .range_url(
+          "key2",
+          RangeUrlOptions::new_with_default_range(&Default::default()),
+        )
+        .await
+        .unwrap();
+      assert!(result.url.starts_with("http://folder.localhost:0/key2"));
+      assert!(result.url.contains("versionId=version"));
+
+      let result = storage
+        .range_url(
+          "key1",
+          RangeUrlOptions::new_with_default_range(&Default::default()),
+        )
+        .await
+        .unwrap();
+      assert!(!result.url.contains("versionId"));
+    })
+    .await;
+  }
+
+  #[tokio::test]
+  async fn url_with_version_of_encrypted_key() {
+    with_aws_s3_storage(|storage, _| async move {
+      let storage = storage.with_version_manifest(Arc::new(HashMap::from([(
+        "key2".to_string(),
+        "version".to_string(),
+      )])));
+
+      let result = storage
+        .range_url(
+          "key2.c4gh",
+          RangeUrlOptions::new_with_default_range(&Default::default()),
+        )
+        .await
+        .unwrap();
+      assert!(result.url.contains("versionId=version"));
+    })
+    .await;
+  }
+
+  #[test]
+  fn version_manifest() {
+    let tmp = TempDir::new().unwrap();
+    let path = tmp.path().join("manifest.json");
+    fs::write(&path, r#"{ "id.bam": "data", "id.bam.bai": "index" }"#).unwrap();
+
+    assert_eq!(
+      *S3Storage::read_version_manifest(&path).unwrap(),
+      HashMap::from([
+        ("id.bam".to_string(), "data".to_string()),
+        ("id.bam.bai".to_string(), "index".to_string()),
+      ])
+    );
+
+    fs::write(&path, "not json").unwrap();
+    assert!(matches!(
+      S3Storage::read_version_manifest(&path),
+      Err(StorageError::InvalidInput(_))
+    ));
+  }
+
+  #[test]
+  fn sse_customer_key() {
+    let key =
+      SseCustomerKey::new("AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=".to_string()).unwrap();
+    assert_eq!(key.key_md5(), "cLyPS3KoaSFGi/joRB3OUQ==");
+    assert!(!format!("{:?}", key).contains("AAAA"));
+
+    assert!(SseCustomerKey::new("AAAA".to_string()).is_err());
+    assert!(SseCustomerKey::new("not base64".to_string()).is_err());
+  }
+
   #[tokio::test]
   async fn file_size() {
     with_aws_s3_storage(|storage, _| async move {
@@ -440,4 +1109,120 @@ pub(crate) mod tests {
     })
     .await;
   }
+
+  #[tokio::test]
+  async fn archived_key_without_restore() {
+    with_archived_s3_storage(
+      RestoreState::Archived,
+      None,
+      |storage, archived| async move {
+        let result = storage
+          .head("key2", HeadOptions::new(&Default::default()))
+          .await;
+
+        assert!(matches!(
+          result,
+          Err(StorageError::Unavailable(message, None)) if message.contains("has not been restored")
+        ));
+        assert_eq!(archived.lock().unwrap()["key2"], RestoreState::Archived);
+      },
+    )
+    .await;
+  }
+
+  #[tokio::test]
+  async fn archived_key_requests_restore() {
+    let restore = Restore::new(RestoreTier::Bulk, 2, 60);
+    with_archived_s3_storage(
+      RestoreState::Archived,
+      Some(restore),
+      |storage, archived| async move {
+        let result = storage
+          .head("key2", HeadOptions::new(&Default::default()))
+          .await;
+        assert!(matches!(
+          result,
+          Err(StorageError::Unavailable(message, Some(60))) if message.contains("restore requested")
+        ));
+        assert_eq!(archived.lock().unwrap()["key2"], RestoreState::Ongoing);
+
+        let result = storage
+          .get(
+            "key2",
+            GetOptions::new_with_default_range(&Default::default()),
+          )
+          .await;
+        assert!(matches!(
+          result,
+          Err(StorageError::Unavailable(message, Some(60))) if message.contains("restore in progress")
+        ));
+      },
+    )
+    .await;
+  }
+
+  #[tokio::test]
+  async fn archived_key_get_requests_restore() {
+    let restore = Restore::new(RestoreTier::Bulk, 2, 60);
+    with_archived_s3_storage(
+      RestoreState::Archived,
+      Some(restore),
+      |storage, archived| async move {
+        let result = storage
+          .get(
+            "key2",
+            GetOptions::new_with_default_range(&Default::default()),
+          )
+          .await;
+        assert!(matches!(
+          result,
+          Err(StorageError::Unavailable(message, Some(60))) if message.contains("restore requested")
+        ));
+        assert_eq!(archived.lock().unwrap()["key2"], RestoreState::Ongoing);
+      },
+    )
+    .await;
+  }
+
+  #[tokio::test]
+  async fn archived_key_restore_in_progress() {
+    with_archived_s3_storage(RestoreState::Ongoing, None, |storage, _| async move {
+      let result = storage
+        .head("key2", HeadOptions::new(&Default::default()))
+        .await;
+
+      assert!(matches!(
+        result,
+        Err(StorageError::Unavailable(message, Some(3600))) if message.contains("`GLACIER`")
+      ));
+      assert!(matches!(
+        storage.get_retrieval_type("key2").await,
+        Ok(Retrieval::Delayed(_))
+      ));
+    })
+    .await;
+  }
+
+  #[tokio::test]
+  async fn restored_key() {
+    with_archived_s3_storage(RestoreState::Restored, None, |storage, _| async move {
+      let result = storage
+        .head("key2", HeadOptions::new(&Default::default()))
+        .await;
+      assert!(matches!(result, Ok(6)));
+
+      assert!(matches!(
+        storage.get_retrieval_type("key2").await,
+        Ok(Retrieval::Immediate(_))
+      ));
+      assert!(storage
+        .get(
+          "key2",
+          GetOptions::new_with_default_range(&Default::default()),
+        )
+        .await
+        .is_ok());
+    })
+    .await;
+  }
 }
@@ -4,6 +4,13 @@ use std::cmp::Ordering;
 use std::fmt;
 use std::fmt::{Display, Formatter};
 use tracing::instrument;
+#[cfg(any(feature = "aws", feature = "gcp", feature = "azure", feature = "url"))]
+use {
+  crate::error::Result,
+  std::collections::HashMap,
+  std::future::Future,
+  std::sync::{Arc, Mutex},
+};
 
 /// A DataBlock is either a range of bytes, or a data blob that gets transformed into a data uri.
 #[derive(Debug, PartialEq, Eq)]
@@ -202,6 +209,7 @@ impl BytesPosition {
 pub struct GetOptions<'a> {
   pub(crate) range: BytesPosition,
   pub(crate) request_headers: &'a HeaderMap,
+  pub(crate) etag: Option<String>,
 }
 
 impl<'a> GetOptions<'a> {
@@ -209,6 +217,7 @@ impl<'a> GetOptions<'a> {
     Self {
       range,
       request_headers,
+      etag: None,
     }
   }
 
@@ -231,10 +240,22 @@ impl<'a> GetOptions<'a> {
     &self.range
   }
 
+  /// Only get the object if it matches the etag, using an `If-Match` header. Storage fails with
+  /// an unavailable error if the object has been modified.
+  pub fn with_etag(mut self, etag: Option<String>) -> Self {
+    self.etag = etag;
+    self
+  }
+
   /// Get the request headers.
   pub fn request_headers(&self) -> &'a HeaderMap {
     self.request_headers
   }
+
+  /// Get the etag.
+  pub fn etag(&self) -> Option<&str> {
+    self.etag.as_deref()
+  }
 }
 
 #[derive(Debug, Clone)]
@@ -273,6 +294,7 @@ impl<'a> BytesPositionOptions<'a> {
 pub struct RangeUrlOptions<'a> {
   range: BytesPosition,
   response_headers: &'a HeaderMap,
+  etag: Option<String>,
 }
 
 impl<'a> RangeUrlOptions<'a> {
@@ -280,6 +302,7 @@ impl<'a> RangeUrlOptions<'a> {
     Self {
       range,
       response_headers,
+      etag: None,
     }
   }
 
@@ -292,6 +315,12 @@ impl<'a> RangeUrlOptions<'a> {
     self
   }
 
+  /// Pin the url to the version of the object identified by the etag, using an `If-Match` header.
+  pub fn with_etag(mut self, etag: Option<String>) -> Self {
+    self.etag = etag;
+    self
+  }
+
   pub fn apply(self, url: Url) -> Url {
     let range: String = String::from(&BytesRange::from(self.range()));
 
@@ -301,6 +330,11 @@ impl<'a> RangeUrlOptions<'a> {
       url.add_headers(Headers::default().with_header("Range", range))
     };
 
+    let url = match self.etag() {
+      Some(etag) => url.add_headers(Headers::default().with_header("If-Match", etag)),
+      None => url,
+    };
+
     url.set_class(self.range().class)
   }
 
@@ -313,6 +347,11 @@ impl<'a> RangeUrlOptions<'a> {
   pub fn response_headers(&self) -> &'a HeaderMap {
     self.response_headers
   }
+
+  /// Get the etag.
+  pub fn etag(&self) -> Option<&str> {
+    self.etag.as_deref()
+  }
 }
 
 /// A struct to represent options passed to a `Storage` head call.
@@ -339,6 +378,41 @@ impl<'a> From<&'a GetOptions<'a>> for HeadOptions<'a> {
   }
 }
 
+/// Remembers the results of head requests made by a storage, so that the size and etag of an
+/// object come from the same request. Storage is created for each query, so results only last
+/// for the duration of a search.
+#[cfg(any(feature = "aws", feature = "gcp", feature = "azure", feature = "url"))]
+#[derive(Debug, Clone)]
+pub(crate) struct HeadCache<T>(Arc<Mutex<HashMap<String, T>>>);
+
+#[cfg(any(feature = "aws", feature = "gcp", feature = "azure", feature = "url"))]
+impl<T> Default for HeadCache<T> {
+  fn default() -> Self {
+    Self(Default::default())
+  }
+}
+
+#[cfg(any(feature = "aws", feature = "gcp", feature = "azure", feature = "url"))]
+impl<T: Clone> HeadCache<T> {
+  /// Get the head result for the key, making the request if it has not been made yet.
+  pub(crate) async fn get_or_try_insert<F, Fut>(&self, key: &str, head: F) -> Result<T>
+  where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<T>>,
+  {
+    if let Some(value) = self.0.lock().ok().and_then(|cache| cache.get(key).cloned()) {
+      return Ok(value);
+    }
+
+    let value = head().await?;
+    if let Ok(mut cache) = self.0.lock() {
+      cache.insert(key.to_string(), value.clone());
+    }
+
+    Ok(value)
+  }
+}
+
 #[cfg(test)]
 mod tests {
   use std::collections::HashMap;
@@ -801,4 +875,25 @@ mod tests {
         .with_class(Class::Header)
     );
   }
+
+  #[test]
+  fn url_options_apply_with_etag() {
+    let result = RangeUrlOptions::new(
+      BytesPosition::new(Some(5), Some(11), Some(Class::Header)),
+      &Default::default(),
+    )
+    .with_etag(Some("\"etag\"".to_string()))
+    .apply(Url::new(""));
+
+    assert_eq!(
+      result,
+      Url::new("")
+        .with_headers(
+          Headers::new(HashMap::new())
+            .with_header("Range", "bytes=5-10")
+            .with_header("If-Match", "\"etag\"")
+        )
+        .with_class(Class::Header)
+    );
+  }
 }
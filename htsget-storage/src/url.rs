@@ -1,26 +1,62 @@
+use std::collections::HashMap;
 use std::fmt::Debug;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::{Arc, LazyLock, Mutex};
 use std::task::{Context, Poll};
+use std::time::{Duration, Instant, SystemTime};
 
 use async_trait::async_trait;
+use base64::engine::general_purpose;
+use base64::Engine;
 use bytes::Bytes;
 use futures::Stream;
 use futures_util::TryStreamExt;
-use http::header::CONTENT_LENGTH;
-use http::{HeaderMap, Method, Request, Uri};
+use http::header::{
+  AUTHORIZATION, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG, IF_MATCH, RANGE,
+};
+use http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode, Uri};
 use pin_project_lite::pin_project;
+use rand::Rng;
 use reqwest::{Client, ClientBuilder};
+use serde::Deserialize;
+use tokio::time::sleep;
 use tokio_util::io::StreamReader;
-use tracing::{debug, instrument};
+use tracing::{debug, instrument, warn};
 
+#[cfg(feature = "otel")]
+use htsget_config::config::telemetry::inject_trace_context;
 use htsget_config::error;
+use htsget_config::storage::url::{CircuitBreaker, Secret, SizeDiscovery, TokenCache, UrlAuth};
+use url::form_urlencoded;
 
-use crate::StorageError::{InternalError, KeyNotFound, ResponseError, UrlParseError};
+use crate::types::HeadCache;
+use crate::StorageError::{
+  InternalError, InvalidInput, IoError, KeyNotFound, PermissionDenied, ResponseError, ServerError,
+  Unavailable, UrlParseError,
+};
 use crate::{
   GetOptions, HeadOptions, RangeUrlOptions, Result, StorageError, StorageMiddleware, StorageTrait,
 };
 use crate::{Streamable, Url as HtsGetUrl};
 
+/// Refresh OAuth2 tokens this many seconds before they expire.
+const REFRESH_BEFORE: u64 = 60;
+
+/// The response from an OAuth2 token endpoint.
+#[derive(Deserialize)]
+struct TokenResponse {
+  access_token: String,
+  expires_in: Option<u64>,
+}
+
+type Sizes = HashMap<String, u64>;
+
+/// A size manifest, along with the modification time and length of the file it was read from.
+type CachedSizes = ((SystemTime, u64), Arc<Sizes>);
+
 /// A storage struct which derives data from HTTP URLs.
 #[derive(Debug, Clone)]
 pub struct UrlStorage {
@@ -29,6 +65,19 @@ pub struct UrlStorage {
   response_url: Uri,
   forward_headers: bool,
   header_blacklist: Vec<String>,
+  retries: u32,
+  retry_backoff: Duration,
+  breaker: CircuitBreaker,
+  failure_threshold: u64,
+  cooldown: Duration,
+  size_discovery: SizeDiscovery,
+  sizes: HashMap<String, u64>,
+  size_manifest: Arc<Sizes>,
+  header_allowlist: Option<Vec<String>>,
+  headers: HashMap<String, String>,
+  auth: Option<UrlAuth>,
+  token_cache: TokenCache,
+  heads: HeadCache<(Option<u64>, Option<String>)>,
 }
 
 impl UrlStorage {
@@ -46,6 +95,19 @@ impl UrlStorage {
       response_url,
       forward_headers,
       header_blacklist,
+      retries: 3,
+      retry_backoff: Duration::from_millis(100),
+      breaker: Default::default(),
+      failure_threshold: 5,
+      cooldown: Duration::from_secs(30),
+      size_discovery: Default::default(),
+      sizes: Default::default(),
+      size_manifest: Default::default(),
+      header_allowlist: None,
+      headers: Default::default(),
+      auth: None,
+      token_cache: Default::default(),
+      heads: Default::default(),
     }
   }
 
@@ -56,15 +118,107 @@ impl UrlStorage {
     forward_headers: bool,
     header_blacklist: Vec<String>,
   ) -> Result<Self> {
-    Ok(Self {
-      client: ClientBuilder::new()
+    Ok(Self::new(
+      ClientBuilder::new()
         .build()
         .map_err(|err| InternalError(format!("failed to build reqwest client: {}", err)))?,
       url,
       response_url,
       forward_headers,
       header_blacklist,
-    })
+    ))
+  }
+
+  /// Set the number of retries and the base backoff between them.
+  pub fn with_retries(mut self, retries: u32, retry_backoff: Duration) -> Self {
+    self.retries = retries;
+    self.retry_backoff = retry_backoff;
+    self
+  }
+
+  /// Set the circuit breaker, which is shared between storage instances.
+  pub fn with_circuit_breaker(
+    mut self,
+    breaker: CircuitBreaker,
+    failure_threshold: u64,
+    cooldown: Duration,
+  ) -> Self {
+    self.breaker = breaker;
+    self.failure_threshold = failure_threshold;
+    self.cooldown = cooldown;
+    self
+  }
+
+  /// Set the method used to discover the size of objects.
+  pub fn with_size_discovery(mut self, size_discovery: SizeDiscovery) -> Self {
+    self.size_discovery = size_discovery;
+    self
+  }
+
+  /// Set the object sizes that are known ahead of time by key.
+  pub fn with_sizes(mut self, sizes: HashMap<String, u64>) -> Self {
+    self.sizes = sizes;
+    self
+  }
+
+  /// Set the object sizes read from a size manifest. Sizes set with `with_sizes` take precedence.
+  pub fn with_size_manifest(mut self, size_manifest: Arc<Sizes>) -> Self {
+    self.size_manifest = size_manifest;
+    self
+  }
+
+  /// Set the headers that are allowed to be forwarded. If set, no other headers are forwarded.
+  pub fn with_header_allowlist(mut self, header_allowlist: Option<Vec<String>>) -> Self {
+    self.header_allowlist = header_allowlist;
+    self
+  }
+
+  /// Set the templated headers which are added to requests to the url server.
+  pub fn with_headers(mut self, headers: HashMap<String, String>) -> Self {
+    self.headers = headers;
+    self
+  }
+
+  /// Set the credentials used for requests to the url server, and the token cache which is
+  /// shared between storage instances.
+  pub fn with_auth(mut self, auth: Option<UrlAuth>, token_cache: TokenCache) -> Self {
+    self.auth = auth;
+    self.token_cache = token_cache;
+    self
+  }
+
+  /// Read a JSON size manifest which maps keys to object sizes. Manifests are cached and only
+  /// read again when their modification time or size changes.
+  pub fn read_size_manifest(path: &Path) -> Result<Arc<Sizes>> {
+    static MANIFESTS: LazyLock<Mutex<HashMap<PathBuf, CachedSizes>>> =
+      LazyLock::new(Default::default);
+
+    let io_err = |err| {
+      IoError(
+        format!("failed to read size manifest `{}`", path.display()),
+        err,
+      )
+    };
+    let metadata = fs::metadata(path).map_err(io_err)?;
+    let modified = (metadata.modified().map_err(io_err)?, metadata.len());
+
+    if let Some(manifest) = MANIFESTS.lock().ok().and_then(|manifests| {
+      manifests
+        .get(path)
+        .filter(|(cached, _)| *cached == modified)
+        .map(|(_, manifest)| manifest.clone())
+    }) {
+      return Ok(manifest);
+    }
+
+    let manifest: Arc<Sizes> = serde_json::from_slice(&fs::read(path).map_err(io_err)?)
+      .map(Arc::new)
+      .map_err(|err| InvalidInput(format!("invalid size manifest: {}", err)))?;
+    if let Ok(mut manifests) = MANIFESTS.lock() {
+      manifests.insert(path.to_path_buf(), (modified, manifest.clone()));
+    }
+
+    Ok(manifest)
   }
 
   /// Get a url from the key.
@@ -81,15 +235,167 @@ impl UrlStorage {
       .map_err(|err| UrlParseError(err.to_string()))
   }
 
-  /// Remove blacklisted headers from the headers.
+  /// Remove blacklisted headers from the headers. If there is an allowlist, any headers which
+  /// are not in it are also removed.
   pub fn remove_blacklisted_headers(&self, mut headers: HeaderMap) -> HeaderMap {
+    if let Some(allowlist) = &self.header_allowlist {
+      let mut allowed = HeaderMap::new();
+      for name in allowlist
+        .iter()
+        .filter_map(|name| HeaderName::from_str(name).ok())
+      {
+        for value in headers.get_all(&name) {
+          allowed.append(name.clone(), value.clone());
+        }
+      }
+      headers = allowed;
+    }
+
     for blacklisted_header in &self.header_blacklist {
       headers.remove(blacklisted_header);
     }
     headers
   }
 
-  /// Construct and send a request
+  /// Get the headers sent to the url server. These contain the forwarded request headers, the
+  /// templated headers and any credentials. Templated headers and credentials are never added
+  /// to tickets.
+  pub async fn upstream_headers(&self, request_headers: &HeaderMap) -> Result<HeaderMap> {
+    let mut headers = self.remove_blacklisted_headers(request_headers.clone());
+
+    for (name, template) in &self.headers {
+      let name = HeaderName::from_str(name)
+        .map_err(|err| InvalidInput(format!("invalid header name `{}`: {}", name, err)))?;
+      let value = Self::render_template(template, request_headers)?;
+
+      headers.insert(
+        name,
+        HeaderValue::from_str(&value)
+          .map_err(|err| InvalidInput(format!("invalid header value: {}", err)))?,
+      );
+    }
+
+    if let Some(auth) = &self.auth {
+      let mut authorization = HeaderValue::from_str(&self.authorization(auth).await?)
+        .map_err(|err| InternalError(format!("invalid authorization header: {}", err)))?;
+      authorization.set_sensitive(true);
+
+      headers.insert(AUTHORIZATION, authorization);
+    }
+
+    Ok(headers)
+  }
+
+  /// Render a header template, replacing `${env.NAME}` with the environment variable and
+  /// `${header.NAME}` with the request header.
+  fn render_template(template: &str, request_headers: &HeaderMap) -> Result<String> {
+    let mut rendered = String::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find("${") {
+      rendered.push_str(&rest[..start]);
+
+      let end = rest[start..]
+        .find('}')
+        .ok_or_else(|| InvalidInput(format!("unterminated header template: {}", template)))?;
+      let variable = &rest[start + 2..start + end];
+
+      let value = match variable.split_once('.') {
+        Some(("env", name)) => std::env::var(name).map_err(|err| {
+          InternalError(format!(
+            "reading header template variable `{}`: {}",
+            name, err
+          ))
+        })?,
+        Some(("header", name)) => request_headers
+          .get(name)
+          .and_then(|value| value.to_str().ok())
+          .ok_or_else(|| InvalidInput(format!("missing request header `{}`", name)))?
+          .to_string(),
+        _ => {
+          return Err(InvalidInput(format!(
+            "invalid header template variable: {}",
+            variable
+          )))
+        }
+      };
+
+      rendered.push_str(&value);
+      rest = &rest[start + end + 1..];
+    }
+
+    rendered.push_str(rest);
+    Ok(rendered)
+  }
+
+  /// Get the value of the authorization header for the credentials.
+  async fn authorization(&self, auth: &UrlAuth) -> Result<String> {
+    let read = |secret: &Secret| {
+      secret
+        .read()
+        .map_err(|err| InternalError(format!("reading url credentials: {}", err)))
+    };
+
+    match auth {
+      UrlAuth::Bearer { token } => Ok(format!("Bearer {}", read(token)?)),
+      UrlAuth::Basic { username, password } => Ok(format!(
+        "Basic {}",
+        general_purpose::STANDARD.encode(format!("{}:{}", username, read(password)?))
+      )),
+      UrlAuth::OAuth2 {
+        token_url,
+        client_id,
+        client_secret,
+        scope,
+      } => {
+        if let Some(token) = self.token_cache.get() {
+          return Ok(format!("Bearer {}", token));
+        }
+
+        // The serializer is not `Send`, so it is finished before any await.
+        let form = {
+          let mut form = form_urlencoded::Serializer::new(String::new());
+          form
+            .append_pair("grant_type", "client_credentials")
+            .append_pair("client_id", client_id)
+            .append_pair("client_secret", &read(client_secret)?);
+          if let Some(scope) = scope {
+            form.append_pair("scope", scope);
+          }
+          form.finish()
+        };
+
+        let response = self
+          .client
+          .post(token_url)
+          .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+          .body(form)
+          .send()
+          .await
+          .and_then(|response| response.error_for_status())
+          .map_err(|err| ServerError(format!("fetching oauth2 token: {}", err)))?;
+        let body = response
+          .bytes()
+          .await
+          .map_err(|err| ServerError(format!("reading oauth2 token: {}", err)))?;
+        let token: TokenResponse = serde_json::from_slice(&body)
+          .map_err(|err| ServerError(format!("invalid oauth2 token response: {}", err)))?;
+
+        if let Some(expires_in) = token.expires_in {
+          self.token_cache.set(
+            token.access_token.clone(),
+            Instant::now() + Duration::from_secs(expires_in.saturating_sub(REFRESH_BEFORE)),
+          );
+        }
+
+        Ok(format!("Bearer {}", token.access_token))
+      }
+    }
+  }
+
+  /// Construct and send a request. Idempotent requests are retried with jittered exponential
+  /// backoff on server and connection errors, and requests to a host are rejected while its
+  /// circuit breaker is open.
   pub async fn send_request<K: AsRef<str> + Send>(
     &self,
     key: K,
@@ -98,34 +404,102 @@ impl UrlStorage {
   ) -> Result<reqwest::Response> {
     let key = key.as_ref();
     let url = self.get_url_from_key(key)?;
+    let host = url
+      .authority()
+      .map(|authority| authority.to_string())
+      .unwrap_or_default();
+
+    #[cfg(feature = "otel")]
+    let headers = &{
+      let mut headers = headers.clone();
+      inject_trace_context(&mut headers);
+      headers
+    };
+
+    let retries = if method == Method::GET || method == Method::HEAD {
+      self.retries
+    } else {
+      0
+    };
+
+    let mut attempt = 0;
+    loop {
+      // Another request may have opened the circuit while this one was backing off.
+      if let Some(open_for) = self.breaker.open_for(&host) {
+        return Err(Unavailable(
+          format!("circuit breaker is open for {}", host),
+          Some(open_for.as_secs().max(1)),
+        ));
+      }
+
+      let result = self
+        .client
+        .request(method.clone(), url.to_string())
+        .headers(headers.clone())
+        .send()
+        .await;
+
+      let retryable = match &result {
+        Ok(response) => response.status().is_server_error(),
+        Err(err) => err.is_connect() || err.is_timeout(),
+      };
+
+      if !retryable {
+        self.breaker.record_success(&host);
+        return Self::map_response(result, key);
+      }
+
+      // A request only counts as one failure once all of its attempts are exhausted.
+      if attempt >= retries {
+        self
+          .breaker
+          .record_failure(&host, self.failure_threshold, self.cooldown);
+        return Self::map_response(result, key);
+      }
+
+      let backoff = self.backoff(attempt);
+      warn!(key, attempt, ?backoff, "retrying url request");
+      sleep(backoff).await;
+      attempt += 1;
+    }
+  }
 
-    println!("url: {:?}", url);
-    let request = Request::builder().method(method).uri(&url);
+  /// Get the backoff for a retry attempt, using full jitter.
+  fn backoff(&self, attempt: u32) -> Duration {
+    let max = self
+      .retry_backoff
+      .saturating_mul(2u32.saturating_pow(attempt))
+      .as_millis();
+    let max = u64::try_from(max).unwrap_or(u64::MAX);
 
-    let request = headers
-      .iter()
-      .fold(request, |acc, (key, value)| acc.header(key, value))
-      .body(vec![])
-      .map_err(|err| UrlParseError(err.to_string()))?;
+    Duration::from_millis(rand::thread_rng().gen_range(0..=max))
+  }
 
-    let response = self
-      .client
-      .execute(
-        request
-          .try_into()
-          .map_err(|err| InternalError(format!("failed to create http request: {}", err)))?,
-      )
-      .await
-      .map_err(|err| KeyNotFound(format!("{} with key {}", err, key)))?;
+  /// Map the response status code or client error to a storage error.
+  fn map_response(
+    result: reqwest::Result<reqwest::Response>,
+    key: &str,
+  ) -> Result<reqwest::Response> {
+    let response =
+      result.map_err(|err| ServerError(format!("sending request for key {}: {}", key, err)))?;
 
     let status = response.status();
-    if status.is_client_error() || status.is_server_error() {
-      Err(KeyNotFound(format!(
+    match status {
+      StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Err(PermissionDenied(format!(
         "url returned {} for key {}",
         status, key
-      )))
-    } else {
-      Ok(response)
+      ))),
+      StatusCode::NOT_FOUND => Err(KeyNotFound(key.to_string())),
+      StatusCode::PRECONDITION_FAILED => Err(StorageError::modified(key)),
+      status if status.is_client_error() => Err(ResponseError(format!(
+        "url returned {} for key {}",
+        status, key
+      ))),
+      status if status.is_server_error() => Err(ServerError(format!(
+        "url returned {} for key {}",
+        status, key
+      ))),
+      _ => Ok(response),
     }
   }
 
@@ -169,6 +543,81 @@ impl UrlStorage {
   ) -> Result<reqwest::Response> {
     self.send_request(key, headers, Method::GET).await
   }
+
+  /// Get the first byte of the key, which is used to discover its size without a HEAD request.
+  pub async fn range_get_key<K: AsRef<str> + Send>(
+    &self,
+    key: K,
+    headers: &HeaderMap,
+  ) -> Result<reqwest::Response> {
+    let mut headers = headers.clone();
+    headers.insert(RANGE, HeaderValue::from_static("bytes=0-0"));
+
+    self.send_request(key, &headers, Method::GET).await
+  }
+
+  /// Send the request used to discover the size and etag of the key, according to the size
+  /// discovery method.
+  async fn discover_key(&self, key: &str, headers: &HeaderMap) -> Result<reqwest::Response> {
+    match self.size_discovery {
+      SizeDiscovery::Head => self.head_key(key, headers).await,
+      SizeDiscovery::Get => self.range_get_key(key, headers).await,
+      SizeDiscovery::HeadOrGet => match self.head_key(key, headers).await {
+        Ok(response) if Self::response_size(&response).is_some() => Ok(response),
+        Err(err @ (KeyNotFound(_) | Unavailable(_, _))) => Err(err),
+        result => {
+          debug!(
+            key,
+            error = ?result.err(),
+            "head request did not return a size, falling back to a ranged get"
+          );
+          self.range_get_key(key, headers).await
+        }
+      },
+    }
+  }
+
+  /// Get the size and etag of the key, reusing the response of an earlier discovery request for
+  /// the key, so that both come from one request.
+  async fn discovered(
+    &self,
+    key: &str,
+    options: HeadOptions<'_>,
+  ) -> Result<(Option<u64>, Option<String>)> {
+    self
+      .heads
+      .get_or_try_insert(key, || async {
+        let request_headers = self.upstream_headers(options.request_headers()).await?;
+        let response = self.discover_key(key, &request_headers).await?;
+
+        let etag = response
+          .headers()
+          .get(ETAG)
+          .and_then(|etag| etag.to_str().ok())
+          .map(ToString::to_string);
+
+        Ok((Self::response_size(&response), etag))
+      })
+      .await
+  }
+
+  /// Get the total size of the object from a response. This is the total in `Content-Range` for
+  /// partial responses, and `Content-Length` otherwise.
+  fn response_size(response: &reqwest::Response) -> Option<u64> {
+    let headers = response.headers();
+    if response.status() == StatusCode::PARTIAL_CONTENT {
+      headers
+        .get(CONTENT_RANGE)
+        .and_then(|content_range| content_range.to_str().ok())
+        .and_then(|content_range| content_range.rsplit_once('/'))
+        .and_then(|(_, size)| size.parse().ok())
+    } else {
+      headers
+        .get(CONTENT_LENGTH)
+        .and_then(|content_length| content_length.to_str().ok())
+        .and_then(|content_length| content_length.parse().ok())
+    }
+  }
 }
 
 pin_project! {
@@ -203,7 +652,13 @@ impl StorageTrait for UrlStorage {
   async fn get(&self, key: &str, options: GetOptions<'_>) -> Result<Streamable> {
     debug!(calling_from = ?self, key, "getting file with key {:?}", key);
 
-    let request_headers = self.remove_blacklisted_headers(options.request_headers().clone());
+    let mut request_headers = self.upstream_headers(options.request_headers()).await?;
+    if let Some(etag) = options.etag() {
+      request_headers.insert(
+        IF_MATCH,
+        HeaderValue::from_str(etag).map_err(|err| InvalidInput(err.to_string()))?,
+      );
+    }
     let response = self.get_key(key.to_string(), &request_headers).await?;
 
     Ok(Streamable::from_async_read(StreamReader::new(
@@ -218,31 +673,38 @@ impl StorageTrait for UrlStorage {
     debug!(calling_from = ?self, key, "getting url with key {:?}", key);
 
     let response_headers = self.remove_blacklisted_headers(options.response_headers().clone());
-    let new_options = RangeUrlOptions::new(options.range().clone(), &response_headers);
+    let new_options = RangeUrlOptions::new(options.range().clone(), &response_headers)
+      .with_etag(options.etag().map(ToString::to_string));
 
     self.format_url(key, new_options)
   }
 
   #[instrument(level = "trace", skip(self))]
   async fn head(&self, key: &str, options: HeadOptions<'_>) -> Result<u64> {
-    let request_headers = self.remove_blacklisted_headers(options.request_headers().clone());
-    let head = self.head_key(key, &request_headers).await?;
+    if let Some(len) = self.sizes.get(key).or_else(|| self.size_manifest.get(key)) {
+      debug!(calling_from = ?self, key, len, "size of key {:?} is {} from size hint", key, len);
+      return Ok(*len);
+    }
 
-    let len = head
-      .headers()
-      .get(CONTENT_LENGTH)
-      .and_then(|content_length| content_length.to_str().ok())
-      .and_then(|content_length| content_length.parse().ok())
-      .ok_or_else(|| {
-        ResponseError(format!(
-          "failed to get content length from head response for key: {}",
-          key
-        ))
-      })?;
+    let (len, _) = self.discovered(key, options).await?;
+    let len = len.ok_or_else(|| {
+      ResponseError(format!(
+        "failed to get content length from response for key: {}",
+        key
+      ))
+    })?;
 
     debug!(calling_from = ?self, key, len, "size of key {:?} is {}", key, len);
     Ok(len)
   }
+
+  #[instrument(level = "trace", skip(self))]
+  async fn etag(&self, key: &str, options: HeadOptions<'_>) -> Result<Option<String>> {
+    let (_, etag) = self.discovered(key, options).await?;
+
+    debug!(calling_from = ?self, key, ?etag, "etag of key {:?} is {:?}", key, etag);
+    Ok(etag)
+  }
 }
 
 #[cfg(test)]
@@ -250,11 +712,14 @@ pub(crate) mod tests {
   use std::future::Future;
   use std::path::{Path, PathBuf};
   use std::str::FromStr;
+  use std::sync::atomic::{AtomicUsize, Ordering};
+  use std::sync::Arc;
   use std::{result, vec};
 
   use axum::body::Body;
   use axum::middleware::Next;
   use axum::response::Response;
+  use axum::routing::{get, post};
   use axum::{middleware, Router};
   use http::header::{AUTHORIZATION, HOST};
   use http::{HeaderName, HeaderValue, Request, StatusCode};
@@ -263,7 +728,9 @@ pub(crate) mod tests {
   use tower_http::services::ServeDir;
 
   use htsget_config::types::Headers;
+  use tempfile::TempDir;
 
+  use crate::error::MODIFIED_RETRY_AFTER;
   use crate::local::tests::create_local_test_files;
 
   use super::*;
@@ -325,6 +792,149 @@ pub(crate) mod tests {
     assert_eq!(headers.len(), 1);
   }
 
+  #[test]
+  fn remove_headers_not_in_allowlist() {
+    let storage = test_storage().with_header_allowlist(Some(vec!["Range".to_string()]));
+
+    let mut headers = HeaderMap::default();
+    headers.insert(HOST, HeaderValue::from_static("example.com"));
+    headers.insert(RANGE, HeaderValue::from_static("bytes=0-10"));
+
+    let headers = storage.remove_blacklisted_headers(headers);
+
+    assert_eq!(headers.len(), 1);
+    assert_eq!(headers.get(RANGE).unwrap(), "bytes=0-10");
+  }
+
+  #[tokio::test]
+  async fn upstream_headers_bearer() {
+    std::env::set_var("HTSGET_TEST_URL_BEARER_TOKEN", "token");
+    let storage = test_storage().with_auth(
+      Some(UrlAuth::Bearer {
+        token: Secret::Env("HTSGET_TEST_URL_BEARER_TOKEN".to_string()),
+      }),
+      Default::default(),
+    );
+
+    let mut headers = HeaderMap::default();
+    headers.insert(AUTHORIZATION, HeaderValue::from_static("client"));
+    let headers = storage.upstream_headers(&headers).await.unwrap();
+
+    assert_eq!(headers.get(AUTHORIZATION).unwrap(), "Bearer token");
+  }
+
+  #[tokio::test]
+  async fn upstream_headers_basic() {
+    let tmp = TempDir::new().unwrap();
+    let password = tmp.path().join("password");
+    fs::write(&password, "password\n").unwrap();
+
+    let storage = test_storage().with_auth(
+      Some(UrlAuth::Basic {
+        username: "user".to_string(),
+        password: Secret::File(password),
+      }),
+      Default::default(),
+    );
+    let headers = storage
+      .upstream_headers(&HeaderMap::default())
+      .await
+      .unwrap();
+
+    assert_eq!(
+      headers.get(AUTHORIZATION).unwrap(),
+      "Basic dXNlcjpwYXNzd29yZA=="
+    );
+  }
+
+  #[tokio::test]
+  async fn upstream_headers_template() {
+    std::env::set_var("HTSGET_TEST_URL_TEMPLATE", "value");
+    let storage = test_storage().with_headers(HashMap::from([(
+      "x-template".to_string(),
+      "${env.HTSGET_TEST_URL_TEMPLATE}-${header.x-user}".to_string(),
+    )]));
+
+    let mut headers = HeaderMap::default();
+    headers.insert("x-user", HeaderValue::from_static("user"));
+    let result = storage.upstream_headers(&headers).await.unwrap();
+    assert_eq!(result.get("x-template").unwrap(), "value-user");
+
+    let result = storage.upstream_headers(&HeaderMap::default()).await;
+    assert!(matches!(result, Err(InvalidInput(_))));
+  }
+
+  #[tokio::test]
+  async fn upstream_headers_oauth2() {
+    std::env::set_var("HTSGET_TEST_URL_CLIENT_SECRET", "secret");
+    let count = Arc::new(AtomicUsize::new(0));
+    let handler = {
+      let count = count.clone();
+      move |body: String| {
+        let count = count.clone();
+        async move {
+          assert!(body.contains("grant_type=client_credentials"));
+          assert!(body.contains("client_secret=secret"));
+          count.fetch_add(1, Ordering::SeqCst);
+
+          r#"{ "access_token": "token", "expires_in": 3600 }"#
+        }
+      }
+    };
+    let router = Router::new().route("/token", post(handler));
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, router.into_make_service()).await });
+
+    let storage = test_storage().with_auth(
+      Some(UrlAuth::OAuth2 {
+        token_url: format!("http://{}/token", addr),
+        client_id: "id".to_string(),
+        client_secret: Secret::Env("HTSGET_TEST_URL_CLIENT_SECRET".to_string()),
+        scope: None,
+      }),
+      Default::default(),
+    );
+
+    for _ in 0..2 {
+      let headers = storage
+        .upstream_headers(&HeaderMap::default())
+        .await
+        .unwrap();
+      assert_eq!(headers.get(AUTHORIZATION).unwrap(), "Bearer token");
+    }
+    assert_eq!(count.load(Ordering::SeqCst), 1);
+  }
+
+  #[tokio::test]
+  async fn range_url_excludes_credentials() {
+    std::env::set_var("HTSGET_TEST_URL_TICKET_TOKEN", "token");
+    let storage = test_storage()
+      .with_auth(
+        Some(UrlAuth::Bearer {
+          token: Secret::Env("HTSGET_TEST_URL_TICKET_TOKEN".to_string()),
+        }),
+        Default::default(),
+      )
+      .with_headers(HashMap::from([(
+        "x-template".to_string(),
+        "value".to_string(),
+      )]));
+
+    let mut headers = HeaderMap::default();
+    let result = storage
+      .range_url("assets/key1", test_range_options(&mut headers))
+      .await
+      .unwrap();
+
+    assert_eq!(
+      result,
+      HtsGetUrl::new("https://localhost:8080/assets/key1")
+        .with_headers(Headers::default().with_header(AUTHORIZATION.as_str(), "secret"))
+    );
+  }
+
   #[tokio::test]
   async fn send_request() {
     with_url_test_server(|storage, _, _| async move {
@@ -470,6 +1080,251 @@ pub(crate) mod tests {
     .await;
   }
 
+  #[tokio::test]
+  async fn send_request_permission_denied() {
+    with_url_test_server(|storage, _, _| async move {
+      let result = storage.get_key("assets/key1", &HeaderMap::default()).await;
+
+      assert!(matches!(result, Err(PermissionDenied(_))));
+    })
+    .await;
+  }
+
+  #[tokio::test]
+  async fn send_request_not_found() {
+    with_url_test_server(|storage, _, _| async move {
+      let mut headers = HeaderMap::default();
+      let headers = test_headers(&mut headers);
+      let result = storage.get_key("assets/missing", headers).await;
+
+      assert!(matches!(result, Err(KeyNotFound(_))));
+    })
+    .await;
+  }
+
+  #[tokio::test]
+  async fn send_request_retries_server_errors() {
+    with_failing_test_server(2, |storage, count| async move {
+      let result = storage
+        .with_retries(3, Duration::from_millis(1))
+        .head_key("key", &HeaderMap::default())
+        .await;
+
+      assert!(result.is_ok());
+      assert_eq!(count.load(Ordering::SeqCst), 3);
+    })
+    .await;
+  }
+
+  #[tokio::test]
+  async fn send_request_retries_exhausted() {
+    with_failing_test_server(10, |storage, count| async move {
+      let result = storage
+        .with_retries(2, Duration::from_millis(1))
+        .head_key("key", &HeaderMap::default())
+        .await;
+
+      assert!(matches!(result, Err(ServerError(_))));
+      assert_eq!(count.load(Ordering::SeqCst), 3);
+    })
+    .await;
+  }
+
+  #[tokio::test]
+  async fn send_request_circuit_breaker_opens() {
+    with_failing_test_server(10, |storage, count| async move {
+      let storage = storage
+        .with_retries(0, Duration::from_millis(1))
+        .with_circuit_breaker(Default::default(), 2, Duration::from_secs(30));
+
+      for _ in 0..2 {
+        let result = storage.head_key("key", &HeaderMap::default()).await;
+        assert!(matches!(result, Err(ServerError(_))));
+      }
+
+      let result = storage.head_key("key", &HeaderMap::default()).await;
+      assert!(matches!(result, Err(Unavailable(_, Some(_)))));
+      assert_eq!(count.load(Ordering::SeqCst), 2);
+    })
+    .await;
+  }
+
+  #[tokio::test]
+  async fn send_request_records_one_failure_per_request() {
+    with_failing_test_server(10, |storage, count| async move {
+      let storage = storage
+        .with_retries(2, Duration::from_millis(1))
+        .with_circuit_breaker(Default::default(), 2, Duration::from_secs(30));
+
+      let result = storage.head_key("key", &HeaderMap::default()).await;
+      assert!(matches!(result, Err(ServerError(_))));
+      assert_eq!(count.load(Ordering::SeqCst), 3);
+
+      let result = storage.head_key("key", &HeaderMap::default()).await;
+      assert!(matches!(result, Err(ServerError(_))));
+      assert_eq!(count.load(Ordering::SeqCst), 6);
+
+      let result = storage.head_key("key", &HeaderMap::default()).await;
+      assert!(matches!(result, Err(Unavailable(_, Some(_)))));
+      assert_eq!(count.load(Ordering::SeqCst), 6);
+    })
+    .await;
+  }
+
+  #[tokio::test]
+  async fn head_falls_back_to_range_get() {
+    with_no_head_test_server(|storage| async move {
+      let mut headers = HeaderMap::default();
+      let options = HeadOptions::new(test_headers(&mut headers));
+
+      assert_eq!(storage.head("assets/key1", options).await.unwrap(), 6);
+    })
+    .await;
+  }
+
+  #[tokio::test]
+  async fn head_without_fallback() {
+    with_no_head_test_server(|storage| async move {
+      let mut headers = HeaderMap::default();
+      let options = HeadOptions::new(test_headers(&mut headers));
+
+      let result = storage
+        .with_size_discovery(SizeDiscovery::Head)
+        .head("assets/key1", options)
+        .await;
+      assert!(matches!(result, Err(ResponseError(_))));
+    })
+    .await;
+  }
+
+  #[tokio::test]
+  async fn head_with_range_get() {
+    with_url_test_server(|storage, _, _| async move {
+      let mut headers = HeaderMap::default();
+      let options = HeadOptions::new(test_headers(&mut headers));
+
+      let result = storage
+        .with_size_discovery(SizeDiscovery::Get)
+        .head("assets/key1", options)
+        .await;
+      assert_eq!(result.unwrap(), 6);
+    })
+    .await;
+  }
+
+  #[tokio::test]
+  async fn head_with_size_hint() {
+    let storage = UrlStorage::new(
+      test_client(),
+      Uri::from_str("http://127.0.0.1:0").unwrap(),
+      Uri::from_str("http://127.0.0.1:0").unwrap(),
+      false,
+      vec![],
+    )
+    .with_sizes(HashMap::from([("assets/key1".to_string(), 10)]));
+
+    let headers = HeaderMap::default();
+    let result = storage
+      .head("assets/key1", HeadOptions::new(&headers))
+      .await;
+    assert_eq!(result.unwrap(), 10);
+  }
+
+  #[test]
+  fn size_manifest() {
+    let tmp = TempDir::new().unwrap();
+    let manifest = tmp.path().join("manifest.json");
+    fs::write(&manifest, r#"{ "assets/key1": 10 }"#).unwrap();
+
+    assert_eq!(
+      *UrlStorage::read_size_manifest(&manifest).unwrap(),
+      HashMap::from([("assets/key1".to_string(), 10)])
+    );
+    assert!(Arc::ptr_eq(
+      &UrlStorage::read_size_manifest(&manifest).unwrap(),
+      &UrlStorage::read_size_manifest(&manifest).unwrap()
+    ));
+    assert!(matches!(
+      UrlStorage::read_size_manifest(&tmp.path().join("missing.json")),
+      Err(IoError(_, _))
+    ));
+  }
+
+  #[tokio::test]
+  async fn etag_storage() {
+    with_url_test_server(|storage, _, _| async move {
+      let mut headers = HeaderMap::default();
+      let headers = test_headers(&mut headers);
+      let options = HeadOptions::new(headers);
+
+      assert_eq!(
+        storage.etag("assets/key1", options).await.unwrap(),
+        Some("\"etag\"".to_string())
+      );
+    })
+    .await;
+  }
+
+  #[tokio::test]
+  async fn etag_and_head_share_request() {
+    with_failing_test_server(0, |storage, count| async move {
+      let headers = HeaderMap::default();
+
+      assert_eq!(
+        storage
+          .etag("key", HeadOptions::new(&headers))
+          .await
+          .unwrap(),
+        None
+      );
+      assert_eq!(
+        storage
+          .head("key", HeadOptions::new(&headers))
+          .await
+          .unwrap(),
+        0
+      );
+      assert_eq!(count.load(Ordering::SeqCst), 1);
+    })
+    .await;
+  }
+
+  #[tokio::test]
+  async fn get_storage_with_etag() {
+    with_url_test_server(|storage, _, _| async move {
+      let mut headers = HeaderMap::default();
+      let headers = test_headers(&mut headers);
+
+      let options =
+        GetOptions::new_with_default_range(headers).with_etag(Some("\"etag\"".to_string()));
+      assert!(storage.get("assets/key1", options).await.is_ok());
+
+      let options =
+        GetOptions::new_with_default_range(headers).with_etag(Some("\"other\"".to_string()));
+      let result = storage.get("assets/key1", options).await;
+      assert!(matches!(
+        result,
+        Err(Unavailable(_, Some(MODIFIED_RETRY_AFTER)))
+      ));
+    })
+    .await;
+  }
+
+  #[tokio::test]
+  async fn range_url_storage_with_etag() {
+    with_url_test_server(|storage, url, _| async move {
+      let mut headers = HeaderMap::default();
+      let options = test_range_options(&mut headers).with_etag(Some("\"etag\"".to_string()));
+
+      assert_eq!(
+        storage.range_url("assets/key1", options).await.unwrap(),
+        HtsGetUrl::new(format!("{}/assets/key1", url))
+          .with_headers(Headers::default().with_header("If-Match", "\"etag\""))
+      );
+    })
+    .await;
+  }
+
   #[test]
   fn format_url() {
     let storage = UrlStorage::new(
@@ -554,6 +1409,23 @@ pub(crate) mod tests {
     }
   }
 
+  async fn test_if_match(
+    request: Request<Body>,
+    next: Next,
+  ) -> result::Result<Response, StatusCode> {
+    match request.headers().get(IF_MATCH) {
+      Some(etag) if etag != "\"etag\"" => Err(StatusCode::PRECONDITION_FAILED),
+      _ => Ok(next.run(request).await),
+    }
+  }
+
+  async fn test_etag(mut response: Response) -> Response {
+    response
+      .headers_mut()
+      .insert(ETAG, HeaderValue::from_static("\"etag\""));
+    response
+  }
+
   pub(crate) async fn with_test_server<F, Fut>(server_base_path: &Path, test: F)
   where
     F: FnOnce(UrlStorage, String, PathBuf) -> Fut,
@@ -562,7 +1434,9 @@ pub(crate) mod tests {
     let path = server_base_path.to_str().unwrap();
     let router = Router::new()
       .nest_service("/assets", ServeDir::new(path))
-      .route_layer(middleware::from_fn(test_auth));
+      .route_layer(middleware::from_fn(test_auth))
+      .layer(middleware::from_fn(test_if_match))
+      .layer(middleware::map_response(test_etag));
 
     // TODO fix this in htsget-test to bind and return tcp listener.
     let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
@@ -585,6 +1459,85 @@ pub(crate) mod tests {
     .await;
   }
 
+  async fn reject_head(request: Request<Body>, next: Next) -> result::Result<Response, StatusCode> {
+    if request.method() == Method::HEAD {
+      Err(StatusCode::METHOD_NOT_ALLOWED)
+    } else {
+      Ok(next.run(request).await)
+    }
+  }
+
+  async fn with_no_head_test_server<F, Fut>(test: F)
+  where
+    F: FnOnce(UrlStorage) -> Fut,
+    Fut: Future<Output = ()>,
+  {
+    let (_, base_path) = create_local_test_files().await;
+    let router = Router::new()
+      .nest_service("/assets", ServeDir::new(base_path.path()))
+      .route_layer(middleware::from_fn(test_auth))
+      .layer(middleware::from_fn(reject_head));
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move { axum::serve(listener, router.into_make_service()).await });
+
+    let url = Uri::from_str(&format!("http://{}", addr)).unwrap();
+    test(UrlStorage::new(
+      test_client(),
+      url.clone(),
+      url,
+      false,
+      vec![],
+    ))
+    .await;
+  }
+
+  async fn with_failing_test_server<F, Fut>(failures: usize, test: F)
+  where
+    F: FnOnce(UrlStorage, Arc<AtomicUsize>) -> Fut,
+    Fut: Future<Output = ()>,
+  {
+    let count = Arc::new(AtomicUsize::new(0));
+    let handler = {
+      let count = count.clone();
+      move || {
+        let count = count.clone();
+        async move {
+          if count.fetch_add(1, Ordering::SeqCst) < failures {
+            StatusCode::SERVICE_UNAVAILABLE
+          } else {
+            StatusCode::OK
+          }
+        }
+      }
+    };
+    let router = Router::new().route("/key", get(handler));
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move { axum::serve(listener, router.into_make_service()).await });
+
+    let url = Uri::from_str(&format!("http://{}", addr)).unwrap();
+    test(
+      UrlStorage::new(test_client(), url.clone(), url, false, vec![]),
+      count,
+    )
+    .await;
+  }
+
+  fn test_storage() -> UrlStorage {
+    UrlStorage::new(
+      test_client(),
+      Uri::from_str("https://example.com").unwrap(),
+      Uri::from_str("https://localhost:8080").unwrap(),
+      true,
+      vec![],
+    )
+  }
+
   pub(crate) fn test_headers(headers: &mut HeaderMap) -> &HeaderMap {
     headers.append(
       HeaderName::from_str(AUTHORIZATION.as_str()).unwrap(),
@@ -1,14 +1,22 @@
+use async_trait::async_trait;
 use aws_config::SdkConfig;
 use aws_credential_types::provider::SharedCredentialsProvider;
 use aws_credential_types::Credentials;
 use aws_sdk_s3::config::{BehaviorVersion, Region};
 use aws_sdk_s3::Client;
 use s3s::auth::SimpleAuth;
+use s3s::dto::{
+  GetObjectInput, GetObjectOutput, HeadObjectInput, HeadObjectOutput, RestoreObjectInput,
+  RestoreObjectOutput, StorageClass,
+};
 use s3s::host::SingleDomain;
 use s3s::service::S3ServiceBuilder;
+use s3s::{s3_error, S3Error, S3ErrorCode, S3Request, S3Response, S3Result, S3};
 use s3s_fs::FileSystem;
+use std::collections::HashMap;
 use std::future::Future;
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 use tempfile::TempDir;
 
 /// Default domain to use for mock s3 server.
@@ -17,6 +25,91 @@ pub const DEFAULT_DOMAIN_NAME: &str = "localhost:0";
 /// Default region to use for mock s3 server.
 pub const DEFAULT_REGION: &str = "ap-southeast-2";
 
+/// The restore state of an archived object in the [ArchivedFileSystem].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestoreState {
+  /// The object is archived and no restore has been requested.
+  Archived,
+  /// A restore has been requested and is ongoing.
+  Ongoing,
+  /// The object has been restored.
+  Restored,
+}
+
+/// A mock s3 service which wraps a `FileSystem`, reporting some objects as archived in the
+/// `GLACIER` storage class. Archived objects cannot be read until they are restored, and
+/// restore requests move objects into the ongoing state.
+pub struct ArchivedFileSystem {
+  inner: FileSystem,
+  archived: Arc<Mutex<HashMap<String, RestoreState>>>,
+}
+
+impl ArchivedFileSystem {
+  /// Create a new archived file system, using the keys and their restore state.
+  pub fn new(server_base_path: &Path, archived: Arc<Mutex<HashMap<String, RestoreState>>>) -> Self {
+    Self {
+      inner: FileSystem::new(server_base_path).unwrap(),
+      archived,
+    }
+  }
+
+  fn state(&self, key: &str) -> Option<RestoreState> {
+    self.archived.lock().unwrap().get(key).copied()
+  }
+}
+
+#[async_trait]
+impl S3 for ArchivedFileSystem {
+  async fn get_object(
+    &self,
+    req: S3Request<GetObjectInput>,
+  ) -> S3Result<S3Response<GetObjectOutput>> {
+    match self.state(&req.input.key) {
+      Some(RestoreState::Archived | RestoreState::Ongoing) => Err(s3_error!(InvalidObjectState)),
+      _ => self.inner.get_object(req).await,
+    }
+  }
+
+  async fn head_object(
+    &self,
+    req: S3Request<HeadObjectInput>,
+  ) -> S3Result<S3Response<HeadObjectOutput>> {
+    let state = self.state(&req.input.key);
+    let mut response = self.inner.head_object(req).await?;
+
+    if let Some(state) = state {
+      response.output.storage_class = Some(StorageClass::from_static(StorageClass::GLACIER));
+      response.output.restore = match state {
+        RestoreState::Archived => None,
+        RestoreState::Ongoing => Some("ongoing-request=\"true\"".to_string()),
+        RestoreState::Restored => Some(
+          "ongoing-request=\"false\", expiry-date=\"Fri, 21 Dec 2012 00:00:00 GMT\"".to_string(),
+        ),
+      };
+    }
+
+    Ok(response)
+  }
+
+  async fn restore_object(
+    &self,
+    req: S3Request<RestoreObjectInput>,
+  ) -> S3Result<S3Response<RestoreObjectOutput>> {
+    let mut archived = self.archived.lock().unwrap();
+    match archived.get_mut(&req.input.key) {
+      Some(state @ RestoreState::Archived) => {
+        *state = RestoreState::Ongoing;
+        Ok(S3Response::new(RestoreObjectOutput::default()))
+      }
+      Some(RestoreState::Ongoing) => Err(s3_error!(RestoreAlreadyInProgress)),
+      // Not every version of `s3s` has a variant for this code.
+      _ => Err(S3Error::new(S3ErrorCode::Custom(
+        "ObjectAlreadyInActiveTierError".into(),
+      ))),
+    }
+  }
+}
+
 /// Run a mock s3 server using the `server_base_path` and a test function. Specify the domain name and region to use for the mock server.
 pub async fn run_s3_test_server<F, Fut>(
   server_base_path: &Path,
@@ -26,16 +119,36 @@ pub async fn run_s3_test_server<F, Fut>(
 ) where
   F: FnOnce(Client, PathBuf) -> Fut,
   Fut: Future<Output = ()>,
+{
+  run_s3_service_test_server(
+    FileSystem::new(server_base_path).unwrap(),
+    server_base_path,
+    test,
+    domain_name,
+    region,
+  )
+  .await;
+}
+
+/// Run a mock s3 server using any s3s service and a test function.
+pub async fn run_s3_service_test_server<S, F, Fut>(
+  s3: S,
+  server_base_path: &Path,
+  test: F,
+  domain_name: &str,
+  region: &'static str,
+) where
+  S: S3,
+  F: FnOnce(Client, PathBuf) -> Fut,
+  Fut: Future<Output = ()>,
 {
   let cred = Credentials::for_tests();
 
   let client = {
-    let fs = FileSystem::new(server_base_path).unwrap();
-
     let auth = SimpleAuth::from_single(cred.access_key_id(), cred.secret_access_key());
     let host = SingleDomain::new(domain_name).unwrap();
 
-    let mut service = S3ServiceBuilder::new(fs);
+    let mut service = S3ServiceBuilder::new(s3);
     service.set_auth(auth);
     service.set_host(host);
 
@@ -68,6 +181,25 @@ where
   .await;
 }
 
+/// Run a mock s3 server with an [ArchivedFileSystem]. Uses the default domain name and region.
+pub async fn with_s3_archived_test_server<F, Fut>(
+  server_base_path: &Path,
+  archived: Arc<Mutex<HashMap<String, RestoreState>>>,
+  test: F,
+) where
+  F: FnOnce(Client) -> Fut,
+  Fut: Future<Output = ()>,
+{
+  run_s3_service_test_server(
+    ArchivedFileSystem::new(server_base_path, archived),
+    server_base_path,
+    |client, _| test(client),
+    DEFAULT_DOMAIN_NAME,
+    DEFAULT_REGION,
+  )
+  .await;
+}
+
 /// Run a mock s3 server. Uses the default domain name and region, and a temporary directory as the base path.
 pub async fn with_s3_test_server_tmp<F, Fut>(test: F)
 where